@@ -6,6 +6,7 @@ use sqlx::{PgPool, Postgres, postgres::PgArguments};
 use sqlx::query::Query;
 use mlua_scheduler::LuaSchedulerAsyncUserData;
 use khronos_runtime::core::datetime::DateTimeUtc as LuaDateTime;
+use khronos_runtime::primitives::blob::Blob;
 
 pub trait DbRow {
     fn row(&self) -> &sqlx::postgres::PgRow;
@@ -244,7 +245,7 @@ impl<T: DbValueMapper> LuaUserData for DbTx<T> {
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(tag = "type", content = "value")]
-/// A simple db value mapper that supports common types like i32, i64, String, bool, f64, timestamptz, json, and jsonb, as well as lists of those types. 
+/// A simple db value mapper that supports common types like i32, i64, String, bool, f64, timestamptz, json, jsonb and bytea, as well as lists of those types. 
 /// 
 /// This can be used for simple cases where you don't need any special behavior in the mapping and just want a straightforward way to convert between Lua values and database values.
 pub enum SimpleDbValueMapper {
@@ -264,6 +265,8 @@ pub enum SimpleDbValueMapper {
     JsonList(Option<Vec<serde_json::Value>>),
     Jsonb(Option<serde_json::Value>),
     JsonbList(Option<Vec<serde_json::Value>>),
+    Bytea(Option<Vec<u8>>),
+    ByteaList(Option<Vec<Vec<u8>>>),
     KhronosValue(KhronosValue)
 }
 
@@ -277,7 +280,8 @@ impl DbValueMapper for SimpleDbValueMapper {
             "f64" | "{f64}" |
             "timestamptz" | "{timestamptz}" |
             "json" | "{json}" |
-            "jsonb" | "{jsonb}" | "custom@khronosvalue"
+            "jsonb" | "{jsonb}" |
+            "bytea" | "{bytea}" | "custom@khronosvalue"
         )
     }
 
@@ -299,6 +303,8 @@ impl DbValueMapper for SimpleDbValueMapper {
             Self::JsonList(_) => "{json}",
             Self::Jsonb(_) => "jsonb",
             Self::JsonbList(_) => "{jsonb}",
+            Self::Bytea(_) => "bytea",
+            Self::ByteaList(_) => "{bytea}",
             Self::KhronosValue(_) => "custom@khronosvalue"
         }
     }
@@ -321,6 +327,8 @@ impl DbValueMapper for SimpleDbValueMapper {
             Self::JsonList(v) => query.bind(v), 
             Self::Jsonb(v) => query.bind(v),
             Self::JsonbList(v) => query.bind(v),
+            Self::Bytea(v) => query.bind(v),
+            Self::ByteaList(v) => query.bind(v),
             Self::KhronosValue(v) => {
                 let v = serde_json::to_value(v).map_err(|e| sqlx::Error::AnyDriverError(Box::new(e)))?;
                 query.bind(v)
@@ -346,6 +354,8 @@ impl DbValueMapper for SimpleDbValueMapper {
             "{json}" => Ok(Self::JsonList(row.try_get(idx)?)),
             "jsonb" => Ok(Self::Jsonb(row.try_get(idx)?)),
             "{jsonb}" => Ok(Self::JsonbList(row.try_get(idx)?)),
+            "bytea" => Ok(Self::Bytea(row.try_get(idx)?)),
+            "{bytea}" => Ok(Self::ByteaList(row.try_get(idx)?)),
             "custom@khronosvalue" => {
                 let json = row.try_get(idx)?;
                 match serde_json::from_value(json) {
@@ -400,6 +410,10 @@ impl DbValueMapper for SimpleDbValueMapper {
             "{json}" => lua.from_value(value).map(Self::JsonList),
             "jsonb" => lua.from_value(value).map(Self::Jsonb),
             "{jsonb}" => lua.from_value(value).map(Self::JsonbList),
+            // Buffers (or strings), nil for NULL
+            "bytea" => Option::<Blob>::from_lua(value, lua).map(|b| Self::Bytea(b.map(|b| b.0.to_vec()))),
+            "{bytea}" => Option::<Vec<Blob>>::from_lua(value, lua)
+                .map(|l| Self::ByteaList(l.map(|l| l.into_iter().map(|b| b.0.to_vec()).collect()))),
             "custom@khronosvalue" => KhronosValue::from_lua(value, lua).map(Self::KhronosValue),
             _ => Err(LuaError::external(format!("Unsupported type name: {}", type_name))),
         }
@@ -423,6 +437,8 @@ impl DbValueMapper for SimpleDbValueMapper {
             Self::JsonList(v) => lua.to_value(&v),
             Self::Jsonb(v) => lua.to_value(&v),
             Self::JsonbList(v) => lua.to_value(&v),
+            Self::Bytea(v) => v.map(|v| Blob(v.into())).into_lua(lua),
+            Self::ByteaList(v) => v.map(|v| v.into_iter().map(|v| Blob(v.into())).collect::<Vec<_>>()).into_lua(lua),
             Self::KhronosValue(v) => v.into_lua(lua),
         }
     }
@@ -483,4 +499,38 @@ impl<T: DbValueMapper, U: DbValueMapper> DbValueMapper for ChainedDbValueMapper<
             Self::Right(right) => right.into_lua(lua),
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bytea() -> LuaResult<()> {
+        let lua = Lua::new();
+        let buf: LuaValue = lua.load(r#"return buffer.fromstring("\0\1\255")"#).eval()?;
+
+        let mapped = SimpleDbValueMapper::from_lua(&lua, buf.clone(), "bytea")?;
+        assert!(matches!(&mapped, SimpleDbValueMapper::Bytea(Some(b)) if b[..] == [0, 1, 255]));
+        let LuaValue::Buffer(back) = DbValueMapper::into_lua(mapped, &lua)? else {
+            panic!("bytea must map to a buffer");
+        };
+        assert_eq!(back.to_vec(), [0, 1, 255]);
+
+        let mapped = SimpleDbValueMapper::from_lua(&lua, LuaValue::Nil, "bytea")?;
+        assert!(matches!(mapped, SimpleDbValueMapper::Bytea(None)));
+        assert!(DbValueMapper::into_lua(mapped, &lua)?.is_nil());
+
+        // Lists may mix buffers and strings
+        let list: LuaValue = lua.load(r#"return { buffer.fromstring("ab"), "c" }"#).eval()?;
+        let mapped = SimpleDbValueMapper::from_lua(&lua, list, "{bytea}")?;
+        assert!(matches!(&mapped, SimpleDbValueMapper::ByteaList(Some(l)) if l == &[b"ab".to_vec(), b"c".to_vec()]));
+        let LuaValue::Table(back) = DbValueMapper::into_lua(mapped, &lua)? else {
+            panic!("{{bytea}} must map to a table");
+        };
+        let back = back.sequence_values::<LuaBuffer>().map(|b| b.map(|b| b.to_vec())).collect::<LuaResult<Vec<_>>>()?;
+        assert_eq!(back, [b"ab".to_vec(), b"c".to_vec()]);
+
+        assert!(SimpleDbValueMapper::from_lua(&lua, LuaValue::Boolean(true), "bytea").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,51 @@
+use mluau::prelude::*;
+use serde::de::DeserializeSeed;
+
+use crate::primitives::blob::blob_ref;
+use crate::utils::luaserde::{Format, LuaValueRef, LuaValueSeed};
+
+pub fn init_plugin(lua: &Lua) -> LuaResult<LuaTable> {
+    let module = lua.create_table()?;
+
+    module.set("tocbor", lua.create_function(|lua, value: LuaValue| {
+        let mut encoded = Vec::new();
+        ciborium::into_writer(&LuaValueRef::new(lua, &value, Format::Cbor), &mut encoded).into_lua_err()?;
+        lua.create_external_buffer(bytes::Bytes::from(encoded))
+    })?)?;
+
+    module.set("fromcbor", lua.create_function(|lua, data: LuaValue| {
+        blob_ref(&data, |s| {
+            let mut de = ciborium::de::Deserializer::from_reader(s);
+            LuaValueSeed::new(lua, Format::Cbor).deserialize(&mut de).into_lua_err()
+        })?
+    })?)?;
+
+    module.set_readonly(true); // Block any attempt to modify this table
+
+    Ok(module)
+}
+
+#[cfg(test)]
+mod tests {
+    use mluau::prelude::*;
+
+    use crate::utils::luaserde::check_roundtrip;
+
+    #[test]
+    fn test_roundtrip() -> LuaResult<()> {
+        let lua = Lua::new();
+        let module = super::init_plugin(&lua)?;
+        let encode: LuaFunction = module.get("tocbor")?;
+        let decode: LuaFunction = module.get("fromcbor")?;
+        check_roundtrip(&lua, &encode, &decode)?;
+
+        // Small Int64's are plain integers any decoder can read
+        let encoded: LuaBuffer = encode.call(LuaValue::Int64(5))?;
+        assert_eq!(encoded.to_vec(), vec![0x05]);
+
+        // Unknown tags (here an epoch timestamp) are ignored
+        assert_eq!(decode.call::<i64>(lua.create_buffer([0xc1u8, 0x00])?)?, 0);
+
+        Ok(())
+    }
+}
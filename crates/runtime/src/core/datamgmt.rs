@@ -7,6 +7,7 @@ use mlua_scheduler::LuaSchedulerAsync;
 use mluau::prelude::*;
 use bstr::ByteSlice;
 use argon2::Argon2;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
 use aes_gcm::aead::Aead;
 use aes_gcm::KeyInit;
 use aes_gcm::{Aes256Gcm, Nonce};
@@ -19,6 +20,52 @@ use async_compression::{
 
 use crate::primitives::blob::{Blob, blob_ref, blob_ref_async};
 
+/// Host-configured upper bounds on the Argon2id cost parameters usable by
+/// ``hashpassword``/``verifypassword``
+#[derive(Debug, Copy, Clone)]
+pub struct PasswordHashLimits {
+    /// Maximum memory cost (in KiB)
+    pub max_m_cost: u32,
+    /// Maximum number of iterations
+    pub max_t_cost: u32,
+    /// Maximum degree of parallelism
+    pub max_p_cost: u32,
+}
+
+impl PasswordHashLimits {
+    /// Ensures the given Argon2 parameters are within the limits
+    fn check(&self, params: &argon2::Params) -> LuaResult<()> {
+        if params.m_cost() > self.max_m_cost {
+            return Err(LuaError::external(format!("m_cost cannot be greater than {}", self.max_m_cost)));
+        }
+        if params.t_cost() > self.max_t_cost {
+            return Err(LuaError::external(format!("t_cost cannot be greater than {}", self.max_t_cost)));
+        }
+        if params.p_cost() > self.max_p_cost {
+            return Err(LuaError::external(format!("p_cost cannot be greater than {}", self.max_p_cost)));
+        }
+        Ok(())
+    }
+}
+
+impl Default for PasswordHashLimits {
+    fn default() -> Self {
+        Self {
+            max_m_cost: 64 * 1024,
+            max_t_cost: 4,
+            max_p_cost: 4,
+        }
+    }
+}
+
+/// Cost parameters for ``hashpassword``. Unset fields use the Argon2 defaults
+#[derive(Debug, Default, serde::Deserialize)]
+struct PasswordHashOpts {
+    m_cost: Option<u32>,
+    t_cost: Option<u32>,
+    p_cost: Option<u32>,
+}
+
 pub struct TarArchive {
     pub entries: HashMap<BString, bytes::Bytes>,
 }
@@ -160,7 +207,7 @@ fn create_aes256_cipher(key: String, salt: &[u8]) -> LuaResult<Aes256Gcm> {
     Ok(cipher)
 }
 
-pub fn init_plugin(lua: &Lua) -> LuaResult<LuaTable> {
+pub fn init_plugin(lua: &Lua, password_limits: PasswordHashLimits) -> LuaResult<LuaTable> {
     let module = lua.create_table()?;
 
     module.set("base64encode", lua.create_function(|_, buf: LuaValue| {
@@ -261,8 +308,122 @@ pub fn init_plugin(lua: &Lua) -> LuaResult<LuaTable> {
         blob_ref_async(&blob, decompress).await?
     })?)?;
 
+    module.set("hashpassword", lua.create_scheduler_async_function(async move |lua, (password, opts): (LuaString, Option<LuaValue>)| {
+        let opts: PasswordHashOpts = match opts {
+            Some(opts) => lua.from_value(opts)?,
+            None => PasswordHashOpts::default(),
+        };
+
+        let params = argon2::Params::new(
+            opts.m_cost.unwrap_or(argon2::Params::DEFAULT_M_COST),
+            opts.t_cost.unwrap_or(argon2::Params::DEFAULT_T_COST),
+            opts.p_cost.unwrap_or(argon2::Params::DEFAULT_P_COST),
+            None,
+        )
+        .map_err(|e| LuaError::external(format!("Invalid Argon2 parameters: {e}")))?;
+
+        password_limits.check(&params)?;
+
+        let password = password.as_bytes().to_vec();
+
+        // Hashing is intentionally expensive, so run it on the blocking pool to avoid stalling the VM
+        tokio::task::spawn_blocking(move || {
+            let mut salt = [0u8; 16];
+            rand::rng().fill_bytes(&mut salt);
+            let salt = SaltString::encode_b64(&salt)
+                .map_err(|e| LuaError::external(format!("Failed to encode salt: {e}")))?;
+
+            let argon2 = Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params);
+            let hash = argon2
+                .hash_password(&password, &salt)
+                .map_err(|e| LuaError::external(format!("Failed to hash password: {e}")))?;
+
+            Ok(hash.to_string())
+        })
+        .await
+        .map_err(LuaError::external)?
+    })?)?;
+
+    module.set("verifypassword", lua.create_scheduler_async_function(async move |_lua, (password, phc): (LuaString, String)| {
+        // Validate the cost parameters stored in the hash before doing any work
+        // as a malicious hash could otherwise request arbitrary amounts of memory
+        {
+            let parsed = PasswordHash::new(&phc)
+                .map_err(|e| LuaError::external(format!("Invalid password hash: {e}")))?;
+
+            if parsed.algorithm != argon2::ARGON2ID_IDENT {
+                return Err(LuaError::external("Password hash must use argon2id"));
+            }
+
+            let params = argon2::Params::try_from(&parsed)
+                .map_err(|e| LuaError::external(format!("Invalid Argon2 parameters: {e}")))?;
+
+            password_limits.check(&params)?;
+        }
+
+        let password = password.as_bytes().to_vec();
+
+        tokio::task::spawn_blocking(move || {
+            let parsed = PasswordHash::new(&phc)
+                .map_err(|e| LuaError::external(format!("Invalid password hash: {e}")))?;
+
+            match Argon2::default().verify_password(&password, &parsed) {
+                Ok(()) => Ok(true),
+                Err(argon2::password_hash::Error::Password) => Ok(false),
+                Err(e) => Err(LuaError::external(format!("Failed to verify password: {e}"))),
+            }
+        })
+        .await
+        .map_err(LuaError::external)?
+    })?)?;
+
     module.set_readonly(true); // Block any attempt to modify this table
 
     Ok(module)
 }
 
+
+#[cfg(test)]
+mod tests {
+    use crate::rt::testutils::run_script;
+    use crate::rt::RuntimeCreateOpts;
+
+    #[test]
+    fn test_password_hashing() {
+        let script = r#"
+            return function()
+                local datamgmt = require"@antiraid/datamgmt"
+
+                local hash = datamgmt.hashpassword("hunter2", { m_cost = 1024, t_cost = 1, p_cost = 1 })
+                assert(string.sub(hash, 1, 10) == "$argon2id$", hash)
+                assert(datamgmt.verifypassword("hunter2", hash) == true, "correct password was rejected")
+                assert(datamgmt.verifypassword("hunter3", hash) == false, "wrong password was accepted")
+
+                -- Parameters above the host's limits
+                local ok, err = pcall(datamgmt.hashpassword, "hunter2", { m_cost = 4096 })
+                assert(not ok and string.find(tostring(err), "m_cost cannot be greater than 2048"), tostring(err))
+                ok, err = pcall(datamgmt.hashpassword, "hunter2", { m_cost = 1024, t_cost = 3 })
+                assert(not ok and string.find(tostring(err), "t_cost cannot be greater than 2"), tostring(err))
+                ok, err = pcall(datamgmt.hashpassword, "hunter2", { m_cost = 1024, p_cost = 2 })
+                assert(not ok and string.find(tostring(err), "p_cost cannot be greater than 1"), tostring(err))
+
+                -- Hashes asking for more than the limits are rejected before doing any work
+                local expensive = "$argon2id$v=19$m=65536,t=1,p=1$c29tZXNhbHRzYWx0$" .. string.rep("A", 43)
+                ok, err = pcall(datamgmt.verifypassword, "hunter2", expensive)
+                assert(not ok and string.find(tostring(err), "m_cost cannot be greater than 2048"), tostring(err))
+            end
+        "#;
+
+        run_script(
+            RuntimeCreateOpts {
+                password_hash_max_m_cost: Some(2048),
+                password_hash_max_t_cost: Some(2),
+                password_hash_max_p_cost: Some(1),
+                ..Default::default()
+            },
+            script,
+            |_| Ok(()),
+        )
+        .unwrap();
+    }
+}
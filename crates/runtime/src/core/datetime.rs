@@ -1,12 +1,157 @@
 use std::{str::FromStr, time::Duration};
 
-use chrono::{Datelike, TimeZone, Timelike};
-use chrono_tz::OffsetComponents;
+use chrono::{Datelike, NaiveDate, NaiveDateTime, Offset, TimeZone, Timelike};
+use chrono_tz::{Africa, America, Asia, Australia, Europe, OffsetComponents, Pacific};
 use mluau::prelude::*;
 
 pub type DateTimeUtc = DateTime<chrono_tz::Tz>;
 pub type DateTimeRef = LuaUserDataRef<DateTime<chrono_tz::Tz>>;
 
+/// The largest duration ``parseduration`` accepts
+const MAX_PARSED_DURATION: chrono::TimeDelta = chrono::TimeDelta::days(5 * 365);
+
+/// The longest string ``parseduration`` accepts
+const MAX_DURATION_STRING_LEN: usize = 128;
+
+/// Units used when humanizing a TimeDelta
+const HUMANIZE_UNITS: &[(&str, u64)] = &[
+    ("week", 7 * 24 * 60 * 60),
+    ("day", 24 * 60 * 60),
+    ("hour", 60 * 60),
+    ("minute", 60),
+    ("second", 1),
+];
+
+/// Units used for relative times. Months and years are approximated as 30 and 365 days
+const RELATIVE_UNITS: &[(&str, u64)] = &[
+    ("year", 365 * 24 * 60 * 60),
+    ("month", 30 * 24 * 60 * 60),
+    ("week", 7 * 24 * 60 * 60),
+    ("day", 24 * 60 * 60),
+    ("hour", 60 * 60),
+    ("minute", 60),
+    ("second", 1),
+];
+
+/// Parses a human duration such as ``1h30m``, ``2 weeks`` or ``3d 12h``
+///
+/// Each component must be a whole number followed by a unit (seconds, minutes, hours, days or weeks).
+/// Components may be separated by whitespace or commas and each unit may only appear once
+pub fn parse_duration(s: &str) -> Result<chrono::TimeDelta, String> {
+    let s = s.trim();
+    if s.is_empty() {
+        return Err("Duration cannot be empty".to_string());
+    }
+
+    if s.len() > MAX_DURATION_STRING_LEN {
+        return Err(format!("Duration cannot be longer than {MAX_DURATION_STRING_LEN} characters"));
+    }
+
+    let bytes = s.as_bytes();
+    let mut total = chrono::TimeDelta::zero();
+    let mut seen_units = 0u8;
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i].is_ascii_whitespace() || bytes[i] == b',' {
+            i += 1;
+            continue;
+        }
+
+        let num_start = i;
+        while i < bytes.len() && bytes[i].is_ascii_digit() {
+            i += 1;
+        }
+        if num_start == i {
+            return Err(format!("Expected a number at position {num_start} in duration"));
+        }
+        let value = s[num_start..i]
+            .parse::<i64>()
+            .map_err(|_| "Duration is too large".to_string())?;
+
+        while i < bytes.len() && bytes[i].is_ascii_whitespace() {
+            i += 1;
+        }
+
+        let unit_start = i;
+        while i < bytes.len() && bytes[i].is_ascii_alphabetic() {
+            i += 1;
+        }
+        if unit_start == i {
+            return Err(format!("Missing unit after '{}' in duration", &s[num_start..unit_start].trim_end()));
+        }
+
+        let unit = s[unit_start..i].to_ascii_lowercase();
+        let (bit, unit_secs) = match unit.as_str() {
+            "s" | "sec" | "secs" | "second" | "seconds" => (0, 1),
+            "m" | "min" | "mins" | "minute" | "minutes" => (1, 60),
+            "h" | "hr" | "hrs" | "hour" | "hours" => (2, 60 * 60),
+            "d" | "day" | "days" => (3, 24 * 60 * 60),
+            "w" | "wk" | "wks" | "week" | "weeks" => (4, 7 * 24 * 60 * 60),
+            _ => return Err(format!("Unknown unit '{unit}' in duration")),
+        };
+
+        if seen_units & (1 << bit) != 0 {
+            return Err(format!("Unit '{unit}' appears more than once in duration"));
+        }
+        seen_units |= 1 << bit;
+
+        total = value
+            .checked_mul(unit_secs)
+            .and_then(chrono::TimeDelta::try_seconds)
+            .and_then(|td| total.checked_add(&td))
+            .filter(|td| *td <= MAX_PARSED_DURATION)
+            .ok_or_else(|| format!("Duration cannot be longer than {} days", MAX_PARSED_DURATION.num_days()))?;
+    }
+
+    Ok(total)
+}
+
+fn pluralize(n: u64, unit: &str) -> String {
+    if n == 1 {
+        format!("{n} {unit}")
+    } else {
+        format!("{n} {unit}s")
+    }
+}
+
+/// Formats a TimeDelta as e.g. ``1 day, 2 hours`` using at most ``max_units`` units
+pub fn humanize_timedelta(td: chrono::TimeDelta, max_units: usize) -> String {
+    let negative = td < chrono::TimeDelta::zero();
+    let mut secs = td.num_seconds().unsigned_abs();
+
+    let mut parts = Vec::new();
+    for (unit, unit_secs) in HUMANIZE_UNITS {
+        if parts.len() >= max_units {
+            break;
+        }
+
+        let n = secs / unit_secs;
+        if n > 0 {
+            parts.push(pluralize(n, unit));
+            secs %= unit_secs;
+        }
+    }
+
+    if parts.is_empty() {
+        return "0 seconds".to_string();
+    }
+
+    let s = parts.join(", ");
+    if negative { format!("-{s}") } else { s }
+}
+
+/// Formats the difference between a time and now as e.g. ``in 3 days`` or ``5 minutes ago``
+pub fn relative_timedelta(diff: chrono::TimeDelta) -> String {
+    let secs = diff.num_seconds();
+    let abs = secs.unsigned_abs();
+    let Some((unit, unit_secs)) = RELATIVE_UNITS.iter().find(|(_, unit_secs)| abs >= *unit_secs) else {
+        return "now".to_string();
+    };
+
+    let s = pluralize(abs / unit_secs, unit);
+    if secs > 0 { format!("in {s}") } else { format!("{s} ago") }
+}
+
 pub struct TimeDelta {
     pub timedelta: chrono::TimeDelta,
 }
@@ -89,6 +234,19 @@ impl LuaUserData for TimeDelta {
                 this.timedelta.num_minutes() % 60
             ))
         });
+
+        // Formats the time delta as e.g. "1 day, 2 hours" using at most max_units (default 2) units
+        methods.add_method("humanize", |_, this, max_units: Option<usize>| {
+            let max_units = max_units.unwrap_or(2);
+            if max_units == 0 || max_units > HUMANIZE_UNITS.len() {
+                return Err(LuaError::external(format!(
+                    "max_units must be between 1 and {}",
+                    HUMANIZE_UNITS.len()
+                )));
+            }
+
+            Ok(humanize_timedelta(this.timedelta, max_units))
+        });
     }
 
     fn add_fields<F: LuaUserDataFields<Self>>(fields: &mut F) {
@@ -142,6 +300,18 @@ where
     pub fn to_utc(&self) -> chrono::DateTime<chrono::Utc> {
         self.dt.with_timezone(&chrono::Utc)
     }
+
+    /// Returns the time in its own timezone as a ``chrono_tz`` time
+    pub fn to_tz(&self) -> chrono::DateTime<chrono_tz::Tz> {
+        let tz: chrono_tz::Tz = self.dt.timezone().into();
+        self.dt.with_timezone(&tz)
+    }
+
+    pub fn from_tz(dt: chrono::DateTime<chrono_tz::Tz>) -> Self {
+        DateTime {
+            dt: dt.with_timezone(&Tz::from(dt.timezone())),
+        }
+    }
 }
 
 impl<Tz> LuaUserData for DateTime<Tz>
@@ -208,6 +378,79 @@ where
                 })
             },
         );
+
+        // Formats the time relative to now (or the given time) as e.g. "in 3 days" or "5 minutes ago"
+        methods.add_method(
+            "relative",
+            |_, this, now: Option<LuaUserDataRef<DateTime<Tz>>>| {
+                let diff = match now {
+                    Some(now) => this.dt.clone().signed_duration_since(&now.dt),
+                    None => this.to_utc().signed_duration_since(chrono::Utc::now()),
+                };
+
+                Ok(relative_timedelta(diff))
+            },
+        );
+
+        // Returns Discord timestamp markup (e.g. <t:1700000000:R>) for the given style (or the default style)
+        methods.add_method("discordtimestamp", |_, this, style: Option<String>| {
+            match style.as_deref() {
+                None => Ok(format!("<t:{}>", this.dt.timestamp())),
+                Some(style @ ("t" | "T" | "d" | "D" | "f" | "F" | "R")) => {
+                    Ok(format!("<t:{}:{style}>", this.dt.timestamp()))
+                }
+                Some(style) => Err(LuaError::external(format!(
+                    "Invalid Discord timestamp style '{style}' (expected one of t, T, d, D, f, F, R)"
+                ))),
+            }
+        });
+
+        // Adds calendar months, clamping the day to the end of the month if needed
+        methods.add_method("addmonths", |_, this, months: i64| {
+            let dt = add_months(&this.to_tz(), months)
+                .ok_or(mluau::Error::RuntimeError("Overflow in DateTime addition".to_string()))?;
+            Ok(DateTime::<Tz>::from_tz(dt))
+        });
+
+        // Adds calendar years, clamping Feb 29 to Feb 28 in non-leap years
+        methods.add_method("addyears", |_, this, years: i64| {
+            let dt = years
+                .checked_mul(12)
+                .and_then(|months| add_months(&this.to_tz(), months))
+                .ok_or(mluau::Error::RuntimeError("Overflow in DateTime addition".to_string()))?;
+            Ok(DateTime::<Tz>::from_tz(dt))
+        });
+
+        // Adds calendar days, keeping the wall clock time across DST transitions
+        methods.add_method("adddays", |_, this, days: i64| {
+            let dt = add_days(&this.to_tz(), days)
+                .ok_or(mluau::Error::RuntimeError("Overflow in DateTime addition".to_string()))?;
+            Ok(DateTime::<Tz>::from_tz(dt))
+        });
+
+        // Truncates to the start of the day, week (Monday), month or year in the DateTime's timezone
+        methods.add_method("truncate", |_, this, unit: String| {
+            let dt = truncate_local(&this.to_tz(), &unit).map_err(LuaError::external)?;
+            Ok(DateTime::<Tz>::from_tz(dt))
+        });
+
+        // Iterates over each calendar day (at the same wall clock time) up to and including ``until``
+        methods.add_method(
+            "iterdays",
+            |_, this, (until, step): (LuaUserDataRef<DateTime<Tz>>, Option<i64>)| {
+                let step = step.unwrap_or(1);
+                if step <= 0 {
+                    return Err(LuaError::external("step must be positive"));
+                }
+
+                Ok(DayIterator {
+                    start: this.to_tz(),
+                    until: until.to_utc(),
+                    step,
+                    index: 0,
+                })
+            },
+        );
     }
 
     fn add_fields<F: LuaUserDataFields<Self>>(fields: &mut F) {
@@ -219,6 +462,11 @@ where
         fields.add_field_method_get("hour", |_, this| Ok(this.dt.hour()));
         fields.add_field_method_get("minute", |_, this| Ok(this.dt.minute()));
         fields.add_field_method_get("second", |_, this| Ok(this.dt.second()));
+        fields.add_field_method_get("weekday", |_, this| Ok(this.dt.weekday().number_from_monday()));
+        fields.add_field_method_get("weekday_name", |_, this| Ok(this.dt.format("%A").to_string()));
+        fields.add_field_method_get("day_of_year", |_, this| Ok(this.dt.ordinal()));
+        fields.add_field_method_get("iso_week", |_, this| Ok(this.dt.iso_week().week()));
+        fields.add_field_method_get("iso_year", |_, this| Ok(this.dt.iso_week().year()));
         fields.add_field_method_get("timestamp_seconds", |_, this| Ok(this.dt.timestamp()));
         fields.add_field_method_get("timestamp_millis", |_, this| Ok(this.dt.timestamp_millis()));
         fields.add_field_method_get("timestamp_micros", |_, this| {
@@ -261,6 +509,63 @@ where
     }
 }
 
+/// Resolves a wall clock time in the timezone
+///
+/// Times repeated when DST ends resolve to the earlier instant, and times skipped when DST
+/// starts resolve to the end of the gap
+pub fn resolve_local(tz: &chrono_tz::Tz, naive: NaiveDateTime) -> Option<chrono::DateTime<chrono_tz::Tz>> {
+    match tz.from_local_datetime(&naive) {
+        chrono::LocalResult::Single(dt) => Some(dt),
+        chrono::LocalResult::Ambiguous(a, _) => Some(a),
+        chrono::LocalResult::None => {
+            (1..=180).find_map(|m| tz.from_local_datetime(&(naive + chrono::TimeDelta::minutes(m))).earliest())
+        }
+    }
+}
+
+/// Adds calendar months to a time, keeping its wall clock time
+///
+/// The day is clamped to the end of the month if needed (e.g. Jan 31 + 1 month is Feb 28/29)
+pub fn add_months(dt: &chrono::DateTime<chrono_tz::Tz>, months: i64) -> Option<chrono::DateTime<chrono_tz::Tz>> {
+    let m = chrono::Months::new(u32::try_from(months.unsigned_abs()).ok()?);
+    let naive = if months >= 0 {
+        dt.naive_local().checked_add_months(m)?
+    } else {
+        dt.naive_local().checked_sub_months(m)?
+    };
+
+    resolve_local(&dt.timezone(), naive)
+}
+
+/// Adds calendar days to a time, keeping its wall clock time across DST transitions
+pub fn add_days(dt: &chrono::DateTime<chrono_tz::Tz>, days: i64) -> Option<chrono::DateTime<chrono_tz::Tz>> {
+    let d = chrono::Days::new(days.unsigned_abs());
+    let naive = if days >= 0 {
+        dt.naive_local().checked_add_days(d)?
+    } else {
+        dt.naive_local().checked_sub_days(d)?
+    };
+
+    resolve_local(&dt.timezone(), naive)
+}
+
+/// Truncates a time to the start of its day, week (Monday), month or year in its own timezone
+pub fn truncate_local(dt: &chrono::DateTime<chrono_tz::Tz>, unit: &str) -> Result<chrono::DateTime<chrono_tz::Tz>, String> {
+    let date = dt.date_naive();
+    let start = match unit {
+        "day" => Some(date),
+        "week" => date.checked_sub_days(chrono::Days::new(date.weekday().num_days_from_monday().into())),
+        "month" => date.with_day(1),
+        "year" => date.with_ordinal(1),
+        _ => return Err(format!("Invalid unit '{unit}' (expected one of day, week, month, year)")),
+    };
+
+    start
+        .and_then(|d| d.and_hms_opt(0, 0, 0))
+        .and_then(|naive| resolve_local(&dt.timezone(), naive))
+        .ok_or_else(|| "Overflow in DateTime truncation".to_string())
+}
+
 pub struct Timezone {
     pub tz: chrono_tz::Tz,
 }
@@ -467,6 +772,775 @@ impl LuaUserData for Timezone {
     }
 }
 
+/// Common timezone abbreviations and names, mapped to candidate zones in order of preference
+const TIMEZONE_ABBREVIATIONS: &[(&str, &[chrono_tz::Tz])] = &[
+    ("UTC", &[chrono_tz::UTC]),
+    ("GMT", &[chrono_tz::UTC, Europe::London]),
+    ("Z", &[chrono_tz::UTC]),
+    ("EST", &[America::New_York]),
+    ("EDT", &[America::New_York]),
+    ("ET", &[America::New_York]),
+    ("Eastern", &[America::New_York]),
+    ("CST", &[America::Chicago, Asia::Shanghai]),
+    ("CDT", &[America::Chicago]),
+    ("CT", &[America::Chicago]),
+    ("Central", &[America::Chicago]),
+    ("MST", &[America::Denver, America::Phoenix]),
+    ("MDT", &[America::Denver]),
+    ("MT", &[America::Denver]),
+    ("Mountain", &[America::Denver, America::Phoenix]),
+    ("PST", &[America::Los_Angeles]),
+    ("PDT", &[America::Los_Angeles]),
+    ("PT", &[America::Los_Angeles]),
+    ("Pacific", &[America::Los_Angeles]),
+    ("AKST", &[America::Anchorage]),
+    ("AKDT", &[America::Anchorage]),
+    ("Alaska", &[America::Anchorage]),
+    ("HST", &[Pacific::Honolulu]),
+    ("Hawaii", &[Pacific::Honolulu]),
+    ("AST", &[America::Halifax, Asia::Riyadh]),
+    ("ADT", &[America::Halifax]),
+    ("Atlantic", &[America::Halifax]),
+    ("NST", &[America::St_Johns]),
+    ("NDT", &[America::St_Johns]),
+    ("BRT", &[America::Sao_Paulo]),
+    ("ART", &[America::Argentina::Buenos_Aires]),
+    ("BST", &[Europe::London, Asia::Dhaka]),
+    ("IST", &[Asia::Kolkata, Europe::Dublin, Asia::Jerusalem]),
+    ("WET", &[Europe::Lisbon]),
+    ("WEST", &[Europe::Lisbon]),
+    ("CET", &[Europe::Paris, Europe::Berlin]),
+    ("CEST", &[Europe::Paris, Europe::Berlin]),
+    ("EET", &[Europe::Athens, Europe::Helsinki]),
+    ("EEST", &[Europe::Athens, Europe::Helsinki]),
+    ("MSK", &[Europe::Moscow]),
+    ("WAT", &[Africa::Lagos]),
+    ("CAT", &[Africa::Maputo]),
+    ("EAT", &[Africa::Nairobi]),
+    ("SAST", &[Africa::Johannesburg]),
+    ("GST", &[Asia::Dubai]),
+    ("PKT", &[Asia::Karachi]),
+    ("ICT", &[Asia::Bangkok]),
+    ("WIB", &[Asia::Jakarta]),
+    ("SGT", &[Asia::Singapore]),
+    ("HKT", &[Asia::Hong_Kong]),
+    ("PHT", &[Asia::Manila]),
+    ("KST", &[Asia::Seoul]),
+    ("JST", &[Asia::Tokyo]),
+    ("AWST", &[Australia::Perth]),
+    ("ACST", &[Australia::Adelaide]),
+    ("ACDT", &[Australia::Adelaide]),
+    ("AEST", &[Australia::Sydney, Australia::Brisbane]),
+    ("AEDT", &[Australia::Sydney]),
+    ("NZST", &[Pacific::Auckland]),
+    ("NZDT", &[Pacific::Auckland]),
+];
+
+/// How a timezone candidate matched a query. Earlier variants rank higher
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TimezoneMatch {
+    /// The query is the (case-insensitive) IANA name
+    Exact,
+    /// The query is a known abbreviation or common name
+    Abbreviation,
+    /// The query is a UTC offset with a fixed offset zone
+    Offset,
+    /// The query is a UTC offset the zone is at right now (which may change with DST)
+    CurrentOffset,
+    /// The query is the city part of the IANA name
+    City,
+    /// The IANA name or its city part starts with the query
+    Prefix,
+    /// The IANA name contains the query
+    Contains,
+}
+
+impl TimezoneMatch {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Exact => "exact",
+            Self::Abbreviation => "abbreviation",
+            Self::Offset => "offset",
+            Self::CurrentOffset => "currentoffset",
+            Self::City => "city",
+            Self::Prefix => "prefix",
+            Self::Contains => "contains",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct TimezoneCandidate {
+    pub tz: chrono_tz::Tz,
+    pub kind: TimezoneMatch,
+}
+
+/// Parses a fixed UTC offset such as ``GMT+5``, ``UTC-03:30`` or ``+0530`` into seconds east of UTC
+fn parse_utc_offset(s: &str) -> Option<i32> {
+    let s = s.trim();
+    let s = ["utc", "gmt"]
+        .iter()
+        .find_map(|p| s.get(..3).filter(|h| h.eq_ignore_ascii_case(p)).map(|_| &s[3..]))
+        .unwrap_or(s);
+
+    let (sign, rest) = match s.as_bytes().first()? {
+        b'+' => (1, &s[1..]),
+        b'-' => (-1, &s[1..]),
+        _ => return None,
+    };
+
+    // Splitting below is by byte index
+    if !rest.is_ascii() {
+        return None;
+    }
+
+    let (hours, minutes) = match rest.split_once(':') {
+        Some((h, m)) => (h, m),
+        None if rest.len() > 2 => rest.split_at(rest.len() - 2),
+        None => (rest, "0"),
+    };
+
+    if hours.is_empty() || hours.len() > 2 || !hours.bytes().chain(minutes.bytes()).all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+
+    let hours: i32 = hours.parse().ok()?;
+    let minutes: i32 = minutes.parse().ok()?;
+    if minutes >= 60 {
+        return None;
+    }
+
+    let secs = sign * (hours * 3600 + minutes * 60);
+    (-12 * 3600..=14 * 3600).contains(&secs).then_some(secs)
+}
+
+/// Returns the zone with a fixed offset (in seconds east of UTC), if there is one
+///
+/// Only whole hour offsets have an Etc zone (note that the sign of Etc zones is inverted)
+pub fn fixed_offset_timezone(offset: i32) -> Option<chrono_tz::Tz> {
+    if offset % 3600 != 0 {
+        return None;
+    }
+
+    let name = match offset / 3600 {
+        0 => "Etc/UTC".to_string(),
+        h => format!("Etc/GMT{:+}", -h),
+    };
+    chrono_tz::Tz::from_str(&name).ok()
+}
+
+fn push_candidate(out: &mut Vec<TimezoneCandidate>, tz: chrono_tz::Tz, kind: TimezoneMatch) {
+    if !out.iter().any(|c| c.tz == tz) {
+        out.push(TimezoneCandidate { tz, kind });
+    }
+}
+
+/// Resolves a user-provided timezone into ranked candidates
+///
+/// Handles IANA names (case-insensitive), common abbreviations (``CET``, ``PST``, ``Eastern``),
+/// fixed UTC offsets (``GMT+5``, ``+05:30``) and searches over city names (``new york``, ``kolk``)
+pub fn resolve_timezone(query: &str, limit: usize) -> Vec<TimezoneCandidate> {
+    let query = query.trim();
+    let mut out = Vec::new();
+    if query.is_empty() || limit == 0 {
+        return out;
+    }
+
+    let norm = query.to_ascii_lowercase().replace(' ', "_");
+
+    if let Some(tz) = chrono_tz::TZ_VARIANTS.iter().find(|tz| tz.name().eq_ignore_ascii_case(&norm)) {
+        push_candidate(&mut out, *tz, TimezoneMatch::Exact);
+    }
+
+    for (abbr, zones) in TIMEZONE_ABBREVIATIONS {
+        if abbr.eq_ignore_ascii_case(query) {
+            for tz in zones.iter() {
+                push_candidate(&mut out, *tz, TimezoneMatch::Abbreviation);
+            }
+        }
+    }
+
+    if let Some(offset) = parse_utc_offset(query) {
+        if let Some(tz) = fixed_offset_timezone(offset) {
+            push_candidate(&mut out, tz, TimezoneMatch::Offset);
+        }
+
+        // Otherwise, suggest zones currently at that offset
+        let now = chrono::Utc::now().naive_utc();
+        for tz in chrono_tz::TZ_VARIANTS.iter() {
+            if tz.name().contains('/')
+                && !tz.name().starts_with("Etc/")
+                && tz.offset_from_utc_datetime(&now).fix().local_minus_utc() == offset
+            {
+                push_candidate(&mut out, *tz, TimezoneMatch::CurrentOffset);
+            }
+        }
+    }
+
+    let mut matches = chrono_tz::TZ_VARIANTS
+        .iter()
+        .filter_map(|tz| {
+            let name = tz.name().to_ascii_lowercase();
+            let city = name.rsplit('/').next().unwrap_or(&name);
+            let kind = if city == norm {
+                TimezoneMatch::City
+            } else if name.starts_with(&norm) || city.starts_with(&norm) {
+                TimezoneMatch::Prefix
+            } else if norm.len() >= 3 && name.contains(&norm) {
+                TimezoneMatch::Contains
+            } else {
+                return None;
+            };
+            Some((kind, *tz))
+        })
+        .collect::<Vec<_>>();
+
+    // Prefer better matches, then shorter (usually more canonical) names
+    matches.sort_by_key(|(kind, tz)| (*kind, tz.name().len(), tz.name()));
+
+    for (kind, tz) in matches {
+        push_candidate(&mut out, tz, kind);
+    }
+
+    out.truncate(limit);
+    out
+}
+
+/// Resolves a user-provided timezone only if the resolver's best match is unambiguous
+///
+/// IANA names, abbreviations naming a single zone and offsets with a fixed offset zone are
+/// accepted, as is a city matching a single zone. Anything else (including zones that are only
+/// currently at a requested offset) is an error listing the candidates
+pub fn resolve_unambiguous_timezone(query: &str) -> Result<chrono_tz::Tz, String> {
+    let candidates = resolve_timezone(query, 5);
+    let count = |kind: TimezoneMatch| candidates.iter().filter(|c| c.kind == kind).count();
+
+    match candidates.as_slice() {
+        [first, ..] if matches!(first.kind, TimezoneMatch::Exact | TimezoneMatch::Offset) => Ok(first.tz),
+        [first, ..] if first.kind == TimezoneMatch::Abbreviation && count(TimezoneMatch::Abbreviation) == 1 => Ok(first.tz),
+        [first, ..] if first.kind == TimezoneMatch::City && count(TimezoneMatch::City) == 1 => Ok(first.tz),
+        [] => Err("Invalid timezone".to_string()),
+        _ => Err(format!(
+            "Ambiguous timezone '{query}' (did you mean {}?)",
+            candidates.iter().map(|c| c.tz.name()).collect::<Vec<_>>().join(", ")
+        )),
+    }
+}
+
+const CRON_MONTH_NAMES: &[&str] = &[
+    "JAN", "FEB", "MAR", "APR", "MAY", "JUN", "JUL", "AUG", "SEP", "OCT", "NOV", "DEC",
+];
+const CRON_WEEKDAY_NAMES: &[&str] = &["SUN", "MON", "TUE", "WED", "THU", "FRI", "SAT"];
+
+/// How many years ahead/behind to search for an occurrence before giving up
+const CRON_SEARCH_YEARS: i32 = 10;
+
+/// The largest amount wall clock time can jump by around a DST transition
+const CRON_DST_MARGIN: chrono::TimeDelta = chrono::TimeDelta::hours(3);
+
+/// Maximum number of occurrences that can be returned by ``Cron:upcoming``
+const CRON_MAX_UPCOMING: usize = 100;
+
+/// A parsed 5 (minute first) or 6 (second first) field cron expression
+///
+/// Each field is stored as a bitset of allowed values. When both the day of month and
+/// day of week fields are restricted, a day matches if *either* matches (as in vixie cron)
+#[derive(Debug, Clone)]
+pub struct CronSchedule {
+    seconds: u64,
+    minutes: u64,
+    hours: u64,
+    days_of_month: u64,
+    months: u64,
+    days_of_week: u64,
+    hours_restricted: bool,
+    days_of_month_restricted: bool,
+    days_of_week_restricted: bool,
+}
+
+fn cron_range_bits(start: u32, end: u32, step: u32) -> u64 {
+    let mut bits = 0;
+    let mut v = start;
+    while v <= end {
+        bits |= 1 << v;
+        v += step;
+    }
+    bits
+}
+
+fn parse_cron_value(s: &str, min: u32, max: u32, names: &[&str]) -> Result<u32, String> {
+    let v = match names.iter().position(|n| n.eq_ignore_ascii_case(s)) {
+        Some(i) => i as u32 + min,
+        None => s
+            .parse::<u32>()
+            .map_err(|_| format!("Invalid value '{s}' in cron expression"))?,
+    };
+
+    if v < min || v > max {
+        return Err(format!("Value {v} is out of range ({min}-{max}) in cron expression"));
+    }
+
+    Ok(v)
+}
+
+/// Parses a single cron field, returning the bitset of allowed values and whether the field is restricted (not ``*``)
+fn parse_cron_field(field: &str, min: u32, max: u32, names: &[&str]) -> Result<(u64, bool), String> {
+    if field == "*" || field == "?" {
+        return Ok((cron_range_bits(min, max, 1), false));
+    }
+
+    let mut bits = 0;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => {
+                let step = step
+                    .parse::<u32>()
+                    .ok()
+                    .filter(|s| *s > 0)
+                    .ok_or_else(|| format!("Invalid step '{step}' in cron field '{field}'"))?;
+                (range, Some(step))
+            }
+            None => (part, None),
+        };
+
+        let (start, end) = if range == "*" {
+            (min, max)
+        } else if let Some((a, b)) = range.split_once('-') {
+            (parse_cron_value(a, min, max, names)?, parse_cron_value(b, min, max, names)?)
+        } else {
+            let v = parse_cron_value(range, min, max, names)?;
+            // ``a/n`` means every n starting at a
+            if step.is_some() { (v, max) } else { (v, v) }
+        };
+
+        if start > end {
+            return Err(format!("Invalid range '{range}' in cron field '{field}'"));
+        }
+
+        bits |= cron_range_bits(start, end, step.unwrap_or(1));
+    }
+
+    Ok((bits, true))
+}
+
+/// Returns true if the UTC offset of the timezone differs between the two (UTC) times
+fn offset_changes(tz: &chrono_tz::Tz, from: NaiveDateTime, to: NaiveDateTime) -> bool {
+    tz.offset_from_utc_datetime(&from).fix() != tz.offset_from_utc_datetime(&to).fix()
+}
+
+impl CronSchedule {
+    pub fn parse(expr: &str) -> Result<Self, String> {
+        let expr = expr.trim();
+        let expr = match expr.to_ascii_lowercase().as_str() {
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            "@monthly" => "0 0 1 * *",
+            "@weekly" => "0 0 * * 0",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@hourly" => "0 * * * *",
+            _ => expr,
+        };
+
+        let fields = expr.split_whitespace().collect::<Vec<_>>();
+        let (seconds, fields) = match fields.len() {
+            5 => ("0", &fields[..]),
+            6 => (fields[0], &fields[1..]),
+            n => return Err(format!("Expected 5 or 6 fields in cron expression, got {n}")),
+        };
+
+        let (seconds, _) = parse_cron_field(seconds, 0, 59, &[])?;
+        let (minutes, _) = parse_cron_field(fields[0], 0, 59, &[])?;
+        let (hours, hours_restricted) = parse_cron_field(fields[1], 0, 23, &[])?;
+        let (days_of_month, days_of_month_restricted) = parse_cron_field(fields[2], 1, 31, &[])?;
+        let (months, _) = parse_cron_field(fields[3], 1, 12, CRON_MONTH_NAMES)?;
+        let (mut days_of_week, days_of_week_restricted) = parse_cron_field(fields[4], 0, 7, CRON_WEEKDAY_NAMES)?;
+
+        // 7 is an alias for Sunday
+        if days_of_week & (1 << 7) != 0 {
+            days_of_week = (days_of_week & !(1 << 7)) | 1;
+        }
+
+        Ok(Self {
+            seconds,
+            minutes,
+            hours,
+            days_of_month,
+            months,
+            days_of_week,
+            hours_restricted,
+            days_of_month_restricted,
+            days_of_week_restricted,
+        })
+    }
+
+    fn matches_day(&self, date: NaiveDate) -> bool {
+        let dom = self.days_of_month & (1 << date.day()) != 0;
+        let dow = self.days_of_week & (1 << date.weekday().num_days_from_sunday()) != 0;
+        if self.days_of_month_restricted && self.days_of_week_restricted {
+            dom || dow
+        } else {
+            dom && dow
+        }
+    }
+
+    /// Returns the first wall clock time matching the schedule strictly after ``after``
+    fn next_naive(&self, after: NaiveDateTime) -> Option<NaiveDateTime> {
+        let limit = after.year() + CRON_SEARCH_YEARS;
+        let mut t = after.with_nanosecond(0)? + chrono::TimeDelta::seconds(1);
+        loop {
+            if t.year() > limit {
+                return None;
+            }
+
+            if self.months & (1 << t.month()) == 0 {
+                let (y, m) = if t.month() == 12 { (t.year() + 1, 1) } else { (t.year(), t.month() + 1) };
+                t = NaiveDate::from_ymd_opt(y, m, 1)?.and_hms_opt(0, 0, 0)?;
+                continue;
+            }
+
+            if !self.matches_day(t.date()) {
+                t = t.date().succ_opt()?.and_hms_opt(0, 0, 0)?;
+                continue;
+            }
+
+            if self.hours & (1 << t.hour()) == 0 {
+                t = t.with_minute(0)?.with_second(0)? + chrono::TimeDelta::hours(1);
+                continue;
+            }
+
+            if self.minutes & (1 << t.minute()) == 0 {
+                t = t.with_second(0)? + chrono::TimeDelta::minutes(1);
+                continue;
+            }
+
+            if self.seconds & (1 << t.second()) == 0 {
+                t += chrono::TimeDelta::seconds(1);
+                continue;
+            }
+
+            return Some(t);
+        }
+    }
+
+    /// Returns the last wall clock time matching the schedule strictly before ``before``
+    fn prev_naive(&self, before: NaiveDateTime) -> Option<NaiveDateTime> {
+        let limit = before.year() - CRON_SEARCH_YEARS;
+        let mut t = if before.nanosecond() > 0 {
+            before.with_nanosecond(0)?
+        } else {
+            before - chrono::TimeDelta::seconds(1)
+        };
+        loop {
+            if t.year() < limit {
+                return None;
+            }
+
+            if self.months & (1 << t.month()) == 0 {
+                t = NaiveDate::from_ymd_opt(t.year(), t.month(), 1)?.and_hms_opt(0, 0, 0)? - chrono::TimeDelta::seconds(1);
+                continue;
+            }
+
+            if !self.matches_day(t.date()) {
+                t = t.date().and_hms_opt(0, 0, 0)? - chrono::TimeDelta::seconds(1);
+                continue;
+            }
+
+            if self.hours & (1 << t.hour()) == 0 {
+                t = t.with_minute(0)?.with_second(0)? - chrono::TimeDelta::seconds(1);
+                continue;
+            }
+
+            if self.minutes & (1 << t.minute()) == 0 {
+                t = t.with_second(0)? - chrono::TimeDelta::seconds(1);
+                continue;
+            }
+
+            if self.seconds & (1 << t.second()) == 0 {
+                t -= chrono::TimeDelta::seconds(1);
+                continue;
+            }
+
+            return Some(t);
+        }
+    }
+
+    /// Maps a matching wall clock time to the instants it occurs at in the timezone
+    ///
+    /// - Times repeated when DST ends occur once for schedules with fixed hours, and at both
+    ///   instants for schedules running every hour
+    /// - Times skipped when DST starts run at the end of the gap for schedules with fixed hours,
+    ///   and are skipped for schedules running every hour
+    fn resolve(&self, tz: &chrono_tz::Tz, naive: NaiveDateTime) -> [Option<chrono::DateTime<chrono_tz::Tz>>; 2] {
+        match tz.from_local_datetime(&naive) {
+            chrono::LocalResult::Single(dt) => [Some(dt), None],
+            chrono::LocalResult::Ambiguous(a, b) => {
+                if self.hours_restricted {
+                    [Some(a), None]
+                } else {
+                    [Some(a), Some(b)]
+                }
+            }
+            chrono::LocalResult::None => {
+                if self.hours_restricted {
+                    let gap_end = (1..=180)
+                        .find_map(|m| tz.from_local_datetime(&(naive + chrono::TimeDelta::minutes(m))).earliest());
+                    [gap_end, None]
+                } else {
+                    [None, None]
+                }
+            }
+        }
+    }
+
+    /// Returns the next occurrence strictly after ``after`` in the given timezone
+    pub fn next_after(&self, tz: chrono_tz::Tz, after: &chrono::DateTime<chrono_tz::Tz>) -> Option<chrono::DateTime<chrono_tz::Tz>> {
+        let after = after.with_timezone(&tz);
+        let local = after.naive_local();
+
+        // Wall clock time may repeat ahead of ``after`` if DST is about to end, so start searching
+        // a little earlier in that case
+        let mut naive = if offset_changes(&tz, after.naive_utc(), after.naive_utc() + CRON_DST_MARGIN) {
+            local - CRON_DST_MARGIN
+        } else {
+            local
+        };
+
+        // Later wall clock times can only occur earlier if the offset changes near the best
+        // occurrence so far, so only then keep searching past it
+        let mut best: Option<chrono::DateTime<chrono_tz::Tz>> = None;
+        let mut near_change = false;
+        while let Some(cand) = self.next_naive(naive) {
+            if let Some(b) = best.as_ref() {
+                if !near_change || cand > b.naive_local() + CRON_DST_MARGIN {
+                    break;
+                }
+            }
+
+            for dt in self.resolve(&tz, cand).into_iter().flatten() {
+                if dt > after && best.as_ref().is_none_or(|b| dt < *b) {
+                    near_change = offset_changes(&tz, dt.naive_utc() - CRON_DST_MARGIN, dt.naive_utc() + CRON_DST_MARGIN);
+                    best = Some(dt);
+                }
+            }
+
+            naive = cand;
+        }
+
+        best
+    }
+
+    /// Returns the previous occurrence strictly before ``before`` in the given timezone
+    pub fn previous_before(&self, tz: chrono_tz::Tz, before: &chrono::DateTime<chrono_tz::Tz>) -> Option<chrono::DateTime<chrono_tz::Tz>> {
+        let before = before.with_timezone(&tz);
+        let local = before.naive_local();
+
+        // Wall clock time may have repeated just before ``before`` if DST just ended, so start
+        // searching a little later in that case
+        let mut naive = if offset_changes(&tz, before.naive_utc() - CRON_DST_MARGIN, before.naive_utc()) {
+            local + CRON_DST_MARGIN
+        } else {
+            local
+        };
+
+        // Earlier wall clock times can only occur later if the offset changes near the best
+        // occurrence so far, so only then keep searching past it
+        let mut best: Option<chrono::DateTime<chrono_tz::Tz>> = None;
+        let mut near_change = false;
+        while let Some(cand) = self.prev_naive(naive) {
+            if let Some(b) = best.as_ref() {
+                if !near_change || cand < b.naive_local() - CRON_DST_MARGIN {
+                    break;
+                }
+            }
+
+            for dt in self.resolve(&tz, cand).into_iter().flatten() {
+                if dt < before && best.as_ref().is_none_or(|b| dt > *b) {
+                    near_change = offset_changes(&tz, dt.naive_utc() - CRON_DST_MARGIN, dt.naive_utc() + CRON_DST_MARGIN);
+                    best = Some(dt);
+                }
+            }
+
+            naive = cand;
+        }
+
+        best
+    }
+}
+
+/// A cron schedule evaluated in a specific timezone
+pub struct Cron {
+    pub expr: String,
+    pub schedule: CronSchedule,
+    pub tz: chrono_tz::Tz,
+}
+
+impl Cron {
+    pub fn new(expr: String, tz: chrono_tz::Tz) -> LuaResult<Self> {
+        let schedule = CronSchedule::parse(&expr).map_err(LuaError::external)?;
+        Ok(Self { expr, schedule, tz })
+    }
+}
+
+impl LuaUserData for Cron {
+    fn add_fields<F: LuaUserDataFields<Self>>(fields: &mut F) {
+        fields.add_meta_field(LuaMetaMethod::Type, "Cron".to_string());
+        fields.add_field_method_get("expression", |_, this| Ok(this.expr.clone()));
+        fields.add_field_method_get("timezone", |_, this| Ok(Timezone { tz: this.tz }));
+    }
+
+    fn add_methods<M: LuaUserDataMethods<Self>>(methods: &mut M) {
+        methods.add_meta_method(LuaMetaMethod::ToString, |_, this, ()| {
+            Ok(this.expr.clone())
+        });
+
+        // Returns the next occurrence after the given time (or now)
+        methods.add_method("next", |_, this, after: Option<DateTimeRef>| {
+            let after = after.map(|a| a.dt).unwrap_or_else(|| chrono::Utc::now().with_timezone(&this.tz));
+            Ok(this.schedule.next_after(this.tz, &after).map(DateTime::new))
+        });
+
+        // Returns the previous occurrence before the given time (or now)
+        methods.add_method("previous", |_, this, before: Option<DateTimeRef>| {
+            let before = before.map(|b| b.dt).unwrap_or_else(|| chrono::Utc::now().with_timezone(&this.tz));
+            Ok(this.schedule.previous_before(this.tz, &before).map(DateTime::new))
+        });
+
+        // Returns the next ``count`` occurrences after the given time (or now)
+        methods.add_method("upcoming", |_, this, (count, after): (usize, Option<DateTimeRef>)| {
+            if count > CRON_MAX_UPCOMING {
+                return Err(LuaError::external(format!("count cannot be greater than {CRON_MAX_UPCOMING}")));
+            }
+
+            let mut after = after.map(|a| a.dt).unwrap_or_else(|| chrono::Utc::now().with_timezone(&this.tz));
+            let mut occurrences = Vec::with_capacity(count);
+            while occurrences.len() < count {
+                let Some(next) = this.schedule.next_after(this.tz, &after) else {
+                    break;
+                };
+                after = next;
+                occurrences.push(DateTime::new(next));
+            }
+
+            Ok(occurrences)
+        });
+
+        // Returns the time until the next occurrence, suitable for use with DelayChannel:add
+        methods.add_method("untilnext", |_, this, now: Option<DateTimeRef>| {
+            let now = now.map(|n| n.dt).unwrap_or_else(|| chrono::Utc::now().with_timezone(&this.tz));
+            Ok(this
+                .schedule
+                .next_after(this.tz, &now)
+                .map(|next| TimeDelta::new(next.signed_duration_since(now))))
+        });
+
+        // Creates a recurrence that yields successive occurrences starting after the given time (or now)
+        methods.add_method("recurring", |_, this, from: Option<DateTimeRef>| {
+            let from = from.map(|f| f.dt).unwrap_or_else(|| chrono::Utc::now().with_timezone(&this.tz));
+            Ok(CronRecurrence {
+                schedule: this.schedule.clone(),
+                tz: this.tz,
+                last: from,
+            })
+        });
+    }
+
+    #[cfg(feature = "repl")]
+    fn register(registry: &mut LuaUserDataRegistry<Self>) {
+        Self::add_fields(registry);
+        Self::add_methods(registry);
+        let fields = registry.fields(false).iter().map(|x| x.to_string()).collect::<Vec<_>>();
+        registry.add_meta_field("__ud_fields", fields);
+    }
+}
+
+/// Yields successive occurrences of a cron schedule as delays from the current time
+///
+/// Unlike repeatedly calling ``Cron:untilnext``, an occurrence is never yielded twice even if
+/// a delay fires slightly early. Occurrences missed entirely (e.g. because the template was
+/// busy) are skipped
+pub struct CronRecurrence {
+    schedule: CronSchedule,
+    tz: chrono_tz::Tz,
+    last: chrono::DateTime<chrono_tz::Tz>,
+}
+
+impl LuaUserData for CronRecurrence {
+    fn add_fields<F: LuaUserDataFields<Self>>(fields: &mut F) {
+        fields.add_meta_field(LuaMetaMethod::Type, "CronRecurrence".to_string());
+        fields.add_field_method_get("last", |_, this| Ok(DateTime::new(this.last)));
+    }
+
+    fn add_methods<M: LuaUserDataMethods<Self>>(methods: &mut M) {
+        // Returns the delay until the next occurrence along with the occurrence itself
+        methods.add_method_mut("next", |_, this, ()| {
+            let now = chrono::Utc::now().with_timezone(&this.tz);
+            let after = if this.last > now { this.last } else { now };
+            let Some(next) = this.schedule.next_after(this.tz, &after) else {
+                return Ok((None, None));
+            };
+
+            this.last = next;
+            Ok((
+                Some(TimeDelta::new(next.signed_duration_since(now))),
+                Some(DateTime::new(next)),
+            ))
+        });
+    }
+
+    #[cfg(feature = "repl")]
+    fn register(registry: &mut LuaUserDataRegistry<Self>) {
+        Self::add_fields(registry);
+        Self::add_methods(registry);
+        let fields = registry.fields(false).iter().map(|x| x.to_string()).collect::<Vec<_>>();
+        registry.add_meta_field("__ud_fields", fields);
+    }
+}
+
+/// Yields each calendar day from a start time (at the same wall clock time) up to an end time
+///
+/// Days are computed from the start time rather than the previous day so that a day
+/// adjusted for a DST gap does not shift the wall clock time of the following days
+pub struct DayIterator {
+    start: chrono::DateTime<chrono_tz::Tz>,
+    until: chrono::DateTime<chrono::Utc>,
+    step: i64,
+    index: i64,
+}
+
+impl DayIterator {
+    fn next_day(&mut self) -> Option<DateTime<chrono_tz::Tz>> {
+        let dt = self
+            .index
+            .checked_mul(self.step)
+            .and_then(|days| add_days(&self.start, days))
+            .filter(|dt| *dt <= self.until)?;
+
+        self.index += 1;
+        Some(DateTime::new(dt))
+    }
+}
+
+impl LuaUserData for DayIterator {
+    fn add_fields<F: LuaUserDataFields<Self>>(fields: &mut F) {
+        fields.add_meta_field(LuaMetaMethod::Type, "DayIterator".to_string());
+    }
+
+    fn add_methods<M: LuaUserDataMethods<Self>>(methods: &mut M) {
+        // Returns the next day, or nil once the end has been reached
+        methods.add_method_mut("next", |_, this, ()| Ok(this.next_day()));
+
+        // Allows ``for day in dt:iterdays(until) do ... end``
+        methods.add_meta_method_mut(LuaMetaMethod::Call, |_, this, _: LuaMultiValue| Ok(this.next_day()));
+    }
+
+    #[cfg(feature = "repl")]
+    fn register(registry: &mut LuaUserDataRegistry<Self>) {
+        Self::add_fields(registry);
+        Self::add_methods(registry);
+        let fields = registry.fields(false).iter().map(|x| x.to_string()).collect::<Vec<_>>();
+        registry.add_meta_field("__ud_fields", fields);
+    }
+}
+
 pub fn init_plugin(lua: &Lua) -> LuaResult<LuaTable> {
     let module = lua.create_table()?;
 
@@ -487,18 +1561,80 @@ pub fn init_plugin(lua: &Lua) -> LuaResult<LuaTable> {
                 }),
                 _ => {
                     if let Ok(tz) = chrono_tz::Tz::from_str(&tz) {
-                        Ok(Timezone { tz })
-                    } else {
-                        Err(mluau::Error::RuntimeError("Invalid timezone".to_string()))
+                        return Ok(Timezone { tz });
                     }
+
+                    // Fall back to the resolver if the match is unambiguous
+                    resolve_unambiguous_timezone(&tz)
+                        .map(|tz| Timezone { tz })
+                        .map_err(mluau::Error::RuntimeError)
                 }
             }
         })?,
     )?;
 
+    // Returns ranked timezone candidates for a user-provided timezone as a list of { timezone, name, match }
+    module.set(
+        "resolvetimezone",
+        lua.create_function(|lua, (query, limit): (String, Option<usize>)| {
+            let candidates = lua.create_table()?;
+            for c in resolve_timezone(&query, limit.unwrap_or(10)) {
+                let entry = lua.create_table()?;
+                entry.set("timezone", Timezone { tz: c.tz })?;
+                entry.set("name", c.tz.name())?;
+                entry.set("match", c.kind.as_str())?;
+                candidates.raw_push(entry)?;
+            }
+            candidates.set_metatable(Some(lua.array_metatable()))?;
+            Ok(candidates)
+        })?,
+    )?;
+
+    // Lists timezone names, optionally filtered by a query (e.g. for autocomplete)
+    //
+    // Without a query, all IANA names are returned. With a query, the best ``limit`` (default 25) matches are returned
+    module.set(
+        "listtimezones",
+        lua.create_function(|lua, (query, limit): (Option<String>, Option<usize>)| {
+            let names = match query.as_deref().map(str::trim).filter(|q| !q.is_empty()) {
+                Some(query) => resolve_timezone(query, limit.unwrap_or(25))
+                    .into_iter()
+                    .map(|c| c.tz.name())
+                    .collect::<Vec<_>>(),
+                None => chrono_tz::TZ_VARIANTS
+                    .iter()
+                    .map(|tz| tz.name())
+                    .take(limit.unwrap_or(usize::MAX))
+                    .collect::<Vec<_>>(),
+            };
+
+            let list = lua.create_sequence_from(names)?;
+            list.set_metatable(Some(lua.array_metatable()))?;
+            Ok(list)
+        })?,
+    )?;
+
     // The standard UTC timezone
     module.set("UTC", Timezone { tz: chrono_tz::UTC })?;
 
+    // Parses a 5 or 6 field cron expression, evaluated in the given timezone (or UTC)
+    module.set(
+        "cron",
+        lua.create_function(|_, (expr, tz): (String, Option<LuaUserDataRef<Timezone>>)| {
+            Cron::new(expr, tz.map(|tz| tz.tz).unwrap_or(chrono_tz::UTC))
+        })?,
+    )?;
+
+    // Parses a human duration (e.g. "1h30m", "2 weeks") into a TimeDelta
+    module.set(
+        "parseduration",
+        lua.create_function(|_, duration: String| {
+            Ok(TimeDelta {
+                timedelta: parse_duration(&duration).map_err(LuaError::external)?,
+            })
+        })?,
+    )?;
+
     // Creates a new TimeDelta object
     module.set(
         "timedelta_weeks",
@@ -670,4 +1806,169 @@ mod tests {
         .call::<()>(module)
         .unwrap();
     }
+
+    #[test]
+    fn test_cron() {
+        let utc = |y, mo, d, h, mi| chrono_tz::UTC.with_ymd_and_hms(y, mo, d, h, mi, 0).unwrap();
+        let ny = chrono_tz::America::New_York;
+
+        // Every Monday at 09:00
+        let cron = CronSchedule::parse("0 9 * * MON").unwrap();
+        let next = cron.next_after(ny, &utc(2024, 1, 1, 15, 0)).unwrap();
+        assert_eq!(next.with_timezone(&chrono_tz::UTC), utc(2024, 1, 8, 14, 0));
+        let prev = cron.previous_before(ny, &utc(2024, 1, 1, 15, 0)).unwrap();
+        assert_eq!(prev.with_timezone(&chrono_tz::UTC), utc(2024, 1, 1, 14, 0));
+
+        // 6 field expressions with seconds
+        let cron = CronSchedule::parse("30 */15 * * * *").unwrap();
+        let next = cron.next_after(chrono_tz::UTC, &utc(2024, 1, 1, 0, 0)).unwrap();
+        assert_eq!(next, chrono_tz::UTC.with_ymd_and_hms(2024, 1, 1, 0, 0, 30).unwrap());
+
+        // Skipped wall clock time when DST starts runs at the end of the gap
+        let cron = CronSchedule::parse("30 2 * * *").unwrap();
+        let next = cron.next_after(ny, &utc(2024, 3, 10, 5, 0)).unwrap();
+        assert_eq!(next.with_timezone(&chrono_tz::UTC), utc(2024, 3, 10, 7, 0));
+
+        // Repeated wall clock time when DST ends runs twice for hourly schedules...
+        let cron = CronSchedule::parse("*/30 * * * *").unwrap();
+        let next = cron.next_after(ny, &utc(2024, 11, 3, 5, 30)).unwrap();
+        assert_eq!(next.with_timezone(&chrono_tz::UTC), utc(2024, 11, 3, 6, 0));
+        let prev = cron.previous_before(ny, &utc(2024, 11, 3, 6, 0)).unwrap();
+        assert_eq!(prev.with_timezone(&chrono_tz::UTC), utc(2024, 11, 3, 5, 30));
+
+        // ...but only once for schedules with fixed hours
+        let cron = CronSchedule::parse("30 1 * * *").unwrap();
+        let next = cron.next_after(ny, &utc(2024, 11, 3, 5, 30)).unwrap();
+        assert_eq!(next.with_timezone(&chrono_tz::UTC), utc(2024, 11, 4, 6, 30));
+
+        // Every second schedules only look at the next (or previous) second away from offset changes
+        let cron = CronSchedule::parse("* * * * * *").unwrap();
+        let mut t = utc(2024, 1, 1, 0, 0).with_timezone(&ny);
+        for _ in 0..10_000 {
+            let next = cron.next_after(ny, &t).unwrap();
+            assert_eq!(next - t, chrono::TimeDelta::seconds(1));
+            t = next;
+        }
+        for _ in 0..10_000 {
+            let prev = cron.previous_before(ny, &t).unwrap();
+            assert_eq!(t - prev, chrono::TimeDelta::seconds(1));
+            t = prev;
+        }
+
+        // ...and still every second across the end of DST
+        let mut t = utc(2024, 11, 3, 5, 59).with_timezone(&ny);
+        for _ in 0..120 {
+            let next = cron.next_after(ny, &t).unwrap();
+            assert_eq!(next - t, chrono::TimeDelta::seconds(1));
+            t = next;
+        }
+
+        // Invalid expressions
+        assert!(CronSchedule::parse("* * * *").is_err());
+        assert!(CronSchedule::parse("60 * * * *").is_err());
+        assert!(CronSchedule::parse("*/0 * * * *").is_err());
+        assert!(CronSchedule::parse("5-1 * * * *").is_err());
+        assert!(CronSchedule::parse("0 0 30 2 *").unwrap().next_after(ny, &utc(2024, 1, 1, 0, 0)).is_none());
+    }
+
+    #[test]
+    fn test_durations() {
+        assert_eq!(parse_duration("1h30m").unwrap(), chrono::TimeDelta::minutes(90));
+        assert_eq!(parse_duration("2 weeks").unwrap(), chrono::TimeDelta::weeks(2));
+        assert_eq!(parse_duration("3d12h").unwrap(), chrono::TimeDelta::hours(84));
+        assert_eq!(parse_duration(" 1 Hour, 30 mins ").unwrap(), chrono::TimeDelta::minutes(90));
+
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("10").is_err());
+        assert!(parse_duration("h").is_err());
+        assert!(parse_duration("1h1h").is_err());
+        assert!(parse_duration("1 fortnight").is_err());
+        assert!(parse_duration("-1h").is_err());
+        assert!(parse_duration("1.5h").is_err());
+        assert!(parse_duration("99999w").is_err());
+        assert!(parse_duration("99999999999999999999s").is_err());
+
+        assert_eq!(humanize_timedelta(chrono::TimeDelta::minutes(90), 2), "1 hour, 30 minutes");
+        assert_eq!(humanize_timedelta(chrono::TimeDelta::seconds(90061), 2), "1 day, 1 hour");
+        assert_eq!(humanize_timedelta(chrono::TimeDelta::seconds(-60), 2), "-1 minute");
+        assert_eq!(humanize_timedelta(chrono::TimeDelta::zero(), 2), "0 seconds");
+
+        assert_eq!(relative_timedelta(chrono::TimeDelta::days(3)), "in 3 days");
+        assert_eq!(relative_timedelta(chrono::TimeDelta::minutes(-5)), "5 minutes ago");
+        assert_eq!(relative_timedelta(chrono::TimeDelta::milliseconds(200)), "now");
+    }
+
+    #[test]
+    fn test_resolve_timezone() {
+        let first = |q: &str| resolve_timezone(q, 10).first().map(|c| (c.tz, c.kind));
+
+        assert_eq!(first("america/new_york"), Some((chrono_tz::America::New_York, TimezoneMatch::Exact)));
+        assert_eq!(first("Eastern"), Some((chrono_tz::America::New_York, TimezoneMatch::Abbreviation)));
+        assert_eq!(first("pst"), Some((chrono_tz::America::Los_Angeles, TimezoneMatch::Abbreviation)));
+        assert_eq!(first("GMT+5").map(|(tz, _)| tz.name()), Some("Etc/GMT-5"));
+        assert_eq!(first("UTC-3").map(|(tz, _)| tz.name()), Some("Etc/GMT+3"));
+        assert_eq!(first("new york"), Some((chrono_tz::America::New_York, TimezoneMatch::City)));
+        assert_eq!(first("kolk"), Some((chrono_tz::Asia::Kolkata, TimezoneMatch::Prefix)));
+        assert!(resolve_timezone("+05:30", 50).iter().any(|c| c.tz == chrono_tz::Asia::Kolkata));
+        assert!(resolve_timezone("IST", 10).len() >= 3);
+        assert!(resolve_timezone("not a timezone", 10).is_empty());
+
+        assert_eq!(parse_utc_offset("+0530"), Some(5 * 3600 + 30 * 60));
+        assert_eq!(parse_utc_offset("utc-03:30"), Some(-(3 * 3600 + 30 * 60)));
+        assert_eq!(parse_utc_offset("5"), None);
+        assert_eq!(parse_utc_offset("+15"), None);
+        assert_eq!(parse_utc_offset("+05:75"), None);
+
+        // Multi-byte characters must not be split
+        assert_eq!(parse_utc_offset("+éa"), None);
+        assert_eq!(parse_utc_offset("utc+1é"), None);
+        assert!(resolve_timezone("+éa", 10).is_empty());
+
+        // Only fixed offsets, single zone abbreviations and single cities resolve unambiguously
+        assert_eq!(resolve_unambiguous_timezone("GMT+5").map(|tz| tz.name()), Ok("Etc/GMT-5"));
+        assert_eq!(resolve_unambiguous_timezone("eastern"), Ok(chrono_tz::America::New_York));
+        assert_eq!(resolve_unambiguous_timezone("new york"), Ok(chrono_tz::America::New_York));
+        // India and Sri Lanka have no DST, so are always at +05:30
+        assert!(resolve_timezone("+05:30", 50).iter().all(|c| c.kind == TimezoneMatch::CurrentOffset));
+        let err = resolve_unambiguous_timezone("+05:30").unwrap_err();
+        assert!(err.starts_with("Ambiguous timezone"), "{err}");
+        let err = resolve_unambiguous_timezone("cst").unwrap_err();
+        assert!(err.contains("America/Chicago") && err.contains("Asia/Shanghai"), "{err}");
+        assert!(resolve_unambiguous_timezone("not a timezone").is_err());
+    }
+
+    #[test]
+    fn test_calendar() {
+        let ny = chrono_tz::America::New_York;
+        let local = |y, mo, d, h, mi| ny.with_ymd_and_hms(y, mo, d, h, mi, 0).unwrap();
+
+        // End of month clamping
+        assert_eq!(add_months(&local(2024, 1, 31, 10, 0), 1).unwrap(), local(2024, 2, 29, 10, 0));
+        assert_eq!(add_months(&local(2023, 1, 31, 10, 0), 1).unwrap(), local(2023, 2, 28, 10, 0));
+        assert_eq!(add_months(&local(2024, 3, 31, 10, 0), -1).unwrap(), local(2024, 2, 29, 10, 0));
+        assert_eq!(add_months(&local(2024, 2, 29, 10, 0), 12).unwrap(), local(2025, 2, 28, 10, 0));
+
+        // Wall clock time is kept across DST, and times in the gap move to the end of the gap
+        assert_eq!(add_days(&local(2024, 3, 9, 12, 0), 1).unwrap(), local(2024, 3, 10, 12, 0));
+        assert_eq!(add_days(&local(2024, 3, 9, 2, 30), 1).unwrap(), local(2024, 3, 10, 3, 0));
+        assert_eq!(add_days(&local(2024, 3, 10, 3, 0), -1).unwrap(), local(2024, 3, 9, 3, 0));
+
+        // Truncation happens in the time's own timezone (2024-05-15 is a Wednesday)
+        let dt = local(2024, 5, 15, 22, 45);
+        assert_eq!(truncate_local(&dt, "day").unwrap(), local(2024, 5, 15, 0, 0));
+        assert_eq!(truncate_local(&dt, "week").unwrap(), local(2024, 5, 13, 0, 0));
+        assert_eq!(truncate_local(&dt, "month").unwrap(), local(2024, 5, 1, 0, 0));
+        assert_eq!(truncate_local(&dt, "year").unwrap(), local(2024, 1, 1, 0, 0));
+        assert!(truncate_local(&dt, "fortnight").is_err());
+
+        // Iteration keeps 12:00 local time across the spring forward transition
+        let mut iter = DayIterator {
+            start: local(2024, 3, 9, 12, 0),
+            until: local(2024, 3, 11, 12, 0).with_timezone(&chrono::Utc),
+            step: 1,
+            index: 0,
+        };
+        let days = std::iter::from_fn(|| iter.next_day()).map(|d| d.dt).collect::<Vec<_>>();
+        assert_eq!(days, vec![local(2024, 3, 9, 12, 0), local(2024, 3, 10, 12, 0), local(2024, 3, 11, 12, 0)]);
+    }
 }
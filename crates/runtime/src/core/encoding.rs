@@ -0,0 +1,212 @@
+use base64::Engine;
+use mluau::prelude::*;
+use percent_encoding::{AsciiSet, NON_ALPHANUMERIC};
+
+use crate::primitives::blob::blob_ref;
+
+/// Characters to percent-encode in a URL component (everything except RFC 3986 unreserved characters)
+const URL_COMPONENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+/// Base64 engines that accept input with or without padding (used for lenient decoding)
+const BASE64_STANDARD_LENIENT: base64::engine::GeneralPurpose = base64::engine::GeneralPurpose::new(
+    &base64::alphabet::STANDARD,
+    base64::engine::GeneralPurposeConfig::new()
+        .with_decode_padding_mode(base64::engine::DecodePaddingMode::Indifferent)
+        .with_decode_allow_trailing_bits(true),
+);
+
+const BASE64_URL_SAFE_LENIENT: base64::engine::GeneralPurpose = base64::engine::GeneralPurpose::new(
+    &base64::alphabet::URL_SAFE,
+    base64::engine::GeneralPurposeConfig::new()
+        .with_decode_padding_mode(base64::engine::DecodePaddingMode::Indifferent)
+        .with_decode_allow_trailing_bits(true),
+);
+
+/// Strips ASCII whitespace from the input. Used by lenient decoders
+fn strip_whitespace(s: &[u8]) -> Vec<u8> {
+    s.iter().copied().filter(|c| !c.is_ascii_whitespace()).collect()
+}
+
+/// Decodes a base32 string. In lenient mode, whitespace is ignored, the input is
+/// case-insensitive and padding is optional
+fn base32_decode(s: &[u8], lenient: bool) -> LuaResult<Vec<u8>> {
+    let res = if lenient {
+        let mut s = strip_whitespace(s);
+        s.make_ascii_uppercase();
+        while s.last() == Some(&b'=') {
+            s.pop();
+        }
+        data_encoding::BASE32_NOPAD.decode(&s)
+    } else {
+        data_encoding::BASE32.decode(s)
+    };
+
+    res.map_err(|e| LuaError::external(format!("Failed to decode base32: {e}")))
+}
+
+/// Decodes a hex string. In lenient mode, whitespace is ignored and the input is case-insensitive
+fn hex_decode(s: &[u8], lenient: bool) -> LuaResult<Vec<u8>> {
+    let res = if lenient {
+        data_encoding::HEXLOWER_PERMISSIVE.decode(&strip_whitespace(s))
+    } else {
+        data_encoding::HEXLOWER.decode(s)
+    };
+
+    res.map_err(|e| LuaError::external(format!("Failed to decode hex: {e}")))
+}
+
+/// Decodes a percent-encoded string. In strict mode, malformed escapes are an error. In lenient
+/// mode, they are passed through as-is
+fn percent_decode(s: &[u8], lenient: bool) -> LuaResult<Vec<u8>> {
+    if !lenient {
+        let mut i = 0;
+        while i < s.len() {
+            if s[i] == b'%' {
+                let valid = s.len() > i + 2 && s[i + 1].is_ascii_hexdigit() && s[i + 2].is_ascii_hexdigit();
+                if !valid {
+                    return Err(LuaError::external(format!(
+                        "Failed to percent-decode: invalid escape at position {i}"
+                    )));
+                }
+                i += 3;
+            } else {
+                i += 1;
+            }
+        }
+    }
+
+    Ok(percent_encoding::percent_decode(s).collect())
+}
+
+pub fn init_plugin(lua: &Lua) -> LuaResult<LuaTable> {
+    let module = lua.create_table()?;
+
+    module.set("hexencode", lua.create_function(|_, data: LuaValue| {
+        blob_ref(&data, |b| data_encoding::HEXLOWER.encode(b))
+    })?)?;
+
+    module.set("hexdecode", lua.create_function(|lua, (data, lenient): (LuaValue, Option<bool>)| {
+        let decoded = blob_ref(&data, |s| hex_decode(s, lenient.unwrap_or(false)))??;
+        lua.create_external_buffer(bytes::Bytes::from(decoded))
+    })?)?;
+
+    module.set("base32encode", lua.create_function(|_, (data, padded): (LuaValue, Option<bool>)| {
+        blob_ref(&data, |b| {
+            if padded.unwrap_or(true) {
+                data_encoding::BASE32.encode(b)
+            } else {
+                data_encoding::BASE32_NOPAD.encode(b)
+            }
+        })
+    })?)?;
+
+    module.set("base32decode", lua.create_function(|lua, (data, lenient): (LuaValue, Option<bool>)| {
+        let decoded = blob_ref(&data, |s| base32_decode(s, lenient.unwrap_or(false)))??;
+        lua.create_external_buffer(bytes::Bytes::from(decoded))
+    })?)?;
+
+    module.set("base64encode", lua.create_function(|_, data: LuaValue| {
+        blob_ref(&data, |b| base64::prelude::BASE64_STANDARD.encode(b))
+    })?)?;
+
+    module.set("base64decode", lua.create_function(|lua, (data, lenient): (LuaValue, Option<bool>)| {
+        let decoded = blob_ref(&data, |s| {
+            if lenient.unwrap_or(false) {
+                BASE64_STANDARD_LENIENT.decode(strip_whitespace(s))
+            } else {
+                base64::prelude::BASE64_STANDARD.decode(s)
+            }
+        })?
+        .map_err(|e| LuaError::external(format!("Failed to decode base64: {e}")))?;
+        lua.create_external_buffer(bytes::Bytes::from(decoded))
+    })?)?;
+
+    module.set("base64urlencode", lua.create_function(|_, data: LuaValue| {
+        blob_ref(&data, |b| base64::prelude::BASE64_URL_SAFE_NO_PAD.encode(b))
+    })?)?;
+
+    module.set("base64urldecode", lua.create_function(|lua, (data, lenient): (LuaValue, Option<bool>)| {
+        let decoded = blob_ref(&data, |s| {
+            if lenient.unwrap_or(false) {
+                BASE64_URL_SAFE_LENIENT.decode(strip_whitespace(s))
+            } else {
+                base64::prelude::BASE64_URL_SAFE_NO_PAD.decode(s)
+            }
+        })?
+        .map_err(|e| LuaError::external(format!("Failed to decode base64: {e}")))?;
+        lua.create_external_buffer(bytes::Bytes::from(decoded))
+    })?)?;
+
+    module.set("percentencode", lua.create_function(|_, data: LuaValue| {
+        blob_ref(&data, |b| percent_encoding::percent_encode(b, URL_COMPONENT).to_string())
+    })?)?;
+
+    module.set("percentdecode", lua.create_function(|lua, (data, lenient): (LuaValue, Option<bool>)| {
+        let decoded = blob_ref(&data, |s| percent_decode(s, lenient.unwrap_or(false)))??;
+        lua.create_external_buffer(bytes::Bytes::from(decoded))
+    })?)?;
+
+    module.set_readonly(true); // Block any attempt to modify this table
+
+    Ok(module)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decoders() {
+        assert_eq!(hex_decode(b"deadbeef", false).unwrap(), vec![0xde, 0xad, 0xbe, 0xef]);
+        assert!(hex_decode(b"DEADBEEF", false).is_err());
+        assert_eq!(hex_decode(b"DE AD\nbe ef", true).unwrap(), vec![0xde, 0xad, 0xbe, 0xef]);
+
+        assert_eq!(base32_decode(b"MZXW6===", false).unwrap(), b"foo");
+        assert!(base32_decode(b"mzxw6", false).is_err());
+        assert_eq!(base32_decode(b"mzxw6", true).unwrap(), b"foo");
+
+        assert_eq!(percent_decode(b"a%20b", false).unwrap(), b"a b");
+        assert!(percent_decode(b"100%", false).is_err());
+        assert_eq!(percent_decode(b"100%", true).unwrap(), b"100%");
+    }
+
+    #[test]
+    fn test_encoding_module() {
+        let script = r#"
+            return function()
+                local encoding = require"@antiraid/encoding"
+
+                assert(encoding.hexencode("\222\173") == "dead")
+                assert(buffer.tostring(encoding.hexdecode("DEAD")) == "\222\173")
+                assert(not pcall(encoding.hexdecode, "de ad"))
+                assert(buffer.tostring(encoding.hexdecode("de ad", true)) == "\222\173")
+
+                assert(encoding.base32encode("foo") == "MZXW6===")
+                assert(encoding.base32encode("foo", false) == "MZXW6")
+                assert(buffer.tostring(encoding.base32decode("mzxw6", true)) == "foo")
+
+                assert(encoding.base64encode("hi?") == "aGk/")
+                assert(encoding.base64urlencode("hi?") == "aGk_")
+                assert(buffer.tostring(encoding.base64decode("aGk/")) == "hi?")
+                assert(buffer.tostring(encoding.base64urldecode("aGk_")) == "hi?")
+                assert(not pcall(encoding.base64decode, "aGk"))
+                assert(buffer.tostring(encoding.base64decode("aG k", true)) == "hi")
+
+                assert(encoding.percentencode("a b/ü") == "a%20b%2F%C3%BC")
+                local decoded = encoding.percentdecode("a%20b%2F%C3%BC")
+                assert(type(decoded) == "string" and decoded == "a b/ü")
+                assert(not pcall(encoding.percentdecode, "100%"))
+                assert(encoding.percentdecode("100%", true) == "100%")
+
+                -- Buffers are accepted wherever strings are
+                assert(encoding.hexencode(buffer.fromstring("\1")) == "01")
+            end
+        "#;
+
+        run_script(RuntimeCreateOpts::default(), script, |_| Ok(())).unwrap();
+    }
+}
@@ -1,10 +1,232 @@
 use mluau::prelude::*;
 
-use crate::primitives::{LUA_DESERIALIZE_OPTIONS, blob::blob_ref};
+use crate::primitives::{LUA_DESERIALIZE_OPTIONS, LUA_SERIALIZE_OPTIONS, blob::blob_ref};
+
+/// Converts a JSON document passed in from Luau to a ``serde_json::Value``
+///
+/// Strings and buffers are parsed as raw JSON, anything else is converted from the Luau value directly.
+/// Returns the value along with whether the document was passed in as raw JSON
+fn doc_from_lua(lua: &Lua, value: LuaValue) -> LuaResult<(serde_json::Value, bool)> {
+    match value {
+        LuaValue::String(_) | LuaValue::Buffer(_) => {
+            let v = blob_ref(&value, |s| serde_json::from_slice(s).into_lua_err())??;
+            Ok((v, true))
+        }
+        _ => Ok((lua.from_value_with(value, LUA_DESERIALIZE_OPTIONS)?, false)),
+    }
+}
+
+/// Converts a ``serde_json::Value`` back to Luau, either as a raw JSON string or as a Luau value
+fn doc_to_lua(lua: &Lua, value: &serde_json::Value, raw: bool) -> LuaResult<LuaValue> {
+    if raw {
+        let json_str = serde_json::to_vec(value).into_lua_err()?;
+        Ok(LuaValue::String(lua.create_string(json_str)?))
+    } else {
+        lua.to_value_with(value, LUA_SERIALIZE_OPTIONS)
+    }
+}
+
+/// Sets the value at the given RFC 6901 JSON pointer, replacing any existing value
+///
+/// The parent of the target must already exist. For arrays, the index may be ``-`` or
+/// the length of the array to append
+fn pointer_set(doc: &mut serde_json::Value, pointer: &str, value: serde_json::Value) -> LuaResult<()> {
+    if pointer.is_empty() {
+        *doc = value;
+        return Ok(());
+    }
+
+    let Some((parent, token)) = pointer.rsplit_once('/').filter(|_| pointer.starts_with('/')) else {
+        return Err(LuaError::external("JSON pointer must be empty or start with '/'"));
+    };
+
+    let token = token.replace("~1", "/").replace("~0", "~");
+
+    let Some(parent) = doc.pointer_mut(parent) else {
+        return Err(LuaError::external(format!("Parent of '{pointer}' does not exist")));
+    };
+
+    match parent {
+        serde_json::Value::Object(map) => {
+            map.insert(token, value);
+        }
+        serde_json::Value::Array(arr) => {
+            if token == "-" {
+                arr.push(value);
+                return Ok(());
+            }
+
+            let idx = token
+                .parse::<usize>()
+                .map_err(|_| LuaError::external(format!("Invalid array index '{token}' in '{pointer}'")))?;
+
+            match idx.cmp(&arr.len()) {
+                std::cmp::Ordering::Less => arr[idx] = value,
+                std::cmp::Ordering::Equal => arr.push(value),
+                std::cmp::Ordering::Greater => {
+                    return Err(LuaError::external(format!("Array index {idx} is out of bounds in '{pointer}'")));
+                }
+            }
+        }
+        _ => {
+            return Err(LuaError::external(format!("Parent of '{pointer}' is not an object or array")));
+        }
+    }
+
+    Ok(())
+}
+
+/// Empty Luau tables are converted to empty arrays, so for documents made from Luau values, an empty
+/// array whose member is being set (a token that is not an array index) is made an object instead
+fn coerce_empty_parent(doc: &mut serde_json::Value, pointer: &str) {
+    let Some((parent, token)) = pointer.rsplit_once('/') else {
+        return;
+    };
+    if token == "-" || token.parse::<usize>().is_ok() {
+        return;
+    }
+    if let Some(parent) = doc.pointer_mut(parent) {
+        if parent.as_array().is_some_and(|arr| arr.is_empty()) {
+            *parent = serde_json::Value::Object(serde_json::Map::new());
+        }
+    }
+}
+
+/// Empty Luau tables are converted to empty arrays, so for schemas made from Luau values, empty
+/// arrays are made objects everywhere a schema (or a map of schemas) is expected
+fn coerce_empty_schema(schema: &mut serde_json::Value) {
+    fn empty_to_object(v: &mut serde_json::Value) {
+        if v.as_array().is_some_and(|arr| arr.is_empty()) {
+            *v = serde_json::Value::Object(serde_json::Map::new());
+        }
+    }
+
+    empty_to_object(schema);
+    let serde_json::Value::Object(map) = schema else {
+        return;
+    };
+
+    for (keyword, v) in map.iter_mut() {
+        match keyword.as_str() {
+            // Maps of schemas
+            "properties" | "patternProperties" | "$defs" | "definitions" | "dependentSchemas" => {
+                empty_to_object(v);
+                if let serde_json::Value::Object(schemas) = v {
+                    schemas.values_mut().for_each(coerce_empty_schema);
+                }
+            }
+            // Lists of schemas
+            "allOf" | "anyOf" | "oneOf" | "prefixItems" => {
+                if let serde_json::Value::Array(schemas) = v {
+                    schemas.iter_mut().for_each(coerce_empty_schema);
+                }
+            }
+            // Single schemas
+            "items" | "additionalProperties" | "unevaluatedProperties" | "unevaluatedItems" | "contains"
+            | "propertyNames" | "not" | "if" | "then" | "else" => coerce_empty_schema(v),
+            // Everything else (required, enum, const etc.) is kept as is
+            _ => {}
+        }
+    }
+}
+
+/// Whether a schema expects an array (rather than an object) at its location
+fn schema_expects_array(schema: Option<&serde_json::Value>) -> bool {
+    let Some(schema) = schema.and_then(|s| s.as_object()) else {
+        return false;
+    };
+
+    match schema.get("type") {
+        Some(serde_json::Value::String(t)) => t == "array",
+        Some(serde_json::Value::Array(types)) => {
+            types.iter().any(|t| t == "array") && !types.iter().any(|t| t == "object")
+        }
+        _ => ["items", "prefixItems", "contains"].iter().any(|k| schema.contains_key(*k)),
+    }
+}
+
+/// Converts the empty arrays of an instance made from a Luau value to objects, unless the schema
+/// expects an array at their location. Only ``properties``, ``additionalProperties``, ``items``
+/// and ``prefixItems`` are followed (references are not resolved)
+fn coerce_empty_instance(instance: &mut serde_json::Value, schema: Option<&serde_json::Value>) {
+    match instance {
+        serde_json::Value::Array(arr) if arr.is_empty() => {
+            if !schema_expects_array(schema) {
+                *instance = serde_json::Value::Object(serde_json::Map::new());
+            }
+        }
+        serde_json::Value::Array(arr) => {
+            for (i, v) in arr.iter_mut().enumerate() {
+                let child = schema.and_then(|s| {
+                    s.get("prefixItems")
+                        .and_then(|p| p.get(i))
+                        .or_else(|| s.get("items").filter(|items| items.is_object()))
+                });
+                coerce_empty_instance(v, child);
+            }
+        }
+        serde_json::Value::Object(map) => {
+            for (k, v) in map.iter_mut() {
+                let child = schema.and_then(|s| {
+                    s.get("properties")
+                        .and_then(|p| p.get(k))
+                        .or_else(|| s.get("additionalProperties").filter(|a| a.is_object()))
+                });
+                coerce_empty_instance(v, child);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// A compiled JSON Schema (draft 2020-12)
+pub struct JsonSchema {
+    validator: jsonschema::Validator,
+    /// The schema, used to decide how empty Luau tables are validated
+    schema: serde_json::Value,
+}
+
+impl LuaUserData for JsonSchema {
+    fn add_fields<F: LuaUserDataFields<Self>>(fields: &mut F) {
+        fields.add_meta_field(LuaMetaMethod::Type, "JsonSchema");
+    }
+
+    fn add_methods<M: LuaUserDataMethods<Self>>(methods: &mut M) {
+        // Returns whether the instance is valid along with a list of path-annotated errors
+        //
+        // Empty Luau tables are validated as objects unless the schema expects an array there
+        methods.add_method("validate", |lua, this, instance: LuaValue| {
+            let (mut instance, raw) = doc_from_lua(lua, instance)?;
+            if !raw {
+                coerce_empty_instance(&mut instance, Some(&this.schema));
+            }
+
+            let errors = lua.create_table()?;
+            for err in this.validator.iter_errors(&instance) {
+                let entry = lua.create_table()?;
+                entry.set("path", err.instance_path.to_string())?;
+                entry.set("schema_path", err.schema_path.to_string())?;
+                entry.set("message", err.to_string())?;
+                errors.raw_push(entry)?;
+            }
+            errors.set_metatable(Some(lua.array_metatable()))?;
+
+            Ok((errors.raw_len() == 0, errors))
+        });
+    }
+
+    #[cfg(feature = "repl")]
+    fn register(registry: &mut LuaUserDataRegistry<Self>) {
+        Self::add_fields(registry);
+        Self::add_methods(registry);
+        let fields = registry.fields(false).iter().map(|x| x.to_string()).collect::<Vec<_>>();
+        registry.add_meta_field("__ud_fields", fields);
+    }
+}
 
 pub fn init_plugin(lua: &Lua) -> LuaResult<LuaTable> {
     let module = lua.create_table()?;
-    
+
     module.set("tojsonstring", lua.create_function(|lua, (value, pretty): (LuaValue, Option<bool>)| {
         let serialized: serde_json::Value = lua.from_value_with(value, LUA_DESERIALIZE_OPTIONS)?;
         let json_str = if pretty.unwrap_or(false) {
@@ -20,9 +242,173 @@ pub fn init_plugin(lua: &Lua) -> LuaResult<LuaTable> {
         lua.to_value(&deser)
     })?)?;
 
+    // RFC 6901 pointer lookup, returns nil if the pointer does not resolve
+    module.set("pointerget", lua.create_function(|lua, (doc, pointer): (LuaValue, String)| {
+        let (doc, raw) = doc_from_lua(lua, doc)?;
+        match doc.pointer(&pointer) {
+            Some(v) => doc_to_lua(lua, v, raw),
+            None => Ok(LuaValue::Nil),
+        }
+    })?)?;
 
+    // RFC 6901 pointer assignment, returns the updated document
+    module.set("pointerset", lua.create_function(|lua, (doc, pointer, value): (LuaValue, String, LuaValue)| {
+        let (mut doc, raw) = doc_from_lua(lua, doc)?;
+        let value: serde_json::Value = lua.from_value_with(value, LUA_DESERIALIZE_OPTIONS)?;
+        if !raw {
+            coerce_empty_parent(&mut doc, &pointer);
+        }
+        pointer_set(&mut doc, &pointer, value)?;
+        doc_to_lua(lua, &doc, raw)
+    })?)?;
+
+    // RFC 6902 patch application, returns the patched document
+    //
+    // The patch is applied atomically (either all operations succeed or the document is left untouched)
+    module.set("patch", lua.create_function(|lua, (doc, patch): (LuaValue, LuaValue)| {
+        let (mut doc, raw) = doc_from_lua(lua, doc)?;
+        let (patch, _) = doc_from_lua(lua, patch)?;
+        let patch: json_patch::Patch = serde_json::from_value(patch)
+            .map_err(|e| LuaError::external(format!("Invalid JSON patch: {e}")))?;
+
+        if !raw {
+            for op in patch.0.iter() {
+                coerce_empty_parent(&mut doc, op.path().as_str());
+            }
+        }
+
+        json_patch::patch(&mut doc, &patch)
+            .map_err(|e| LuaError::external(format!("Failed to apply JSON patch: {e}")))?;
+
+        doc_to_lua(lua, &doc, raw)
+    })?)?;
+
+    // RFC 6902 diff, returns a patch that transforms ``from`` into ``to``
+    module.set("diff", lua.create_function(|lua, (from, to): (LuaValue, LuaValue)| {
+        let (from, raw) = doc_from_lua(lua, from)?;
+        let (to, _) = doc_from_lua(lua, to)?;
+        let patch = serde_json::to_value(json_patch::diff(&from, &to)).into_lua_err()?;
+        doc_to_lua(lua, &patch, raw)
+    })?)?;
+
+    // Compiles a JSON Schema (draft 2020-12) for validation
+    //
+    // Remote references are not resolved. Empty Luau tables are objects wherever a schema is expected
+    module.set("compileschema", lua.create_function(|lua, schema: LuaValue| {
+        let (mut schema, raw) = doc_from_lua(lua, schema)?;
+        if !raw {
+            coerce_empty_schema(&mut schema);
+        }
+        let validator = jsonschema::draft202012::new(&schema)
+            .map_err(|e| LuaError::external(format!("Invalid JSON schema: {e}")))?;
+        Ok(JsonSchema { validator, schema })
+    })?)?;
 
     module.set_readonly(true); // Block any attempt to modify this table
 
     Ok(module)
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pointer_set() {
+        let mut doc = serde_json::json!({"a": {"b": [1, 2]}, "c/d": 1});
+        pointer_set(&mut doc, "/a/b/0", serde_json::json!(5)).unwrap();
+        pointer_set(&mut doc, "/a/b/-", serde_json::json!(6)).unwrap();
+        pointer_set(&mut doc, "/a/b/3", serde_json::json!(7)).unwrap();
+        pointer_set(&mut doc, "/c~1d", serde_json::json!(2)).unwrap();
+        pointer_set(&mut doc, "/a/e", serde_json::json!("x")).unwrap();
+        assert_eq!(doc, serde_json::json!({"a": {"b": [5, 2, 6, 7], "e": "x"}, "c/d": 2}));
+
+        assert!(pointer_set(&mut doc, "/a/b/9", serde_json::json!(1)).is_err());
+        assert!(pointer_set(&mut doc, "/x/y", serde_json::json!(1)).is_err());
+        assert!(pointer_set(&mut doc, "a", serde_json::json!(1)).is_err());
+
+        // Empty Luau tables are empty arrays
+        let mut doc = serde_json::json!({"a": [], "b": []});
+        coerce_empty_parent(&mut doc, "/a/x");
+        coerce_empty_parent(&mut doc, "/b/0");
+        assert_eq!(doc, serde_json::json!({"a": {}, "b": []}));
+
+        let mut schema = serde_json::json!({"properties": [], "items": [], "required": [], "anyOf": [[]]});
+        coerce_empty_schema(&mut schema);
+        assert_eq!(schema, serde_json::json!({"properties": {}, "items": {}, "required": [], "anyOf": [{}]}));
+
+        let schema = serde_json::json!({"properties": {"list": {"type": "array"}}});
+        let mut instance = serde_json::json!({"list": [], "other": [], "nested": [[]]});
+        coerce_empty_instance(&mut instance, Some(&schema));
+        assert_eq!(instance, serde_json::json!({"list": [], "other": {}, "nested": [{}]}));
+    }
+
+    #[test]
+    fn test_json_module() -> LuaResult<()> {
+        let lua = Lua::new();
+        let json = init_plugin(&lua)?;
+
+        lua.load(
+            r#"
+            local json = ...
+
+            local doc = { a = { b = { 1, 2 } }, s = "x" }
+            assert(json.pointerget(doc, "/a/b/1") == 2)
+            assert(json.pointerget(doc, "/missing") == nil)
+            assert(json.pointerget('{"a":{"b":[1,2]}}', "/a/b") == "[1,2]")
+
+            -- Empty tables become objects when a member is set
+            assert(json.pointerset({}, "/a", 1).a == 1)
+            assert(json.pointerset({ list = {} }, "/list/0", "x").list[1] == "x")
+            assert(json.pointerset("[]", "/0", 1) == "[1]")
+            assert(not pcall(json.pointerset, "[]", "/a", 1))
+
+            local patched = json.patch({}, {
+                { op = "add", path = "/b", value = { 1 } },
+                { op = "add", path = "/a", value = 2 },
+            })
+            assert(patched.a == 2 and patched.b[1] == 1)
+            assert(not pcall(json.patch, { a = 1 }, { { op = "remove", path = "/a" }, { op = "remove", path = "/zzz" } }))
+            assert(not pcall(json.patch, { a = 1 }, { { op = "explode", path = "/a" } }))
+
+            local diff = json.diff({ a = 1, b = 2 }, { a = 3 })
+            assert(#diff == 2)
+            local applied = json.patch({ a = 1, b = 2 }, diff)
+            assert(applied.a == 3 and applied.b == nil)
+            assert(json.diff('{"a":1}', '{"a":1}') == "[]")
+
+            local schema = json.compileschema({
+                type = "object",
+                properties = { age = { type = "integer", minimum = 0 } },
+                required = { "age" },
+            })
+            assert(typeof(schema) == "JsonSchema")
+            local valid, errors = schema:validate({ age = 5 })
+            assert(valid and #errors == 0)
+            valid, errors = schema:validate({ age = -1 })
+            assert(not valid and #errors == 1 and errors[1].path == "/age", errors[1] and errors[1].path)
+            valid, errors = schema:validate('{"name": "x"}')
+            assert(not valid and #errors == 1)
+            assert(not pcall(json.compileschema, { type = 5 }))
+
+            -- Empty tables follow the schema
+            local object = json.compileschema({ type = "object" })
+            assert(object:validate({}))
+            assert(not object:validate("[]"))
+            local nested = json.compileschema({
+                type = "object",
+                properties = {
+                    meta = { type = "object", properties = {} },
+                    tags = { type = "array" },
+                    pairs = { type = "array", items = { type = "object" } },
+                },
+                required = {},
+            })
+            assert(nested:validate({ meta = {}, tags = {}, pairs = { {}, {} } }))
+            valid, errors = nested:validate({ meta = { 1 } })
+            assert(not valid and errors[1].path == "/meta", errors[1] and errors[1].path)
+        "#,
+        )
+        .call::<()>(json)
+    }
+}
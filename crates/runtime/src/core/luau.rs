@@ -1,7 +1,299 @@
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
 use mlua_scheduler::{LuaSchedulerAsyncUserData, taskmgr::SchedulerImpl};
 use mluau::prelude::*;
 
+use crate::core::datetime::TimeDelta;
 use crate::rt::runtime::S;
+use crate::utils::luauscan::{ParseOptions, parse_luau};
+use crate::utils::proxyglobal::proxy_global;
+
+/// Resource limits applied to a single execution of a Chunk (and the threads it creates)
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ChunkLimits {
+    /// Maximum wall clock time the chunk may run for
+    pub time_limit: Option<Duration>,
+    /// Maximum number of VM interrupts (roughly, loop iterations and function calls) the chunk
+    /// may trigger. This is not a count of bytecode instructions
+    pub interrupt_limit: Option<u64>,
+    /// Maximum growth (in bytes) of the VM's memory usage while the chunk is running
+    pub memory_limit: Option<usize>,
+}
+
+impl ChunkLimits {
+    pub fn is_empty(&self) -> bool {
+        self.time_limit.is_none() && self.interrupt_limit.is_none() && self.memory_limit.is_none()
+    }
+}
+
+type ThreadPtr = *const std::ffi::c_void;
+
+struct ChunkState {
+    /// The limited chunk that started this one, if any. Its limits apply as well
+    parent: Option<u64>,
+    limits: ChunkLimits,
+    /// When the chunk's time limit runs out, clamped to the deadline of its parent
+    deadline: Option<Instant>,
+    interrupts: u64,
+    /// The VM's memory usage may not exceed this, clamped to the cap of its parent
+    memory_cap: Option<usize>,
+    /// Number of references to the chunk: the running call, threads tagged with it and nested chunks
+    refs: usize,
+}
+
+impl ChunkState {
+    fn tick(&mut self) -> LuaResult<()> {
+        self.interrupts += 1;
+
+        if let Some(max) = self.limits.interrupt_limit {
+            if self.interrupts > max {
+                return Err(LuaError::RuntimeError("Chunk interrupt limit exceeded".to_string()));
+            }
+        }
+
+        if let Some(deadline) = self.deadline {
+            if Instant::now() > deadline {
+                return Err(LuaError::RuntimeError("Chunk execution time limit exceeded".to_string()));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Tracks the Chunks running with resource limits
+///
+/// Every thread created while a limited chunk runs (by ``coroutine``, ``task.spawn`` etc.) is
+/// tagged with the chunk through the VM's thread creation callback, so the chunk's limits follow
+/// it across threads, including ones that outlive the call. Time and interrupt limits are
+/// enforced from the VM interrupt (see ``ChunkLimiter::check``), walking from the current thread's
+/// chunk up to the outermost limited chunk. Exceeding one raises a regular runtime error inside
+/// the chunk, so the runtime itself is never marked as broken
+///
+/// Memory limits are enforced by the allocator: the VM's memory limit is lowered to the chunk's
+/// cap whenever one of its threads is running and restored at the next interrupt outside of it
+///
+/// Chunk limits only ever tighten the limits of the caller: the runtime's own time and memory
+/// limits still apply while a limited chunk runs
+#[derive(Default)]
+pub struct ChunkLimiter {
+    chunks: RefCell<HashMap<u64, ChunkState>>,
+    /// The limited chunk each (tagged) thread runs on behalf of
+    threads: RefCell<HashMap<ThreadPtr, u64>>,
+    next_id: Cell<u64>,
+    /// The memory limit set on the runtime (0 for none)
+    runtime_memory_limit: Cell<usize>,
+    /// The memory limit currently set on the VM
+    applied_memory_limit: Cell<usize>,
+}
+
+impl ChunkLimiter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the runtime's own memory limit (0 for none), which chunk memory limits are applied on top of
+    pub fn set_runtime_memory_limit(&self, lua: &Lua, limit: usize) -> LuaResult<usize> {
+        self.runtime_memory_limit.set(limit);
+        self.applied_memory_limit.set(limit);
+        lua.set_memory_limit(limit)
+    }
+
+    fn apply_memory_limit(&self, lua: &Lua, cap: Option<usize>) {
+        let limit = match (cap, self.runtime_memory_limit.get()) {
+            (Some(cap), 0) => cap.max(1),
+            (Some(cap), runtime) => cap.clamp(1, runtime),
+            (None, runtime) => runtime,
+        };
+        if limit != self.applied_memory_limit.get() {
+            self.applied_memory_limit.set(limit);
+            let _ = lua.set_memory_limit(limit);
+        }
+    }
+
+    /// Drops a reference to a chunk, forgetting it (and dropping its reference to its parent) once unused
+    fn release(chunks: &mut HashMap<u64, ChunkState>, mut id: u64) {
+        loop {
+            let Some(chunk) = chunks.get_mut(&id) else {
+                return;
+            };
+            chunk.refs -= 1;
+            if chunk.refs > 0 {
+                return;
+            }
+            match chunks.remove(&id).and_then(|c| c.parent) {
+                Some(parent) => id = parent,
+                None => return,
+            }
+        }
+    }
+
+    /// Tags ``thread`` with ``chunk`` (or untags it), replacing any previous tag
+    fn tag(&self, thread: ThreadPtr, chunk: Option<u64>) {
+        let (Ok(mut threads), Ok(mut chunks)) = (self.threads.try_borrow_mut(), self.chunks.try_borrow_mut()) else {
+            return;
+        };
+
+        let old = match chunk {
+            Some(id) => match chunks.get_mut(&id) {
+                Some(state) => {
+                    state.refs += 1;
+                    threads.insert(thread, id)
+                }
+                None => threads.remove(&thread),
+            },
+            None => threads.remove(&thread),
+        };
+        if let Some(old) = old {
+            Self::release(&mut chunks, old);
+        }
+    }
+
+    /// Called when the VM creates a thread, tagging it with the chunk of the thread creating it
+    pub fn on_thread_created(&self, lua: &Lua, thread: &LuaThread) {
+        let parent = lua.current_thread().to_pointer();
+        let chunk = self.threads.try_borrow().ok().and_then(|t| t.get(&parent).copied());
+        self.tag(thread.to_pointer(), chunk);
+    }
+
+    /// Called when the VM collects a thread
+    pub fn on_thread_collected(&self, thread: ThreadPtr) {
+        self.tag(thread, None);
+    }
+
+    /// Applies the memory cap of the chunk the current thread runs on behalf of (if any)
+    fn apply_current_memory_limit(&self, lua: &Lua) {
+        let thread = lua.current_thread().to_pointer();
+        let cap = match (self.threads.try_borrow(), self.chunks.try_borrow()) {
+            (Ok(threads), Ok(chunks)) => threads.get(&thread).and_then(|id| chunks.get(id)).and_then(|c| c.memory_cap),
+            _ => return,
+        };
+        self.apply_memory_limit(lua, cap);
+    }
+
+    /// Checks the limits of the chunk the current thread runs on behalf of (and of any limited
+    /// chunks that started it), applying its memory cap
+    ///
+    /// Returns ``Ok`` if the current thread is not running a limited chunk
+    pub fn check(&self, lua: &Lua) -> LuaResult<()> {
+        let thread = lua.current_thread().to_pointer();
+        let Some(mut id) = self.threads.try_borrow().ok().and_then(|t| t.get(&thread).copied()) else {
+            self.apply_memory_limit(lua, None);
+            return Ok(());
+        };
+
+        let memory_cap = {
+            let Ok(mut chunks) = self.chunks.try_borrow_mut() else {
+                return Ok(());
+            };
+            let memory_cap = chunks.get(&id).and_then(|c| c.memory_cap);
+            loop {
+                let Some(chunk) = chunks.get_mut(&id) else {
+                    break;
+                };
+                chunk.tick()?;
+                match chunk.parent {
+                    Some(p) => id = p,
+                    None => break,
+                }
+            }
+            memory_cap
+        };
+
+        self.apply_memory_limit(lua, memory_cap);
+        Ok(())
+    }
+
+    /// Starts enforcing limits for a chunk about to run on ``thread``. The call's reference to
+    /// the chunk is dropped with the guard, but the threads it created keep its limits
+    fn enter(lua: &Lua, thread: &LuaThread, limits: ChunkLimits) -> LuaResult<Option<ChunkGuard>> {
+        if limits.is_empty() {
+            return Ok(None);
+        }
+
+        let Some(limiter) = lua.app_data_ref::<Rc<ChunkLimiter>>().map(|l| Rc::clone(&l)) else {
+            return Err(LuaError::runtime("Chunk limits are not supported by this runtime"));
+        };
+
+        let caller = lua.current_thread().to_pointer();
+        let used_memory = lua.used_memory();
+        let id = limiter.next_id.get();
+        limiter.next_id.set(id + 1);
+
+        let memory_cap = {
+            let parent = limiter.threads.try_borrow().map_err(LuaError::external)?.get(&caller).copied();
+            let mut chunks = limiter.chunks.try_borrow_mut().map_err(LuaError::external)?;
+            let parent_state = parent.and_then(|p| chunks.get(&p));
+
+            // A nested chunk can never outlive the time left to (or use more memory than) the chunk that started it
+            let deadline = limits.time_limit.and_then(|limit| Instant::now().checked_add(limit));
+            let deadline = match (deadline, parent_state.and_then(|p| p.deadline)) {
+                (Some(d), Some(p)) => Some(d.min(p)),
+                (d, p) => d.or(p),
+            };
+            let memory_cap = limits.memory_limit.map(|limit| used_memory.saturating_add(limit));
+            let memory_cap = match (memory_cap, parent_state.and_then(|p| p.memory_cap)) {
+                (Some(c), Some(p)) => Some(c.min(p)),
+                (c, p) => c.or(p),
+            };
+
+            if let Some(parent) = parent.and_then(|p| chunks.get_mut(&p)) {
+                parent.refs += 1;
+            }
+            chunks.insert(id, ChunkState {
+                parent,
+                limits,
+                deadline,
+                interrupts: 0,
+                memory_cap,
+                refs: 1, // The guard
+            });
+            memory_cap
+        };
+
+        limiter.tag(thread.to_pointer(), Some(id));
+        limiter.apply_memory_limit(lua, memory_cap);
+        Ok(Some(ChunkGuard {
+            lua: lua.clone(),
+            limiter,
+            thread: thread.to_pointer(),
+            id,
+        }))
+    }
+}
+
+struct ChunkGuard {
+    lua: Lua,
+    limiter: Rc<ChunkLimiter>,
+    thread: ThreadPtr,
+    id: u64,
+}
+
+impl Drop for ChunkGuard {
+    fn drop(&mut self) {
+        // The chunk's own thread is done, in case it gets reused
+        self.limiter.tag(self.thread, None);
+        if let Ok(mut chunks) = self.limiter.chunks.try_borrow_mut() {
+            ChunkLimiter::release(&mut chunks, self.id);
+        }
+
+        // Give the caller back its own memory limit
+        self.limiter.apply_current_memory_limit(&self.lua);
+    }
+}
+
+/// Reports running out of a chunk's memory as a regular error, a VM memory error would mark the whole runtime as broken
+fn map_chunk_error(limits: &ChunkLimits, err: LuaError) -> LuaError {
+    match err {
+        LuaError::MemoryError(_) if limits.memory_limit.is_some() => {
+            LuaError::RuntimeError("Chunk memory limit exceeded".to_string())
+        }
+        err => err,
+    }
+}
 
 #[derive(Clone)]
 /// An lockdown executor is used to manage AntiRaid lockdowns from Lua
@@ -11,6 +303,8 @@ pub struct Chunk {
     chunk_name: Option<String>,
     environment: Option<LuaTable>,
     optimization_level: Option<u8>,
+    limits: ChunkLimits,
+    isolated: bool,
 }
 
 impl Chunk {
@@ -20,6 +314,7 @@ impl Chunk {
             compiler = compiler.set_optimization_level(level);
         }
 
+        // Template-built code is not shared with other runtimes (see rt::bytecodecache)
         let bytecode = compiler.compile(&self.code)?;
 
         let mut chunk = lua.load(bytecode);
@@ -31,6 +326,8 @@ impl Chunk {
 
         if let Some(env) = &self.environment {
             chunk = chunk.set_environment(env.clone());
+        } else if self.isolated {
+            chunk = chunk.set_environment(proxy_global(lua)?);
         } else {
             chunk = chunk.set_environment(lua.globals());
         }
@@ -76,12 +373,56 @@ impl LuaUserData for Chunk {
             this.chunk_name = name;
             Ok(())
         });
+        fields.add_field_method_get("isolated", |_, this| Ok(this.isolated));
+        fields.add_field_method_set("isolated", |_, this, isolated: bool| {
+            this.isolated = isolated;
+            Ok(())
+        });
+        fields.add_field_method_get("time_limit", |_, this| {
+            Ok(this.limits.time_limit.and_then(|d| TimeDelta::from_std(d).ok()))
+        });
+        fields.add_field_method_set("time_limit", |_, this, limit: Option<LuaUserDataRef<TimeDelta>>| {
+            this.limits.time_limit = match limit {
+                Some(limit) => Some(
+                    limit
+                        .timedelta
+                        .to_std()
+                        .map_err(|_| LuaError::runtime("Time limit must not be negative"))?,
+                ),
+                None => None,
+            };
+            Ok(())
+        });
+        // Counts VM interrupts (roughly, loop iterations and function calls), not bytecode instructions
+        fields.add_field_method_get("interrupt_limit", |_, this| Ok(this.limits.interrupt_limit));
+        fields.add_field_method_set("interrupt_limit", |_, this, limit: Option<u64>| {
+            this.limits.interrupt_limit = limit;
+            Ok(())
+        });
+        fields.add_field_method_get("memory_limit", |_, this| Ok(this.limits.memory_limit));
+        fields.add_field_method_set("memory_limit", |_, this, limit: Option<usize>| {
+            this.limits.memory_limit = limit;
+            Ok(())
+        });
     }
 
     fn add_methods<M: LuaUserDataMethods<Self>>(methods: &mut M) {
         methods.add_method("call", |lua, this, args: LuaMultiValue| {
             let chunk = this.setup_chunk(lua)?;
-            let res = chunk.call::<LuaMultiValue>(args)?;
+            if this.limits.is_empty() {
+                let res = chunk.call::<LuaMultiValue>(args)?;
+                return Ok(res);
+            }
+
+            // Run limited chunks on their own thread so the interrupt can tell them apart from the caller
+            let th = lua.create_thread(chunk.into_function()?)?;
+            let _guard = ChunkLimiter::enter(lua, &th, this.limits)?;
+            let res = th
+                .resume::<LuaMultiValue>(args)
+                .map_err(|e| map_chunk_error(&this.limits, e))?;
+            if th.status() == LuaThreadStatus::Resumable {
+                return Err(LuaError::runtime("Chunk attempted to yield, use call_async instead"));
+            }
 
             Ok(res)
         });
@@ -93,8 +434,13 @@ impl LuaUserData for Chunk {
 
                 let th = lua.create_thread(func)?;
 
+                let _guard = ChunkLimiter::enter(&lua, &th, this.limits)?;
+
                 let scheduler = S::get(&lua);
-                scheduler.run_in_scheduler(th, args).await
+                scheduler
+                    .run_in_scheduler(th, args)
+                    .await
+                    .map_err(|e| map_chunk_error(&this.limits, e))
             },
         );
     }
@@ -119,12 +465,67 @@ pub fn init_plugin(lua: &Lua) -> LuaResult<LuaTable> {
                 chunk_name: None,
                 environment: None,
                 optimization_level: None,
+                limits: ChunkLimits::default(),
+                isolated: false,
             };
 
             Ok(chunk)
         })?,
     )?;
 
+    // Compiles code without executing it, returning { ok, diagnostics, requires?, globals? }
+    //
+    // Options: requires (collect literal require paths), globals (collect referenced global names),
+    // lint (warn about unknown globals) and knownglobals (extra global names to treat as known)
+    module.set(
+        "parse",
+        lua.create_function(|lua, (code, opts): (String, Option<LuaTable>)| {
+            let mut parse_opts = ParseOptions::default();
+            let mut known_globals = Vec::new();
+            if let Some(opts) = opts {
+                parse_opts.requires = opts.get::<Option<bool>>("requires")?.unwrap_or(false);
+                parse_opts.globals = opts.get::<Option<bool>>("globals")?.unwrap_or(false);
+                parse_opts.lint_globals = opts.get::<Option<bool>>("lint")?.unwrap_or(false);
+                known_globals = opts.get::<Option<Vec<String>>>("knownglobals")?.unwrap_or_default();
+            }
+
+            let globals = lua.globals();
+            let result = parse_luau(&mluau::Compiler::new(), &code, &parse_opts, |name| {
+                // require and _G are provided by the proxied global table templates run in
+                matches!(name, "require" | "_G")
+                    || known_globals.iter().any(|g| g == name)
+                    || globals.contains_key(name).unwrap_or(false)
+            });
+
+            let diagnostics = lua.create_table()?;
+            for d in result.diagnostics.iter() {
+                let entry = lua.create_table()?;
+                entry.set("severity", d.severity.as_str())?;
+                entry.set("line", d.line)?;
+                entry.set("column", d.column)?;
+                entry.set("message", d.message.as_str())?;
+                diagnostics.raw_push(entry)?;
+            }
+            diagnostics.set_metatable(Some(lua.array_metatable()))?;
+
+            let res = lua.create_table()?;
+            res.set("ok", result.ok())?;
+            res.set("diagnostics", diagnostics)?;
+            if let Some(requires) = result.requires {
+                let requires = lua.create_sequence_from(requires)?;
+                requires.set_metatable(Some(lua.array_metatable()))?;
+                res.set("requires", requires)?;
+            }
+            if let Some(globals) = result.globals {
+                let globals = lua.create_sequence_from(globals)?;
+                globals.set_metatable(Some(lua.array_metatable()))?;
+                res.set("globals", globals)?;
+            }
+
+            Ok(res)
+        })?,
+    )?;
+
     module.set(
         "format",
         lua.create_function(|_, values: LuaMultiValue| {
@@ -144,3 +545,213 @@ pub fn init_plugin(lua: &Lua) -> LuaResult<LuaTable> {
 
     Ok(module)
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::rt::testutils::run_script;
+    use crate::rt::RuntimeCreateOpts;
+
+    fn run_limited(script: &str) -> String {
+        let err = run_script(
+            RuntimeCreateOpts {
+                time_limit: Some(Duration::from_millis(200)),
+                ..Default::default()
+            },
+            script,
+            |_| Ok(()),
+        )
+        .expect_err("script should have hit the runtime's time limit");
+
+        err.to_string()
+    }
+
+    #[test]
+    fn test_chunk_limits_keep_runtime_time_limit() {
+        // A chunk with only a memory limit
+        let err = run_limited(
+            r#"
+            return function()
+                local luau = require"@antiraid/luau"
+                local chunk = luau.load("while true do end")
+                chunk.memory_limit = 1024 * 1024 * 64
+                chunk:call()
+            end
+        "#,
+        );
+        assert!(err.contains("Script execution time limit exceeded"), "{err}");
+
+        // A chunk with a time limit far above the runtime's
+        let err = run_limited(
+            r#"
+            return function()
+                local luau = require"@antiraid/luau"
+                local datetime = require"@antiraid/datetime"
+                local chunk = luau.load("while true do end")
+                chunk.time_limit = datetime.timedelta_hours(1)
+                chunk:call()
+            end
+        "#,
+        );
+        assert!(err.contains("Script execution time limit exceeded"), "{err}");
+
+        // Catching the error does not give the caller more time
+        let err = run_limited(
+            r#"
+            return function()
+                local luau = require"@antiraid/luau"
+                local chunk = luau.load("while true do end")
+                chunk.interrupt_limit = 1e15
+                pcall(chunk.call, chunk)
+                while true do end
+            end
+        "#,
+        );
+        assert!(err.contains("Script execution time limit exceeded"), "{err}");
+
+        // Asynchronous chunks
+        let err = run_limited(
+            r#"
+            return function()
+                local luau = require"@antiraid/luau"
+                local chunk = luau.load("while true do end")
+                chunk.memory_limit = 1024 * 1024 * 64
+                chunk:call_async()
+            end
+        "#,
+        );
+        assert!(err.contains("Script execution time limit exceeded"), "{err}");
+    }
+
+    #[test]
+    fn test_nested_chunk_deadline() {
+        // The inner chunk asks for an hour but only gets what is left of the outer chunk's 50ms
+        let script = r#"
+            return function()
+                local luau = require"@antiraid/luau"
+                local datetime = require"@antiraid/datetime"
+
+                local inner = luau.load("while true do end")
+                inner.time_limit = datetime.timedelta_hours(1)
+
+                local outer = luau.load("local inner = ...; return pcall(inner.call, inner)")
+                outer.time_limit = datetime.timedelta_millis(50)
+
+                local ok, inner_ok, err = pcall(outer.call, outer, inner)
+                assert(not (ok and inner_ok), "nested chunk ran past the outer chunk's time limit")
+                err = if ok then err else inner_ok
+                assert(string.find(tostring(err), "Chunk execution time limit exceeded"), tostring(err))
+            end
+        "#;
+
+        run_script(
+            RuntimeCreateOpts {
+                time_limit: Some(Duration::from_secs(30)),
+                ..Default::default()
+            },
+            script,
+            |_| Ok(()),
+        )
+        .unwrap();
+
+        // Interrupt limits of the outer chunk still apply
+        let script = r#"
+            return function()
+                local luau = require"@antiraid/luau"
+
+                local inner = luau.load("for i = 1, 1e9 do end")
+                local outer = luau.load("local inner = ...; inner:call()")
+                outer.interrupt_limit = 1000
+
+                local ok, err = pcall(outer.call, outer, inner)
+                assert(not ok, "nested chunk ran past the outer chunk's interrupt limit")
+                assert(string.find(tostring(err), "Chunk interrupt limit exceeded"), tostring(err))
+            end
+        "#;
+
+        run_script(RuntimeCreateOpts::default(), script, |_| Ok(())).unwrap();
+    }
+
+    #[test]
+    fn test_chunk_limits_follow_created_threads() {
+        // Without the chunk's limits following its threads, these would only be stopped by the runtime's time limit
+        let script = r#"
+            return function()
+                local luau = require"@antiraid/luau"
+
+                -- coroutine.wrap
+                local chunk = luau.load("coroutine.wrap(function() while true do end end)()")
+                chunk.interrupt_limit = 1000
+                local ok, err = pcall(chunk.call, chunk)
+                assert(not ok, "coroutine escaped the chunk's interrupt limit")
+                assert(string.find(tostring(err), "Chunk interrupt limit exceeded"), tostring(err))
+
+                -- Nested coroutines
+                chunk = luau.load([[
+                    local co = coroutine.create(function()
+                        coroutine.wrap(function() while true do end end)()
+                    end)
+                    local ok, err = coroutine.resume(co)
+                    error(err)
+                ]])
+                chunk.interrupt_limit = 1000
+                ok, err = pcall(chunk.call, chunk)
+                assert(not ok)
+                assert(string.find(tostring(err), "Chunk interrupt limit exceeded"), tostring(err))
+
+                -- task.spawn, the spawned thread is stopped and the chunk carries on
+                chunk = luau.load([[
+                    local ok, err = pcall(task.spawn, function() while true do end end)
+                    return "done"
+                ]])
+                chunk.interrupt_limit = 1000
+                assert(chunk:call() == "done")
+
+                -- Threads spawned by a chunk keep its limits once it has returned
+                chunk = luau.load("task.spawn(function() task.wait(); while true do end end)")
+                chunk.interrupt_limit = 1000
+                chunk:call()
+                task.wait(0.1)
+            end
+        "#;
+
+        run_script(
+            RuntimeCreateOpts {
+                time_limit: Some(Duration::from_secs(10)),
+                ..Default::default()
+            },
+            script,
+            |_| Ok(()),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_chunk_memory_limit() {
+        let script = r#"
+            return function()
+                local luau = require"@antiraid/luau"
+
+                -- A single large allocation is stopped by the allocator, not at the next interrupt
+                local chunk = luau.load("local s = buffer.create(64 * 1024 * 1024); return buffer.len(s)")
+                chunk.memory_limit = 1024 * 1024
+                local ok, err = pcall(chunk.call, chunk)
+                assert(not ok, "chunk allocated past its memory limit")
+                assert(string.find(tostring(err), "Chunk memory limit exceeded"), tostring(err))
+
+                -- Including from a coroutine
+                chunk = luau.load("return coroutine.wrap(function() return buffer.create(64 * 1024 * 1024) end)()")
+                chunk.memory_limit = 1024 * 1024
+                ok, err = pcall(chunk.call, chunk)
+                assert(not ok, "coroutine allocated past the chunk's memory limit")
+
+                -- The caller gets its memory back once the chunk is done
+                local b = buffer.create(8 * 1024 * 1024)
+                assert(buffer.len(b) == 8 * 1024 * 1024)
+            end
+        "#;
+
+        run_script(RuntimeCreateOpts::default(), script, |_| Ok(())).unwrap();
+    }
+}
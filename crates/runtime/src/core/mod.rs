@@ -3,6 +3,14 @@ pub mod interop;
 pub mod luau;
 pub mod typesext;
 pub mod wasm;
+pub mod wasi;
+pub mod wasmregion;
+pub mod wasmcomponent;
 pub mod datamgmt;
 pub mod channel;
 pub mod json;
+pub mod encoding;
+pub mod msgpack;
+pub mod cbor;
+pub mod toml;
+pub mod yaml;
@@ -0,0 +1,50 @@
+use mluau::prelude::*;
+use serde::de::DeserializeSeed;
+
+use crate::primitives::blob::blob_ref;
+use crate::utils::luaserde::{Format, LuaValueRef, LuaValueSeed};
+
+pub fn init_plugin(lua: &Lua) -> LuaResult<LuaTable> {
+    let module = lua.create_table()?;
+
+    module.set("tomsgpack", lua.create_function(|lua, value: LuaValue| {
+        let encoded = rmp_serde::to_vec(&LuaValueRef::new(lua, &value, Format::MessagePack)).into_lua_err()?;
+        lua.create_external_buffer(bytes::Bytes::from(encoded))
+    })?)?;
+
+    module.set("frommsgpack", lua.create_function(|lua, data: LuaValue| {
+        blob_ref(&data, |s| {
+            let mut de = rmp_serde::Deserializer::new(s);
+            LuaValueSeed::new(lua, Format::MessagePack).deserialize(&mut de).into_lua_err()
+        })?
+    })?)?;
+
+    module.set_readonly(true); // Block any attempt to modify this table
+
+    Ok(module)
+}
+
+#[cfg(test)]
+mod tests {
+    use mluau::prelude::*;
+
+    use crate::utils::luaserde::check_roundtrip;
+
+    #[test]
+    fn test_roundtrip() -> LuaResult<()> {
+        let lua = Lua::new();
+        let module = super::init_plugin(&lua)?;
+        let encode: LuaFunction = module.get("tomsgpack")?;
+        let decode: LuaFunction = module.get("frommsgpack")?;
+        check_roundtrip(&lua, &encode, &decode)?;
+
+        // Small Int64's are plain integers any decoder can read
+        let encoded: LuaBuffer = encode.call(LuaValue::Int64(5))?;
+        assert_eq!(encoded.to_vec(), vec![0x05]);
+
+        // Unknown extension types
+        assert!(decode.call::<LuaValue>(lua.create_buffer([0xd4u8, 0x05, 0x00])?).is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,194 @@
+use mluau::prelude::*;
+
+use crate::core::datetime::{fixed_offset_timezone, DateTime};
+use crate::primitives::blob::blob_ref;
+use crate::utils::luaserde::{MAX_DEPTH, TableRepr, integer_to_lua, table_repr};
+
+/// The maximum size of a TOML document that can be parsed
+const MAX_INPUT_SIZE: usize = 4 * 1024 * 1024;
+
+/// Converts a parsed TOML value to Luau
+///
+/// Offset date-times are mapped to ``DateTime`` userdata, keeping their offset when it is a whole
+/// number of hours (and in UTC otherwise). Local dates and times have no timezone information and
+/// are kept as strings
+pub(crate) fn toml_to_lua(lua: &Lua, value: toml::Value, depth: usize) -> LuaResult<LuaValue> {
+    if depth > MAX_DEPTH {
+        return Err(LuaError::external("Recursion limit exceeded"));
+    }
+
+    match value {
+        toml::Value::String(s) => Ok(LuaValue::String(lua.create_string(s)?)),
+        toml::Value::Integer(i) => Ok(integer_to_lua(i)),
+        toml::Value::Float(f) => Ok(LuaValue::Number(f)),
+        toml::Value::Boolean(b) => Ok(LuaValue::Boolean(b)),
+        toml::Value::Datetime(dt) => {
+            if dt.date.is_some() && dt.time.is_some() && dt.offset.is_some() {
+                let parsed = chrono::DateTime::parse_from_rfc3339(&dt.to_string())
+                    .map_err(|e| LuaError::external(format!("Invalid TOML datetime: {e}")))?;
+                let tz = fixed_offset_timezone(parsed.offset().local_minus_utc()).unwrap_or(chrono_tz::Tz::UTC);
+                DateTime::<chrono_tz::Tz>::from_tz(parsed.with_timezone(&tz)).into_lua(lua)
+            } else {
+                Ok(LuaValue::String(lua.create_string(dt.to_string())?))
+            }
+        }
+        toml::Value::Array(arr) => {
+            let table = lua.create_table_with_capacity(arr.len(), 0)?;
+            for v in arr {
+                table.raw_push(toml_to_lua(lua, v, depth + 1)?)?;
+            }
+            table.set_metatable(Some(lua.array_metatable()))?;
+            Ok(LuaValue::Table(table))
+        }
+        toml::Value::Table(map) => {
+            let table = lua.create_table_with_capacity(0, map.len())?;
+            for (k, v) in map {
+                table.raw_set(k, toml_to_lua(lua, v, depth + 1)?)?;
+            }
+            Ok(LuaValue::Table(table))
+        }
+    }
+}
+
+/// Converts a Luau value to TOML. Returns ``None`` for nil (which TOML cannot represent)
+fn lua_to_toml(lua: &Lua, value: &LuaValue, depth: usize) -> LuaResult<Option<toml::Value>> {
+    if depth > MAX_DEPTH {
+        return Err(LuaError::external("Recursion limit exceeded"));
+    }
+
+    if value.is_null() {
+        return Err(LuaError::external("TOML cannot represent null"));
+    }
+
+    match value {
+        LuaValue::Nil => Ok(None),
+        LuaValue::Boolean(b) => Ok(Some(toml::Value::Boolean(*b))),
+        LuaValue::Integer(i) => Ok(Some(toml::Value::Integer(*i))),
+        LuaValue::Int64(i) => Ok(Some(toml::Value::Integer(*i))),
+        LuaValue::Number(n) => Ok(Some(toml::Value::Float(*n))),
+        LuaValue::String(s) => Ok(Some(toml::Value::String(s.to_str()?.to_string()))),
+        LuaValue::Table(table) => match table_repr(lua, table)? {
+            TableRepr::Array(values) => {
+                let mut arr = Vec::with_capacity(values.len());
+                for v in values.iter() {
+                    let Some(v) = lua_to_toml(lua, v, depth + 1)? else {
+                        return Err(LuaError::external("TOML arrays cannot contain nil"));
+                    };
+                    arr.push(v);
+                }
+                Ok(Some(toml::Value::Array(arr)))
+            }
+            TableRepr::Map(pairs) => {
+                let mut map = toml::map::Map::new();
+                for (k, v) in pairs.iter() {
+                    let LuaValue::String(k) = k else {
+                        return Err(LuaError::external("TOML table keys must be strings"));
+                    };
+                    if let Some(v) = lua_to_toml(lua, v, depth + 1)? {
+                        map.insert(k.to_str()?.to_string(), v);
+                    }
+                }
+                Ok(Some(toml::Value::Table(map)))
+            }
+        },
+        LuaValue::UserData(ud) => {
+            if let Ok(dt) = ud.borrow::<DateTime<chrono_tz::Tz>>() {
+                let dt = dt
+                    .dt
+                    .to_rfc3339()
+                    .parse::<toml::value::Datetime>()
+                    .map_err(|e| LuaError::external(format!("Failed to convert DateTime to TOML: {e}")))?;
+                return Ok(Some(toml::Value::Datetime(dt)));
+            }
+
+            Err(LuaError::external("Only DateTime userdata can be serialized to TOML"))
+        }
+        _ => Err(LuaError::external(format!("cannot serialize <{}> to TOML", value.type_name()))),
+    }
+}
+
+pub fn init_plugin(lua: &Lua) -> LuaResult<LuaTable> {
+    let module = lua.create_table()?;
+
+    module.set("totomlstring", lua.create_function(|lua, (value, pretty): (LuaValue, Option<bool>)| {
+        let Some(value @ toml::Value::Table(_)) = lua_to_toml(lua, &value, 0)? else {
+            return Err(LuaError::external("TOML documents must be a table at the top level"));
+        };
+
+        let toml_str = if pretty.unwrap_or(false) {
+            toml::to_string_pretty(&value).into_lua_err()?
+        } else {
+            toml::to_string(&value).into_lua_err()?
+        };
+        lua.create_string(toml_str)
+    })?)?;
+
+    module.set("fromtomlstring", lua.create_function(|lua, toml_str: LuaValue| {
+        let deser: toml::Value = blob_ref(&toml_str, |s| {
+            if s.len() > MAX_INPUT_SIZE {
+                return Err(LuaError::external(format!("TOML document exceeds maximum size of {MAX_INPUT_SIZE} bytes")));
+            }
+
+            let s = std::str::from_utf8(s).into_lua_err()?;
+            toml::from_str(s).into_lua_err()
+        })??;
+        toml_to_lua(lua, deser, 0)
+    })?)?;
+
+    module.set_readonly(true); // Block any attempt to modify this table
+
+    Ok(module)
+}
+
+#[cfg(test)]
+mod tests {
+    use mluau::prelude::*;
+
+    #[test]
+    fn test_toml_module() -> LuaResult<()> {
+        let lua = Lua::new();
+        let toml = super::init_plugin(&lua)?;
+
+        let big: LuaValue = lua
+            .load(
+                r#"
+            local toml = ...
+
+            local doc = toml.fromtomlstring([[
+title = "x"
+big = 9007199254740993
+when = 2024-01-02T03:04:05+05:00
+odd = 2024-01-02T03:04:05+05:30
+day = 2024-01-02
+
+[server]
+ports = [80, 443]
+]])
+            assert(doc.title == "x")
+            assert(doc.day == "2024-01-02")
+            assert(doc.server.ports[2] == 443)
+            assert(typeof(doc.when) == "DateTime")
+
+            -- Whole hour offsets are kept, others are converted to UTC
+            local out = toml.totomlstring({ when = doc.when, odd = doc.odd })
+            assert(string.find(out, "when = 2024-01-02T03:04:05+05:00", 1, true), out)
+            assert(string.find(out, "odd = 2024-01-01T21:34:05", 1, true), out)
+
+            local compact = toml.totomlstring({ server = { ports = { 80, 443 } } })
+            local pretty = toml.totomlstring({ server = { ports = { 80, 443 } } }, true)
+            assert(string.find(compact, "ports = [80, 443]", 1, true), compact)
+            assert(string.find(pretty, "443,\n", 1, true), pretty)
+
+            assert(not pcall(toml.totomlstring, 5))
+            assert(not pcall(toml.fromtomlstring, "a = "))
+            assert(not pcall(toml.fromtomlstring, string.rep("a", 5 * 1024 * 1024)))
+
+            return doc.big
+        "#,
+            )
+            .call(toml)?;
+        assert!(matches!(big, LuaValue::Int64(9007199254740993)));
+
+        Ok(())
+    }
+}
@@ -1,24 +1,36 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{collections::HashMap, io::Read, sync::Arc};
 
 use mluau::prelude::*;
 use mluau_require::AssetRequirer;
 use rand::distr::{Alphanumeric, SampleString};
 
-use crate::{primitives::opaque::Opaque, utils::{khronos_value::KhronosValue, pp::pretty_print, proxyglobal::proxy_global}};
+use crate::{primitives::{blob::blob_ref, opaque::{wrap_opaque_require, Opaque}}, rt::{assets::{AssetKind, AssetOptions, VfsFiles}, bytecodecache::{CachedRequirer, VFS_ROOT_MODULE}, runtime::KhronosRuntime}, utils::{khronos_value::KhronosValue, pp::pretty_print, proxyglobal::proxy_global}};
 
 pub struct MemoryVfs {
     pub data: HashMap<String, String>,
+
+    /// Denotes whether this MemoryVfs holds the contents of an Opaque type,
+    /// in which case its data is never revealed to Luau
+    pub from_opaque: bool,
 }
 
 impl MemoryVfs {
     pub fn new(data: HashMap<String, String>) -> Self {
-        Self { data }
+        Self { data, from_opaque: false }
+    }
+
+    pub fn new_opaque(data: HashMap<String, String>) -> Self {
+        Self { data, from_opaque: true }
     }
 }
 
 impl LuaUserData for MemoryVfs {
     fn add_methods<M: LuaUserDataMethods<Self>>(methods: &mut M) {
         methods.add_method("data", |lua, this, _: ()| {
+            if this.from_opaque {
+                return Err(LuaError::external("Cannot read the data of an opaque MemoryVfs"));
+            }
+
             lua.to_value(&this.data)
         });
     }
@@ -28,35 +40,159 @@ impl LuaUserData for MemoryVfs {
 pub struct Vfs {
     pub vfs: Arc<mluau_require::Vfs>,
 
-    #[allow(dead_code)]
-    /// Not used currently, but may be useful in the future
+    /// The files ``vfs`` was built from, so that modules required from it are compiled through
+    /// the bytecode cache and assets are loaded (see ``rt::bytecodecache::CachedRequirer``)
+    pub files: Arc<VfsFiles>,
+
+    /// Denotes whether this VFS was created from an Opaque type (directly or through an overlay)
     ///
-    /// Denotes whether this VFS was created from an Opaque type
-    /// 
-    /// Will in future block certain operations that would expose the underlying data
+    /// Modules required from an opaque VFS have their locations redacted from errors, tracebacks
+    /// and ``debug.info`` (see ``primitives::opaque``)
     from_opaque: bool,
 }
 
 impl Vfs {
-    pub fn new(vfs: Arc<mluau_require::Vfs>, opaque: bool) -> Self {
-        Self { vfs, from_opaque: opaque }
+    pub fn new(vfs: Arc<mluau_require::Vfs>, files: Arc<VfsFiles>, opaque: bool) -> Self {
+        Self { vfs, files, from_opaque: opaque }
+    }
+
+    /// Builds a Vfs from ``files`` (see ``rt::assets``)
+    pub fn from_files(files: VfsFiles, opaque: bool) -> Self {
+        Self::new(files.to_vfs().into(), files.into(), opaque)
+    }
+}
+
+/// Limits applied when building a Vfs from a tar archive
+#[derive(Debug, Clone, Copy)]
+pub struct TarVfsLimits {
+    /// Maximum number of files
+    pub max_files: usize,
+    /// Maximum size of a single file in bytes
+    pub max_file_size: usize,
+    /// Maximum total size of all files in bytes
+    pub max_total_size: usize,
+}
+
+impl Default for TarVfsLimits {
+    fn default() -> Self {
+        Self {
+            max_files: 1000,
+            max_file_size: 4 * 1024 * 1024,
+            max_total_size: 32 * 1024 * 1024,
+        }
+    }
+}
+
+/// Normalizes a path from a tar archive into a relative Vfs path
+///
+/// ``.`` components and repeated slashes are removed. Absolute paths and paths containing ``..``
+/// are rejected. Returns ``None`` if nothing remains (e.g. ``./``)
+fn normalize_tar_path(path: &str) -> Result<Option<String>, String> {
+    if path.starts_with('/') || path.starts_with('\\') || path.as_bytes().get(1) == Some(&b':') {
+        return Err(format!("Absolute path '{path}' is not allowed in a Vfs archive"));
+    }
+
+    let mut components = Vec::new();
+    for component in path.split(['/', '\\']) {
+        match component {
+            "" | "." => {}
+            ".." => return Err(format!("Path '{path}' must not contain '..'")),
+            c => components.push(c),
+        }
+    }
+
+    if components.is_empty() {
+        return Ok(None);
+    }
+
+    Ok(Some(components.join("/")))
+}
+
+/// Reads the files of a tar archive a Vfs is built from
+///
+/// Only regular files are included (directories and links are skipped), as are files whose
+/// contents are not valid UTF-8 (unless they are buffer assets). Paths are normalized with
+/// ``normalize_tar_path``
+pub fn files_from_tar(data: &[u8], limits: TarVfsLimits, assets: &AssetOptions) -> Result<VfsFiles, String> {
+    let mut files: HashMap<String, Vec<u8>> = HashMap::new();
+    let mut total_size = 0;
+
+    let mut archive = tar::Archive::new(data);
+    let entries = archive.entries().map_err(|e| format!("Failed to read tar archive: {e}"))?;
+    for entry in entries {
+        let entry = entry.map_err(|e| format!("Failed to read tar entry: {e}"))?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+
+        let path = {
+            let raw_path = entry.path_bytes();
+            let Ok(raw_path) = std::str::from_utf8(&raw_path) else {
+                return Err("Tar entry paths must be valid UTF-8".to_string());
+            };
+            normalize_tar_path(raw_path)?
+        };
+        let Some(path) = path else {
+            continue;
+        };
+
+        let size = entry.header().size().map_err(|e| format!("Invalid size for '{path}': {e}"))? as usize;
+        if size > limits.max_file_size {
+            return Err(format!("File '{path}' exceeds the maximum file size of {} bytes", limits.max_file_size));
+        }
+
+        let mut contents = Vec::with_capacity(size.min(data.len()));
+        entry
+            .take(size as u64)
+            .read_to_end(&mut contents)
+            .map_err(|e| format!("Failed to read '{path}': {e}"))?;
+
+        if std::str::from_utf8(&contents).is_err() && assets.kind_for(&path) != Some(AssetKind::Buffer) {
+            continue; // Not a text file
+        }
+
+        // Later entries replace earlier ones, as with tar itself
+        total_size += contents.len();
+        if let Some(old) = files.insert(path, contents) {
+            total_size -= old.len();
+        }
+
+        if files.len() > limits.max_files {
+            return Err(format!("Archive contains more than {} files", limits.max_files));
+        }
+
+        if total_size > limits.max_total_size {
+            return Err(format!("Archive exceeds the maximum total size of {} bytes", limits.max_total_size));
+        }
     }
+
+    Ok(VfsFiles::new(files, assets))
 }
 
 impl LuaUserData for Vfs {
     fn add_methods<M: LuaUserDataMethods<Self>>(methods: &mut M) {
-        methods.add_function("newoverlay", |_lua, vfs_list: Vec<LuaValue>| {
+        methods.add_function("newoverlay", |lua, vfs_list: Vec<LuaValue>| {
             let mut final_vfs = mluau_require::Vfs::new();
+            let mut files = VfsFiles::default();
             let mut from_opaque = false;
             for vfs in vfs_list {
                 match vfs {
                     LuaValue::UserData(vfs) => {
+                        let mut add_memory_vfs = |vfs: &MemoryVfs| {
+                            // Assets would expose the contents of opaque files, so they are never allowed there
+                            let assets = if vfs.from_opaque { AssetOptions::empty() } else { AssetOptions::get(lua) };
+                            let part = VfsFiles::new(vfs.data.clone(), &assets);
+                            final_vfs.extend(part.to_vfs());
+                            files.extend(part);
+                            from_opaque |= vfs.from_opaque; // propagate taint
+                        };
+
                         if vfs.is::<MemoryVfs>() {
                             let vfs = vfs
                             .borrow::<MemoryVfs>()
                             .map_err(|_| LuaError::external("Failed to borrow MemoryVfs"))?;
 
-                            final_vfs.extend(mluau_require::create_memory_vfs_from_map_ref(&vfs.data));
+                            add_memory_vfs(&vfs);
                             continue;
                         } else if vfs.is::<Opaque>() {
                             let opaque = vfs
@@ -67,8 +203,7 @@ impl LuaUserData for Vfs {
                                 KhronosValue::MemoryVfs(vfs) => vfs,
                                 _ => return Err(LuaError::external("Opaque must contain a Vfs KhronosValue to be used as a VFS")),
                             };
-                            final_vfs.extend(mluau_require::create_memory_vfs_from_map_ref(&map));
-                            from_opaque = true; // taint as opaque
+                            add_memory_vfs(&MemoryVfs::new_opaque((**map).clone())); // taint as opaque
                             continue;
                         } else if vfs.is::<Vfs>() {
                             let vfs = vfs
@@ -76,6 +211,8 @@ impl LuaUserData for Vfs {
                             .map_err(|_| LuaError::external("Failed to borrow Vfs"))?;
 
                             final_vfs.extend_ref(&vfs.vfs);
+                            files.extend((*vfs.files).clone());
+                            from_opaque |= vfs.from_opaque; // propagate taint
                             continue;
                         } else {
                             return Err(LuaError::external(
@@ -91,12 +228,49 @@ impl LuaUserData for Vfs {
                 }
             }
 
-            Ok(Vfs::new(final_vfs.into(), from_opaque))
+            Ok(Vfs::new(final_vfs.into(), files.into(), from_opaque))
+        });
+
+        // Builds a Vfs from a tar archive (string or buffer)
+        //
+        // Limits may be lowered (but not raised) with { maxfiles, maxfilesize, maxtotalsize }
+        methods.add_function("fromtar", |lua, (blob, opts): (LuaValue, Option<LuaTable>)| {
+            let mut limits = TarVfsLimits::default();
+            if let Some(opts) = opts {
+                if let Some(v) = opts.get::<Option<usize>>("maxfiles")? {
+                    limits.max_files = limits.max_files.min(v);
+                }
+                if let Some(v) = opts.get::<Option<usize>>("maxfilesize")? {
+                    limits.max_file_size = limits.max_file_size.min(v);
+                }
+                if let Some(v) = opts.get::<Option<usize>>("maxtotalsize")? {
+                    limits.max_total_size = limits.max_total_size.min(v);
+                }
+            }
+
+            let assets = AssetOptions::get(lua);
+            let files = blob_ref(&blob, |data| files_from_tar(data, limits, &assets))?.map_err(LuaError::external)?;
+            Ok(Vfs::from_files(files, false))
         });
 
         methods.add_method("createrequirefunction", |lua, this, (id, global_table): (String, LuaTable)| {
-            let controller = AssetRequirer::new_arc(this.vfs.clone(), id, global_table);
-            lua.create_require_function(controller)
+            let controller = CachedRequirer::new(
+                AssetRequirer::new_arc(this.vfs.clone(), id, global_table.clone()),
+                this.files.clone(),
+                KhronosRuntime::compile_options(),
+                global_table,
+            )
+            .with_base(VFS_ROOT_MODULE);
+            let require = lua.create_require_function(controller)?;
+            if this.from_opaque {
+                return wrap_opaque_require(lua, require);
+            }
+
+            Ok(require)
+        });
+
+        methods.add_method("isopaque", |_lua, this, _: ()| {
+            Ok(this.from_opaque)
         });
     }
 }
@@ -126,6 +300,11 @@ pub fn init_plugin(lua: &Lua) -> LuaResult<LuaTable> {
 
     module.set("Vfs", lua.create_proxy::<Vfs>()?)?;
 
+    // Builds a KhronosSchema from a descriptor table (see utils::khronos_schema)
+    module.set("schema", lua.create_function(|lua, descriptor: LuaValue| {
+        crate::utils::khronos_schema::KhronosSchema::from_lua_descriptor(lua, descriptor)
+    })?)?;
+
     module.set("createglobalproxy", lua.create_function(|lua, _: ()| {
         proxy_global(lua)
     })?)?;
@@ -138,3 +317,220 @@ pub fn init_plugin(lua: &Lua) -> LuaResult<LuaTable> {
 
     Ok(module)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tar_with(entries: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (path, data) in entries {
+            // Write the name directly as set_path refuses '..' and absolute paths
+            let mut header = tar::Header::new_gnu();
+            header.as_gnu_mut().unwrap().name[..path.len()].copy_from_slice(path.as_bytes());
+            header.set_size(data.len() as u64);
+            header.set_entry_type(tar::EntryType::Regular);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append(&header, *data).unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    #[test]
+    fn test_opaque_vfs() {
+        use crate::rt::testutils::run_script;
+        use crate::rt::RuntimeCreateOpts;
+
+        let secret = HashMap::from([
+            (
+                "init.luau".to_string(),
+                // Read-only tables are wrapped too
+                "local SECRET = 'hunter2'; return table.freeze({ check = function(s) return s == SECRET end })".to_string(),
+            ),
+            (
+                "state.luau".to_string(),
+                r#"
+                local M = { count = 0, list = { 1, 2 } }
+                function M.inc() M.count += 1; return M.count end
+                function M.make() return function() error("boom") end end
+                function M.get() return M end
+                return M
+                "#
+                .to_string(),
+            ),
+        ]);
+
+        // Opaque MemoryVfs's can't be converted back into plain data
+        let lua = Lua::new();
+        let ud = lua.create_userdata(MemoryVfs::new_opaque(secret.clone())).unwrap();
+        assert!(KhronosValue::from_lua(LuaValue::UserData(ud), &lua).is_err());
+
+        let script = r#"
+            return function(opaque, opaquemem)
+                local typesext = require"@antiraid/typesext"
+
+                local ok, err = pcall(opaquemem.data, opaquemem)
+                assert(not ok and string.find(tostring(err), "Cannot read the data of an opaque MemoryVfs"), tostring(err))
+
+                local plain = typesext.Vfs.newoverlay({ typesext.createvfs({ ["other.luau"] = "return 1" }) })
+                assert(not plain:isopaque())
+
+                for _, source in { opaque, opaquemem } do
+                    local vfs = typesext.Vfs.newoverlay({ plain, source })
+                    assert(vfs:isopaque(), "overlay of an opaque Vfs is not opaque")
+
+                    -- Modules can be used, but not read
+                    local mod = vfs:createrequirefunction("secret", typesext.createglobalproxy())("./init")
+                    assert(mod.check("hunter2") == true and mod.check("x") == false)
+                    assert(table.isfrozen(mod), "module table was left writable")
+                    local src = debug.info(mod.check, "s")
+                    assert(src == nil or not string.find(src, "init"), tostring(src))
+
+                    -- Module state is read through the proxy, which keeps its identity
+                    local state = vfs:createrequirefunction("state", typesext.createglobalproxy())("./state")
+                    assert(state.inc() == 1 and state.count == 1)
+                    assert(state.get() == state and state.inc == state.inc)
+                    assert(#state.list == 2)
+                    state.extra = true
+                    assert(state.extra == true)
+                    local keys = 0
+                    for _ in state do
+                        keys += 1
+                    end
+                    assert(keys == 6, tostring(keys))
+
+                    -- Closures returned by calls are wrapped too
+                    local f = state.make()
+                    local ok, err = pcall(f)
+                    assert(not ok and string.find(tostring(err), "boom") and not string.find(tostring(err), "state"), tostring(err))
+                    src = debug.info(f, "s")
+                    assert(src == nil or not string.find(src, "state"), tostring(src))
+                end
+            end
+        "#;
+
+        run_script(RuntimeCreateOpts::default(), script, |lua| {
+            Ok((
+                Opaque::new(KhronosValue::MemoryVfs(secret.clone().into())),
+                lua.create_userdata(MemoryVfs::new_opaque(secret))?,
+            ))
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_schema() {
+        use crate::rt::testutils::run_script;
+        use crate::rt::RuntimeCreateOpts;
+
+        let script = r#"
+            return function()
+                local typesext = require"@antiraid/typesext"
+
+                local schema = typesext.schema({
+                    type = "struct",
+                    fields = {
+                        name = { type = "text", pattern = "^[a-z]+$", maxlength = 8 },
+                        id = { type = "integer", min = 1 },
+                        data = { type = "bytes" },
+                        tags = { type = "list", items = { type = "union", variants = { { type = "text" }, { type = "integer" } } } },
+                        -- Empty tables are accepted for both fields and variants
+                        meta = { type = "optional", inner = { type = "struct", fields = {} } },
+                        never = { type = "optional", inner = { type = "union", variants = {} } },
+                    },
+                })
+                assert(schema.type == "struct")
+
+                local ok, errors = schema:validate({ name = "Bad Name", id = 0, tags = { "a", true }, extra = 1 })
+                assert(not ok)
+                local expected = {
+                    { "$.data", "missing required field of type bytes" },
+                    { "$.id", "0 is less than the minimum of 1" },
+                    { "$.name", "does not match the pattern" },
+                    { "$.tags[2]", "expected one of text | integer, got boolean" },
+                    { "$.extra", "unknown field" },
+                }
+                assert(#errors == #expected, typesext.fmtpretty(errors))
+                for i, e in expected do
+                    assert(errors[i].path == e[1], errors[i].path)
+                    assert(string.find(errors[i].message, e[2], 1, true), errors[i].message)
+                end
+
+                -- Strings are coerced to bytes
+                local value = { name = "abc", id = 5, data = "abc", tags = {}, meta = {} }
+                assert(not schema:validate(value))
+                local coerced, errors = schema:coerce(value)
+                assert(coerced and errors == nil, typesext.fmtpretty(errors))
+                assert(coerced.name == "abc" and coerced.id == 5)
+                assert(type(coerced.data) == "buffer" and buffer.tostring(coerced.data) == "abc")
+                assert(#coerced.tags == 0 and type(coerced.meta) == "table")
+
+                local coerced, errors = schema:coerce({ name = "abc", id = "x", data = "", meta = { x = 1 } })
+                assert(coerced == nil)
+                assert(#errors == 3, typesext.fmtpretty(errors))
+                assert(errors[1].path == "$.id" and errors[1].message == "expected integer, got text")
+                assert(errors[2].path == "$.meta.x" and errors[2].message == "unknown field")
+                assert(errors[3].path == "$.tags" and errors[3].message == "missing required field of type list")
+
+                local ok, err = pcall(typesext.schema, { type = "bogus" })
+                assert(not ok and string.find(tostring(err), "Invalid schema"), tostring(err))
+            end
+        "#;
+
+        run_script(RuntimeCreateOpts::default(), script, |_| Ok(())).unwrap();
+    }
+
+    #[test]
+    fn test_normalize_tar_path() {
+        assert_eq!(normalize_tar_path("./src//init.luau").unwrap(), Some("src/init.luau".to_string()));
+        assert_eq!(normalize_tar_path("a/./b").unwrap(), Some("a/b".to_string()));
+        assert_eq!(normalize_tar_path("./").unwrap(), None);
+        assert!(normalize_tar_path("../evil.luau").is_err());
+        assert!(normalize_tar_path("a/../../b").is_err());
+        assert!(normalize_tar_path("/etc/passwd").is_err());
+        assert!(normalize_tar_path("C:/x").is_err());
+    }
+
+    #[test]
+    fn test_files_from_tar() {
+        let limits = TarVfsLimits::default();
+        let assets = AssetOptions::default().allow("dat", AssetKind::Buffer);
+        let tar = tar_with(&[
+            ("./init.luau", b"return 1"),
+            ("src//./util.luau", b"return 2"),
+            ("config.json", b"{\"a\": 1}"),
+            ("bin.dat", &[0xff, 0xfe]),
+            ("image.png", &[0x89, 0x50, 0x4e, 0x47, 0xff]),
+        ]);
+        let files = files_from_tar(&tar, limits, &assets).unwrap();
+
+        // Paths are normalized and text files are kept as-is
+        assert_eq!(files.files.get("init.luau").map(|f| &f[..]), Some(&b"return 1"[..]));
+        assert_eq!(files.files.get("src/util.luau").map(|f| &f[..]), Some(&b"return 2"[..]));
+        assert_eq!(files.files.get("config.json").map(|f| &f[..]), Some(&b"{\"a\": 1}"[..]));
+
+        // Binary buffer assets are kept, other binary files are skipped
+        assert_eq!(files.files.get("bin.dat").map(|f| &f[..]), Some(&[0xff, 0xfe][..]));
+        assert!(files.files.contains_key("bin.dat.luau"));
+        assert_eq!(files.assets.get("bin.dat.luau").map(|a| &a.contents[..]), Some(&[0xff, 0xfe][..]));
+        assert!(!files.files.keys().any(|k| k.starts_with("image.png")));
+        assert_eq!(files.files.len(), 5);
+        assert_eq!(files.assets.len(), 1);
+
+        // Later entries replace earlier ones
+        let tar = tar_with(&[("a.luau", b"return 1"), ("./a.luau", b"return 2")]);
+        let files = files_from_tar(&tar, limits, &assets).unwrap();
+        assert_eq!(files.files.get("a.luau").map(|f| &f[..]), Some(&b"return 2"[..]));
+
+        assert!(files_from_tar(&tar_with(&[("../init.luau", b"")]), limits, &assets).is_err());
+        assert!(files_from_tar(&tar_with(&[("/init.luau", b"")]), limits, &assets).is_err());
+
+        let tar = tar_with(&[("a.luau", b"1"), ("b.luau", b"2")]);
+        assert!(files_from_tar(&tar, TarVfsLimits { max_files: 1, ..limits }, &assets).is_err());
+
+        let tar = tar_with(&[("a.luau", b"return 12345")]);
+        assert!(files_from_tar(&tar, TarVfsLimits { max_file_size: 4, ..limits }, &assets).is_err());
+        assert!(files_from_tar(&tar, TarVfsLimits { max_total_size: 4, ..limits }, &assets).is_err());
+    }
+}
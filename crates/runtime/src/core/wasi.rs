@@ -0,0 +1,628 @@
+//! Optional WASI (preview 1) support for WASM workers
+//!
+//! Workers never get access to the host filesystem, clocks or environment. Instead, files are
+//! served from a read-only in-memory directory mounted at ``/``, stdout/stderr are captured into
+//! bounded buffers readable from Luau and the clocks are virtual.
+
+use std::any::Any;
+use std::collections::{BTreeMap, HashMap};
+use std::io::{IoSliceMut, SeekFrom, Write};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use mluau::prelude::*;
+use wasi_common::clocks::{WasiClocks, WasiMonotonicClock, WasiSystemClock};
+use wasi_common::dir::{OpenResult, ReaddirCursor, ReaddirEntity, WasiDir};
+use wasi_common::file::{FdFlags, FileType, Filestat, OFlags, WasiFile};
+use wasi_common::pipe::{ReadPipe, WritePipe};
+use wasi_common::{Error, ErrorExt, WasiCtx};
+
+use crate::core::typesext::MemoryVfs;
+use crate::primitives::blob::blob_ref;
+
+/// Default (and maximum) number of bytes captured from each of stdout and stderr
+pub const MAX_CAPTURED_OUTPUT: usize = 1024 * 1024;
+
+/// Resolution of the virtual clocks, coarse enough to not be useful as a timer for side channels
+const CLOCK_RESOLUTION: Duration = Duration::from_millis(1);
+
+/// Options for the WASI environment of a worker
+#[derive(Debug, Clone)]
+pub struct WasiOptions {
+    pub args: Vec<String>,
+    pub env: Vec<(String, String)>,
+    /// Files mounted read-only at ``/``, keyed by their path relative to the root
+    pub files: HashMap<String, Arc<[u8]>>,
+    pub stdin: Vec<u8>,
+    /// The time the virtual system clock starts at, the unix epoch by default so the host's
+    /// time never leaks to the worker unless asked for
+    pub start_time: SystemTime,
+    /// Whether the virtual clocks stay at their start time instead of advancing
+    pub frozen_time: bool,
+    /// Maximum number of bytes captured from each of stdout and stderr
+    pub max_output: usize,
+}
+
+impl Default for WasiOptions {
+    fn default() -> Self {
+        Self {
+            args: Vec::new(),
+            env: Vec::new(),
+            files: HashMap::new(),
+            stdin: Vec::new(),
+            start_time: SystemTime::UNIX_EPOCH,
+            frozen_time: false,
+            max_output: MAX_CAPTURED_OUTPUT,
+        }
+    }
+}
+
+impl WasiOptions {
+    /// Parses ``{ args, env, files, stdin, time, frozentime, maxoutput }``
+    ///
+    /// ``files`` may be a MemoryVfs or a table of paths to strings/buffers
+    pub fn from_lua_table(opts: &LuaTable) -> LuaResult<Self> {
+        let mut wasi = Self::default();
+
+        if let Some(args) = opts.get::<Option<Vec<String>>>("args")? {
+            wasi.args = args;
+        }
+
+        if let Some(env) = opts.get::<Option<HashMap<String, String>>>("env")? {
+            wasi.env = env.into_iter().collect();
+            wasi.env.sort();
+        }
+
+        match opts.get::<LuaValue>("files")? {
+            LuaValue::Nil => {}
+            LuaValue::UserData(ud) => {
+                let vfs = ud
+                    .borrow::<MemoryVfs>()
+                    .map_err(|_| LuaError::external("files must be a MemoryVfs or a table of files"))?;
+                if vfs.from_opaque {
+                    return Err(LuaError::external("An opaque MemoryVfs cannot be exposed to WASM"));
+                }
+                for (path, contents) in vfs.data.iter() {
+                    wasi.add_file(path, contents.as_bytes())?;
+                }
+            }
+            LuaValue::Table(files) => {
+                for pair in files.pairs::<String, LuaValue>() {
+                    let (path, contents) = pair?;
+                    blob_ref(&contents, |b| wasi.add_file(&path, b))??;
+                }
+            }
+            _ => return Err(LuaError::external("files must be a MemoryVfs or a table of files")),
+        }
+
+        let stdin = opts.get::<LuaValue>("stdin")?;
+        if !stdin.is_nil() {
+            wasi.stdin = blob_ref(&stdin, |b| b.to_vec())?;
+        }
+
+        if let Some(time) = opts.get::<Option<f64>>("time")? {
+            wasi.start_time = Duration::try_from_secs_f64(time)
+                .ok()
+                .and_then(|d| SystemTime::UNIX_EPOCH.checked_add(d))
+                .ok_or_else(|| LuaError::external("time must be a non-negative unix timestamp in range"))?;
+        }
+
+        if let Some(frozen) = opts.get::<Option<bool>>("frozentime")? {
+            wasi.frozen_time = frozen;
+        }
+
+        if let Some(max_output) = opts.get::<Option<usize>>("maxoutput")? {
+            wasi.max_output = max_output.min(MAX_CAPTURED_OUTPUT);
+        }
+
+        Ok(wasi)
+    }
+
+    fn add_file(&mut self, path: &str, contents: &[u8]) -> LuaResult<()> {
+        let Some(path) = normalize_path("", path) else {
+            return Err(LuaError::external(format!("Invalid file path '{path}'")));
+        };
+        if path.is_empty() {
+            return Err(LuaError::external("File paths cannot be empty"));
+        }
+
+        self.files.insert(path, contents.into());
+        Ok(())
+    }
+
+    /// Builds the WASI context, returning it along with the captured stdout and stderr
+    pub fn build(self) -> Result<(WasiCtx, WasiOutput), Error> {
+        let clock = VirtualClock::new(self.start_time, self.frozen_time);
+
+        let mut ctx = WasiCtx::new(
+            wasi_common::sync::random_ctx(),
+            WasiClocks::new().with_system(clock).with_monotonic(clock),
+            wasi_common::tokio::sched::sched_ctx(),
+            wasi_common::Table::new(),
+        );
+
+        for arg in &self.args {
+            ctx.push_arg(arg).map_err(|e| Error::invalid_argument().context(e.to_string()))?;
+        }
+        for (k, v) in &self.env {
+            ctx.push_env(k, v).map_err(|e| Error::invalid_argument().context(e.to_string()))?;
+        }
+
+        let output = WasiOutput {
+            stdout: CapturedOutput::new(self.max_output),
+            stderr: CapturedOutput::new(self.max_output),
+        };
+
+        ctx.set_stdin(Box::new(ReadPipe::from(self.stdin)));
+        ctx.set_stdout(Box::new(WritePipe::new(output.stdout.clone())));
+        ctx.set_stderr(Box::new(WritePipe::new(output.stderr.clone())));
+
+        let files: BTreeMap<String, Arc<[u8]>> = self.files.into_iter().collect();
+        ctx.push_preopened_dir(
+            Box::new(MemoryDir {
+                files: Arc::new(files),
+                prefix: String::new(),
+            }),
+            "/",
+        )?;
+
+        Ok((ctx, output))
+    }
+}
+
+/// The captured stdout and stderr of a worker
+#[derive(Clone)]
+pub struct WasiOutput {
+    pub stdout: CapturedOutput,
+    pub stderr: CapturedOutput,
+}
+
+struct CapturedInner {
+    data: Vec<u8>,
+    limit: usize,
+    truncated: bool,
+}
+
+/// A bounded in-memory sink. Output past the limit is dropped (the guest is not told, but
+/// ``take`` reports it)
+#[derive(Clone)]
+pub struct CapturedOutput {
+    inner: Arc<Mutex<CapturedInner>>,
+}
+
+impl CapturedOutput {
+    fn new(limit: usize) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(CapturedInner {
+                data: Vec::new(),
+                limit,
+                truncated: false,
+            })),
+        }
+    }
+
+    /// Takes the output captured so far, returning it and whether any output was dropped
+    pub fn take(&self) -> (Vec<u8>, bool) {
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        let truncated = std::mem::take(&mut inner.truncated);
+        (std::mem::take(&mut inner.data), truncated)
+    }
+}
+
+impl Write for CapturedOutput {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        let available = inner.limit.saturating_sub(inner.data.len());
+        if available < buf.len() {
+            inner.truncated = true;
+        }
+        inner.data.extend_from_slice(&buf[..available.min(buf.len())]);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A clock starting at a configurable time which only advances while the worker exists
+///
+/// Also backs the ``time`` interface of WASM components
+#[derive(Clone, Copy)]
+pub(crate) struct VirtualClock {
+    start: SystemTime,
+    /// When the clock was created. The monotonic clock is reported relative to its value at
+    /// creation, so the worker always sees it starting at zero rather than the host's uptime
+    base: Instant,
+    frozen: bool,
+}
+
+impl VirtualClock {
+    pub(crate) fn new(start: SystemTime, frozen: bool) -> Self {
+        Self {
+            start,
+            base: Instant::now(),
+            frozen,
+        }
+    }
+
+    /// The current time of the virtual system clock
+    pub(crate) fn system_time(&self) -> SystemTime {
+        self.start.checked_add(self.elapsed()).unwrap_or(self.start)
+    }
+
+    /// Time elapsed since the clock was created, in steps of ``CLOCK_RESOLUTION``
+    pub(crate) fn elapsed(&self) -> Duration {
+        if self.frozen {
+            return Duration::ZERO;
+        }
+
+        let elapsed = self.base.elapsed();
+        elapsed - Duration::from_nanos((elapsed.as_nanos() % CLOCK_RESOLUTION.as_nanos()) as u64)
+    }
+}
+
+impl WasiSystemClock for VirtualClock {
+    fn resolution(&self) -> Duration {
+        CLOCK_RESOLUTION
+    }
+
+    fn now(&self, _precision: Duration) -> cap_std::time::SystemTime {
+        cap_std::time::SystemTime::from_std(self.system_time())
+    }
+}
+
+impl WasiMonotonicClock for VirtualClock {
+    fn resolution(&self) -> Duration {
+        CLOCK_RESOLUTION
+    }
+
+    fn now(&self, _precision: Duration) -> cap_std::time::Instant {
+        cap_std::time::Instant::from_std(self.base + self.elapsed())
+    }
+}
+
+/// Normalizes ``path`` relative to the directory ``prefix`` (both relative to the root)
+///
+/// Returns ``None`` if the path escapes the root
+fn normalize_path(prefix: &str, path: &str) -> Option<String> {
+    let mut components: Vec<&str> = if path.starts_with('/') {
+        Vec::new()
+    } else {
+        prefix.split('/').filter(|c| !c.is_empty()).collect()
+    };
+
+    for component in path.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => {
+                components.pop()?;
+            }
+            c => components.push(c),
+        }
+    }
+
+    Some(components.join("/"))
+}
+
+fn filestat(filetype: FileType, size: u64) -> Filestat {
+    Filestat {
+        device_id: 0,
+        inode: 0,
+        filetype,
+        nlink: 1,
+        size,
+        atim: None,
+        mtim: None,
+        ctim: None,
+    }
+}
+
+/// A read-only directory of an in-memory file tree
+struct MemoryDir {
+    files: Arc<BTreeMap<String, Arc<[u8]>>>,
+    /// Path of this directory relative to the root, empty for the root itself
+    prefix: String,
+}
+
+impl MemoryDir {
+    fn is_dir(&self, path: &str) -> bool {
+        if path.is_empty() {
+            return true;
+        }
+
+        let dir = format!("{path}/");
+        self.files.range(dir.clone()..).next().is_some_and(|(k, _)| k.starts_with(&dir))
+    }
+
+    fn resolve(&self, path: &str) -> Result<String, Error> {
+        normalize_path(&self.prefix, path).ok_or_else(|| Error::perm().context("path escapes the root"))
+    }
+
+    /// The entries directly inside this directory
+    fn entries(&self) -> BTreeMap<String, FileType> {
+        let dir = if self.prefix.is_empty() { String::new() } else { format!("{}/", self.prefix) };
+        let mut entries = BTreeMap::new();
+        for path in self.files.range(dir.clone()..).map(|(k, _)| k).take_while(|k| k.starts_with(&dir)) {
+            match path[dir.len()..].split_once('/') {
+                Some((name, _)) => entries.insert(name.to_string(), FileType::Directory),
+                None => entries.insert(path[dir.len()..].to_string(), FileType::RegularFile),
+            };
+        }
+        entries
+    }
+}
+
+#[async_trait::async_trait]
+impl WasiDir for MemoryDir {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    async fn open_file(
+        &self,
+        _symlink_follow: bool,
+        path: &str,
+        oflags: OFlags,
+        _read: bool,
+        write: bool,
+        _fdflags: FdFlags,
+    ) -> Result<OpenResult, Error> {
+        if write || oflags.intersects(OFlags::CREATE | OFlags::TRUNCATE | OFlags::EXCLUSIVE) {
+            return Err(Error::perm().context("the filesystem is read-only"));
+        }
+
+        let path = self.resolve(path)?;
+        if let Some(data) = self.files.get(&path) {
+            if oflags.contains(OFlags::DIRECTORY) {
+                return Err(Error::not_dir());
+            }
+
+            return Ok(OpenResult::File(Box::new(MemoryFile {
+                data: data.clone(),
+                position: Mutex::new(0),
+            })));
+        }
+
+        if self.is_dir(&path) {
+            return Ok(OpenResult::Dir(Box::new(MemoryDir {
+                files: self.files.clone(),
+                prefix: path,
+            })));
+        }
+
+        Err(Error::not_found())
+    }
+
+    async fn readdir(
+        &self,
+        cursor: ReaddirCursor,
+    ) -> Result<Box<dyn Iterator<Item = Result<ReaddirEntity, Error>> + Send>, Error> {
+        let entries = [(".".to_string(), FileType::Directory), ("..".to_string(), FileType::Directory)]
+            .into_iter()
+            .chain(self.entries())
+            .enumerate()
+            .skip(u64::from(cursor) as usize)
+            .map(|(i, (name, filetype))| {
+                Ok(ReaddirEntity {
+                    next: ReaddirCursor::from(i as u64 + 1),
+                    inode: 0,
+                    name,
+                    filetype,
+                })
+            })
+            .collect::<Vec<_>>();
+
+        Ok(Box::new(entries.into_iter()))
+    }
+
+    async fn get_filestat(&self) -> Result<Filestat, Error> {
+        Ok(filestat(FileType::Directory, 0))
+    }
+
+    async fn get_path_filestat(&self, path: &str, _follow_symlinks: bool) -> Result<Filestat, Error> {
+        let path = self.resolve(path)?;
+        if let Some(data) = self.files.get(&path) {
+            return Ok(filestat(FileType::RegularFile, data.len() as u64));
+        }
+
+        if self.is_dir(&path) {
+            return Ok(filestat(FileType::Directory, 0));
+        }
+
+        Err(Error::not_found())
+    }
+}
+
+/// A read-only file of a MemoryDir
+struct MemoryFile {
+    data: Arc<[u8]>,
+    position: Mutex<u64>,
+}
+
+impl MemoryFile {
+    fn read_at(&self, bufs: &mut [IoSliceMut<'_>], offset: u64) -> u64 {
+        let mut offset = (offset as usize).min(self.data.len());
+        let start = offset;
+        for buf in bufs {
+            let n = buf.len().min(self.data.len() - offset);
+            buf[..n].copy_from_slice(&self.data[offset..offset + n]);
+            offset += n;
+        }
+        (offset - start) as u64
+    }
+}
+
+#[async_trait::async_trait]
+impl WasiFile for MemoryFile {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    async fn get_filetype(&self) -> Result<FileType, Error> {
+        Ok(FileType::RegularFile)
+    }
+
+    async fn get_filestat(&self) -> Result<Filestat, Error> {
+        Ok(filestat(FileType::RegularFile, self.data.len() as u64))
+    }
+
+    async fn read_vectored<'a>(&self, bufs: &mut [IoSliceMut<'a>]) -> Result<u64, Error> {
+        let mut position = self.position.lock().unwrap_or_else(|e| e.into_inner());
+        let n = self.read_at(bufs, *position);
+        *position += n;
+        Ok(n)
+    }
+
+    async fn read_vectored_at<'a>(&self, bufs: &mut [IoSliceMut<'a>], offset: u64) -> Result<u64, Error> {
+        Ok(self.read_at(bufs, offset))
+    }
+
+    async fn seek(&self, pos: SeekFrom) -> Result<u64, Error> {
+        let mut position = self.position.lock().unwrap_or_else(|e| e.into_inner());
+        let new = match pos {
+            SeekFrom::Start(n) => Some(n),
+            SeekFrom::Current(n) => position.checked_add_signed(n),
+            SeekFrom::End(n) => (self.data.len() as u64).checked_add_signed(n),
+        };
+
+        *position = new.ok_or_else(|| Error::invalid_argument().context("seek before start of file"))?;
+        Ok(*position)
+    }
+
+    fn num_ready_bytes(&self) -> Result<u64, Error> {
+        let position = self.position.lock().unwrap_or_else(|e| e.into_inner());
+        Ok((self.data.len() as u64).saturating_sub(*position))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rt::testutils::run_script;
+    use crate::rt::RuntimeCreateOpts;
+
+    /// Prints a greeting and then a file read from the preopened root, checks that both clocks
+    /// start near zero and exits with code 7. ``main`` traps so only ``_start`` may run
+    const WASI_WAT: &str = r#"
+        (module
+            (import "wasi_snapshot_preview1" "fd_write"
+                (func $fd_write (param i32 i32 i32 i32) (result i32)))
+            (import "wasi_snapshot_preview1" "path_open"
+                (func $path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+            (import "wasi_snapshot_preview1" "fd_read"
+                (func $fd_read (param i32 i32 i32 i32) (result i32)))
+            (import "wasi_snapshot_preview1" "clock_time_get"
+                (func $clock_time_get (param i32 i64 i32) (result i32)))
+            (import "wasi_snapshot_preview1" "proc_exit" (func $proc_exit (param i32)))
+            (memory (export "memory") 1)
+            (data (i32.const 0) "dir/data.txt")
+            (data (i32.const 16) "hello from wasi\n")
+            (func $check_clock (param $id i32)
+                (if (call $clock_time_get (local.get $id) (i64.const 1) (i32.const 136))
+                    (then (call $proc_exit (i32.const 4))))
+                (if (i64.ge_u (i64.load (i32.const 136)) (i64.const 60000000000))
+                    (then (call $proc_exit (i32.const 5)))))
+            (func (export "main") unreachable)
+            (func (export "_start")
+                (i32.store (i32.const 100) (i32.const 16))
+                (i32.store (i32.const 104) (i32.const 16))
+                (drop (call $fd_write (i32.const 1) (i32.const 100) (i32.const 1) (i32.const 108)))
+                (if (call $path_open (i32.const 3) (i32.const 0) (i32.const 0) (i32.const 12)
+                        (i32.const 0) (i64.const 2) (i64.const 0) (i32.const 0) (i32.const 112))
+                    (then (call $proc_exit (i32.const 2))))
+                (i32.store (i32.const 120) (i32.const 256))
+                (i32.store (i32.const 124) (i32.const 64))
+                (if (call $fd_read (i32.load (i32.const 112)) (i32.const 120) (i32.const 1) (i32.const 128))
+                    (then (call $proc_exit (i32.const 3))))
+                (i32.store (i32.const 124) (i32.load (i32.const 128)))
+                (drop (call $fd_write (i32.const 1) (i32.const 120) (i32.const 1) (i32.const 108)))
+                (call $check_clock (i32.const 0))
+                (call $check_clock (i32.const 1))
+                (call $proc_exit (i32.const 7))))
+    "#;
+
+    #[test]
+    fn test_normalize_path() {
+        assert_eq!(normalize_path("", "./a//b.txt").as_deref(), Some("a/b.txt"));
+        assert_eq!(normalize_path("a", "../b").as_deref(), Some("b"));
+        assert_eq!(normalize_path("a", "/b").as_deref(), Some("b"));
+        assert_eq!(normalize_path("a", "../../b"), None);
+    }
+
+    #[test]
+    fn test_memory_dir() {
+        let mut files = BTreeMap::new();
+        files.insert("a.txt".to_string(), Arc::from(&b"a"[..]));
+        files.insert("dir/b.txt".to_string(), Arc::from(&b"bb"[..]));
+        files.insert("dir/sub/c.txt".to_string(), Arc::from(&b"c"[..]));
+        let root = MemoryDir {
+            files: Arc::new(files),
+            prefix: String::new(),
+        };
+
+        assert!(root.is_dir("dir") && root.is_dir("dir/sub") && !root.is_dir("di"));
+        let entries = root.entries();
+        assert_eq!(entries.get("a.txt"), Some(&FileType::RegularFile));
+        assert_eq!(entries.get("dir"), Some(&FileType::Directory));
+        assert_eq!(entries.len(), 2);
+
+        let dir = MemoryDir {
+            files: root.files.clone(),
+            prefix: "dir".to_string(),
+        };
+        assert_eq!(dir.entries().len(), 2);
+        assert_eq!(dir.resolve("../a.txt").unwrap(), "a.txt");
+        assert!(dir.resolve("../../a.txt").is_err());
+
+        let mut out = CapturedOutput::new(3);
+        out.write_all(b"hello").unwrap();
+        assert_eq!(out.take(), (b"hel".to_vec(), true));
+        assert_eq!(out.take(), (Vec::new(), false));
+    }
+
+    #[test]
+    fn test_start_time() {
+        let lua = Lua::new();
+        let opts = |time: f64| {
+            let table = lua.create_table()?;
+            table.set("time", time)?;
+            WasiOptions::from_lua_table(&table)
+        };
+
+        let wasi = opts(1.5).unwrap();
+        assert_eq!(wasi.start_time, SystemTime::UNIX_EPOCH + Duration::from_millis(1500));
+        for time in [-1.0, f64::NAN, f64::INFINITY, 1e300] {
+            assert!(opts(time).is_err(), "{time}");
+        }
+
+        assert_eq!(WasiOptions::default().start_time, SystemTime::UNIX_EPOCH);
+    }
+
+    #[test]
+    fn test_wasi_worker() {
+        let script = r#"
+            return function(guest)
+                local wasm = require"@antiraid/wasm"
+
+                local worker = wasm.newwasm(guest, {
+                    wasi = { files = { ["dir/data.txt"] = buffer.fromstring("file contents") } },
+                })
+                worker:start()
+                local ok, err = pcall(worker.wait, worker)
+                assert(not ok and string.find(tostring(err), "exited with code 7"), tostring(err))
+
+                local stdout, truncated = worker:stdout()
+                assert(buffer.tostring(stdout) == "hello from wasi\nfile contents", buffer.tostring(stdout))
+                assert(truncated == false)
+                local stderr = worker:stderr()
+                assert(buffer.len(stderr) == 0)
+
+                -- Without WASI the imports cannot be satisfied
+                assert(not pcall(wasm.newwasm, guest))
+            end
+        "#;
+
+        run_script(RuntimeCreateOpts::default(), script, |lua| lua.create_string(WASI_WAT)).unwrap();
+    }
+}
@@ -1,11 +1,315 @@
-use wasmtime::{Config, Engine, Store, Linker, Instance, ResourceLimiter};
+use wasmtime::{AsContextMut, Config, Engine, Store, Linker, Instance, ResourceLimiter};
 use mluau::prelude::*;
-use std::sync::{Arc, Mutex, atomic::{AtomicUsize, Ordering}};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::sync::{Arc, LazyLock, Mutex, MutexGuard, atomic::{AtomicU64, AtomicUsize, Ordering}};
+use std::time::{Duration, Instant};
 use tokio::sync::broadcast::{channel as broadcast_channel, Sender as BroadcastSender, Receiver as BroadcastReceiver};
-use tokio::sync::mpsc::{channel as mpsc_channel, Receiver as MpscReceiver};
+use tokio::sync::mpsc::{channel as mpsc_channel, Receiver as MpscReceiver, Sender as MpscSender};
+use tokio::sync::{oneshot, Notify};
 use tokio::sync::Mutex as AsyncMutex;
-use mlua_scheduler::{LuaSchedulerAsyncUserData, LuaSchedulerAsync};
+use tokio_util::sync::CancellationToken;
+use mlua_scheduler::{LuaSchedulerAsyncUserData, LuaSchedulerAsync, taskmgr::SchedulerImpl};
+use serde::de::DeserializeSeed;
+use crate::core::wasi::{CapturedOutput, WasiOptions, WasiOutput};
+use crate::core::wasmregion::{RegionInner, RegionRegistry, SharedRegion, MAX_SHARED_REGIONS};
 use crate::primitives::blob::Blob;
+use crate::rt::runtime::{SyncCell, S};
+use crate::utils::luaserde::{integer_to_lua, Format, LuaValueRef, LuaValueSeed};
+
+/// Maximum number of compiled modules kept in the module cache
+pub const MAX_CACHED_MODULES: usize = 64;
+
+/// The default module namespace host functions are linked under
+pub const DEFAULT_IMPORT_MODULE: &str = "khronos";
+
+/// Maximum size of the MessagePack encoded arguments (or results) of a host function call
+pub const MAX_HOST_CALL_BYTES: usize = 4 * 1024 * 1024;
+
+/// Fuel charged for every host function call, on top of one unit per byte of arguments and results
+const HOST_CALL_FUEL: u64 = 10_000;
+
+/// Maximum number of RPC requests awaiting a reply from a single worker
+pub const MAX_IN_FLIGHT_REQUESTS: usize = 64;
+
+/// Default time to wait for the reply to an RPC request
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How often the engine's epoch is incremented. Running WASM yields to the executor (and checks
+/// whether it was killed or ran out of time) on every tick
+const EPOCH_TICK: Duration = Duration::from_millis(10);
+
+static ENGINE: LazyLock<Result<Engine, String>> = LazyLock::new(|| {
+    let mut config = Config::new();
+    config.async_support(true);
+    config.consume_fuel(true);
+    config.epoch_interruption(true);
+    Engine::new(&config).map_err(|e| e.to_string())
+});
+
+struct EpochState {
+    /// Number of live stores using the shared engine
+    stores: usize,
+    /// Whether the epoch thread is running
+    ticking: bool,
+}
+
+static EPOCH_STATE: Mutex<EpochState> = Mutex::new(EpochState { stores: 0, ticking: false });
+
+/// Keeps the thread incrementing the shared engine's epoch running. Every store holds one and
+/// the thread exits once the last is dropped (to be started again by the next store)
+pub(crate) struct EpochGuard(());
+
+impl EpochGuard {
+    pub(crate) fn new() -> wasmtime::Result<Self> {
+        let engine = shared_engine()?;
+        let mut state = EPOCH_STATE.lock().unwrap_or_else(|e| e.into_inner());
+        if !state.ticking {
+            std::thread::Builder::new()
+                .name("khronos-wasm-epoch".to_string())
+                .spawn(move || loop {
+                    std::thread::sleep(EPOCH_TICK);
+                    let mut state = EPOCH_STATE.lock().unwrap_or_else(|e| e.into_inner());
+                    if state.stores == 0 {
+                        state.ticking = false;
+                        return;
+                    }
+                    drop(state);
+                    engine.increment_epoch();
+                })
+                .map_err(|e| wasmtime::Error::msg(format!("Failed to spawn epoch thread: {e}")))?;
+            state.ticking = true;
+        }
+
+        state.stores += 1;
+        Ok(Self(()))
+    }
+}
+
+impl Drop for EpochGuard {
+    fn drop(&mut self) {
+        EPOCH_STATE.lock().unwrap_or_else(|e| e.into_inner()).stores -= 1;
+    }
+}
+
+static MODULE_CACHE: LazyLock<WasmModuleCache> = LazyLock::new(|| WasmModuleCache::new(MAX_CACHED_MODULES));
+
+/// Returns the engine shared by all runtimes
+///
+/// Compiled (and precompiled) modules can only be used with the engine they were compiled for,
+/// so a single engine is used to allow sharing modules between runtimes
+pub fn shared_engine() -> wasmtime::Result<Engine> {
+    ENGINE.clone().map_err(wasmtime::Error::msg)
+}
+
+/// The SHA-256 hash of a WASM binary
+pub type ModuleHash = [u8; 32];
+
+pub fn module_hash(wasm_bytes: &[u8]) -> ModuleHash {
+    Sha256::digest(wasm_bytes).into()
+}
+
+/// Compiles a WASM binary into a serialized module which can later be loaded with
+/// ``WasmModuleCache::insert_precompiled`` to skip compilation entirely
+pub fn precompile(wasm_bytes: &[u8]) -> wasmtime::Result<Vec<u8>> {
+    shared_engine()?.precompile_module(wasm_bytes)
+}
+
+struct CompiledCacheInner<T> {
+    entries: HashMap<ModuleHash, (T, u64)>,
+    max_entries: usize,
+    tick: u64,
+}
+
+impl<T> CompiledCacheInner<T> {
+    /// Evicts the least recently used entries until at most max_entries are left
+    fn evict(&mut self) {
+        while self.entries.len() > self.max_entries {
+            let Some(oldest) = self.entries.iter().min_by_key(|(_, (_, t))| *t).map(|(k, _)| *k) else {
+                break;
+            };
+            self.entries.remove(&oldest);
+        }
+    }
+}
+
+/// A least recently used cache of compiled modules (or components) keyed by the hash of their WASM binary
+pub(crate) struct CompiledCache<T> {
+    inner: Mutex<CompiledCacheInner<T>>,
+}
+
+impl<T: Clone> CompiledCache<T> {
+    pub(crate) fn new(max_entries: usize) -> Self {
+        Self {
+            inner: Mutex::new(CompiledCacheInner {
+                entries: HashMap::new(),
+                max_entries,
+                tick: 0,
+            }),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, CompiledCacheInner<T>> {
+        // The cache is always left in a consistent state, so a poisoned lock is still usable
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub(crate) fn get(&self, hash: &ModuleHash) -> Option<T> {
+        let mut inner = self.lock();
+        inner.tick += 1;
+        let tick = inner.tick;
+        let (entry, last_used) = inner.entries.get_mut(hash)?;
+        *last_used = tick;
+        Some(entry.clone())
+    }
+
+    pub(crate) fn insert(&self, hash: ModuleHash, entry: T) {
+        let mut inner = self.lock();
+        if inner.max_entries == 0 {
+            return;
+        }
+
+        inner.tick += 1;
+        let tick = inner.tick;
+        inner.entries.insert(hash, (entry, tick));
+        inner.evict();
+    }
+
+    /// Returns the cached entry for a WASM binary, compiling (and caching) it with ``compile`` if needed
+    pub(crate) fn get_or_insert_with(
+        &self,
+        wasm_bytes: &[u8],
+        compile: impl FnOnce(&Engine, &[u8]) -> wasmtime::Result<T>,
+    ) -> wasmtime::Result<(ModuleHash, T)> {
+        let hash = module_hash(wasm_bytes);
+        if let Some(entry) = self.get(&hash) {
+            return Ok((hash, entry));
+        }
+
+        // Compile without holding the lock so other runtimes are not blocked
+        let entry = compile(&shared_engine()?, wasm_bytes)?;
+        self.insert(hash, entry.clone());
+        Ok((hash, entry))
+    }
+
+    pub(crate) fn set_max_entries(&self, max_entries: usize) {
+        let mut inner = self.lock();
+        inner.max_entries = max_entries;
+        inner.evict();
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.lock().entries.len()
+    }
+
+    pub(crate) fn clear(&self) {
+        self.lock().entries.clear();
+    }
+}
+
+/// A process-wide cache of compiled modules keyed by the hash of their WASM binary
+pub struct WasmModuleCache {
+    cache: CompiledCache<wasmtime::Module>,
+}
+
+impl WasmModuleCache {
+    pub fn new(max_modules: usize) -> Self {
+        Self { cache: CompiledCache::new(max_modules) }
+    }
+
+    /// Returns the cache shared by all runtimes in the process
+    pub fn global() -> &'static WasmModuleCache {
+        &MODULE_CACHE
+    }
+
+    pub fn get(&self, hash: &ModuleHash) -> Option<wasmtime::Module> {
+        self.cache.get(hash)
+    }
+
+    /// Returns the compiled module for a WASM binary, compiling (and caching) it if needed
+    pub fn get_or_compile(&self, wasm_bytes: &[u8]) -> wasmtime::Result<WasmModule> {
+        let (hash, module) = self.cache.get_or_insert_with(wasm_bytes, |engine, bytes| wasmtime::Module::new(engine, bytes))?;
+        Ok(WasmModule { module, hash })
+    }
+
+    /// Loads a module serialized with ``precompile`` (or ``WasmModule::serialize``) into the cache
+    /// under the hash of its original WASM binary, so that compiling that binary is skipped
+    ///
+    /// # Safety
+    ///
+    /// ``serialized`` must come from trusted storage. wasmtime performs only basic checks on
+    /// precompiled modules and loading an arbitrary or tampered artifact is undefined behaviour
+    pub unsafe fn insert_precompiled(&self, hash: ModuleHash, serialized: &[u8]) -> wasmtime::Result<WasmModule> {
+        let module = wasmtime::Module::deserialize(&shared_engine()?, serialized)?;
+        self.cache.insert(hash, module.clone());
+        Ok(WasmModule { module, hash })
+    }
+
+    /// Sets the maximum number of cached modules, evicting modules if needed
+    pub fn set_max_modules(&self, max_modules: usize) {
+        self.cache.set_max_entries(max_modules);
+    }
+
+    pub fn len(&self) -> usize {
+        self.cache.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn clear(&self) {
+        self.cache.clear();
+    }
+}
+
+/// A compiled WASM module that can be instantiated any number of times
+#[derive(Clone)]
+pub struct WasmModule {
+    pub module: wasmtime::Module,
+    pub hash: ModuleHash,
+}
+
+impl WasmModule {
+    /// Compiles a WASM binary, reusing the cached module if it was compiled before
+    pub fn compile(wasm_bytes: &[u8]) -> LuaResult<Self> {
+        WasmModuleCache::global()
+            .get_or_compile(wasm_bytes)
+            .map_err(LuaError::external)
+    }
+
+    /// Serializes the compiled module for use with ``WasmModuleCache::insert_precompiled``
+    pub fn serialize(&self) -> wasmtime::Result<Vec<u8>> {
+        self.module.serialize()
+    }
+
+    pub fn hash_hex(&self) -> String {
+        self.hash.iter().map(|b| format!("{b:02x}")).collect()
+    }
+}
+
+impl LuaUserData for WasmModule {
+    fn add_fields<F: LuaUserDataFields<Self>>(fields: &mut F) {
+        fields.add_meta_field(LuaMetaMethod::Type, "WasmModule");
+        fields.add_field_method_get("hash", |_, this| Ok(this.hash_hex()));
+    }
+
+    fn add_methods<M: LuaUserDataMethods<Self>>(methods: &mut M) {
+        // Returns the names of the module's imports as "module.name"
+        methods.add_method("imports", |_, this, ()| {
+            Ok(this
+                .module
+                .imports()
+                .map(|i| format!("{}.{}", i.module(), i.name()))
+                .collect::<Vec<_>>())
+        });
+
+        methods.add_method("exports", |_, this, ()| {
+            Ok(this.module.exports().map(|e| e.name().to_string()).collect::<Vec<_>>())
+        });
+    }
+}
 
 #[derive(Clone)]
 pub struct SharedWasmLimits {
@@ -13,17 +317,40 @@ pub struct SharedWasmLimits {
     pub allocated_memory: Arc<AtomicUsize>,
 }
 
-impl ResourceLimiter for SharedWasmLimits {
+/// The memory limits of a single instance, which also counts towards the shared limit
+///
+/// The instance's memory is returned to the shared limit when its store is dropped
+pub(crate) struct InstanceLimits {
+    shared: SharedWasmLimits,
+    max_memory: usize,
+    allocated: usize,
+}
+
+impl InstanceLimits {
+    pub(crate) fn new(shared: SharedWasmLimits, max_memory: Option<usize>) -> Self {
+        Self {
+            max_memory: max_memory.unwrap_or(shared.max_memory),
+            shared,
+            allocated: 0,
+        }
+    }
+}
+
+impl ResourceLimiter for InstanceLimits {
     fn memory_growing(&mut self, current: usize, desired: usize, _maximum: Option<usize>) -> wasmtime::Result<bool> {
-        let addition = desired - current;
-        
-        let old = self.allocated_memory.fetch_add(addition, Ordering::SeqCst);
-        if old + addition > self.max_memory {
+        let addition = desired.saturating_sub(current);
+        if self.allocated + addition > self.max_memory {
+            return Ok(false);
+        }
+
+        let old = self.shared.allocated_memory.fetch_add(addition, Ordering::SeqCst);
+        if old + addition > self.shared.max_memory {
             // Revert the allocation if it exceeded the limit
-            self.allocated_memory.fetch_sub(addition, Ordering::SeqCst);
+            self.shared.allocated_memory.fetch_sub(addition, Ordering::SeqCst);
             return Ok(false);
         }
-        
+
+        self.allocated += addition;
         Ok(true)
     }
 
@@ -32,17 +359,425 @@ impl ResourceLimiter for SharedWasmLimits {
     }
 }
 
+impl Drop for InstanceLimits {
+    fn drop(&mut self) {
+        self.shared.allocated_memory.fetch_sub(self.allocated, Ordering::SeqCst);
+    }
+}
+
+/// The time limits of the runtime a worker was created in, shared with the runtime so that
+/// changes to them (e.g. through ``KhronosRuntime::set_time_limit``) apply to running workers
+#[derive(Clone, Default)]
+pub struct WasmTimeLimits {
+    /// Maximum wall clock time of a slice
+    pub time_limit: SyncCell<Option<Duration>>,
+    /// The time the runtime's current execution stops at. WASM running past it traps, just as
+    /// Luau would error
+    pub stop_time: SyncCell<Option<Instant>>,
+}
+
+/// Fuel and time given to an instance between yield points (a slice)
+pub(crate) struct ExecutionBudget {
+    max_fuel_per_slice: u64,
+    /// Total fuel the instance may use over its lifetime
+    max_total_fuel: Option<u64>,
+    time_limits: WasmTimeLimits,
+    /// Fuel the current slice started with
+    slice_fuel: u64,
+    slice_deadline: Option<Instant>,
+    fuel_used: u64,
+}
+
+impl ExecutionBudget {
+    pub(crate) fn new(max_fuel_per_slice: u64, max_total_fuel: Option<u64>, time_limits: WasmTimeLimits) -> Self {
+        Self {
+            max_fuel_per_slice,
+            max_total_fuel,
+            time_limits,
+            slice_fuel: 0,
+            slice_deadline: None,
+            fuel_used: 0,
+        }
+    }
+
+    /// Starts a new slice given the fuel left in the store, accounting the fuel used in the
+    /// previous one against the total fuel cap. Returns the fuel the store should be given
+    pub(crate) fn next_slice(&mut self, remaining: u64) -> wasmtime::Result<u64> {
+        self.fuel_used += self.slice_fuel.saturating_sub(remaining);
+
+        let mut fuel = self.max_fuel_per_slice;
+        if let Some(max_total_fuel) = self.max_total_fuel {
+            let left = max_total_fuel.saturating_sub(self.fuel_used);
+            if left == 0 {
+                return Err(wasmtime::Trap::OutOfFuel.into());
+            }
+            fuel = fuel.min(left);
+        }
+
+        self.slice_fuel = fuel;
+        self.slice_deadline = self.time_limits.time_limit.get().and_then(|t| Instant::now().checked_add(t));
+        Ok(fuel)
+    }
+
+    /// Whether the current slice has run past its deadline (or the runtime's execution past its
+    /// stop time)
+    pub(crate) fn expired(&self) -> bool {
+        let now = Instant::now();
+        self.slice_deadline.is_some_and(|d| now > d) || self.time_limits.stop_time.get().is_some_and(|d| now > d)
+    }
+}
+
+/// Starts a new slice (see ``ExecutionBudget::next_slice``)
+fn new_slice(mut ctx: wasmtime::StoreContextMut<'_, WasmContext>) -> wasmtime::Result<()> {
+    let remaining = ctx.get_fuel()?;
+    let fuel = ctx.data_mut().budget.next_slice(remaining)?;
+    ctx.set_fuel(fuel)?;
+    Ok(())
+}
+
+/// The execution state of a worker
+#[derive(Debug, Clone)]
+pub enum WorkerStatus {
+    /// Not started (exports may still be called)
+    Idle,
+    Running,
+    Finished,
+    Trapped(String),
+    Killed,
+}
+
+impl WorkerStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Idle => "idle",
+            Self::Running => "running",
+            Self::Finished => "finished",
+            Self::Trapped(_) => "trapped",
+            Self::Killed => "killed",
+        }
+    }
+}
+
+/// Awaits ``fut`` in a host function, trapping if the worker is killed first
+pub(crate) async fn unless_killed<F: Future>(killed: &CancellationToken, fut: F) -> wasmtime::Result<F::Output> {
+    killed
+        .run_until_cancelled(fut)
+        .await
+        .ok_or_else(|| wasmtime::Error::msg("WASM was killed"))
+}
+
 pub struct WasmContext {
-    limits: SharedWasmLimits,
+    limits: InstanceLimits,
+    budget: ExecutionBudget,
+    killed: CancellationToken,
     next_msg: Option<bytes::Bytes>,
     luau_rx: Option<BroadcastReceiver<bytes::Bytes>>,
+    /// The results of the last host function call, until copied out with ``__result_into``
+    host_result: Option<Vec<u8>>,
+    rpc: RpcQueue,
+    /// The awaited request, until copied out with ``rpc_take``
+    next_request: Option<(u64, bytes::Bytes)>,
+    regions: RegionRegistry,
+    region_tx: MpscSender<Arc<RegionInner>>,
+    /// Only set (and linked) if the worker was created with WASI enabled
+    wasi: Option<wasi_common::WasiCtx>,
+    _epoch: EpochGuard,
+}
+
+impl Drop for WasmContext {
+    fn drop(&mut self) {
+        // The store drops its data before freeing the instance's memory
+        self.regions.revoke_all();
+    }
+}
+
+/// The instance and store of a worker, taken out while it is running
+struct Runner {
+    instance: Instance,
+    store: Store<WasmContext>,
+}
+
+/// An argument to an exported function
+enum CallArg {
+    Int(i64),
+    Float(f64),
+    /// Copied into guest memory through the exported allocator and passed as ``(ptr: i32, len: i32)``
+    Bytes(bytes::Bytes),
+}
+
+impl CallArg {
+    fn from_lua(value: LuaValue, lua: &Lua) -> LuaResult<Self> {
+        match value {
+            LuaValue::Integer(i) | LuaValue::Int64(i) => Ok(Self::Int(i)),
+            LuaValue::Number(n) => Ok(Self::Float(n)),
+            LuaValue::Boolean(b) => Ok(Self::Int(b as i64)),
+            v @ (LuaValue::String(_) | LuaValue::Buffer(_)) => Ok(Self::Bytes(Blob::from_lua(v, lua)?.0)),
+            v => Err(LuaError::external(format!("Cannot pass a {} to WASM", v.type_name()))),
+        }
+    }
+}
+
+fn val_type_name(ty: &wasmtime::ValType) -> &'static str {
+    match ty {
+        wasmtime::ValType::I32 => "i32",
+        wasmtime::ValType::I64 => "i64",
+        wasmtime::ValType::F32 => "f32",
+        wasmtime::ValType::F64 => "f64",
+        wasmtime::ValType::V128 => "v128",
+        _ => "ref",
+    }
+}
+
+/// Names of the exports tried (in order) when copying byte buffers into guest memory, along with
+/// the matching deallocator. Allocators have the signature ``(len: i32) -> i32`` and deallocators
+/// ``(ptr: i32, len: i32)``, except for ``free`` which only takes the pointer
+const ALLOCATOR_EXPORTS: [(&str, &str); 3] = [("khronos_alloc", "khronos_free"), ("alloc", "dealloc"), ("malloc", "free")];
+
+impl Runner {
+    /// Calls an exported function, converting the arguments using its signature
+    ///
+    /// Byte buffers copied into guest memory are freed once the call returns. If the guest does
+    /// not export a deallocator, it takes ownership of them instead
+    async fn call(&mut self, name: &str, args: Vec<CallArg>) -> wasmtime::Result<Vec<wasmtime::Val>> {
+        let mut allocations = Vec::new();
+        let res = self.call_with(name, args, &mut allocations).await;
+        if self.store.data().killed.is_cancelled() {
+            return res;
+        }
+
+        let freed = self.free(allocations).await;
+        let results = res?;
+        freed?;
+        Ok(results)
+    }
+
+    async fn call_with(
+        &mut self,
+        name: &str,
+        args: Vec<CallArg>,
+        allocations: &mut Vec<(u32, u32)>,
+    ) -> wasmtime::Result<Vec<wasmtime::Val>> {
+        let func = self
+            .instance
+            .get_func(&mut self.store, name)
+            .ok_or_else(|| wasmtime::Error::msg(format!("No exported function named '{name}'")))?;
+        let ty = func.ty(&self.store);
+        let params = ty.params().collect::<Vec<_>>();
+
+        new_slice(self.store.as_context_mut())?;
+
+        let mut vals = Vec::with_capacity(params.len());
+        for arg in args {
+            let Some(param) = params.get(vals.len()) else {
+                return Err(wasmtime::Error::msg(format!("Too many arguments for '{name}' (expected {})", params.len())));
+            };
+
+            let val = match (arg, param) {
+                (CallArg::Int(i), wasmtime::ValType::I32) => wasmtime::Val::I32(
+                    i32::try_from(i).or_else(|_| u32::try_from(i).map(|u| u as i32))
+                        .map_err(|_| wasmtime::Error::msg(format!("Argument {} is out of range for i32", vals.len() + 1)))?,
+                ),
+                (CallArg::Int(i), wasmtime::ValType::I64) => wasmtime::Val::I64(i),
+                (CallArg::Int(i), wasmtime::ValType::F32) => wasmtime::Val::F32((i as f32).to_bits()),
+                (CallArg::Int(i), wasmtime::ValType::F64) => wasmtime::Val::F64((i as f64).to_bits()),
+                (CallArg::Float(f), wasmtime::ValType::F32) => wasmtime::Val::F32((f as f32).to_bits()),
+                (CallArg::Float(f), wasmtime::ValType::F64) => wasmtime::Val::F64(f.to_bits()),
+                (CallArg::Float(f), wasmtime::ValType::I32) if f.fract() == 0.0 && f >= i32::MIN as f64 && f <= u32::MAX as f64 => {
+                    wasmtime::Val::I32(f as i64 as i32)
+                }
+                (CallArg::Float(f), wasmtime::ValType::I64) if f.fract() == 0.0 && f.abs() <= i64::MAX as f64 => {
+                    wasmtime::Val::I64(f as i64)
+                }
+                (CallArg::Bytes(b), wasmtime::ValType::I32) if matches!(params.get(vals.len() + 1), Some(wasmtime::ValType::I32)) => {
+                    let ptr = self.copy_in(&b, allocations).await?;
+                    vals.push(wasmtime::Val::I32(ptr as i32));
+                    wasmtime::Val::I32(b.len() as i32)
+                }
+                (_, param) => {
+                    return Err(wasmtime::Error::msg(format!(
+                        "Argument {} cannot be converted to {} (byte buffers take an (i32, i32) pair)",
+                        vals.len() + 1,
+                        val_type_name(param)
+                    )))
+                }
+            };
+            vals.push(val);
+        }
+
+        if vals.len() != params.len() {
+            return Err(wasmtime::Error::msg(format!("Expected {} arguments for '{name}', got {}", params.len(), vals.len())));
+        }
+
+        let mut results = vec![wasmtime::Val::I32(0); ty.results().len()];
+        func.call_async(&mut self.store, &vals, &mut results).await?;
+        Ok(results)
+    }
+
+    /// Copies bytes into guest memory using the exported allocator, returning the pointer. The
+    /// allocation is recorded in ``allocations`` so it can be freed after the call
+    async fn copy_in(&mut self, data: &[u8], allocations: &mut Vec<(u32, u32)>) -> wasmtime::Result<u32> {
+        let len = i32::try_from(data.len()).map_err(|_| wasmtime::Error::msg("Byte buffer is too large"))?;
+        let alloc = ALLOCATOR_EXPORTS
+            .iter()
+            .find_map(|(name, _)| self.instance.get_typed_func::<i32, i32>(&mut self.store, name).ok())
+            .ok_or_else(|| wasmtime::Error::msg("Passing byte buffers requires an exported allocator (khronos_alloc, alloc or malloc)"))?;
+        let ptr = alloc.call_async(&mut self.store, len).await? as u32;
+        allocations.push((ptr, len as u32));
+
+        let memory = self
+            .instance
+            .get_memory(&mut self.store, "memory")
+            .or_else(|| self.instance.get_memory(&mut self.store, "mem"))
+            .ok_or_else(|| wasmtime::Error::msg("memory export not found (tried 'memory' and 'mem')"))?;
+        memory.write(&mut self.store, ptr as usize, data)?;
+        Ok(ptr)
+    }
+
+    /// Frees buffers copied in with ``copy_in`` using the deallocator matching the allocator
+    async fn free(&mut self, allocations: Vec<(u32, u32)>) -> wasmtime::Result<()> {
+        if allocations.is_empty() {
+            return Ok(());
+        }
+
+        let Some((_, dealloc)) = ALLOCATOR_EXPORTS
+            .iter()
+            .find(|(alloc, _)| self.instance.get_typed_func::<i32, i32>(&mut self.store, alloc).is_ok())
+        else {
+            return Ok(());
+        };
+
+        if *dealloc == "free" {
+            if let Ok(free) = self.instance.get_typed_func::<i32, ()>(&mut self.store, dealloc) {
+                for (ptr, _) in allocations {
+                    free.call_async(&mut self.store, ptr as i32).await?;
+                }
+            }
+        } else if let Ok(free) = self.instance.get_typed_func::<(i32, i32), ()>(&mut self.store, dealloc) {
+            for (ptr, len) in allocations {
+                free.call_async(&mut self.store, (ptr as i32, len as i32)).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Puts a runner (of a worker or component) back into its slot once it is done, even if the call
+/// panicked or the Luau thread awaiting it was cancelled. Runners of killed workers are dropped instead
+pub(crate) struct RunnerGuard<R> {
+    pub(crate) slot: Arc<Mutex<Option<R>>>,
+    pub(crate) runner: Option<R>,
+    pub(crate) killed: CancellationToken,
+}
+
+impl<R> Drop for RunnerGuard<R> {
+    fn drop(&mut self) {
+        let Some(runner) = self.runner.take() else {
+            return;
+        };
+
+        // Checked under the lock so that kill() cannot miss the runner being put back
+        let mut slot = self.slot.lock().unwrap_or_else(|e| e.into_inner());
+        if !self.killed.is_cancelled() {
+            *slot = Some(runner);
+        }
+    }
+}
+
+#[derive(Default)]
+struct RpcQueueInner {
+    /// Requests not yet taken by the guest, in the order they were made
+    queued: VecDeque<(u64, bytes::Bytes)>,
+    /// Reply senders of the requests awaiting a reply (queued or taken), by message id
+    pending: HashMap<u64, oneshot::Sender<bytes::Bytes>>,
+    closed: bool,
+}
+
+/// The RPC requests made to a worker
+///
+/// Requests that time out are removed from the queue so the guest never sees them, and closing
+/// the queue (once the worker stops) fails every request awaiting a reply
+#[derive(Clone, Default)]
+struct RpcQueue {
+    inner: Arc<Mutex<RpcQueueInner>>,
+    notify: Arc<Notify>,
+}
+
+impl RpcQueue {
+    fn lock(&self) -> MutexGuard<'_, RpcQueueInner> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Queues a request, returning the receiver of its reply
+    fn push(&self, id: u64, payload: bytes::Bytes) -> Result<oneshot::Receiver<bytes::Bytes>, &'static str> {
+        let mut inner = self.lock();
+        if inner.closed {
+            return Err("WASM is not accepting requests");
+        }
+        if inner.pending.len() >= MAX_IN_FLIGHT_REQUESTS {
+            return Err("Too many in-flight requests");
+        }
+
+        let (reply_tx, reply_rx) = oneshot::channel();
+        inner.pending.insert(id, reply_tx);
+        inner.queued.push_back((id, payload));
+        self.notify.notify_one();
+        Ok(reply_rx)
+    }
+
+    /// Forgets a request (e.g. once it timed out), whether or not the guest took it
+    fn cancel(&self, id: u64) {
+        let mut inner = self.lock();
+        inner.pending.remove(&id);
+        inner.queued.retain(|(queued_id, _)| *queued_id != id);
+    }
+
+    /// Awaits the next request, returning None once the queue is closed
+    async fn next(&self) -> Option<(u64, bytes::Bytes)> {
+        loop {
+            {
+                let mut inner = self.lock();
+                if let Some(request) = inner.queued.pop_front() {
+                    return Some(request);
+                }
+                if inner.closed {
+                    return None;
+                }
+            }
+
+            // notify_one stores a permit if nothing is waiting, so a request pushed in between is not missed
+            self.notify.notified().await;
+        }
+    }
+
+    /// Takes the reply sender of a request, if it is still awaiting a reply
+    fn take_reply(&self, id: u64) -> Option<oneshot::Sender<bytes::Bytes>> {
+        self.lock().pending.remove(&id)
+    }
+
+    /// Stops accepting requests and fails every request awaiting a reply
+    fn close(&self) {
+        let mut inner = self.lock();
+        inner.closed = true;
+        inner.queued.clear();
+        inner.pending.clear();
+        self.notify.notify_one();
+    }
 }
 
 pub struct WasmState {
-    runner: Option<(Instance, Store<WasmContext>, u64)>,
+    module: wasmtime::Module,
+    /// Taken out while the worker is running (through start or call)
+    runner: Arc<Mutex<Option<Runner>>>,
+    status: Arc<Mutex<WorkerStatus>>,
+    killed: CancellationToken,
+    wasi_output: Option<WasiOutput>,
     join_handle: Mutex<Option<tokio::task::JoinHandle<wasmtime::Result<()>>>>,
     luau_tx: BroadcastSender<bytes::Bytes>, 
     wasm_rx: Arc<AsyncMutex<MpscReceiver<bytes::Bytes>>>,
+    rpc: RpcQueue,
+    next_request_id: AtomicU64,
+    regions: RegionRegistry,
+    region_rx: Arc<AsyncMutex<MpscReceiver<Arc<RegionInner>>>>,
 }
 
 impl LuaUserData for WasmState {
@@ -53,6 +788,38 @@ impl LuaUserData for WasmState {
             Ok(())
         });
 
+        // Sends a request to WASM and waits for the matching reply (async)
+        //
+        // Requests are delivered in order and never dropped. At most MAX_IN_FLIGHT_REQUESTS may await
+        // a reply at once and each waits up to timeout seconds (30 by default). Requests that time
+        // out are withdrawn, and requests still awaiting a reply fail once the worker stops
+        methods.add_scheduler_async_method("request", |_lua, this, (payload, timeout): (Blob, Option<f64>)| {
+            let timeout = match timeout {
+                Some(t) if t.is_finite() && t > 0.0 => Ok(Duration::from_secs_f64(t)),
+                Some(_) => Err(LuaError::external("timeout must be a positive number of seconds")),
+                None => Ok(DEFAULT_REQUEST_TIMEOUT),
+            };
+            let id = this.next_request_id.fetch_add(1, Ordering::SeqCst);
+            let rpc = this.rpc.clone();
+            let killed = this.killed.clone();
+            async move {
+                let timeout = timeout?;
+                if killed.is_cancelled() {
+                    return Err(LuaError::external("WASM was killed"));
+                }
+
+                let reply_rx = rpc.push(id, payload.0).map_err(LuaError::external)?;
+                match tokio::time::timeout(timeout, reply_rx).await {
+                    Ok(Ok(reply)) => Ok(Blob(reply)),
+                    Ok(Err(_)) => Err(LuaError::external("WASM stopped before replying")),
+                    Err(_) => {
+                        rpc.cancel(id);
+                        Err(LuaError::external("Request timed out"))
+                    }
+                }
+            }
+        });
+
         // Receive a message from WASM to Luau (async)
         methods.add_scheduler_async_method("recv", |_lua, this, ()| {
             let rx_arc = this.wasm_rx.clone();
@@ -64,14 +831,118 @@ impl LuaUserData for WasmState {
                 }
             }
         });
+
+        // Receive a region of memory shared by WASM (async)
+        //
+        // The region is owned by Luau until released (or garbage collected)
+        methods.add_scheduler_async_method("recvregion", |_lua, this, ()| {
+            let rx_arc = this.region_rx.clone();
+            async move {
+                let mut rx = rx_arc.lock().await;
+                Ok(rx.recv().await.map(SharedRegion::new))
+            }
+        });
         
+        // Takes the stdout captured so far (WASI only), along with whether any of it was dropped
+        methods.add_method("stdout", |_lua, this, ()| {
+            Ok(Self::take_output(this.wasi_output.as_ref().map(|o| &o.stdout)))
+        });
+
+        // Takes the stderr captured so far (WASI only), along with whether any of it was dropped
+        methods.add_method("stderr", |_lua, this, ()| {
+            Ok(Self::take_output(this.wasi_output.as_ref().map(|o| &o.stderr)))
+        });
+
+        // Returns the exported functions as { name, params, results } with WASM type names
+        methods.add_method("exports", |lua, this, ()| {
+            let exports = lua.create_table()?;
+            for export in this.module.exports() {
+                let wasmtime::ExternType::Func(ty) = export.ty() else {
+                    continue;
+                };
+
+                let entry = lua.create_table()?;
+                entry.set("name", export.name())?;
+                entry.set("params", ty.params().map(|t| val_type_name(&t)).collect::<Vec<_>>())?;
+                entry.set("results", ty.results().map(|t| val_type_name(&t)).collect::<Vec<_>>())?;
+                exports.raw_push(entry)?;
+            }
+            Ok(exports)
+        });
+
+        // Calls an exported function (async)
+        //
+        // Numbers are converted based on the function's signature while strings and buffers are
+        // copied in through the exported allocator and passed as (ptr, len). The copies are freed
+        // after the call through khronos_free(ptr, len), dealloc(ptr, len) or free(ptr), whichever
+        // matches the allocator. Guests exporting none of these own the copies
+        methods.add_scheduler_async_method("call", |lua, this, (name, args): (String, LuaMultiValue)| {
+            let runner_slot = this.runner.clone();
+            let runner = runner_slot.lock().unwrap().take();
+            let args = args.into_iter().map(|v| CallArg::from_lua(v, &lua)).collect::<LuaResult<Vec<_>>>();
+            let killed = this.killed.clone();
+            async move {
+                let Some(runner) = runner else {
+                    return Err(LuaError::external("WASM is running, being called or was killed and cannot be called"));
+                };
+                let mut guard = RunnerGuard { slot: runner_slot, runner: Some(runner), killed };
+                let args = args?;
+
+                // The store is Send, so run the call off the Luau thread
+                let handle = tokio::spawn(async move {
+                    let runner = guard.runner.as_mut().expect("the guard holds the runner until dropped");
+                    let res = runner.call(&name, args).await;
+                    drop(guard);
+                    res
+                });
+                let res = handle.await.map_err(LuaError::external)?;
+
+                res.map_err(|e| LuaError::external(format!("{e:#}")))?
+                    .into_iter()
+                    .map(|v| match v {
+                        wasmtime::Val::I32(i) => Ok(LuaValue::Integer(i as i64)),
+                        wasmtime::Val::I64(i) => Ok(integer_to_lua(i)),
+                        wasmtime::Val::F32(f) => Ok(LuaValue::Number(f32::from_bits(f) as f64)),
+                        wasmtime::Val::F64(f) => Ok(LuaValue::Number(f64::from_bits(f))),
+                        _ => Err(LuaError::external("Unsupported WASM return type")),
+                    })
+                    .collect::<LuaResult<LuaMultiValue>>()
+            }
+        });
+
         // Start execution (synchronous, spawns tokio task)
-        methods.add_method_mut("start", |_lua, this, ()| {
-            if let Some((instance, mut store, max_fuel_per_slice)) = this.runner.take() {
+        methods.add_method("start", |_lua, this, ()| {
+            let runner = this.runner.lock().unwrap().take();
+            if let Some(Runner { instance, mut store }) = runner {
+                let status = this.status.clone();
+                let rpc = this.rpc.clone();
+                *status.lock().unwrap() = WorkerStatus::Running;
                 let handle = tokio::spawn(async move {
-                    let func = instance.get_typed_func::<(), ()>(&mut store, "main")?;
-                    store.set_fuel(max_fuel_per_slice)?;
-                    func.call_async(&mut store, ()).await
+                    let res = async {
+                        // WASI commands are started through _start
+                        let entrypoint = if instance.get_export(&mut store, "_start").is_some() { "_start" } else { "main" };
+                        let func = instance.get_typed_func::<(), ()>(&mut store, entrypoint)?;
+                        new_slice(store.as_context_mut())?;
+                        func.call_async(&mut store, ()).await
+                    }
+                    .await;
+
+                    {
+                        let mut status = status.lock().unwrap_or_else(|e| e.into_inner());
+                        if !matches!(*status, WorkerStatus::Killed) {
+                            *status = match &res {
+                                Ok(()) => WorkerStatus::Finished,
+                                Err(e) => match e.downcast_ref::<wasi_common::I32Exit>() {
+                                    Some(wasi_common::I32Exit(0)) => WorkerStatus::Finished,
+                                    _ => WorkerStatus::Trapped(format!("{e:#}")),
+                                },
+                            };
+                        }
+                    }
+
+                    // Nothing serves requests once the worker stops
+                    rpc.close();
+                    res
                 });
                 
                 *this.join_handle.lock().unwrap() = Some(handle);
@@ -81,6 +952,22 @@ impl LuaUserData for WasmState {
             }
         });
         
+        // Stops the worker, dropping its instance (and memory)
+        methods.add_method("kill", |_lua, this, ()| {
+            this.kill();
+            Ok(())
+        });
+
+        // Returns the worker's status (idle, running, finished, trapped or killed) and the trap message if it trapped
+        methods.add_method("status", |_lua, this, ()| {
+            let status = this.status.lock().unwrap().clone();
+            let message = match &status {
+                WorkerStatus::Trapped(msg) => Some(msg.clone()),
+                _ => None,
+            };
+            Ok((status.as_str(), message))
+        });
+
         // Wait for execution to finish
         methods.add_scheduler_async_method("wait", |_lua, this, ()| {
             let handle = this.join_handle.lock().unwrap().take();
@@ -88,7 +975,13 @@ impl LuaUserData for WasmState {
                 if let Some(handle) = handle {
                     match handle.await {
                         Ok(Ok(_)) => Ok(()),
-                        Ok(Err(e)) => Err(LuaError::external(e)),
+                        // proc_exit(0) is a successful exit
+                        Ok(Err(e)) => match e.downcast_ref::<wasi_common::I32Exit>() {
+                            Some(wasi_common::I32Exit(0)) => Ok(()),
+                            Some(wasi_common::I32Exit(code)) => Err(LuaError::external(format!("WASM exited with code {code}"))),
+                            None => Err(LuaError::external(e)),
+                        },
+                        Err(e) if e.is_cancelled() => Err(LuaError::external("WASM was killed")),
                         Err(e) => Err(LuaError::external(e)),
                     }
                 } else {
@@ -99,6 +992,158 @@ impl LuaUserData for WasmState {
     }
 }
 
+/// A call from WASM to a Luau host function
+struct HostCall {
+    /// Index of the function in ``HostImports::functions``
+    index: usize,
+    /// MessagePack encoded array of arguments
+    args: Vec<u8>,
+    reply: oneshot::Sender<Result<Vec<u8>, String>>,
+}
+
+/// Luau functions exposed to a worker as imports
+///
+/// Each function is linked as ``<module>.<name>(args_ptr: u32, args_len: u32) -> u32``. The
+/// arguments are a MessagePack array at ``args_ptr`` and the call returns the length of the
+/// MessagePack array of results, which the guest then copies into its own memory with
+/// ``<module>.__result_into(ptr: u32) -> u32``. Luau errors trap the guest.
+///
+/// Calls are run one at a time in the Luau scheduler, so host functions may yield
+pub struct HostImports {
+    module: String,
+    functions: Vec<String>,
+    tx: MpscSender<HostCall>,
+}
+
+impl HostImports {
+    /// Creates the imports, spawning the (local) task that runs calls in Luau
+    pub fn new(lua: &Lua, module: String, functions: Vec<(String, LuaFunction)>) -> LuaResult<Self> {
+        if module.is_empty() {
+            return Err(LuaError::external("Import module name cannot be empty"));
+        }
+        for (name, _) in &functions {
+            if name.is_empty() || name.starts_with("__") {
+                return Err(LuaError::external(format!("Invalid host function name '{name}'")));
+            }
+        }
+
+        let (names, funcs): (Vec<_>, Vec<_>) = functions.into_iter().unzip();
+        let (tx, mut rx) = mpsc_channel::<HostCall>(1);
+        let weak_lua = lua.weak();
+        tokio::task::spawn_local(async move {
+            // Ends once the worker (and so every sender) is dropped
+            while let Some(call) = rx.recv().await {
+                let Some(lua) = weak_lua.try_upgrade() else {
+                    break;
+                };
+
+                let res = Self::call(&lua, funcs[call.index].clone(), &call.args).await;
+                let _ = call.reply.send(res.map_err(|e| e.to_string()));
+            }
+        });
+
+        Ok(Self { module, functions: names, tx })
+    }
+
+    async fn call(lua: &Lua, func: LuaFunction, args: &[u8]) -> LuaResult<Vec<u8>> {
+        let args = {
+            let mut de = rmp_serde::Deserializer::new(args);
+            match LuaValueSeed::new(lua, Format::Plain).deserialize(&mut de).into_lua_err()? {
+                LuaValue::Table(t) => t.sequence_values::<LuaValue>().collect::<LuaResult<LuaMultiValue>>()?,
+                _ => return Err(LuaError::external("Host function arguments must be a MessagePack array")),
+            }
+        };
+
+        let th = lua.create_thread(func)?;
+        let res = S::get(lua).run_in_scheduler(th, args).await?;
+
+        let res = res.iter().map(|v| LuaValueRef::new(lua, v, Format::Plain)).collect::<Vec<_>>();
+        let encoded = rmp_serde::to_vec(&res).into_lua_err()?;
+        if encoded.len() > MAX_HOST_CALL_BYTES {
+            return Err(LuaError::external("Host function results are too large"));
+        }
+        Ok(encoded)
+    }
+
+    fn link(&self, linker: &mut Linker<WasmContext>) -> wasmtime::Result<()> {
+        for (index, name) in self.functions.iter().enumerate() {
+            let tx = self.tx.clone();
+            let fn_name = name.clone();
+            linker.func_wrap2_async(
+                &self.module,
+                name,
+                move |mut caller: wasmtime::Caller<'_, WasmContext>, ptr: u32, len: u32| {
+                    let tx = tx.clone();
+                    let name = fn_name.clone();
+                    Box::new(async move {
+                        if len as usize > MAX_HOST_CALL_BYTES {
+                            return Err(wasmtime::Error::msg(format!("Arguments to host function '{name}' are too large")));
+                        }
+
+                        let memory = get_memory(&mut caller)?;
+                        let mut args = vec![0u8; len as usize];
+                        memory.read(&caller, ptr as usize, &mut args)?;
+                        charge_fuel(&mut caller, HOST_CALL_FUEL + len as u64)?;
+
+                        let killed = caller.data().killed.clone();
+                        let (reply_tx, reply_rx) = oneshot::channel();
+                        unless_killed(&killed, tx.send(HostCall { index, args, reply: reply_tx }))
+                            .await?
+                            .map_err(|_| wasmtime::Error::msg("Luau is no longer running"))?;
+                        let result = unless_killed(&killed, reply_rx)
+                            .await?
+                            .map_err(|_| wasmtime::Error::msg("Luau is no longer running"))?
+                            .map_err(|e| wasmtime::Error::msg(format!("Host function '{name}' failed: {e}")))?;
+
+                        // The call was a yield point, so the guest gets a new slice less the cost of the results
+                        let result_len = result.len() as u32;
+                        caller.data_mut().host_result = Some(result);
+                        new_slice(caller.as_context_mut())?;
+                        charge_fuel(&mut caller, result_len as u64)?;
+                        Ok(result_len)
+                    })
+                },
+            )?;
+        }
+
+        linker.func_wrap(
+            &self.module,
+            "__result_into",
+            move |mut caller: wasmtime::Caller<'_, WasmContext>, ptr: u32| -> wasmtime::Result<u32> {
+                let result = caller.data_mut().host_result.take().unwrap_or_default();
+                let memory = get_memory(&mut caller)?;
+                memory.write(&mut caller, ptr as usize, &result)?;
+                Ok(result.len() as u32)
+            },
+        )?;
+
+        Ok(())
+    }
+}
+
+/// Deducts ``cost`` fuel from the guest, trapping if it does not have enough
+fn charge_fuel(caller: &mut wasmtime::Caller<'_, WasmContext>, cost: u64) -> wasmtime::Result<()> {
+    let fuel = caller.get_fuel()?;
+    if fuel < cost {
+        return Err(wasmtime::Trap::OutOfFuel.into());
+    }
+    caller.set_fuel(fuel - cost)?;
+    Ok(())
+}
+
+/// Options for instantiating a worker
+#[derive(Default)]
+pub struct WasmInstanceOptions {
+    pub wasi: Option<WasiOptions>,
+    pub host_imports: Option<HostImports>,
+    /// Maximum memory of this instance (on top of the shared limit)
+    pub max_memory: Option<usize>,
+    /// Maximum total fuel this instance may use
+    pub max_total_fuel: Option<u64>,
+    /// The time limits of the runtime the instance is created in
+    pub time_limits: WasmTimeLimits,
+}
+
 /// Tries to find memory export at either memory or mem. Bails out if we dont see it
 fn get_memory(caller: &mut wasmtime::Caller<'_, WasmContext>) -> std::io::Result<wasmtime::Memory> {
     if let Some(mem) = caller.get_export("memory").and_then(|e| e.into_memory()) {
@@ -110,24 +1155,104 @@ fn get_memory(caller: &mut wasmtime::Caller<'_, WasmContext>) -> std::io::Result
     Err(std::io::Error::new(std::io::ErrorKind::Other, "memory export not found (tried 'memory' and 'mem')"))
 }
 
+impl Drop for WasmState {
+    fn drop(&mut self) {
+        // Otherwise a started worker would keep running (and holding its memory) forever
+        self.kill();
+    }
+}
+
 impl WasmState {
+    /// Kills the worker. Running WASM traps at the next epoch tick (or is cancelled if awaiting)
+    pub fn kill(&self) {
+        // Also wakes host functions the worker is blocked in
+        self.killed.cancel();
+        *self.status.lock().unwrap_or_else(|e| e.into_inner()) = WorkerStatus::Killed;
+
+        // Revoke shared regions before the memory they point into can be freed
+        self.regions.revoke_all();
+        if let Some(handle) = self.join_handle.lock().unwrap_or_else(|e| e.into_inner()).as_ref() {
+            handle.abort();
+        }
+
+        // Drop the instance if it is not running
+        self.runner.lock().unwrap_or_else(|e| e.into_inner()).take();
+
+        // Fail every request awaiting a reply
+        self.rpc.close();
+    }
+
+    /// Takes captured output as ``(data, truncated)``, or nothing if WASI is not enabled
+    fn take_output(output: Option<&CapturedOutput>) -> (Option<Blob>, Option<bool>) {
+        match output.map(|o| o.take()) {
+            Some((data, truncated)) => (Some(Blob(data.into())), Some(truncated)),
+            None => (None, None),
+        }
+    }
+
     pub async fn instantiate(
-        engine: Engine,
+        module: &WasmModule,
         limits: SharedWasmLimits,
-        wasm_bytes: &[u8],
         max_fuel_per_slice: u64,
+        opts: WasmInstanceOptions,
     ) -> wasmtime::Result<Self> {
+        let engine = module.module.engine().clone();
+
+        let (wasi, wasi_output) = match opts.wasi {
+            Some(wasi) => {
+                let (ctx, output) = wasi.build().map_err(|e| wasmtime::Error::msg(e.to_string()))?;
+                (Some(ctx), Some(output))
+            }
+            None => (None, None),
+        };
+        let wasi_enabled = wasi.is_some();
         let (luau_tx, luau_rx) = broadcast_channel::<bytes::Bytes>(1024);
         let (wasm_tx, wasm_rx) = mpsc_channel::<bytes::Bytes>(1024);
+        let rpc = RpcQueue::default();
+        let (region_tx, region_rx) = mpsc_channel::<Arc<RegionInner>>(MAX_SHARED_REGIONS);
+        let regions = RegionRegistry::default();
         
+        let killed = CancellationToken::new();
         let mut store = Store::new(&engine, WasmContext { 
-            limits,
+            limits: InstanceLimits::new(limits, opts.max_memory),
+            budget: ExecutionBudget::new(max_fuel_per_slice, opts.max_total_fuel, opts.time_limits),
+            killed: killed.clone(),
             next_msg: None,
             luau_rx: Some(luau_rx),
+            host_result: None,
+            rpc: rpc.clone(),
+            next_request: None,
+            regions: regions.clone(),
+            region_tx,
+            wasi,
+            _epoch: EpochGuard::new()?,
         });
         store.limiter(|ctx| &mut ctx.limits);
+
+        // Yield on every epoch tick, trapping if the worker was killed or ran out of time
+        store.set_epoch_deadline(1);
+        store.epoch_deadline_callback(|ctx| {
+            let data = ctx.data();
+            if data.killed.is_cancelled() {
+                return Err(wasmtime::Error::msg("WASM was killed"));
+            }
+            if data.budget.expired() {
+                return Err(wasmtime::Error::msg("WASM execution time limit exceeded"));
+            }
+            Ok(wasmtime::UpdateDeadline::Yield(1))
+        });
         
         let mut linker = Linker::new(&engine);
+
+        if wasi_enabled {
+            wasi_common::tokio::add_to_linker(&mut linker, |ctx| {
+                ctx.wasi.as_mut().expect("WASI is only linked when enabled")
+            })?;
+        }
+
+        if let Some(host_imports) = &opts.host_imports {
+            host_imports.link(&mut linker)?;
+        }
         
         // Import: WASM sends message to Luau
         linker.func_wrap2_async(
@@ -142,7 +1267,7 @@ impl WasmState {
                     memory.read(&caller, ptr as usize, &mut buffer)?;
                     
                     let _ = tx.send(buffer.into()).await;
-                    caller.set_fuel(max_fuel_per_slice)?; // reset fuel
+                    new_slice(caller.as_context_mut())?; // reset fuel
                     Ok(())
                 })
             }
@@ -157,30 +1282,147 @@ impl WasmState {
                 let tx = luau_tx_len.clone();
                 Box::new(async move {
                     if caller.data().next_msg.is_none() {
+                        let killed = caller.data().killed.clone();
                         let mut rx = caller.data_mut().luau_rx.take().unwrap_or_else(|| tx.subscribe());
-                        loop {
-                            match rx.recv().await {
-                                Ok(msg) => {
-                                    caller.data_mut().next_msg = Some(msg);
-                                    break;
+                        let msg = unless_killed(&killed, async {
+                            loop {
+                                match rx.recv().await {
+                                    Ok(msg) => break Some(msg),
+                                    Err(tokio::sync::broadcast::error::RecvError::Closed) => break None,
+                                    Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => continue,
                                 }
-                                Err(tokio::sync::broadcast::error::RecvError::Closed) => {
-                                    caller.data_mut().next_msg = None;
-                                    break;
-                                }
-                                Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => continue,
                             }
-                        }
+                        })
+                        .await;
                         caller.data_mut().luau_rx = Some(rx);
+                        caller.data_mut().next_msg = msg?;
                     }
                     
                     let len = caller.data().next_msg.as_ref().map(|m| m.len()).unwrap_or(0);
-                    caller.set_fuel(max_fuel_per_slice)?; // reset fuel
+                    new_slice(caller.as_context_mut())?; // reset fuel
                     Ok(len as u32)
                 })
             }
         )?;
 
+        // RPC imports
+        //
+        // The guest serves requests made with WasmState:request in a loop:
+        //
+        // - rpc_next() -> u32 awaits the next request and returns the length of its payload
+        // - rpc_take(ptr: u32) -> u64 copies the payload to ptr and returns the request's id
+        // - rpc_reply(id: u64, ptr: u32, len: u32) replies to the request with the given id
+        //
+        // Requests may be replied to in any order (or not at all, in which case they time out).
+        // rpc_take and rpc_reply are charged like host function calls. test_plugin's rpc module
+        // is a guest side implementation of this ABI
+        linker.func_wrap0_async(
+            "env",
+            "rpc_next",
+            move |mut caller: wasmtime::Caller<'_, WasmContext>| {
+                Box::new(async move {
+                    if caller.data().next_request.is_none() {
+                        let killed = caller.data().killed.clone();
+                        let rpc = caller.data().rpc.clone();
+                        let Some(request) = unless_killed(&killed, rpc.next()).await? else {
+                            return Err(wasmtime::Error::msg("RPC channel closed"));
+                        };
+                        caller.data_mut().next_request = Some(request);
+                    }
+
+                    let len = caller.data().next_request.as_ref().map(|(_, p)| p.len()).unwrap_or(0);
+                    new_slice(caller.as_context_mut())?; // reset fuel
+                    Ok(len as u32)
+                })
+            }
+        )?;
+
+        linker.func_wrap(
+            "env",
+            "rpc_take",
+            move |mut caller: wasmtime::Caller<'_, WasmContext>, ptr: u32| -> wasmtime::Result<u64> {
+                let Some((id, payload)) = caller.data_mut().next_request.take() else {
+                    return Err(wasmtime::Error::msg("rpc_take called without a pending request (call rpc_next first)"));
+                };
+                charge_fuel(&mut caller, HOST_CALL_FUEL + payload.len() as u64)?;
+
+                let memory = get_memory(&mut caller)?;
+                memory.write(&mut caller, ptr as usize, &payload)?;
+                Ok(id)
+            }
+        )?;
+
+        linker.func_wrap(
+            "env",
+            "rpc_reply",
+            move |mut caller: wasmtime::Caller<'_, WasmContext>, id: u64, ptr: u32, len: u32| -> wasmtime::Result<()> {
+                charge_fuel(&mut caller, HOST_CALL_FUEL + len as u64)?;
+
+                // Requests that timed out are no longer pending and their replies are dropped
+                let reply_tx = caller.data().rpc.take_reply(id);
+                let Some(reply_tx) = reply_tx else {
+                    return Ok(());
+                };
+
+                let memory = get_memory(&mut caller)?;
+                let reply = memory
+                    .data(&caller)
+                    .get(ptr as usize..ptr as usize + len as usize)
+                    .ok_or_else(|| wasmtime::Error::msg("rpc_reply payload is out of bounds"))?
+                    .to_vec();
+                let _ = reply_tx.send(reply.into());
+                Ok(())
+            }
+        )?;
+
+        // Shared region imports (see core::wasmregion)
+        //
+        // - region_send(ptr: u32, len: u32) -> u32 registers a window of memory and returns its id
+        // - region_wait(id: u32) -> u32 hands the region to Luau and awaits Luau releasing it,
+        //   returning 1 (or 0 if it was revoked)
+        linker.func_wrap(
+            "env",
+            "region_send",
+            move |mut caller: wasmtime::Caller<'_, WasmContext>, ptr: u32, len: u32| -> wasmtime::Result<u32> {
+                let memory = get_memory(&mut caller)?;
+                let memory_len = memory.data_size(&caller);
+                let region = caller.data().regions
+                    .share(memory_len, ptr, len)
+                    .map_err(wasmtime::Error::msg)?;
+                Ok(region.id())
+            }
+        )?;
+
+        linker.func_wrap1_async(
+            "env",
+            "region_wait",
+            move |mut caller: wasmtime::Caller<'_, WasmContext>, id: u32| {
+                Box::new(async move {
+                    let regions = caller.data().regions.clone();
+                    let Some(region) = regions.get(id) else {
+                        return Err(wasmtime::Error::msg(format!("Unknown shared region {id}")));
+                    };
+
+                    // The guest cannot run (or grow its memory) until region_wait returns, so Luau
+                    // may access the region until the parked guard is dropped
+                    let memory = get_memory(&mut caller)?;
+                    let parked = region.park(memory.data_mut(&mut caller)).map_err(wasmtime::Error::msg)?;
+                    if caller.data().region_tx.try_send(region.clone()).is_err() {
+                        regions.remove(id);
+                        return Err(wasmtime::Error::msg("Too many shared regions awaiting Luau"));
+                    }
+
+                    let killed = caller.data().killed.clone();
+                    let released = unless_killed(&killed, region.wait_released()).await;
+                    drop(parked);
+                    regions.remove(id);
+                    let released = released?;
+                    new_slice(caller.as_context_mut())?; // reset fuel
+                    Ok(released as u32)
+                })
+            }
+        )?;
+
         // Import: WASM synchronously copies the awaited message into the allocated pointer
         linker.func_wrap(
             "env", 
@@ -196,46 +1438,125 @@ impl WasmState {
             }
         )?;
 
-        let module = wasmtime::Module::new(&engine, wasm_bytes)?;
-            
-        let instance = linker.instantiate_async(&mut store, &module).await?; 
+        let instance = linker.instantiate_async(&mut store, &module.module).await?; 
 
         Ok(WasmState { 
-            runner: Some((instance, store, max_fuel_per_slice)), 
+            module: module.module.clone(),
+            runner: Arc::new(Mutex::new(Some(Runner { instance, store }))),
+            status: Arc::new(Mutex::new(WorkerStatus::Idle)),
+            killed,
+            wasi_output,
             join_handle: Mutex::new(None),
             luau_tx, 
-            wasm_rx: Arc::new(AsyncMutex::new(wasm_rx)), 
+            wasm_rx: Arc::new(AsyncMutex::new(wasm_rx)),
+            rpc,
+            next_request_id: AtomicU64::new(1), 
+            regions,
+            region_rx: Arc::new(AsyncMutex::new(region_rx)),
         })
     }
 }
 
-pub fn init_plugin(lua: &Lua, max_memory: usize, max_fuel_per_slice: u64) -> LuaResult<LuaTable> {
+fn parse_instance_options(lua: &Lua, opts: &LuaTable) -> LuaResult<WasmInstanceOptions> {
+    let wasi = match opts.get::<LuaValue>("wasi")? {
+        LuaValue::Nil | LuaValue::Boolean(false) => None,
+        LuaValue::Boolean(true) => Some(WasiOptions::default()),
+        LuaValue::Table(t) => Some(WasiOptions::from_lua_table(&t)?),
+        _ => return Err(LuaError::external("opts.wasi must be a table or boolean")),
+    };
+
+    let host_imports = match opts.get::<Option<LuaTable>>("imports")? {
+        Some(imports) => {
+            let mut functions = imports
+                .pairs::<String, LuaFunction>()
+                .collect::<LuaResult<Vec<_>>>()?;
+            functions.sort_by(|a, b| a.0.cmp(&b.0));
+
+            let module = opts
+                .get::<Option<String>>("importmodule")?
+                .unwrap_or_else(|| DEFAULT_IMPORT_MODULE.to_string());
+            Some(HostImports::new(lua, module, functions)?)
+        }
+        None => None,
+    };
+
+    Ok(WasmInstanceOptions {
+        wasi,
+        host_imports,
+        max_memory: opts.get::<Option<usize>>("maxmemory")?,
+        max_total_fuel: opts.get::<Option<u64>>("maxfuel")?,
+        time_limits: WasmTimeLimits::default(),
+    })
+}
+
+pub fn init_plugin(
+    lua: &Lua,
+    max_memory: usize,
+    max_fuel_per_slice: u64,
+    time_limits: WasmTimeLimits,
+) -> LuaResult<LuaTable> {
     let module = lua.create_table()?;
 
-    let mut config = Config::new();
-    config.async_support(true);
-    config.consume_fuel(true);
-    
-    let engine = Engine::new(&config).map_err(|e| LuaError::external(e))?;
-    
     let shared_limits = SharedWasmLimits {
         max_memory,
         allocated_memory: Arc::new(AtomicUsize::new(0)),
     };
-    
-    let newwasm = lua.create_scheduler_async_function(move |_lua, wasm_bytes: Blob| {
-        let engine = engine.clone(); 
+    // Components count towards the same memory limit as modules
+    let component_limits = shared_limits.clone();
+    let component_time_limits = time_limits.clone();
+
+    // Compiles (or fetches from the module cache) a WASM binary into a reusable module
+    module.set("compile", lua.create_function(|_lua, wasm_bytes: LuaValue| {
+        crate::primitives::blob::blob_ref(&wasm_bytes, WasmModule::compile)?
+    })?)?;
+
+    // Instantiates a module (or a WASM binary) into a new worker
+    //
+    // opts.wasi (a table, see WasiOptions::from_lua_table, or true) enables WASI
+    // opts.imports is a table of Luau functions linked under opts.importmodule (see HostImports)
+    // opts.maxmemory and opts.maxfuel cap the instance's memory and total fuel
+    let newwasm = lua.create_scheduler_async_function(move |lua, (wasm, opts): (LuaValue, Option<LuaTable>)| {
         let limits = shared_limits.clone();
-        
+        let module = match wasm {
+            LuaValue::UserData(ud) => ud.borrow::<WasmModule>().map(|m| (*m).clone()),
+            wasm_bytes => crate::primitives::blob::blob_ref(&wasm_bytes, WasmModule::compile).and_then(|m| m),
+        };
+        let opts = match opts {
+            Some(opts) => parse_instance_options(&lua, &opts),
+            None => Ok(WasmInstanceOptions::default()),
+        }
+        .map(|opts| WasmInstanceOptions { time_limits: time_limits.clone(), ..opts });
+
         async move {
-            WasmState::instantiate(engine, limits, &wasm_bytes.0, max_fuel_per_slice)
+            WasmState::instantiate(&module?, limits, max_fuel_per_slice, opts?)
                 .await
                 .map_err(|e| LuaError::external(e))
         }
     })?;
 
-
     module.set("newwasm", newwasm)?;
+
+    // Instantiates a WebAssembly component implementing the Khronos WIT world (see core::wasmcomponent)
+    //
+    // opts.maxmemory and opts.maxfuel cap the instance's memory and total fuel
+    module.set("newcomponent", lua.create_scheduler_async_function(move |_lua, (wasm, opts): (LuaValue, Option<LuaTable>)| {
+        let limits = component_limits.clone();
+        let wasm_bytes = crate::primitives::blob::blob_ref(&wasm, |b| b.to_vec());
+        let opts = match opts {
+            Some(opts) => crate::core::wasmcomponent::parse_component_options(&opts),
+            None => Ok(Default::default()),
+        }
+        .map(|opts| crate::core::wasmcomponent::ComponentOptions { time_limits: component_time_limits.clone(), ..opts });
+
+        async move {
+            crate::core::wasmcomponent::WasmComponentState::instantiate(&wasm_bytes?, limits, max_fuel_per_slice, opts?)
+                .await
+                .map_err(|e| LuaError::external(format!("{e:#}")))
+        }
+    })?)?;
+
+    // The WIT world components are built against
+    module.set("wit", crate::core::wasmcomponent::KHRONOS_WIT)?;
     module.set_readonly(true);
     Ok(module)
 }
@@ -249,8 +1570,475 @@ mod tests {
     use mluau_require::create_memory_vfs_from_map;
     use tokio::runtime::LocalOptions;
 
+    use crate::rt::testutils::{create_runtime, run_script};
     use crate::rt::{KhronosRuntime, RuntimeCreateOpts};
 
+    #[test]
+    fn test_module_cache() {
+        let cache = super::WasmModuleCache::new(1);
+        let a = cache.get_or_compile(b"(module)").unwrap();
+        let b = cache.get_or_compile(b"(module)").unwrap();
+        assert_eq!(a.hash, b.hash);
+        assert!(cache.get(&super::module_hash(b"(module)")).is_some());
+
+        // Evicts the first module
+        cache.get_or_compile(b"(module (func))").unwrap();
+        assert_eq!(cache.len(), 1);
+        assert!(cache.get(&a.hash).is_none());
+
+        // Precompiled modules are inserted under the hash of their WASM binary
+        let serialized = a.serialize().unwrap();
+        let c = unsafe { cache.insert_precompiled(a.hash, &serialized) }.unwrap();
+        assert_eq!(c.hash, a.hash);
+        assert!(cache.get(&a.hash).is_some());
+    }
+
+    /// Calls ``khronos.<name>`` with the MessagePack array ``[2, 3]`` and sends the results to Luau
+    const HOST_IMPORTS_WAT: &str = r#"
+        (module
+            (import "khronos" "add" (func $add (param i32 i32) (result i32)))
+            (import "khronos" "fail" (func $fail (param i32 i32) (result i32)))
+            (import "khronos" "__result_into" (func $result_into (param i32) (result i32)))
+            (import "env" "send" (func $send (param i32 i32)))
+            (memory (export "memory") 1)
+            (data (i32.const 0) "\92\02\03")
+
+            (func (export "run_add") (result i32)
+                (local $len i32)
+                (local.set $len (call $add (i32.const 0) (i32.const 3)))
+                (call $send (i32.const 1024) (call $result_into (i32.const 1024)))
+                (local.get $len))
+
+            (func (export "run_fail") (result i32)
+                (call $fail (i32.const 0) (i32.const 3))))
+    "#;
+
+    #[test]
+    fn test_host_imports() {
+        let script = r#"
+            return function(guest)
+                local wasm = require"@antiraid/wasm"
+                local msgpack = require"@antiraid/msgpack"
+
+                local seen
+                local imports = {
+                    add = function(a, b)
+                        seen = { a, b }
+                        task.wait() -- host functions may yield
+                        return a + b, "sum"
+                    end,
+                    fail = function()
+                        error("boom")
+                    end,
+                }
+
+                local worker = wasm.newwasm(guest, { imports = imports })
+                local len = worker:call("run_add")
+                local results = worker:recv()
+                assert(seen[1] == 2 and seen[2] == 3)
+                assert(len == buffer.len(results), "__result_into copies the whole result")
+                local decoded = msgpack.frommsgpack(results)
+                assert(decoded[1] == 5 and decoded[2] == "sum")
+
+                -- Luau errors trap the guest, which can still be called afterwards
+                local ok, err = pcall(worker.call, worker, "run_fail")
+                assert(not ok and string.find(tostring(err), "Host function 'fail' failed", 1, true), tostring(err))
+                assert(worker:call("run_add") == len)
+
+                -- Every call costs HOST_CALL_FUEL on top of its arguments and results
+                local starved = wasm.newwasm(guest, { imports = imports, maxfuel = 10000 })
+                local ok, err = pcall(starved.call, starved, "run_add")
+                assert(not ok and string.find(tostring(err), "fuel"), tostring(err))
+                local fed = wasm.newwasm(guest, { imports = imports, maxfuel = 100000 })
+                assert(fed:call("run_add") == len)
+            end
+        "#;
+
+        run_script(RuntimeCreateOpts::default(), script, |lua| lua.create_string(HOST_IMPORTS_WAT)).unwrap();
+    }
+
+    /// Exports functions of every argument type, along with an allocator tracking frees
+    const EXPORTS_WAT: &str = r#"
+        (module
+            (memory (export "memory") 1)
+            (global $next (mut i32) (i32.const 1024))
+            (global $freed (mut i32) (i32.const 0))
+
+            (func (export "khronos_alloc") (param $len i32) (result i32)
+                (local $ptr i32)
+                (local.set $ptr (global.get $next))
+                (global.set $next (i32.add (global.get $next) (local.get $len)))
+                (local.get $ptr))
+            (func (export "khronos_free") (param $ptr i32) (param $len i32)
+                (global.set $freed (i32.add (global.get $freed) (local.get $len))))
+            (func (export "freed") (result i32)
+                (global.get $freed))
+
+            (func (export "add") (param i32 i32) (result i32)
+                (i32.add (local.get 0) (local.get 1)))
+            (func (export "mul64") (param i64 i64) (result i64)
+                (i64.mul (local.get 0) (local.get 1)))
+            (func (export "half") (param f64) (result f64)
+                (f64.mul (local.get 0) (f64.const 0.5)))
+            (func (export "sum") (param $ptr i32) (param $len i32) (result i32)
+                (local $i i32)
+                (local $total i32)
+                (block $done
+                    (loop $next
+                        (br_if $done (i32.ge_u (local.get $i) (local.get $len)))
+                        (local.set $total (i32.add (local.get $total) (i32.load8_u (i32.add (local.get $ptr) (local.get $i)))))
+                        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+                        (br $next)))
+                (local.get $total)))
+    "#;
+
+    #[test]
+    fn test_exports_and_call() {
+        let script = r#"
+            return function(guest)
+                local wasm = require"@antiraid/wasm"
+                local worker = wasm.newwasm(guest)
+
+                local exports = {}
+                for _, export in worker:exports() do
+                    exports[export.name] = export
+                end
+                assert(exports.memory == nil, "only functions are listed")
+                assert(#exports.add.params == 2 and exports.add.params[1] == "i32" and exports.add.results[1] == "i32")
+                assert(exports.mul64.results[1] == "i64" and exports.half.params[1] == "f64")
+                assert(#exports.khronos_free.results == 0)
+
+                assert(worker:call("add", 2, 3) == 5)
+                assert(worker:call("add", true, 1) == 2)
+                assert(worker:call("add", 4294967295, 1) == 0, "u32 arguments wrap into i32")
+                assert(worker:call("mul64", 2^40, 1024) == 2^50)
+                assert(worker:call("half", 3) == 1.5)
+
+                -- Byte buffers are passed as (ptr, len) and freed after the call
+                assert(worker:call("sum", "abc") == 294)
+                assert(worker:call("sum", buffer.fromstring("")) == 3)
+                assert(worker:call("freed") == 5)
+
+                local bad = {
+                    { "missing" },
+                    { "add", 1 },
+                    { "add", 1, 2, 3 },
+                    { "add", 1.5, 2 },
+                    { "add", {}, 2 },
+                    { "half", "abc" },
+                }
+                for _, args in bad do
+                    assert(not pcall(worker.call, worker, table.unpack(args)))
+                end
+
+                -- Failed calls give the worker back
+                assert(worker:call("add", 1, 2) == 3)
+            end
+        "#;
+
+        run_script(RuntimeCreateOpts::default(), script, |lua| lua.create_string(EXPORTS_WAT)).unwrap();
+    }
+
+    /// Spins in main and spin, blocks in recv_await in block and grows its memory in grow
+    const WORKER_WAT: &str = r#"
+        (module
+            (import "env" "recv_await" (func $recv_await (result i32)))
+            (memory (export "memory") 1)
+            (func (export "main")
+                (loop $spin (br $spin)))
+            (func (export "spin")
+                (loop $spin (br $spin)))
+            (func (export "block") (result i32)
+                (call $recv_await))
+            (func (export "grow") (param i32) (result i32)
+                (memory.grow (local.get 0))))
+    "#;
+
+    /// Options giving workers enough fuel to spin until killed, with 4 pages of shared memory
+    fn worker_opts() -> RuntimeCreateOpts {
+        RuntimeCreateOpts {
+            wasm_max_fuel_per_slice: Some(1 << 40),
+            wasm_max_memory_bytes: Some(4 * 65536),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_worker_limits() {
+        let script = r#"
+            return function(guest)
+                local wasm = require"@antiraid/wasm"
+
+                -- Memory counts towards the shared limit until the worker is killed or dropped
+                local hog = wasm.newwasm(guest)
+                assert(hog:call("grow", 3) == 1)
+                assert(not pcall(wasm.newwasm, guest), "the shared limit is used up")
+                hog:kill()
+                hog = wasm.newwasm(guest)
+                assert(hog:call("grow", 3) == 1)
+                hog = nil
+                collectgarbage("collect")
+                wasm.newwasm(guest):kill()
+
+                -- maxmemory caps a single worker
+                local small = wasm.newwasm(guest, { maxmemory = 2 * 65536 })
+                assert(small:call("grow", 1) == 1)
+                assert(small:call("grow", 1) == -1)
+                small:kill()
+
+                -- maxfuel caps the fuel used over the worker's lifetime
+                local limited = wasm.newwasm(guest, { maxfuel = 1000000 })
+                local ok, err = pcall(limited.call, limited, "spin")
+                assert(not ok and string.find(tostring(err), "fuel"), tostring(err))
+                limited:kill()
+            end
+        "#;
+
+        run_script(worker_opts(), script, |lua| lua.create_string(WORKER_WAT)).unwrap();
+    }
+
+    #[test]
+    fn test_worker_status_and_kill() {
+        let script = r#"
+            return function(guest)
+                local wasm = require"@antiraid/wasm"
+
+                local worker = wasm.newwasm(guest)
+                assert(worker:status() == "idle")
+                worker:start()
+                assert(worker:status() == "running")
+                task.wait(0.05)
+                worker:kill()
+                local status, message = worker:status()
+                assert(status == "killed" and message == nil)
+                assert(not pcall(worker.wait, worker))
+                assert(not pcall(worker.call, worker, "grow", 0))
+
+                local finished = wasm.newwasm('(module (func (export "main")))')
+                finished:start()
+                finished:wait()
+                assert(finished:status() == "finished")
+
+                local trapped = wasm.newwasm('(module (func (export "main") unreachable))')
+                trapped:start()
+                assert(not pcall(trapped.wait, trapped))
+                local status, message = trapped:status()
+                assert(status == "trapped" and string.find(message, "unreachable"), message)
+
+                -- Killing interrupts calls, whether running or blocked in a host function
+                for _, name in { "spin", "block" } do
+                    local called = wasm.newwasm(guest)
+                    local result
+                    task.spawn(function()
+                        result = table.pack(pcall(called.call, called, name))
+                    end)
+                    task.wait(0.05)
+                    assert(result == nil, name)
+                    called:kill()
+                    for _ = 1, 100 do
+                        if result then
+                            break
+                        end
+                        task.wait(0.01)
+                    end
+                    assert(result and not result[1], name)
+                    assert(string.find(tostring(result[2]), "killed"), tostring(result[2]))
+                end
+            end
+        "#;
+
+        run_script(worker_opts(), script, |lua| lua.create_string(WORKER_WAT)).unwrap();
+    }
+
+    #[test]
+    fn test_worker_runtime_time_limit() -> LuaResult<()> {
+        let tokio_rt = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build_local(LocalOptions::default())
+            .unwrap();
+
+        tokio_rt.block_on(async move {
+            let script = r#"
+                return function(guest)
+                    require"@antiraid/wasm".newwasm(guest):call("spin")
+                end
+            "#;
+            let rt = create_runtime(worker_opts(), script)?;
+
+            // Workers see time limits set after the runtime was created
+            rt.set_time_limit(Some(std::time::Duration::from_millis(200)));
+            let guest = rt.with_lua(|lua| lua.create_string(WORKER_WAT))?;
+            let f = rt.eval_script::<LuaFunction>("./init")?;
+            let err = rt.call_in_scheduler::<_, ()>(f, guest).await.unwrap_err();
+            assert!(err.to_string().contains("WASM execution time limit exceeded"), "{err}");
+            Ok(())
+        })
+    }
+
+    /// Echoes RPC requests, forever in main or once in serve_one
+    const RPC_WAT: &str = r#"
+        (module
+            (import "env" "rpc_next" (func $rpc_next (result i32)))
+            (import "env" "rpc_take" (func $rpc_take (param i32) (result i64)))
+            (import "env" "rpc_reply" (func $rpc_reply (param i64 i32 i32)))
+            (memory (export "memory") 1)
+            (func $serve_one (export "serve_one")
+                (local $len i32)
+                (local.set $len (call $rpc_next))
+                (call $rpc_reply (call $rpc_take (i32.const 0)) (i32.const 0) (local.get $len)))
+            (func (export "main")
+                (loop $serve
+                    (call $serve_one)
+                    (br $serve))))
+    "#;
+
+    /// Takes a single request and finishes without replying
+    const RPC_UNANSWERED_WAT: &str = r#"
+        (module
+            (import "env" "rpc_next" (func $rpc_next (result i32)))
+            (import "env" "rpc_take" (func $rpc_take (param i32) (result i64)))
+            (memory (export "memory") 1)
+            (func (export "main")
+                (drop (call $rpc_next))
+                (drop (call $rpc_take (i32.const 0)))))
+    "#;
+
+    #[test]
+    fn test_rpc_requests() {
+        let script = r#"
+            return function(guest, unanswered)
+                local wasm = require"@antiraid/wasm"
+
+                local function await(get)
+                    for _ = 1, 200 do
+                        local value = get()
+                        if value ~= nil then
+                            return value
+                        end
+                        task.wait(0.01)
+                    end
+                    error("timed out waiting")
+                end
+
+                -- Concurrent requests each get their own reply
+                local worker = wasm.newwasm(guest)
+                worker:start()
+                local replies = {}
+                for i = 1, 5 do
+                    task.spawn(function()
+                        replies[i] = buffer.tostring(worker:request("message " .. i))
+                    end)
+                end
+                await(function() return replies[5] end)
+                for i = 1, 5 do
+                    assert(replies[i] == "message " .. i, replies[i])
+                end
+                worker:kill()
+
+                -- Timed out requests are withdrawn before the guest sees them
+                local idle = wasm.newwasm(guest)
+                local ok, err = pcall(idle.request, idle, "stale", 0.05)
+                assert(not ok and string.find(tostring(err), "timed out"), tostring(err))
+                local reply
+                task.spawn(function()
+                    reply = buffer.tostring(idle:request("fresh"))
+                end)
+                idle:call("serve_one")
+                assert(await(function() return reply end) == "fresh")
+
+                -- Requests awaiting a reply fail once the worker stops
+                local stopping = wasm.newwasm(unanswered)
+                stopping:start()
+                local ok, err = pcall(stopping.request, stopping, "ignored", 5)
+                assert(not ok and string.find(tostring(err), "stopped before replying"), tostring(err))
+                assert(stopping:status() == "finished")
+                assert(not pcall(stopping.request, stopping, "late"))
+
+                -- rpc_take and rpc_reply are charged like host function calls
+                local starved = wasm.newwasm(guest, { maxfuel = 15000 })
+                task.spawn(pcall, starved.request, starved, "payload", 1)
+                local ok, err = pcall(starved.call, starved, "serve_one")
+                assert(not ok and string.find(tostring(err), "fuel"), tostring(err))
+
+                local fed = wasm.newwasm(guest, { maxfuel = 100000 })
+                local reply
+                task.spawn(function()
+                    reply = buffer.tostring(fed:request("payload"))
+                end)
+                fed:call("serve_one")
+                assert(await(function() return reply end) == "payload")
+            end
+        "#;
+
+        run_script(RuntimeCreateOpts::default(), script, |lua| {
+            Ok((lua.create_string(RPC_WAT)?, lua.create_string(RPC_UNANSWERED_WAT)?))
+        })
+        .unwrap();
+    }
+
+    /// Shares "hello" with Luau and returns what Luau wrote after it (plus 1000 once released)
+    const REGION_WAT: &str = r#"
+        (module
+            (import "env" "region_send" (func $region_send (param i32 i32) (result i32)))
+            (import "env" "region_wait" (func $region_wait (param i32) (result i32)))
+            (memory (export "memory") 1)
+            (data (i32.const 16) "hello")
+            (func (export "share") (result i32)
+                (local $released i32)
+                (local.set $released (call $region_wait (call $region_send (i32.const 16) (i32.const 8))))
+                ;; Grows (and may move) memory once Luau is done with the region
+                (drop (memory.grow (i32.const 16)))
+                (i32.add
+                    (i32.mul (local.get $released) (i32.const 1000))
+                    (i32.load8_u (i32.const 21)))))
+    "#;
+
+    #[test]
+    fn test_shared_region_guest() {
+        let script = r#"
+            return function(guest)
+                local wasm = require"@antiraid/wasm"
+                local worker = wasm.newwasm(guest)
+
+                local result
+                task.spawn(function()
+                    result = worker:call("share")
+                end)
+
+                -- The guest is parked in region_wait by the time Luau receives the region
+                local region = worker:recvregion()
+                assert(region.len == 8 and region.owned)
+                assert(buffer.tostring(region:read(0, 5)) == "hello")
+                local buf = buffer.create(3)
+                region:readinto(buf, 0, 1, 3)
+                assert(buffer.tostring(buf) == "ell")
+                region:writeu8(5, 42)
+                region:release()
+                assert(not region.owned)
+                assert(not pcall(region.readu8, region, 0))
+                assert(not pcall(region.release, region))
+
+                for _ = 1, 200 do
+                    if result ~= nil then
+                        break
+                    end
+                    task.wait(0.01)
+                end
+                assert(result == 1042, tostring(result))
+
+                -- Killing the worker revokes regions the guest is waiting on
+                local killed = wasm.newwasm(guest)
+                task.spawn(pcall, killed.call, killed, "share")
+                local region = killed:recvregion()
+                killed:kill()
+                local ok, err = pcall(region.readu8, region, 0)
+                assert(not ok and string.find(tostring(err), "revoked"), tostring(err))
+            end
+        "#;
+
+        run_script(RuntimeCreateOpts::default(), script, |lua| lua.create_string(REGION_WAT)).unwrap();
+    }
+
     #[test]
     fn test_wasm_execution() -> LuaResult<()> {
         let rt = tokio::runtime::Builder::new_current_thread().build_local(LocalOptions::default()).unwrap();
@@ -279,6 +2067,25 @@ mod tests {
                 if reply_str ~= "[WASM Echo] You sent: Testing 123" then
                     error("WASM replied: " .. reply_str)
                 end
+                wasm:kill()
+
+                -- Serve requests through the guest's RPC helper
+                local server = wasm_pkg.newwasm(wasm_bytes)
+                local served
+                task.spawn(function()
+                    served = table.pack(pcall(server.call, server, "serve_rpc"))
+                end)
+                for _, payload in { "abc", "Testing 123", "" } do
+                    local reversed = buffer.tostring(server:request(payload, 5))
+                    if reversed ~= string.reverse(payload) then
+                        error("WASM replied to request: " .. reversed)
+                    end
+                end
+
+                server:kill()
+                task.wait(0.05)
+                assert(served and not served[1], "serve_rpc never returns")
+                assert(not pcall(server.request, server, "abc"))
             end
             "#;
             let mut vfs_map = HashMap::new();
@@ -0,0 +1,944 @@
+//! WebAssembly components implementing the Khronos WIT world (``wit/khronos.wit``)
+//!
+//! Unlike core modules, which talk to Luau over the ``env.send/recv_await/recv_into`` ABI, a
+//! component imports typed messaging, logging and time interfaces, so guests built with
+//! ``wit-bindgen`` need no hand-written glue. Exports may be called from Luau with WIT values
+//! converted to and from Luau values:
+//!
+//! - ``bool``, integers, floats and ``string`` map to the matching Luau types (``char`` is a one character string)
+//! - ``list<u8>`` maps to a ``buffer`` (strings are accepted too)
+//! - other lists and tuples map to arrays, records map to tables keyed by field name
+//! - enums map to the case name and flags to an array of set flag names
+//! - variants map to ``{ tag = "case", value = payload }``
+//! - ``option<T>`` maps to ``nil`` or the value and ``result`` maps to ``{ ok = value }`` or ``{ err = value }``
+//!
+//! Resources are not supported.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::UNIX_EPOCH;
+
+use mlua_scheduler::LuaSchedulerAsyncUserData;
+use mluau::prelude::*;
+use tokio::sync::mpsc::{channel as mpsc_channel, Receiver as MpscReceiver, Sender as MpscSender};
+use tokio::sync::Mutex as AsyncMutex;
+use tokio_util::sync::CancellationToken;
+use wasmtime::component::{Component, ComponentType, Func, Instance, Lift, Linker, Lower, Type, Val};
+use wasmtime::{AsContextMut, Store};
+
+use crate::core::wasm::{
+    shared_engine, unless_killed, CompiledCache, EpochGuard, ExecutionBudget, InstanceLimits, RunnerGuard, SharedWasmLimits,
+    WasmTimeLimits, WorkerStatus,
+};
+use crate::core::wasi::VirtualClock;
+use crate::primitives::blob::Blob;
+use crate::utils::luaserde::integer_to_lua;
+
+/// The WIT world Khronos components are built against
+pub const KHRONOS_WIT: &str = include_str!("../../wit/khronos.wit");
+
+/// Maximum number of compiled components kept in the component cache
+pub const MAX_CACHED_COMPONENTS: usize = 64;
+
+/// Maximum number of log entries kept until taken with ``WasmComponent:logs``
+pub const MAX_LOG_ENTRIES: usize = 1024;
+
+/// Maximum nesting depth of WIT values converted to or from Luau
+const MAX_VALUE_DEPTH: usize = 64;
+
+/// ``khronos:runtime/logging.level``
+#[derive(ComponentType, Lift, Lower, Clone, Copy, Debug)]
+#[component(enum)]
+#[repr(u8)]
+enum Level {
+    #[component(name = "trace")]
+    Trace,
+    #[component(name = "debug")]
+    Debug,
+    #[component(name = "info")]
+    Info,
+    #[component(name = "warn")]
+    Warn,
+    #[component(name = "error")]
+    Error,
+}
+
+impl Level {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Trace => "trace",
+            Self::Debug => "debug",
+            Self::Info => "info",
+            Self::Warn => "warn",
+            Self::Error => "error",
+        }
+    }
+}
+
+type Logs = Arc<Mutex<VecDeque<(Level, String)>>>;
+
+static COMPONENT_CACHE: LazyLock<WasmComponentCache> = LazyLock::new(|| WasmComponentCache::new(MAX_CACHED_COMPONENTS));
+
+/// A process-wide cache of compiled components keyed by the hash of their WASM binary
+pub struct WasmComponentCache {
+    cache: CompiledCache<Component>,
+}
+
+impl WasmComponentCache {
+    pub fn new(max_components: usize) -> Self {
+        Self { cache: CompiledCache::new(max_components) }
+    }
+
+    /// Returns the cache shared by all runtimes in the process
+    pub fn global() -> &'static WasmComponentCache {
+        &COMPONENT_CACHE
+    }
+
+    /// Returns the compiled component for a WASM binary, compiling (and caching) it if needed
+    pub fn get_or_compile(&self, wasm_bytes: &[u8]) -> wasmtime::Result<Component> {
+        let (_, component) = self.cache.get_or_insert_with(wasm_bytes, |engine, bytes| Component::new(engine, bytes))?;
+        Ok(component)
+    }
+
+    /// Sets the maximum number of cached components, evicting components if needed
+    pub fn set_max_components(&self, max_components: usize) {
+        self.cache.set_max_entries(max_components);
+    }
+
+    pub fn len(&self) -> usize {
+        self.cache.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn clear(&self) {
+        self.cache.clear();
+    }
+}
+
+pub struct ComponentContext {
+    limits: InstanceLimits,
+    budget: ExecutionBudget,
+    killed: CancellationToken,
+    luau_rx: MpscReceiver<bytes::Bytes>,
+    wasm_tx: MpscSender<bytes::Bytes>,
+    logs: Logs,
+    /// Starts at the unix epoch, like the clocks of WASI workers
+    clock: VirtualClock,
+    _epoch: EpochGuard,
+}
+
+/// Starts a new slice (see ``ExecutionBudget::next_slice``)
+fn new_slice(mut ctx: wasmtime::StoreContextMut<'_, ComponentContext>) -> wasmtime::Result<()> {
+    let remaining = ctx.get_fuel()?;
+    let fuel = ctx.data_mut().budget.next_slice(remaining)?;
+    ctx.set_fuel(fuel)?;
+    Ok(())
+}
+
+/// Links the imports of the Khronos world
+fn link_khronos_world(linker: &mut Linker<ComponentContext>) -> wasmtime::Result<()> {
+    let mut messaging = linker.instance("khronos:runtime/messaging@0.1.0")?;
+    messaging.func_wrap_async(
+        "send",
+        |mut store: wasmtime::StoreContextMut<'_, ComponentContext>, (payload,): (Vec<u8>,)| {
+            Box::new(async move {
+                let tx = store.data().wasm_tx.clone();
+                let _ = tx.send(payload.into()).await;
+                new_slice(store.as_context_mut())?; // reset fuel
+                Ok(())
+            })
+        },
+    )?;
+    messaging.func_wrap_async(
+        "recv",
+        |mut store: wasmtime::StoreContextMut<'_, ComponentContext>, (): ()| {
+            Box::new(async move {
+                let killed = store.data().killed.clone();
+                let msg = unless_killed(&killed, store.data_mut().luau_rx.recv()).await?;
+                new_slice(store.as_context_mut())?; // reset fuel
+                Ok((msg.map(|m| m.to_vec()),))
+            })
+        },
+    )?;
+
+    let mut logging = linker.instance("khronos:runtime/logging@0.1.0")?;
+    logging.func_wrap(
+        "log",
+        |store: wasmtime::StoreContextMut<'_, ComponentContext>, (level, message): (Level, String)| {
+            let mut logs = store.data().logs.lock().unwrap_or_else(|e| e.into_inner());
+            if logs.len() >= MAX_LOG_ENTRIES {
+                logs.pop_front();
+            }
+            logs.push_back((level, message));
+            Ok(())
+        },
+    )?;
+
+    let mut time = linker.instance("khronos:runtime/time@0.1.0")?;
+    time.func_wrap("now", |store: wasmtime::StoreContextMut<'_, ComponentContext>, (): ()| {
+        let now = store.data().clock.system_time().duration_since(UNIX_EPOCH).unwrap_or_default();
+        Ok((now.as_millis() as u64,))
+    })?;
+    time.func_wrap("monotonic", |store: wasmtime::StoreContextMut<'_, ComponentContext>, (): ()| {
+        Ok((store.data().clock.elapsed().as_nanos() as u64,))
+    })?;
+
+    Ok(())
+}
+
+/// Options for instantiating a component
+#[derive(Default)]
+pub struct ComponentOptions {
+    /// Maximum memory of this instance (defaults to the shared limit)
+    pub max_memory: Option<usize>,
+    /// Maximum total fuel this instance may use
+    pub max_total_fuel: Option<u64>,
+    /// The time limits of the runtime the instance is created in
+    pub time_limits: WasmTimeLimits,
+}
+
+impl ComponentOptions {
+    pub fn from_lua_table(opts: &LuaTable) -> LuaResult<Self> {
+        Ok(Self {
+            max_memory: opts.get::<Option<usize>>("maxmemory")?,
+            max_total_fuel: opts.get::<Option<u64>>("maxfuel")?,
+            time_limits: WasmTimeLimits::default(),
+        })
+    }
+}
+
+/// The instance and store of a component, taken out while it is running
+struct ComponentRunner {
+    instance: Instance,
+    store: Store<ComponentContext>,
+}
+
+impl ComponentRunner {
+    /// Looks up an export and converts the arguments to it, returning the function, its
+    /// arguments and space for its results
+    fn prepare_call(&mut self, name: &str, args: LuaMultiValue) -> LuaResult<(Func, Vec<Val>, Vec<Val>)> {
+        let func = self
+            .instance
+            .get_func(&mut self.store, name)
+            .ok_or_else(|| LuaError::external(format!("Component does not export a function named '{name}'")))?;
+        let params = func.params(&self.store);
+        if args.len() != params.len() {
+            return Err(LuaError::external(format!(
+                "'{name}' takes {} arguments but {} were given",
+                params.len(),
+                args.len()
+            )));
+        }
+
+        let args = args
+            .iter()
+            .zip(params.iter())
+            .enumerate()
+            .map(|(i, (v, ty))| {
+                lua_to_val(v, ty, 0).map_err(|e| LuaError::external(format!("argument #{}: {e}", i + 1)))
+            })
+            .collect::<LuaResult<Vec<_>>>()?;
+        let results = vec![Val::Bool(false); func.results(&self.store).len()];
+        Ok((func, args, results))
+    }
+}
+
+pub struct WasmComponentState {
+    /// Taken out while the component is running (through start or call)
+    runner: Arc<Mutex<Option<ComponentRunner>>>,
+    status: Arc<Mutex<WorkerStatus>>,
+    killed: CancellationToken,
+    join_handle: Mutex<Option<tokio::task::JoinHandle<Result<(), String>>>>,
+    luau_tx: MpscSender<bytes::Bytes>,
+    wasm_rx: Arc<AsyncMutex<MpscReceiver<bytes::Bytes>>>,
+    logs: Logs,
+}
+
+impl Drop for WasmComponentState {
+    fn drop(&mut self) {
+        self.kill();
+    }
+}
+
+impl WasmComponentState {
+    /// Kills the component. Running WASM traps at the next epoch tick (or is cancelled if awaiting)
+    pub fn kill(&self) {
+        self.killed.cancel();
+        *self.status.lock().unwrap_or_else(|e| e.into_inner()) = WorkerStatus::Killed;
+        if let Some(handle) = self.join_handle.lock().unwrap_or_else(|e| e.into_inner()).as_ref() {
+            handle.abort();
+        }
+
+        self.runner.lock().unwrap_or_else(|e| e.into_inner()).take();
+    }
+
+    pub async fn instantiate(
+        wasm_bytes: &[u8],
+        limits: SharedWasmLimits,
+        max_fuel_per_slice: u64,
+        opts: ComponentOptions,
+    ) -> wasmtime::Result<Self> {
+        let engine = shared_engine()?;
+        let component = WasmComponentCache::global().get_or_compile(wasm_bytes)?;
+
+        let (luau_tx, luau_rx) = mpsc_channel::<bytes::Bytes>(1024);
+        let (wasm_tx, wasm_rx) = mpsc_channel::<bytes::Bytes>(1024);
+        let logs: Logs = Arc::default();
+        let killed = CancellationToken::new();
+
+        let mut store = Store::new(&engine, ComponentContext {
+            limits: InstanceLimits::new(limits, opts.max_memory),
+            budget: ExecutionBudget::new(max_fuel_per_slice, opts.max_total_fuel, opts.time_limits),
+            killed: killed.clone(),
+            luau_rx,
+            wasm_tx,
+            logs: logs.clone(),
+            clock: VirtualClock::new(UNIX_EPOCH, false),
+            _epoch: EpochGuard::new()?,
+        });
+        store.limiter(|ctx| &mut ctx.limits);
+
+        // Yield on every epoch tick, trapping if the component was killed or ran out of time
+        store.set_epoch_deadline(1);
+        store.epoch_deadline_callback(|ctx| {
+            let data = ctx.data();
+            if data.killed.is_cancelled() {
+                return Err(wasmtime::Error::msg("WASM was killed"));
+            }
+            if data.budget.expired() {
+                return Err(wasmtime::Error::msg("WASM execution time limit exceeded"));
+            }
+            Ok(wasmtime::UpdateDeadline::Yield(1))
+        });
+
+        let mut linker = Linker::new(&engine);
+        link_khronos_world(&mut linker)?;
+
+        new_slice(store.as_context_mut())?;
+        let instance = linker.instantiate_async(&mut store, &component).await?;
+
+        Ok(Self {
+            runner: Arc::new(Mutex::new(Some(ComponentRunner { instance, store }))),
+            status: Arc::new(Mutex::new(WorkerStatus::Idle)),
+            killed,
+            join_handle: Mutex::new(None),
+            luau_tx,
+            wasm_rx: Arc::new(AsyncMutex::new(wasm_rx)),
+            logs,
+        })
+    }
+}
+
+impl LuaUserData for WasmComponentState {
+    fn add_fields<F: LuaUserDataFields<Self>>(fields: &mut F) {
+        fields.add_meta_field(LuaMetaMethod::Type, "WasmComponent");
+    }
+
+    fn add_methods<M: LuaUserDataMethods<Self>>(methods: &mut M) {
+        // Send a message from Luau to the component (khronos:runtime/messaging.recv)
+        methods.add_method("send", |_lua, this, payload: Blob| {
+            this.luau_tx
+                .try_send(payload.0)
+                .map_err(|e| LuaError::external(format!("Failed to send message: {e}")))
+        });
+
+        // Receive a message sent by the component (async)
+        methods.add_scheduler_async_method("recv", |_lua, this, ()| {
+            let rx_arc = this.wasm_rx.clone();
+            async move {
+                let mut rx = rx_arc.lock().await;
+                Ok(rx.recv().await.map(Blob))
+            }
+        });
+
+        // Takes the logs collected so far as { level, message } entries
+        methods.add_method("logs", |lua, this, ()| {
+            let logs = std::mem::take(&mut *this.logs.lock().unwrap());
+            let entries = lua.create_table_with_capacity(logs.len(), 0)?;
+            for (level, message) in logs {
+                let entry = lua.create_table()?;
+                entry.set("level", level.as_str())?;
+                entry.set("message", message)?;
+                entries.raw_push(entry)?;
+            }
+            Ok(entries)
+        });
+
+        // Calls an exported function (async), converting arguments and results (see module docs)
+        methods.add_scheduler_async_method("call", |lua, this, (name, args): (String, LuaMultiValue)| {
+            let runner_slot = this.runner.clone();
+            let runner = runner_slot.lock().unwrap().take();
+            let killed = this.killed.clone();
+            async move {
+                let Some(runner) = runner else {
+                    return Err(LuaError::external("Component is running, being called or was killed and cannot be called"));
+                };
+                let mut guard = RunnerGuard { slot: runner_slot, runner: Some(runner), killed };
+                let runner = guard.runner.as_mut().expect("the guard holds the runner until dropped");
+                let (func, args, mut results) = runner.prepare_call(&name, args)?;
+
+                // The store is Send, so run the call off the Luau thread
+                let handle = tokio::spawn(async move {
+                    let runner = guard.runner.as_mut().expect("the guard holds the runner until dropped");
+                    let res = async {
+                        new_slice(runner.store.as_context_mut())?;
+                        func.call_async(&mut runner.store, &args, &mut results).await?;
+                        func.post_return_async(&mut runner.store).await
+                    }
+                    .await;
+                    drop(guard);
+                    res.map(|_| results)
+                });
+                let res = handle.await.map_err(LuaError::external)?;
+
+                res.map_err(|e| LuaError::external(format!("{e:#}")))?
+                    .iter()
+                    .map(|v| val_to_lua(&lua, v, 0))
+                    .collect::<LuaResult<LuaMultiValue>>()
+            }
+        });
+
+        // Starts the component's run export (synchronous, spawns tokio task)
+        methods.add_method("start", |_lua, this, ()| {
+            let Some(ComponentRunner { instance, mut store }) = this.runner.lock().unwrap().take() else {
+                return Err(LuaError::external("Component is already running or has finished"));
+            };
+
+            let status = this.status.clone();
+            *status.lock().unwrap() = WorkerStatus::Running;
+            let handle = tokio::spawn(async move {
+                let res = async {
+                    let func = instance.get_typed_func::<(), (Result<(), String>,)>(&mut store, "run")?;
+                    new_slice(store.as_context_mut())?;
+                    let (res,) = func.call_async(&mut store, ()).await?;
+                    func.post_return_async(&mut store).await?;
+                    Ok::<_, wasmtime::Error>(res)
+                }
+                .await;
+
+                let res = match res {
+                    Ok(Ok(())) => Ok(()),
+                    Ok(Err(e)) => Err(format!("Component run failed: {e}")),
+                    Err(e) => Err(format!("{e:#}")),
+                };
+
+                let mut status = status.lock().unwrap_or_else(|e| e.into_inner());
+                if !matches!(*status, WorkerStatus::Killed) {
+                    *status = match &res {
+                        Ok(()) => WorkerStatus::Finished,
+                        Err(e) => WorkerStatus::Trapped(e.clone()),
+                    };
+                }
+                res
+            });
+
+            *this.join_handle.lock().unwrap() = Some(handle);
+            Ok(())
+        });
+
+        // Stops the component, dropping its instance (and memory)
+        methods.add_method("kill", |_lua, this, ()| {
+            this.kill();
+            Ok(())
+        });
+
+        // Returns the component's status (idle, running, finished, trapped or killed) and the error if it trapped
+        methods.add_method("status", |_lua, this, ()| {
+            let status = this.status.lock().unwrap().clone();
+            let message = match &status {
+                WorkerStatus::Trapped(msg) => Some(msg.clone()),
+                _ => None,
+            };
+            Ok((status.as_str(), message))
+        });
+
+        // Wait for the run export to return
+        methods.add_scheduler_async_method("wait", |_lua, this, ()| {
+            let handle = this.join_handle.lock().unwrap().take();
+            async move {
+                let Some(handle) = handle else {
+                    return Err(LuaError::external("Component is not running or has already been waited on"));
+                };
+
+                match handle.await {
+                    Ok(res) => res.map_err(LuaError::external),
+                    Err(e) if e.is_cancelled() => Err(LuaError::external("WASM was killed")),
+                    Err(e) => Err(LuaError::external(e)),
+                }
+            }
+        });
+    }
+}
+
+fn lua_integer(value: &LuaValue) -> LuaResult<i128> {
+    match value {
+        LuaValue::Integer(i) => Ok(*i as i128),
+        LuaValue::Int64(i) => Ok(*i as i128),
+        LuaValue::Number(n) if n.fract() == 0.0 && n.is_finite() => Ok(*n as i128),
+        _ => Err(LuaError::external(format!("Expected an integer, got {}", value.type_name()))),
+    }
+}
+
+fn lua_number(value: &LuaValue) -> LuaResult<f64> {
+    match value {
+        LuaValue::Integer(i) | LuaValue::Int64(i) => Ok(*i as f64),
+        LuaValue::Number(n) => Ok(*n),
+        _ => Err(LuaError::external(format!("Expected a number, got {}", value.type_name()))),
+    }
+}
+
+fn lua_string(value: &LuaValue) -> LuaResult<String> {
+    match value {
+        LuaValue::String(s) => Ok(s.to_str()?.to_string()),
+        _ => Err(LuaError::external(format!("Expected a string, got {}", value.type_name()))),
+    }
+}
+
+fn lua_table(value: &LuaValue) -> LuaResult<&LuaTable> {
+    match value {
+        LuaValue::Table(t) => Ok(t),
+        _ => Err(LuaError::external(format!("Expected a table, got {}", value.type_name()))),
+    }
+}
+
+/// Converts a Luau value into a WIT value of the given type
+fn lua_to_val(value: &LuaValue, ty: &Type, depth: usize) -> LuaResult<Val> {
+    if depth > MAX_VALUE_DEPTH {
+        return Err(LuaError::external(format!("Value is nested deeper than {MAX_VALUE_DEPTH} levels")));
+    }
+
+    macro_rules! int {
+        ($variant:ident, $t:ty) => {{
+            let i = lua_integer(value)?;
+            Val::$variant(<$t>::try_from(i).map_err(|_| {
+                LuaError::external(format!("{i} is out of range for {}", stringify!($t)))
+            })?)
+        }};
+    }
+
+    let err = |e: wasmtime::Error| LuaError::external(format!("{e:#}"));
+    Ok(match ty {
+        Type::Bool => match value {
+            LuaValue::Boolean(b) => Val::Bool(*b),
+            _ => return Err(LuaError::external(format!("Expected a boolean, got {}", value.type_name()))),
+        },
+        Type::S8 => int!(S8, i8),
+        Type::U8 => int!(U8, u8),
+        Type::S16 => int!(S16, i16),
+        Type::U16 => int!(U16, u16),
+        Type::S32 => int!(S32, i32),
+        Type::U32 => int!(U32, u32),
+        Type::S64 => int!(S64, i64),
+        Type::U64 => int!(U64, u64),
+        Type::Float32 => Val::Float32(lua_number(value)? as f32),
+        Type::Float64 => Val::Float64(lua_number(value)?),
+        Type::Char => {
+            let s = lua_string(value)?;
+            let mut chars = s.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => Val::Char(c),
+                _ => return Err(LuaError::external("Expected a single character string")),
+            }
+        }
+        Type::String => Val::String(lua_string(value)?.into()),
+        Type::List(list) if matches!(list.ty(), Type::U8) && !matches!(value, LuaValue::Table(_)) => {
+            let bytes = crate::primitives::blob::blob_ref(value, |b| b.iter().map(|b| Val::U8(*b)).collect::<Vec<_>>())?;
+            list.new_val(bytes.into()).map_err(err)?
+        }
+        Type::List(list) => {
+            let elem = list.ty();
+            let values = lua_table(value)?
+                .sequence_values::<LuaValue>()
+                .map(|v| lua_to_val(&v?, &elem, depth + 1))
+                .collect::<LuaResult<Vec<_>>>()?;
+            list.new_val(values.into()).map_err(err)?
+        }
+        Type::Record(record) => {
+            let table = lua_table(value)?;
+            let mut fields = Vec::new();
+            for field in record.fields() {
+                let v = table.get::<LuaValue>(field.name)?;
+                let v = lua_to_val(&v, &field.ty, depth + 1)
+                    .map_err(|e| LuaError::external(format!("field '{}': {e}", field.name)))?;
+                fields.push((field.name, v));
+            }
+            record.new_val(fields).map_err(err)?
+        }
+        Type::Tuple(tuple) => {
+            let table = lua_table(value)?;
+            let values = tuple
+                .types()
+                .enumerate()
+                .map(|(i, ty)| lua_to_val(&table.raw_get::<LuaValue>(i + 1)?, &ty, depth + 1))
+                .collect::<LuaResult<Vec<_>>>()?;
+            tuple.new_val(values.into()).map_err(err)?
+        }
+        Type::Variant(variant) => {
+            let table = lua_table(value)?;
+            let tag = table.get::<String>("tag")?;
+            let Some(case) = variant.cases().find(|c| c.name == tag) else {
+                return Err(LuaError::external(format!("Unknown variant case '{tag}'")));
+            };
+            let payload = match &case.ty {
+                Some(ty) => Some(lua_to_val(&table.get::<LuaValue>("value")?, ty, depth + 1)?),
+                None => None,
+            };
+            variant.new_val(&tag, payload).map_err(err)?
+        }
+        Type::Enum(enum_) => enum_.new_val(&lua_string(value)?).map_err(err)?,
+        Type::Option(option) => match value {
+            LuaValue::Nil => option.new_val(None).map_err(err)?,
+            v => option.new_val(Some(lua_to_val(v, &option.ty(), depth + 1)?)).map_err(err)?,
+        },
+        Type::Result(result) => {
+            let table = lua_table(value)?;
+            let (ok, err_value) = (table.get::<LuaValue>("ok")?, table.get::<LuaValue>("err")?);
+            let value = match (&ok, result.ok(), result.err()) {
+                (LuaValue::Nil, _, Some(ty)) if !err_value.is_nil() => Err(Some(lua_to_val(&err_value, &ty, depth + 1)?)),
+                (LuaValue::Nil, _, None) if !err_value.is_nil() => Err(None),
+                (_, Some(ty), _) => Ok(Some(lua_to_val(&ok, &ty, depth + 1)?)),
+                (_, None, _) => Ok(None),
+            };
+            result.new_val(value).map_err(err)?
+        }
+        Type::Flags(flags) => {
+            let names = lua_table(value)?.sequence_values::<String>().collect::<LuaResult<Vec<_>>>()?;
+            flags.new_val(&names.iter().map(|n| n.as_str()).collect::<Vec<_>>()).map_err(err)?
+        }
+        Type::Own(_) | Type::Borrow(_) => return Err(LuaError::external("Resources are not supported")),
+    })
+}
+
+/// Converts a WIT value into a Luau value
+fn val_to_lua(lua: &Lua, value: &Val, depth: usize) -> LuaResult<LuaValue> {
+    if depth > MAX_VALUE_DEPTH {
+        return Err(LuaError::external(format!("Value is nested deeper than {MAX_VALUE_DEPTH} levels")));
+    }
+
+    Ok(match value {
+        Val::Bool(b) => LuaValue::Boolean(*b),
+        Val::S8(i) => LuaValue::Integer(*i as i64),
+        Val::U8(i) => LuaValue::Integer(*i as i64),
+        Val::S16(i) => LuaValue::Integer(*i as i64),
+        Val::U16(i) => LuaValue::Integer(*i as i64),
+        Val::S32(i) => LuaValue::Integer(*i as i64),
+        Val::U32(i) => LuaValue::Integer(*i as i64),
+        Val::S64(i) => integer_to_lua(*i),
+        Val::U64(i) => match i64::try_from(*i) {
+            Ok(i) => integer_to_lua(i),
+            Err(_) => LuaValue::Number(*i as f64),
+        },
+        Val::Float32(f) => LuaValue::Number(*f as f64),
+        Val::Float64(f) => LuaValue::Number(*f),
+        Val::Char(c) => LuaValue::String(lua.create_string(c.to_string())?),
+        Val::String(s) => LuaValue::String(lua.create_string(&**s)?),
+        Val::List(list) if matches!(list.ty().ty(), Type::U8) => {
+            let bytes = list
+                .iter()
+                .map(|v| match v {
+                    Val::U8(b) => *b,
+                    _ => 0,
+                })
+                .collect::<Vec<_>>();
+            Blob(bytes.into()).into_lua(lua)?
+        }
+        Val::List(list) => {
+            let table = lua.create_table_with_capacity(list.len(), 0)?;
+            for v in list.iter() {
+                table.raw_push(val_to_lua(lua, v, depth + 1)?)?;
+            }
+            LuaValue::Table(table)
+        }
+        Val::Record(record) => {
+            let table = lua.create_table()?;
+            for (name, v) in record.fields() {
+                table.raw_set(name, val_to_lua(lua, v, depth + 1)?)?;
+            }
+            LuaValue::Table(table)
+        }
+        Val::Tuple(tuple) => {
+            let table = lua.create_table_with_capacity(tuple.values().len(), 0)?;
+            for v in tuple.values() {
+                table.raw_push(val_to_lua(lua, v, depth + 1)?)?;
+            }
+            LuaValue::Table(table)
+        }
+        Val::Variant(variant) => {
+            let table = lua.create_table()?;
+            table.raw_set("tag", variant.discriminant())?;
+            if let Some(payload) = variant.payload() {
+                table.raw_set("value", val_to_lua(lua, payload, depth + 1)?)?;
+            }
+            LuaValue::Table(table)
+        }
+        Val::Enum(enum_) => LuaValue::String(lua.create_string(enum_.discriminant())?),
+        Val::Option(option) => match option.value() {
+            Some(v) => val_to_lua(lua, v, depth + 1)?,
+            None => LuaValue::Nil,
+        },
+        Val::Result(result) => {
+            let table = lua.create_table()?;
+            match result.value() {
+                Ok(v) => table.raw_set("ok", v.map(|v| val_to_lua(lua, v, depth + 1)).transpose()?.unwrap_or(LuaValue::Boolean(true)))?,
+                Err(v) => table.raw_set("err", v.map(|v| val_to_lua(lua, v, depth + 1)).transpose()?.unwrap_or(LuaValue::Boolean(true)))?,
+            }
+            LuaValue::Table(table)
+        }
+        Val::Flags(flags) => LuaValue::Table(lua.create_sequence_from(flags.flags().map(|f| f.to_string()))?),
+        Val::Resource(_) => return Err(LuaError::external("Resources are not supported")),
+    })
+}
+
+/// Parses an instantiation options table, rejecting options only core modules support
+pub(crate) fn parse_component_options(opts: &LuaTable) -> LuaResult<ComponentOptions> {
+    for key in ["wasi", "imports", "importmodule"] {
+        if !opts.get::<LuaValue>(key)?.is_nil() {
+            return Err(LuaError::external(format!("opts.{key} is not supported for components")));
+        }
+    }
+
+    ComponentOptions::from_lua_table(opts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rt::testutils::run_script;
+    use crate::rt::RuntimeCreateOpts;
+
+    #[test]
+    fn test_component_values() -> LuaResult<()> {
+        let lua = Lua::new();
+        let s = match lua_to_val(&LuaValue::String(lua.create_string("hi")?), &Type::String, 0)? {
+            Val::String(s) => s,
+            _ => unreachable!(),
+        };
+        assert_eq!(&*s, "hi");
+
+        assert!(lua_to_val(&LuaValue::Integer(256), &Type::U8, 0).is_err());
+        assert!(lua_to_val(&LuaValue::Number(1.5), &Type::S32, 0).is_err());
+        assert!(matches!(lua_to_val(&LuaValue::Number(3.0), &Type::S32, 0)?, Val::S32(3)));
+
+        assert!(matches!(val_to_lua(&lua, &Val::U64(u64::MAX), 0)?, LuaValue::Number(_)));
+        assert!(matches!(val_to_lua(&lua, &Val::Char('x'), 0)?, LuaValue::String(s) if s.to_str()? == "x"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_component_instantiate() {
+        let rt = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        rt.block_on(async {
+            let limits = SharedWasmLimits {
+                max_memory: 1024 * 1024,
+                allocated_memory: Arc::default(),
+            };
+            let state = WasmComponentState::instantiate(b"(component)", limits, 10_000, ComponentOptions::default())
+                .await
+                .unwrap();
+            assert!(state.runner.lock().unwrap().is_some());
+            state.kill();
+            assert!(state.runner.lock().unwrap().is_none());
+        });
+    }
+
+    #[test]
+    fn test_component_cache() {
+        let cache = WasmComponentCache::new(1);
+        cache.get_or_compile(b"(component)").unwrap();
+        cache.get_or_compile(b"(component)").unwrap();
+        assert_eq!(cache.len(), 1);
+
+        // Evicts the first component
+        cache.get_or_compile(b"(component (core module))").unwrap();
+        assert_eq!(cache.len(), 1);
+        assert!(cache.cache.get(&crate::core::wasm::module_hash(b"(component)")).is_none());
+    }
+
+    /// Exports functions taking and returning records, lists and strings, and a run export that succeeds
+    const EXPORTS_COMPONENT_WAT: &str = r#"
+        (component
+            (core module $m
+                (memory (export "memory") 1)
+                (global $next (mut i32) (i32.const 1024))
+                ;; Bump allocator used to lower arguments into the component
+                (func (export "realloc") (param i32 i32 i32 i32) (result i32)
+                    (local $ptr i32)
+                    (local.set $ptr
+                        (i32.and
+                            (i32.add (global.get $next) (i32.sub (local.get 2) (i32.const 1)))
+                            (i32.sub (i32.const 0) (local.get 2))))
+                    (global.set $next (i32.add (local.get $ptr) (local.get 3)))
+                    (local.get $ptr))
+                (func (export "swap") (param i32 i32) (result i32)
+                    (i32.store (i32.const 0) (local.get 1))
+                    (i32.store (i32.const 4) (local.get 0))
+                    (i32.const 0))
+                (func (export "sum") (param $ptr i32) (param $len i32) (result i64)
+                    (local $total i64)
+                    (block $done
+                        (loop $next
+                            (br_if $done (i32.eqz (local.get $len)))
+                            (local.set $total
+                                (i64.add (local.get $total) (i64.extend_i32_u (i32.load (local.get $ptr)))))
+                            (local.set $ptr (i32.add (local.get $ptr) (i32.const 4)))
+                            (local.set $len (i32.sub (local.get $len) (i32.const 1)))
+                            (br $next)))
+                    (local.get $total))
+                (func (export "echo") (param i32 i32) (result i32)
+                    (i32.store (i32.const 8) (local.get 0))
+                    (i32.store (i32.const 12) (local.get 1))
+                    (i32.const 8))
+                ;; Points at zeroed memory, which is an ok result
+                (func (export "run") (result i32)
+                    (i32.const 16)))
+            (core instance $i (instantiate $m))
+
+            (type $point (record (field "x" s32) (field "y" s32)))
+            (export $point-export "point" (type $point))
+
+            (func (export "swap") (param "p" $point-export) (result $point-export)
+                (canon lift (core func $i "swap") (memory $i "memory")))
+            (func (export "sum") (param "values" (list u32)) (result u64)
+                (canon lift (core func $i "sum") (memory $i "memory") (realloc (func $i "realloc"))))
+            (func (export "echo") (param "s" string) (result string)
+                (canon lift (core func $i "echo") (memory $i "memory") (realloc (func $i "realloc"))))
+            (func (export "run") (result (result (error string)))
+                (canon lift (core func $i "run") (memory $i "memory"))))
+    "#;
+
+    #[test]
+    fn test_component_exports() {
+        let script = r#"
+            return function(guest)
+                local wasm = require"@antiraid/wasm"
+                local component = wasm.newcomponent(guest)
+
+                local p = component:call("swap", { x = 1, y = -2 })
+                assert(p.x == -2 and p.y == 1)
+                assert(component:call("sum", { 1, 2, 3, 4000000000 }) == 4000000006)
+                assert(component:call("sum", {}) == 0)
+                assert(component:call("echo", "héllo") == "héllo")
+
+                local ok, err = pcall(component.call, component, "swap", { x = "a", y = 1 })
+                assert(not ok and string.find(tostring(err), "field 'x'"), tostring(err))
+                local ok, err = pcall(component.call, component, "echo")
+                assert(not ok and string.find(tostring(err), "takes 1 arguments"), tostring(err))
+
+                -- The run export is started in the background
+                component:start()
+                component:wait()
+                assert(component:status() == "finished")
+                assert(not pcall(component.call, component, "swap", { x = 1, y = 2 }))
+            end
+        "#;
+
+        run_script(RuntimeCreateOpts::default(), script, |lua| lua.create_string(EXPORTS_COMPONENT_WAT)).unwrap();
+    }
+
+    /// Imports the Khronos world. Its run export logs, sends "ready", echoes one message back and
+    /// logs again, and its now/monotonic exports return the time interface's clocks
+    const IMPORTS_COMPONENT_WAT: &str = r#"
+        (component
+            (import "khronos:runtime/messaging@0.1.0" (instance $messaging
+                (export "send" (func (param "payload" (list u8))))
+                (export "recv" (func (result (option (list u8)))))))
+            (import "khronos:runtime/logging@0.1.0" (instance $logging
+                (type $level (enum "trace" "debug" "info" "warn" "error"))
+                (export "level" (type $level-export (eq $level)))
+                (export "log" (func (param "level" $level-export) (param "message" string)))))
+            (import "khronos:runtime/time@0.1.0" (instance $time
+                (export "now" (func (result u64)))
+                (export "monotonic" (func (result u64)))))
+
+            (core module $libc
+                (memory (export "memory") 1)
+                (global $next (mut i32) (i32.const 1024))
+                (func (export "realloc") (param i32 i32 i32 i32) (result i32)
+                    (local $ptr i32)
+                    (local.set $ptr
+                        (i32.and
+                            (i32.add (global.get $next) (i32.sub (local.get 2) (i32.const 1)))
+                            (i32.sub (i32.const 0) (local.get 2))))
+                    (global.set $next (i32.add (local.get $ptr) (local.get 3)))
+                    (local.get $ptr)))
+            (core instance $libc (instantiate $libc))
+
+            (core func $send (canon lower (func $messaging "send") (memory $libc "memory")))
+            (core func $recv (canon lower (func $messaging "recv") (memory $libc "memory") (realloc (func $libc "realloc"))))
+            (core func $log (canon lower (func $logging "log") (memory $libc "memory")))
+            (core func $now (canon lower (func $time "now")))
+            (core func $monotonic (canon lower (func $time "monotonic")))
+
+            (core module $m
+                (import "libc" "memory" (memory 1))
+                (import "host" "send" (func $send (param i32 i32)))
+                (import "host" "recv" (func $recv (param i32)))
+                (import "host" "log" (func $log (param i32 i32 i32)))
+                (import "host" "now" (func $now (result i64)))
+                (import "host" "monotonic" (func $monotonic (result i64)))
+                (data (i32.const 0) "ready")
+                (data (i32.const 8) "starting")
+                (data (i32.const 16) "done")
+                (func (export "run") (result i32)
+                    (call $log (i32.const 2) (i32.const 8) (i32.const 8))
+                    (call $send (i32.const 0) (i32.const 5))
+                    ;; option<list<u8>>: the case at 32, the list's pointer and length at 36 and 40
+                    (call $recv (i32.const 32))
+                    (if (i32.load8_u (i32.const 32))
+                        (then (call $send (i32.load (i32.const 36)) (i32.load (i32.const 40)))))
+                    (call $log (i32.const 3) (i32.const 16) (i32.const 4))
+                    ;; Points at zeroed memory, which is an ok result
+                    (i32.const 48))
+                (func (export "now") (result i64)
+                    (call $now))
+                (func (export "monotonic") (result i64)
+                    (call $monotonic)))
+            (core instance $i (instantiate $m
+                (with "libc" (instance $libc))
+                (with "host" (instance
+                    (export "send" (func $send))
+                    (export "recv" (func $recv))
+                    (export "log" (func $log))
+                    (export "now" (func $now))
+                    (export "monotonic" (func $monotonic))))))
+
+            (func (export "run") (result (result (error string)))
+                (canon lift (core func $i "run") (memory $libc "memory")))
+            (func (export "now") (result u64)
+                (canon lift (core func $i "now")))
+            (func (export "monotonic") (result u64)
+                (canon lift (core func $i "monotonic"))))
+    "#;
+
+    #[test]
+    fn test_component_imports() {
+        let script = r#"
+            return function(guest)
+                local wasm = require"@antiraid/wasm"
+                local component = wasm.newcomponent(guest)
+
+                -- The clocks start at the unix epoch and zero rather than the host's time
+                assert(component:call("now") < 60000)
+                assert(component:call("monotonic") < 60e9)
+
+                component:start()
+                assert(buffer.tostring(component:recv()) == "ready")
+                component:send("hello")
+                assert(buffer.tostring(component:recv()) == "hello")
+                component:wait()
+                assert(component:status() == "finished")
+
+                local logs = component:logs()
+                assert(#logs == 2)
+                assert(logs[1].level == "info" and logs[1].message == "starting")
+                assert(logs[2].level == "warn" and logs[2].message == "done")
+                assert(#component:logs() == 0)
+            end
+        "#;
+
+        run_script(RuntimeCreateOpts::default(), script, |lua| lua.create_string(IMPORTS_COMPONENT_WAT)).unwrap();
+    }
+}
@@ -0,0 +1,347 @@
+//! Shared regions of WASM linear memory
+//!
+//! A guest registers a window of its memory with ``region_send(ptr: u32, len: u32) -> u32``,
+//! which returns the region's id, and then hands it to Luau with ``region_wait(id: u32) -> u32``.
+//! Luau receives it (``WasmState:recvregion()``) as a ``SharedRegion`` and calls
+//! ``SharedRegion:release()`` once done, after which ``region_wait`` returns ``1`` (or ``0`` if the
+//! worker is being torn down).
+//!
+//! Luau can only touch a region while the guest is parked in ``region_wait`` for it, so guest code
+//! never runs (or grows and moves its memory) during an access. Accessors copy directly between
+//! guest memory and Luau values/buffers: ``readinto``/``write`` copy once, while ``read`` copies
+//! into a new buffer. Every access is bounds checked and regions are revoked before the guest's
+//! memory is freed, so a misbehaving guest can only corrupt its own memory.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use mluau::prelude::*;
+use tokio::sync::Notify;
+
+use crate::primitives::blob::Blob;
+
+/// Maximum number of regions a worker may have shared at once
+pub const MAX_SHARED_REGIONS: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Owner {
+    Host,
+    Guest,
+    /// The guest's memory is gone (or going away)
+    Revoked,
+}
+
+struct RegionState {
+    owner: Owner,
+    /// Address of the start of the window in the host's address space, only set while the guest
+    /// is parked in region_wait for the region
+    base: Option<usize>,
+}
+
+pub struct RegionInner {
+    id: u32,
+    /// Offset of the window in the guest's linear memory
+    ptr: usize,
+    len: usize,
+    state: Mutex<RegionState>,
+    notify: Notify,
+}
+
+impl RegionInner {
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    fn state(&self) -> MutexGuard<'_, RegionState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn set_owner(&self, owner: Owner) {
+        self.state().owner = owner;
+        self.notify.notify_waiters();
+    }
+
+    /// Hands the region back to the guest, returning false if the host did not own it
+    fn release(&self) -> bool {
+        let mut state = self.state();
+        if state.owner != Owner::Host {
+            return false;
+        }
+        state.owner = Owner::Guest;
+        drop(state);
+        self.notify.notify_waiters();
+        true
+    }
+
+    /// Makes the region accessible to Luau while the guest is parked waiting for it
+    ///
+    /// ``memory`` must be the guest's whole linear memory, which cannot move or be freed until
+    /// the returned guard is dropped (the guest is not running and regions are revoked before
+    /// the memory is freed)
+    pub fn park(self: &Arc<Self>, memory: &mut [u8]) -> Result<ParkedRegion, String> {
+        if self.ptr + self.len > memory.len() {
+            return Err("Shared region is out of bounds".to_string());
+        }
+        self.state().base = Some(memory.as_mut_ptr() as usize + self.ptr);
+        Ok(ParkedRegion(self.clone()))
+    }
+
+    /// Waits until the host releases the region, returning false if it was revoked instead
+    pub async fn wait_released(&self) -> bool {
+        loop {
+            let notified = self.notify.notified();
+            match self.state().owner {
+                Owner::Guest => return true,
+                Owner::Revoked => return false,
+                Owner::Host => {}
+            }
+            notified.await;
+        }
+    }
+}
+
+#[derive(Default)]
+struct Regions {
+    shared: HashMap<u32, Arc<RegionInner>>,
+    next_id: u32,
+    /// Set once the guest's memory is (about to be) freed, after which nothing can be shared
+    revoked: bool,
+}
+
+/// The regions a worker has shared, revoked when its memory is freed
+#[derive(Default, Clone)]
+pub struct RegionRegistry {
+    regions: Arc<Mutex<Regions>>,
+}
+
+impl RegionRegistry {
+    fn lock(&self) -> MutexGuard<'_, Regions> {
+        self.regions.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Registers a window of guest memory (of ``memory_len`` bytes) as owned by the host
+    pub fn share(&self, memory_len: usize, ptr: u32, len: u32) -> Result<Arc<RegionInner>, String> {
+        let (ptr, len) = (ptr as usize, len as usize);
+        if ptr.checked_add(len).is_none_or(|end| end > memory_len) {
+            return Err("Shared region is out of bounds".to_string());
+        }
+
+        let mut regions = self.lock();
+        if regions.revoked {
+            return Err("Shared regions have been revoked".to_string());
+        }
+        if regions.shared.len() >= MAX_SHARED_REGIONS {
+            return Err("Too many shared regions".to_string());
+        }
+        if regions.shared.values().any(|r| r.ptr < ptr + len && ptr < r.ptr + r.len) {
+            return Err("Shared region overlaps an already shared region".to_string());
+        }
+
+        regions.next_id = regions.next_id.wrapping_add(1);
+        let region = Arc::new(RegionInner {
+            id: regions.next_id,
+            ptr,
+            len,
+            state: Mutex::new(RegionState { owner: Owner::Host, base: None }),
+            notify: Notify::new(),
+        });
+        regions.shared.insert(region.id, region.clone());
+        Ok(region)
+    }
+
+    pub fn get(&self, id: u32) -> Option<Arc<RegionInner>> {
+        self.lock().shared.get(&id).cloned()
+    }
+
+    pub fn remove(&self, id: u32) {
+        self.lock().shared.remove(&id);
+    }
+
+    /// Revokes every region for good. Must be called before the guest's memory is freed
+    pub fn revoke_all(&self) {
+        let mut regions = self.lock();
+        regions.revoked = true;
+        for (_, region) in regions.shared.drain() {
+            // Waits for any access in progress to finish
+            region.set_owner(Owner::Revoked);
+        }
+    }
+}
+
+/// Keeps a region accessible to Luau until dropped, which happens before the guest resumes
+pub struct ParkedRegion(Arc<RegionInner>);
+
+impl Drop for ParkedRegion {
+    fn drop(&mut self) {
+        // Waits for any access in progress to finish
+        self.0.state().base = None;
+    }
+}
+
+/// A window of WASM memory owned by Luau until released
+pub struct SharedRegion {
+    inner: Arc<RegionInner>,
+}
+
+impl SharedRegion {
+    pub fn new(inner: Arc<RegionInner>) -> Self {
+        Self { inner }
+    }
+
+    /// Runs ``f`` with the region's memory while Luau owns it and the guest is parked waiting for it
+    fn with_memory<R>(&self, offset: usize, len: usize, f: impl FnOnce(*mut u8) -> R) -> LuaResult<R> {
+        let state = self.inner.state();
+        let base = match (state.owner, state.base) {
+            (Owner::Host, Some(base)) => base,
+            (Owner::Host, None) => {
+                return Err(LuaError::external("Shared region is not accessible while the guest is running"))
+            }
+            (Owner::Guest, _) => return Err(LuaError::external("Shared region has been released")),
+            (Owner::Revoked, _) => return Err(LuaError::external("Shared region was revoked")),
+        };
+
+        if offset.checked_add(len).is_none_or(|end| end > self.inner.len) {
+            return Err(LuaError::external(format!(
+                "Access of {len} bytes at offset {offset} is out of bounds of the {} byte region",
+                self.inner.len
+            )));
+        }
+
+        // Holding the state lock keeps the guest parked and the region from being revoked (and the
+        // memory freed) during the access, so nothing else touches the memory concurrently
+        Ok(f((base + offset) as *mut u8))
+    }
+
+    fn read_array<const N: usize>(&self, offset: usize) -> LuaResult<[u8; N]> {
+        let mut out = [0u8; N];
+        // SAFETY: with_memory checks the range is inside the (live) region
+        self.with_memory(offset, N, |p| unsafe { std::ptr::copy_nonoverlapping(p, out.as_mut_ptr(), N) })?;
+        Ok(out)
+    }
+
+    fn write_bytes(&self, offset: usize, data: &[u8]) -> LuaResult<()> {
+        // SAFETY: with_memory checks the range is inside the (live) region
+        self.with_memory(offset, data.len(), |p| unsafe {
+            std::ptr::copy_nonoverlapping(data.as_ptr(), p, data.len())
+        })
+    }
+}
+
+impl Drop for SharedRegion {
+    fn drop(&mut self) {
+        // Hand the region back to the guest if Luau forgot to
+        self.inner.release();
+    }
+}
+
+impl LuaUserData for SharedRegion {
+    fn add_fields<F: LuaUserDataFields<Self>>(fields: &mut F) {
+        fields.add_meta_field(LuaMetaMethod::Type, "SharedRegion");
+        fields.add_field_method_get("id", |_, this| Ok(this.inner.id));
+        fields.add_field_method_get("len", |_, this| Ok(this.inner.len));
+        fields.add_field_method_get("owned", |_, this| Ok(this.inner.state().owner == Owner::Host));
+    }
+
+    fn add_methods<M: LuaUserDataMethods<Self>>(methods: &mut M) {
+        methods.add_method("readu8", |_, this, offset: usize| {
+            Ok(this.read_array::<1>(offset)?[0])
+        });
+
+        methods.add_method("writeu8", |_, this, (offset, value): (usize, u8)| {
+            this.write_bytes(offset, &[value])
+        });
+
+        methods.add_method("readu32", |_, this, offset: usize| {
+            Ok(u32::from_le_bytes(this.read_array::<4>(offset)?))
+        });
+
+        methods.add_method("writeu32", |_, this, (offset, value): (usize, u32)| {
+            this.write_bytes(offset, &value.to_le_bytes())
+        });
+
+        methods.add_method("readf64", |_, this, offset: usize| {
+            Ok(f64::from_le_bytes(this.read_array::<8>(offset)?))
+        });
+
+        methods.add_method("writef64", |_, this, (offset, value): (usize, f64)| {
+            this.write_bytes(offset, &value.to_le_bytes())
+        });
+
+        // Copies len bytes (the rest of the region by default) into a new buffer. Use readinto to
+        // avoid allocating a buffer per read
+        methods.add_method("read", |_, this, (offset, len): (Option<usize>, Option<usize>)| {
+            let offset = offset.unwrap_or(0);
+            let len = len.unwrap_or_else(|| this.inner.len.saturating_sub(offset));
+            let out = this.with_memory(offset, len, |p| {
+                // SAFETY: with_memory checks the range is inside the (live) region
+                unsafe { std::slice::from_raw_parts(p, len) }.to_vec()
+            })?;
+            Ok(Blob(out.into()))
+        });
+
+        // Copies len bytes at offset directly into an existing buffer at bufferoffset
+        methods.add_method(
+            "readinto",
+            |_, this, (buffer, bufferoffset, offset, len): (LuaBuffer, usize, usize, usize)| {
+                if bufferoffset.checked_add(len).is_none_or(|end| end > buffer.len()) {
+                    return Err(LuaError::external("Buffer is too small"));
+                }
+
+                this.with_memory(offset, len, |p| {
+                    // SAFETY: with_memory checks the range is inside the (live) region
+                    buffer.write_bytes(bufferoffset, unsafe { std::slice::from_raw_parts(p, len) })
+                })
+            },
+        );
+
+        // Copies a string or buffer into the region at offset
+        methods.add_method("write", |_, this, (offset, data): (usize, LuaValue)| {
+            crate::primitives::blob::blob_ref(&data, |data| this.write_bytes(offset, data))?
+        });
+
+        methods.add_method("fill", |_, this, (value, offset, len): (u8, Option<usize>, Option<usize>)| {
+            let offset = offset.unwrap_or(0);
+            let len = len.unwrap_or_else(|| this.inner.len.saturating_sub(offset));
+            // SAFETY: with_memory checks the range is inside the (live) region
+            this.with_memory(offset, len, |p| unsafe { std::ptr::write_bytes(p, value, len) })
+        });
+
+        // Hands the region back to the guest. The region cannot be used afterwards
+        methods.add_method("release", |_, this, ()| {
+            if !this.inner.release() {
+                return Err(LuaError::external("Shared region is not owned by Luau"));
+            }
+            Ok(())
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shared_region() {
+        let mut memory = vec![0u8; 64];
+        let registry = RegionRegistry::default();
+        assert!(registry.share(memory.len(), 60, 8).is_err());
+        assert!(registry.share(memory.len(), u32::MAX, 2).is_err());
+
+        let inner = registry.share(memory.len(), 8, 16).unwrap();
+        assert!(registry.share(memory.len(), 20, 4).is_err()); // Overlaps
+        let region = SharedRegion::new(inner.clone());
+        assert!(region.read_array::<1>(0).is_err()); // Guest not parked yet
+
+        let parked = inner.park(&mut memory).unwrap();
+        region.write_bytes(0, &[1, 2, 3]).unwrap();
+        assert_eq!(region.read_array::<2>(1).unwrap(), [2, 3]);
+        assert!(region.write_bytes(15, &[0, 0]).is_err());
+        drop(parked);
+        assert!(region.read_array::<1>(0).is_err()); // Guest resumed
+
+        let _parked = inner.park(&mut memory).unwrap();
+        registry.revoke_all();
+        assert!(region.read_array::<1>(0).is_err());
+        assert_eq!(&memory[8..11], &[1, 2, 3]);
+    }
+}
@@ -0,0 +1,212 @@
+use mluau::prelude::*;
+
+use crate::core::datetime::DateTime;
+use crate::primitives::blob::blob_ref;
+use crate::utils::luaserde::{MAX_DEPTH, TableRepr, integer_to_lua, table_repr};
+
+/// The maximum size of a YAML document that can be parsed
+const MAX_INPUT_SIZE: usize = 4 * 1024 * 1024;
+
+/// Converts a parsed YAML value to Luau
+///
+/// YAML has no native date type, so timestamps are kept as strings. Tags are ignored
+fn yaml_to_lua(lua: &Lua, value: serde_yaml_ng::Value, depth: usize) -> LuaResult<LuaValue> {
+    if depth > MAX_DEPTH {
+        return Err(LuaError::external("Recursion limit exceeded"));
+    }
+
+    match value {
+        serde_yaml_ng::Value::Null => Ok(LuaValue::Nil),
+        serde_yaml_ng::Value::Bool(b) => Ok(LuaValue::Boolean(b)),
+        serde_yaml_ng::Value::Number(n) => {
+            if let Some(i) = n.as_i64() {
+                Ok(integer_to_lua(i))
+            } else {
+                Ok(LuaValue::Number(n.as_f64().unwrap_or(f64::NAN)))
+            }
+        }
+        serde_yaml_ng::Value::String(s) => Ok(LuaValue::String(lua.create_string(s)?)),
+        serde_yaml_ng::Value::Sequence(seq) => {
+            let table = lua.create_table_with_capacity(seq.len(), 0)?;
+            for (i, v) in seq.into_iter().enumerate() {
+                table.raw_set(i + 1, yaml_to_lua(lua, v, depth + 1)?)?;
+            }
+            table.set_metatable(Some(lua.array_metatable()))?;
+            Ok(LuaValue::Table(table))
+        }
+        serde_yaml_ng::Value::Mapping(map) => {
+            let table = lua.create_table_with_capacity(0, map.len())?;
+            for (k, v) in map {
+                let k = yaml_to_lua(lua, k, depth + 1)?;
+                if k.is_nil() {
+                    continue; // Luau tables cannot have nil keys
+                }
+                table.raw_set(k, yaml_to_lua(lua, v, depth + 1)?)?;
+            }
+            Ok(LuaValue::Table(table))
+        }
+        serde_yaml_ng::Value::Tagged(tagged) => yaml_to_lua(lua, tagged.value, depth + 1),
+    }
+}
+
+/// Converts a Luau value to YAML
+fn lua_to_yaml(lua: &Lua, value: &LuaValue, depth: usize) -> LuaResult<serde_yaml_ng::Value> {
+    if depth > MAX_DEPTH {
+        return Err(LuaError::external("Recursion limit exceeded"));
+    }
+
+    if value.is_null() {
+        return Ok(serde_yaml_ng::Value::Null);
+    }
+
+    match value {
+        LuaValue::Nil => Ok(serde_yaml_ng::Value::Null),
+        LuaValue::Boolean(b) => Ok(serde_yaml_ng::Value::Bool(*b)),
+        LuaValue::Integer(i) => Ok(serde_yaml_ng::Value::Number((*i).into())),
+        LuaValue::Int64(i) => Ok(serde_yaml_ng::Value::Number((*i).into())),
+        LuaValue::Number(n) => Ok(serde_yaml_ng::Value::Number((*n).into())),
+        LuaValue::String(s) => Ok(serde_yaml_ng::Value::String(s.to_str()?.to_string())),
+        LuaValue::Table(table) => match table_repr(lua, table)? {
+            TableRepr::Array(values) => {
+                let mut seq = Vec::with_capacity(values.len());
+                for v in values.iter() {
+                    seq.push(lua_to_yaml(lua, v, depth + 1)?);
+                }
+                Ok(serde_yaml_ng::Value::Sequence(seq))
+            }
+            TableRepr::Map(pairs) => {
+                let mut map = serde_yaml_ng::Mapping::with_capacity(pairs.len());
+                for (k, v) in pairs.iter() {
+                    map.insert(lua_to_yaml(lua, k, depth + 1)?, lua_to_yaml(lua, v, depth + 1)?);
+                }
+                Ok(serde_yaml_ng::Value::Mapping(map))
+            }
+        },
+        LuaValue::UserData(ud) => {
+            if let Ok(dt) = ud.borrow::<DateTime<chrono_tz::Tz>>() {
+                return Ok(serde_yaml_ng::Value::String(dt.dt.to_rfc3339()));
+            }
+
+            Err(LuaError::external("Only DateTime userdata can be serialized to YAML"))
+        }
+        _ => Err(LuaError::external(format!("cannot serialize <{}> to YAML", value.type_name()))),
+    }
+}
+
+/// Writes YAML on a single line in flow style (strings are double quoted, so documents without
+/// non-string keys are also valid JSON)
+fn write_flow(out: &mut String, value: &serde_yaml_ng::Value) -> LuaResult<()> {
+    match value {
+        serde_yaml_ng::Value::Null => out.push_str("null"),
+        serde_yaml_ng::Value::Bool(b) => out.push_str(if *b { "true" } else { "false" }),
+        serde_yaml_ng::Value::Number(n) => out.push_str(&n.to_string()),
+        // JSON escapes are a subset of YAML's double quoted escapes
+        serde_yaml_ng::Value::String(s) => out.push_str(&serde_json::to_string(s).into_lua_err()?),
+        serde_yaml_ng::Value::Sequence(seq) => {
+            out.push('[');
+            for (i, v) in seq.iter().enumerate() {
+                if i > 0 {
+                    out.push_str(", ");
+                }
+                write_flow(out, v)?;
+            }
+            out.push(']');
+        }
+        serde_yaml_ng::Value::Mapping(map) => {
+            out.push('{');
+            for (i, (k, v)) in map.iter().enumerate() {
+                if i > 0 {
+                    out.push_str(", ");
+                }
+                // Collections can only be keys when marked explicitly
+                if matches!(k, serde_yaml_ng::Value::Sequence(_) | serde_yaml_ng::Value::Mapping(_)) {
+                    out.push_str("? ");
+                }
+                write_flow(out, k)?;
+                out.push_str(": ");
+                write_flow(out, v)?;
+            }
+            out.push('}');
+        }
+        serde_yaml_ng::Value::Tagged(tagged) => {
+            out.push_str(&tagged.tag.to_string());
+            out.push(' ');
+            write_flow(out, &tagged.value)?;
+        }
+    }
+    Ok(())
+}
+
+pub fn init_plugin(lua: &Lua) -> LuaResult<LuaTable> {
+    let module = lua.create_table()?;
+
+    // Pretty output is block style, otherwise the document is written on a single line in flow style
+    module.set("toyamlstring", lua.create_function(|lua, (value, pretty): (LuaValue, Option<bool>)| {
+        let value = lua_to_yaml(lua, &value, 0)?;
+        let yaml_str = if pretty.unwrap_or(false) {
+            serde_yaml_ng::to_string(&value).into_lua_err()?
+        } else {
+            let mut out = String::new();
+            write_flow(&mut out, &value)?;
+            out
+        };
+        lua.create_string(yaml_str)
+    })?)?;
+
+    module.set("fromyamlstring", lua.create_function(|lua, yaml_str: LuaValue| {
+        let deser: serde_yaml_ng::Value = blob_ref(&yaml_str, |s| {
+            if s.len() > MAX_INPUT_SIZE {
+                return Err(LuaError::external(format!("YAML document exceeds maximum size of {MAX_INPUT_SIZE} bytes")));
+            }
+
+            serde_yaml_ng::from_slice(s).into_lua_err()
+        })??;
+        yaml_to_lua(lua, deser, 0)
+    })?)?;
+
+    module.set_readonly(true); // Block any attempt to modify this table
+
+    Ok(module)
+}
+
+#[cfg(test)]
+mod tests {
+    use mluau::prelude::*;
+
+    #[test]
+    fn test_yaml_module() -> LuaResult<()> {
+        let lua = Lua::new();
+        let yaml = super::init_plugin(&lua)?;
+
+        lua.load(
+            r#"
+            local yaml = ...
+
+            local doc = yaml.fromyamlstring("a: 1\nb: [x, 'y']\nc: null\nd: !custom 5\ne: 1.5\n")
+            assert(doc.a == 1 and doc.b[1] == "x" and doc.b[2] == "y" and doc.c == nil and doc.d == 5 and doc.e == 1.5)
+
+            local value = { a = 1, b = { "x", "y" }, s = "line\nbreak", n = 0.5 }
+            local compact = yaml.toyamlstring(value)
+            assert(compact == '{"a": 1, "b": ["x", "y"], "n": 0.5, "s": "line\\nbreak"}', compact)
+            local roundtrip = yaml.fromyamlstring(compact)
+            assert(roundtrip.s == "line\nbreak" and roundtrip.b[2] == "y" and roundtrip.n == 0.5)
+            assert(yaml.toyamlstring({ [1] = "x", k = true }) == '{1: "x", "k": true}')
+
+            local pretty = yaml.toyamlstring({ a = 1, b = { "x" } }, true)
+            assert(pretty == "a: 1\nb:\n- x\n", pretty)
+            assert(yaml.fromyamlstring(pretty).b[1] == "x")
+
+            local nested = {}
+            local inner = nested
+            for _ = 1, 100 do
+                inner[1] = {}
+                inner = inner[1]
+            end
+            assert(not pcall(yaml.toyamlstring, nested))
+            assert(not pcall(yaml.fromyamlstring, string.rep("[", 100) .. string.rep("]", 100)))
+            assert(not pcall(yaml.fromyamlstring, "a: [1"))
+        "#,
+        )
+        .call::<()>(yaml)
+    }
+}
@@ -1,3 +1,5 @@
+use std::{cell::RefCell, collections::HashSet, rc::Rc};
+
 use mluau::prelude::*;
 
 use crate::utils::khronos_value::KhronosValue;
@@ -25,4 +27,465 @@ impl std::fmt::Debug for Opaque {
     }
 }
 
-impl LuaUserData for Opaque {}
\ No newline at end of file
+impl LuaUserData for Opaque {}
+/// Short source names shown in place of opaque chunks
+const OPAQUE_SOURCE: &str = "[opaque]";
+
+/// Tracks the chunks loaded from opaque Vfs's so that their locations can be redacted from
+/// error messages, tracebacks and ``debug.info``
+#[derive(Default)]
+pub struct OpaqueSources {
+    /// Short source names (as seen in error messages and tracebacks) of opaque chunks
+    sources: RefCell<HashSet<String>>,
+}
+
+impl OpaqueSources {
+    /// Returns the opaque source registry of the Lua state, creating it if needed
+    pub fn get(lua: &Lua) -> Rc<OpaqueSources> {
+        if let Some(sources) = lua.app_data_ref::<Rc<OpaqueSources>>() {
+            return sources.clone();
+        }
+
+        let sources = Rc::new(OpaqueSources::default());
+        lua.set_app_data(sources.clone());
+        sources
+    }
+
+    pub fn register(&self, source: String) {
+        if let Ok(mut sources) = self.sources.try_borrow_mut() {
+            sources.insert(source);
+        }
+    }
+
+    pub fn is_opaque(&self, source: &str) -> bool {
+        source == OPAQUE_SOURCE
+            || self
+                .sources
+                .try_borrow()
+                .map(|s| s.contains(source))
+                .unwrap_or(true) // Err on the side of caution
+    }
+
+    /// Replaces every ``source:line`` location of an opaque chunk in ``msg`` with ``[opaque]``
+    pub fn redact(&self, msg: &str) -> String {
+        let Ok(sources) = self.sources.try_borrow() else {
+            return OPAQUE_SOURCE.to_string();
+        };
+
+        let mut msg = msg.to_string();
+        for source in sources.iter() {
+            if msg.contains(source.as_str()) {
+                msg = redact_locations(&msg, source);
+            }
+        }
+
+        msg
+    }
+
+    /// Redacts an error raised from within an opaque module
+    ///
+    /// Unlike ``redact``, the leading location of the error is always removed as it may point to a
+    /// chunk of the module that was never registered (e.g. one only required internally)
+    pub fn redact_error(&self, msg: &str) -> String {
+        let msg = self.redact(msg);
+        match strip_location(&msg) {
+            Some(rest) => format!("{OPAQUE_SOURCE}: {rest}"),
+            None => msg,
+        }
+    }
+
+    /// Redacts a traceback, replacing every frame of an opaque chunk with ``[opaque]``
+    pub fn redact_traceback(&self, traceback: &str) -> String {
+        let mut lines: Vec<String> = Vec::new();
+        for line in self.redact(traceback).lines() {
+            let line = if line.contains(OPAQUE_SOURCE) && !lines.is_empty() {
+                OPAQUE_SOURCE.to_string()
+            } else {
+                line.to_string()
+            };
+
+            // Collapse consecutive opaque frames
+            if line == OPAQUE_SOURCE && lines.last().is_some_and(|l| l == OPAQUE_SOURCE) {
+                continue;
+            }
+
+            lines.push(line);
+        }
+
+        lines.join("\n")
+    }
+}
+
+/// Replaces ``source:line`` (or a bare ``source``) in ``msg`` with ``[opaque]``
+fn redact_locations(msg: &str, source: &str) -> String {
+    let mut out = String::with_capacity(msg.len());
+    let mut rest = msg;
+    while let Some(i) = rest.find(source) {
+        out.push_str(&rest[..i]);
+        out.push_str(OPAQUE_SOURCE);
+        rest = &rest[i + source.len()..];
+
+        if let Some(after) = rest.strip_prefix(':') {
+            let digits = after.bytes().take_while(|b| b.is_ascii_digit()).count();
+            if digits > 0 {
+                rest = &after[digits..];
+            }
+        }
+    }
+
+    out.push_str(rest);
+    out
+}
+
+/// Returns the rest of ``msg`` if it starts with a ``chunk:line: `` location
+fn strip_location(msg: &str) -> Option<&str> {
+    let (location, rest) = msg.split_once(": ")?;
+    let (chunk, line) = location.rsplit_once(':')?;
+    if chunk.is_empty() || line.is_empty() || !line.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+
+    Some(rest)
+}
+
+/// Replaces ``debug.info`` and ``debug.traceback`` with versions that redact opaque chunks
+///
+/// Must be called before the Lua state is sandboxed
+pub fn install_redaction(lua: &Lua) -> LuaResult<()> {
+    let sources = OpaqueSources::get(lua);
+    let debug: LuaTable = lua.globals().get("debug")?;
+
+    let info: LuaFunction = debug.get("info")?;
+    let info_sources = sources.clone();
+    debug.set(
+        "info",
+        lua.create_function(move |lua, mut args: LuaMultiValue| {
+            // debug.info([thread,] level | function, options)
+            let offset = usize::from(matches!(args.front(), Some(LuaValue::Thread(_))));
+            if offset == 0 {
+                // Account for this function's own stack frame
+                bump_level(args.get_mut(0));
+            }
+
+            let Some(LuaValue::String(options)) = args.get(offset + 1).cloned() else {
+                return info.call::<LuaMultiValue>(args);
+            };
+            let options = options.to_str()?.to_string();
+            args[offset + 1] = LuaValue::String(lua.create_string(format!("s{options}"))?);
+
+            let mut res = info.call::<LuaMultiValue>(args)?.into_iter();
+            let opaque = match res.next() {
+                Some(LuaValue::String(s)) => info_sources.is_opaque(&s.to_string_lossy()),
+                _ => return Ok(LuaMultiValue::new()), // Level out of range
+            };
+
+            if !opaque {
+                return Ok(res.collect());
+            }
+
+            let mut out = LuaMultiValue::new();
+            for c in options.chars() {
+                match c {
+                    's' => {
+                        res.next();
+                        out.push_back(LuaValue::String(lua.create_string(OPAQUE_SOURCE)?));
+                    }
+                    'l' => {
+                        res.next();
+                        out.push_back(LuaValue::Integer(-1));
+                    }
+                    'n' => {
+                        res.next();
+                        out.push_back(LuaValue::Nil);
+                    }
+                    'a' => {
+                        // Arity and whether the function is variadic
+                        out.push_back(res.next().unwrap_or(LuaValue::Nil));
+                        out.push_back(res.next().unwrap_or(LuaValue::Nil));
+                    }
+                    _ => out.push_back(res.next().unwrap_or(LuaValue::Nil)),
+                }
+            }
+
+            Ok(out)
+        })?,
+    )?;
+
+    let traceback: LuaFunction = debug.get("traceback")?;
+    debug.set(
+        "traceback",
+        lua.create_function(move |lua, mut args: LuaMultiValue| {
+            // debug.traceback([thread,] [message [, level]])
+            if !matches!(args.front(), Some(LuaValue::Thread(_))) {
+                while args.len() < 2 {
+                    args.push_back(LuaValue::Nil);
+                }
+                if args[1].is_nil() {
+                    args[1] = LuaValue::Integer(1);
+                }
+                bump_level(args.get_mut(1));
+            }
+
+            match traceback.call::<LuaValue>(args)? {
+                LuaValue::String(s) => Ok(LuaValue::String(
+                    lua.create_string(sources.redact_traceback(&s.to_string_lossy()))?,
+                )),
+                v => Ok(v),
+            }
+        })?,
+    )?;
+
+    Ok(())
+}
+
+fn bump_level(level: Option<&mut LuaValue>) {
+    match level {
+        Some(LuaValue::Integer(l)) => *l += 1,
+        Some(LuaValue::Number(n)) => *n += 1.0,
+        _ => {}
+    }
+}
+
+/// Wraps a require function created for an opaque Vfs
+///
+/// Errors raised while loading a module are redacted and the value returned by the module is
+/// passed through ``wrap_opaque_value``. The wrapper is written in Luau so that modules may still
+/// yield. Paths are resolved from the root of the Vfs as ``require`` is given an explicit base
+/// (see ``rt::bytecodecache::CachedRequirer::with_base``)
+pub fn wrap_opaque_require(lua: &Lua, require: LuaFunction) -> LuaResult<LuaFunction> {
+    let sources = OpaqueSources::get(lua);
+    let redact = redact_function(lua, sources)?;
+    let wrap = opaque_wrapper(lua)?;
+
+    lua.load(
+        r#"
+local require, redact, wrap, pcall, error = ...
+return function(path)
+    local ok, res = pcall(function()
+        return require(path)
+    end)
+    if not ok then
+        error(redact(res), 0)
+    end
+    return wrap(res)
+end
+"#,
+    )
+    .set_name("=[opaque]")
+    .set_environment(lua.create_table()?)
+    .call((
+        require,
+        redact,
+        wrap,
+        lua.globals().get::<LuaFunction>("pcall")?,
+        lua.globals().get::<LuaFunction>("error")?,
+    ))
+}
+
+fn redact_function(lua: &Lua, sources: Rc<OpaqueSources>) -> LuaResult<LuaFunction> {
+    lua.create_function(move |lua, err: LuaValue| match err {
+        LuaValue::String(s) => Ok(LuaValue::String(
+            lua.create_string(sources.redact_error(&s.to_string_lossy()))?,
+        )),
+        v => Ok(v),
+    })
+}
+
+/// Wraps a value coming out of an opaque module
+///
+/// Luau functions are replaced with wrappers that redact errors and hide the function from
+/// ``debug.info``, and whose arguments and results are wrapped (and unwrapped) in turn, so that
+/// closures returned by later calls are covered too. Tables are replaced with proxies that wrap
+/// values as they are read and forward writes (and calls, ``#`` and iteration) to the table, which
+/// itself is never modified. Proxies of frozen tables are frozen
+///
+/// Wrappers and proxies are cached per Lua state, so wrapping the same value twice gives the same
+/// result. Other metamethods (e.g. arithmetic) of tables are not forwarded, and native functions
+/// and other values are returned as-is
+pub fn wrap_opaque_value(lua: &Lua, value: LuaValue) -> LuaResult<LuaValue> {
+    opaque_wrapper(lua)?.call(value)
+}
+
+/// The ``wrap`` function of the Lua state (see ``wrap_opaque_value``)
+struct OpaqueWrapper(LuaFunction);
+
+fn opaque_wrapper(lua: &Lua) -> LuaResult<LuaFunction> {
+    if let Some(wrapper) = lua.app_data_ref::<OpaqueWrapper>() {
+        return Ok(wrapper.0.clone());
+    }
+
+    let sources = OpaqueSources::get(lua);
+    let prepare_sources = sources.clone();
+    // Registers the chunk of a Luau function as opaque, native functions reveal nothing and are not wrapped
+    let prepare = lua.create_function(move |_, f: LuaFunction| {
+        let info = f.info();
+        if info.what == "C" {
+            return Ok(false);
+        }
+
+        if let Some(source) = info.short_src {
+            prepare_sources.register(source);
+        }
+        Ok(true)
+    })?;
+
+    // table.freeze refuses tables with a locked metatable, and proxies are fresh tables
+    let freeze = lua.create_function(|_, t: LuaTable| {
+        t.set_readonly(true);
+        Ok(())
+    })?;
+
+    let table_lib: LuaTable = lua.globals().get("table")?;
+    let wrap = lua
+        .load(
+            r#"
+local prepare, redact, pcall, error, next, tostring, type, setmetatable, pack, unpack, freeze, isfrozen = ...
+
+-- Originals by wrapper or proxy, and wrappers or proxies by original. Wrappers hold on to their
+-- original, so the latter is weak on both sides to let unused wrappers be collected
+local originals = setmetatable({}, { __mode = "k" })
+local wrapped = setmetatable({}, { __mode = "kv" })
+
+local function map(f, ...)
+    local values = pack(...)
+    for i = 1, values.n do
+        values[i] = f(values[i])
+    end
+    return unpack(values, 1, values.n)
+end
+
+-- Calls f, redacting any error it raises
+local function guard(f, ...)
+    local res = pack(pcall(f, ...))
+    if not res[1] then
+        error(redact(res[2]), 0)
+    end
+    return unpack(res, 2, res.n)
+end
+
+local function unwrap(v)
+    if v == nil then
+        return nil
+    end
+    return originals[v] or v
+end
+
+local function index(t, k)
+    return t[k]
+end
+
+local function newindex(t, k, v)
+    t[k] = v
+end
+
+local function len(t)
+    return #t
+end
+
+local wrap
+function wrap(v)
+    local kind = type(v)
+    if (kind ~= "function" and kind ~= "table") or originals[v] then
+        return v
+    end
+
+    local existing = wrapped[v]
+    if existing then
+        return existing
+    end
+
+    local w
+    if kind == "function" then
+        if not prepare(v) then
+            return v
+        end
+
+        w = function(...)
+            return map(wrap, guard(v, map(unwrap, ...)))
+        end
+    else
+        w = setmetatable({}, {
+            __index = function(_, k)
+                return wrap(guard(index, v, unwrap(k)))
+            end,
+            __newindex = function(_, k, value)
+                guard(newindex, v, unwrap(k), unwrap(value))
+            end,
+            __call = function(_, ...)
+                return map(wrap, guard(v, map(unwrap, ...)))
+            end,
+            __len = function()
+                return guard(len, v)
+            end,
+            __iter = function()
+                local key = nil
+                return function()
+                    local value
+                    key, value = guard(next, v, key)
+                    if key ~= nil then
+                        return wrap(key), wrap(value)
+                    end
+                    return nil
+                end
+            end,
+            __tostring = function()
+                return guard(tostring, v)
+            end,
+            __metatable = "The metatable is locked",
+        })
+
+        if isfrozen(v) then
+            freeze(w)
+        end
+    end
+
+    originals[w] = v
+    wrapped[v] = w
+    return w
+end
+
+return wrap
+"#,
+        )
+        .set_name("=[opaque]")
+        .set_environment(lua.create_table()?)
+        .call::<LuaFunction>((
+            prepare,
+            redact_function(lua, sources)?,
+            lua.globals().get::<LuaFunction>("pcall")?,
+            lua.globals().get::<LuaFunction>("error")?,
+            lua.globals().get::<LuaFunction>("next")?,
+            lua.globals().get::<LuaFunction>("tostring")?,
+            lua.globals().get::<LuaFunction>("type")?,
+            lua.globals().get::<LuaFunction>("setmetatable")?,
+            table_lib.get::<LuaFunction>("pack")?,
+            table_lib.get::<LuaFunction>("unpack")?,
+            freeze,
+            table_lib.get::<LuaFunction>("isfrozen")?,
+        ))?;
+
+    lua.set_app_data(OpaqueWrapper(wrap.clone()));
+    Ok(wrap)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redact() {
+        let sources = OpaqueSources::default();
+        sources.register("main/secret.luau".to_string());
+
+        assert_eq!(sources.redact("main/secret.luau:12: boom"), "[opaque]: boom");
+        assert_eq!(sources.redact("at main/secret.luau and user.luau:3"), "at [opaque] and user.luau:3");
+        assert_eq!(sources.redact_error("internal.luau:3: boom"), "[opaque]: boom");
+        assert_eq!(sources.redact_error("[opaque]: boom"), "[opaque]: boom");
+        assert_eq!(sources.redact_error("no location"), "no location");
+
+        let traceback = "msg\nmain/secret.luau:3 function f\nmain/secret.luau:9\nuser.luau:1";
+        assert_eq!(sources.redact_traceback(traceback), "msg\n[opaque]\nuser.luau:1");
+        assert!(sources.is_opaque("[opaque]"));
+        assert!(!sources.is_opaque("user.luau"));
+    }
+}
@@ -0,0 +1,390 @@
+//! Requiring data assets (JSON, TOML, text and binary files) through a Vfs
+//!
+//! ``require`` only resolves Luau modules, so every allowed asset ``path`` in a file map gets a
+//! ``path.luau`` module. This lets templates ``require("./config.json")`` and means assets are
+//! resolved, and cached, exactly like modules. The module itself is only a stub: the runtime's
+//! requirer (see ``rt::bytecodecache::CachedRequirer``) converts the asset into a Luau value directly.
+//!
+//! The runtime's own files use the ``AssetOptions`` its ``VfsFiles`` were built with (see
+//! ``KhronosRuntime::new_with_files``). Vfs's built
+//! from within Luau (``Vfs.newoverlay``, ``Vfs.fromtar``) only get assets if the host opts in with
+//! ``KhronosRuntime::set_asset_options``, and never for the contents of opaque Vfs's.
+
+use std::collections::HashMap;
+
+use bytes::Bytes;
+use mluau::prelude::*;
+
+use crate::utils::luaserde::integer_to_lua;
+
+/// Maximum nesting depth of JSON/TOML values converted to Luau
+const MAX_ASSET_DEPTH: usize = 64;
+
+/// How an asset is loaded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AssetKind {
+    /// Parsed into a table
+    Json,
+    /// Parsed into a table
+    Toml,
+    /// Loaded as a string
+    Text,
+    /// Loaded as a buffer
+    Buffer,
+}
+
+impl AssetKind {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "json" => Some(Self::Json),
+            "toml" => Some(Self::Toml),
+            "text" => Some(Self::Text),
+            "buffer" => Some(Self::Buffer),
+            _ => None,
+        }
+    }
+}
+
+/// The extensions (without the leading dot) that may be required as assets
+#[derive(Debug, Clone)]
+pub struct AssetOptions {
+    extensions: HashMap<String, AssetKind>,
+}
+
+impl Default for AssetOptions {
+    fn default() -> Self {
+        Self::empty()
+            .allow("json", AssetKind::Json)
+            .allow("toml", AssetKind::Toml)
+            .allow("txt", AssetKind::Text)
+    }
+}
+
+impl AssetOptions {
+    /// Options allowing no assets at all
+    pub fn empty() -> Self {
+        Self { extensions: HashMap::new() }
+    }
+
+    /// Allows files with the given extension to be required as the given kind of asset
+    pub fn allow(mut self, extension: &str, kind: AssetKind) -> Self {
+        self.extensions.insert(extension.trim_start_matches('.').to_lowercase(), kind);
+        self
+    }
+
+    /// Returns the kind of asset ``path`` is loaded as, if its extension is allowed
+    pub fn kind_for(&self, path: &str) -> Option<AssetKind> {
+        let file_name = path.rsplit('/').next().unwrap_or(path);
+        let (stem, ext) = file_name.rsplit_once('.')?;
+        if stem.is_empty() {
+            return None; // Dotfiles
+        }
+
+        self.extensions.get(&ext.to_lowercase()).copied()
+    }
+
+    /// Returns the options of the Lua state (set by ``KhronosRuntime::set_asset_options``), allowing
+    /// no assets if none were set
+    pub fn get(lua: &Lua) -> Self {
+        lua.app_data_ref::<AssetOptions>()
+            .map(|opts| opts.clone())
+            .unwrap_or_else(Self::empty)
+    }
+}
+
+/// Source of the module generated for an asset
+///
+/// Assets are loaded by the runtime's requirer, this only makes the module resolvable
+const ASSET_MODULE_STUB: &str = "error(\"Assets can only be loaded through require\", 0)\n";
+
+/// A file that can be required as an asset
+#[derive(Debug, Clone)]
+pub struct Asset {
+    pub kind: AssetKind,
+    /// Path of the asset file itself (not of its module)
+    pub path: String,
+    pub contents: Bytes,
+}
+
+impl Asset {
+    /// Converts the asset into a Luau value
+    ///
+    /// JSON and TOML documents become tables. ``null`` object fields are dropped, while ``null``
+    /// array elements become the ``null`` sentinel so that arrays keep their length
+    pub fn load(&self, lua: &Lua) -> LuaResult<LuaValue> {
+        let result = match self.kind {
+            AssetKind::Json => serde_json::from_slice::<serde_json::Value>(&self.contents)
+                .map_err(|e| e.to_string())
+                .and_then(|v| json_to_lua(lua, v, 0).map_err(|e| e.to_string())),
+            AssetKind::Toml => std::str::from_utf8(&self.contents)
+                .map_err(|e| e.to_string())
+                .and_then(|s| s.parse::<toml::Table>().map_err(|e| e.to_string()))
+                .and_then(|t| {
+                    crate::core::toml::toml_to_lua(lua, toml::Value::Table(t), 0).map_err(|e| e.to_string())
+                }),
+            AssetKind::Text => match std::str::from_utf8(&self.contents) {
+                Ok(s) => lua.create_string(s).map(LuaValue::String).map_err(|e| e.to_string()),
+                Err(_) => Err("Text assets must be valid UTF-8".to_string()),
+            },
+            AssetKind::Buffer => lua
+                .create_buffer(&*self.contents)
+                .map(LuaValue::Buffer)
+                .map_err(|e| e.to_string()),
+        };
+
+        result.map_err(|e| LuaError::runtime(format!("Failed to load asset '{}': {e}", self.path)))
+    }
+}
+
+/// The files a Vfs is built from, along with the assets among them
+#[derive(Debug, Clone, Default)]
+pub struct VfsFiles {
+    /// Files by path, including the module generated for every asset
+    ///
+    /// Files are kept as bytes so that binary assets can be loaded, only valid UTF-8 files can be
+    /// required as modules
+    pub files: HashMap<String, Bytes>,
+    /// Assets by the path of their module (``path.luau``)
+    pub assets: HashMap<String, Asset>,
+}
+
+impl VfsFiles {
+    /// Makes the allowed assets among ``files`` requirable
+    pub fn new<C: Into<Bytes>>(files: impl IntoIterator<Item = (String, C)>, opts: &AssetOptions) -> Self {
+        let files = files
+            .into_iter()
+            .map(|(path, contents)| (path, contents.into()))
+            .collect::<HashMap<_, Bytes>>();
+
+        let assets = files
+            .iter()
+            .filter_map(|(path, contents)| {
+                Some(Asset {
+                    kind: opts.kind_for(path)?,
+                    path: path.clone(),
+                    contents: contents.clone(),
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let mut vfs_files = Self {
+            files,
+            assets: HashMap::new(),
+        };
+        for asset in assets {
+            vfs_files.add_asset(asset);
+        }
+        vfs_files
+    }
+
+    /// Makes ``asset`` requirable, unless a module of the same name (``path.luau``) already exists
+    pub fn add_asset(&mut self, asset: Asset) {
+        let module_path = format!("{}.luau", asset.path);
+        if self.files.contains_key(&module_path) && !self.assets.contains_key(&module_path) {
+            return;
+        }
+
+        self.files.insert(module_path.clone(), Bytes::from_static(ASSET_MODULE_STUB.as_bytes()));
+        self.assets.insert(module_path, asset);
+    }
+
+    /// Adds the files of ``other``, replacing files of the same name
+    pub fn extend(&mut self, other: VfsFiles) {
+        for (path, contents) in other.files {
+            if !other.assets.contains_key(&path) {
+                self.assets.remove(&path); // A module replacing an asset
+            }
+            self.files.insert(path, contents);
+        }
+        self.assets.extend(other.assets);
+    }
+
+    /// Creates a memory Vfs from the files. Files that are not valid UTF-8 can only be required
+    /// as assets, so they are left out
+    pub fn to_vfs(&self) -> mluau_require::Vfs {
+        let files = self
+            .files
+            .iter()
+            .filter_map(|(path, contents)| Some((path.clone(), std::str::from_utf8(contents).ok()?.to_string())))
+            .collect::<HashMap<_, _>>();
+        mluau_require::create_memory_vfs_from_map(files)
+    }
+}
+
+/// Converts a JSON value to Luau, keeping integers exact (see ``integer_to_lua``)
+fn json_to_lua(lua: &Lua, value: serde_json::Value, depth: usize) -> LuaResult<LuaValue> {
+    if depth > MAX_ASSET_DEPTH {
+        return Err(LuaError::runtime(format!("Asset is nested deeper than {MAX_ASSET_DEPTH} levels")));
+    }
+
+    Ok(match value {
+        serde_json::Value::Null => LuaValue::NULL,
+        serde_json::Value::Bool(b) => LuaValue::Boolean(b),
+        serde_json::Value::Number(n) => match n.as_i64() {
+            Some(i) => integer_to_lua(i),
+            None => LuaValue::Number(n.as_f64().unwrap_or(f64::NAN)),
+        },
+        serde_json::Value::String(s) => LuaValue::String(lua.create_string(s)?),
+        serde_json::Value::Array(a) => {
+            let table = lua.create_table_with_capacity(a.len(), 0)?;
+            for v in a {
+                table.raw_push(json_to_lua(lua, v, depth + 1)?)?;
+            }
+            table.set_metatable(Some(lua.array_metatable()))?;
+            LuaValue::Table(table)
+        }
+        serde_json::Value::Object(o) => {
+            let table = lua.create_table_with_capacity(0, o.len())?;
+            for (k, v) in o {
+                if v.is_null() {
+                    continue;
+                }
+                table.raw_set(k, json_to_lua(lua, v, depth + 1)?)?;
+            }
+            LuaValue::Table(table)
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_asset_options() {
+        let opts = AssetOptions::default().allow("bin", AssetKind::Buffer);
+        assert_eq!(opts.kind_for("config.JSON"), Some(AssetKind::Json));
+        assert_eq!(opts.kind_for("dir.json/init.luau"), None);
+        assert_eq!(opts.kind_for(".json"), None);
+        assert_eq!(opts.kind_for("data.bin"), Some(AssetKind::Buffer));
+
+        // Nothing is allowed unless the host opts in
+        assert_eq!(AssetOptions::get(&Lua::new()).kind_for("config.json"), None);
+    }
+
+    #[test]
+    fn test_vfs_files() {
+        let opts = AssetOptions::default();
+        let files = HashMap::from([
+            ("init.luau".to_string(), "return 1".to_string()),
+            ("config.json".to_string(), "1".to_string()),
+            ("notes.txt".to_string(), "hi".to_string()),
+            ("notes.txt.luau".to_string(), "return 2".to_string()),
+        ]);
+
+        let mut vfs_files = VfsFiles::new(files, &opts);
+        assert_eq!(vfs_files.files.get("config.json.luau").map(|f| &f[..]), Some(ASSET_MODULE_STUB.as_bytes()));
+        assert_eq!(vfs_files.files.get("notes.txt.luau").map(|f| &f[..]), Some(&b"return 2"[..]));
+        assert!(vfs_files.assets.contains_key("config.json.luau"));
+        assert!(!vfs_files.assets.contains_key("notes.txt.luau"));
+        assert_eq!(vfs_files.files.len(), 5);
+
+        // Modules replace assets of the same name
+        let overlay = VfsFiles::new(
+            HashMap::from([("config.json.luau".to_string(), "return 3".to_string())]),
+            &opts,
+        );
+        vfs_files.extend(overlay);
+        assert_eq!(vfs_files.files.get("config.json.luau").map(|f| &f[..]), Some(&b"return 3"[..]));
+        assert!(vfs_files.assets.is_empty());
+    }
+
+    #[test]
+    fn test_load_asset() -> LuaResult<()> {
+        let lua = Lua::new();
+        let asset = |kind, path: &str, contents: &[u8]| Asset {
+            kind,
+            path: path.to_string(),
+            contents: Bytes::copy_from_slice(contents),
+        };
+
+        let json = asset(
+            AssetKind::Json,
+            "a.json",
+            br#"{"a": [1, null, 2.5, 9007199254740993], "b": null, "c": {"d": "x"}}"#,
+        )
+        .load(&lua)?;
+        let toml = asset(AssetKind::Toml, "a.toml", b"name = \"x\"\nbig = 9007199254740993\n").load(&lua)?;
+        let text = asset(AssetKind::Text, "a.txt", "é\"\n".as_bytes()).load(&lua)?;
+        let buffer = asset(AssetKind::Buffer, "a.bin", &[0, 255]).load(&lua)?;
+
+        let big: Vec<LuaValue> = lua
+            .load(
+                r#"
+            local json, toml, text, buf = ...
+
+            -- nulls in arrays are kept as the null sentinel, nulls in objects are dropped
+            assert(#json.a == 4)
+            assert(json.a[1] == 1 and json.a[3] == 2.5)
+            assert(json.a[2] ~= nil and type(json.a[2]) == "userdata")
+            assert(json.b == nil and json.c.d == "x")
+
+            assert(toml.name == "x")
+            assert(text == "é\"\n")
+            assert(buffer.len(buf) == 2 and buffer.readu8(buf, 1) == 255)
+
+            return json.a[4], toml.big
+        "#,
+            )
+            .call::<LuaMultiValue>((json, toml, text, buffer))?
+            .into_vec();
+        assert!(matches!(big[..], [LuaValue::Int64(9007199254740993), LuaValue::Int64(9007199254740993)]));
+
+        let err = asset(AssetKind::Json, "a.json", b"{").load(&lua).unwrap_err();
+        assert!(err.to_string().contains("Failed to load asset 'a.json'"), "{err}");
+        assert!(asset(AssetKind::Text, "a.txt", &[0xff]).load(&lua).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_require_assets() {
+        use crate::rt::testutils::run_files;
+        use crate::rt::RuntimeCreateOpts;
+
+        let files = HashMap::from([
+            (
+                "init.luau".to_string(),
+                r#"
+                local config = require("./config.json")
+                assert(config.name == "x" and #config.list == 2)
+                assert(require("./notes.txt") == "hi")
+
+                -- Nothing else is requirable as an asset
+                assert(not pcall(require, "./data.bin"))
+                return function() end
+                "#
+                .to_string(),
+            ),
+            ("config.json".to_string(), r#"{"name": "x", "list": [1, null]}"#.to_string()),
+            ("notes.txt".to_string(), "hi".to_string()),
+            ("data.bin".to_string(), "abc".to_string()),
+        ]);
+        run_files(RuntimeCreateOpts::default(), files, &AssetOptions::default(), |_| Ok(())).unwrap();
+    }
+
+    #[test]
+    fn test_require_binary_asset() {
+        use crate::rt::testutils::run_files;
+        use crate::rt::RuntimeCreateOpts;
+
+        let files = HashMap::from([
+            (
+                "init.luau".to_string(),
+                br#"
+                return function()
+                    local data = require("./data.bin")
+                    assert(type(data) == "buffer" and buffer.len(data) == 4)
+                    assert(buffer.readu8(data, 0) == 0xff and buffer.readu8(data, 1) == 0x00)
+                    assert(buffer.readu8(data, 2) == 0xfe and buffer.readu8(data, 3) == 0x80)
+                end
+                "#
+                .to_vec(),
+            ),
+            ("data.bin".to_string(), vec![0xff, 0x00, 0xfe, 0x80]),
+        ]);
+
+        let opts = AssetOptions::empty().allow("bin", AssetKind::Buffer);
+        run_files(RuntimeCreateOpts::default(), files, &opts, |_| Ok(())).unwrap();
+    }
+}
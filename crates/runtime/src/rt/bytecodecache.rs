@@ -0,0 +1,450 @@
+//! Process-wide cache of compiled Luau bytecode
+//!
+//! Many runtimes load the same template sources, so compiled bytecode is shared between all
+//! runtimes in the process, keyed by a hash of the source and the compiler options used to
+//! compile it. The cache is bounded by the total size of the bytecode it holds, evicting the
+//! least recently used entries first.
+//!
+//! Host-provided code goes through this cache: ``eval_chunk`` and modules loaded through
+//! ``require`` (see ``CachedRequirer``). Code built at runtime by templates (``luau.load``) is
+//! compiled on its own so that it can't fill (or probe) the cache shared with other runtimes.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::Result as IoResult;
+use std::sync::{Arc, LazyLock, Mutex, MutexGuard};
+
+use mluau::prelude::*;
+use mluau::{NavigateError, Require};
+use sha2::{Digest, Sha256};
+
+use super::assets::VfsFiles;
+
+/// The default maximum total size of cached bytecode
+pub const DEFAULT_MAX_BYTES: usize = 64 * 1024 * 1024;
+
+static GLOBAL_CACHE: LazyLock<BytecodeCache> = LazyLock::new(|| BytecodeCache::new(DEFAULT_MAX_BYTES));
+
+type CacheKey = [u8; 32];
+
+/// The compiler options code is compiled with
+///
+/// ``mluau::Compiler`` does not expose its options, so the options that make up a cache key are
+/// kept here and the compiler is built from them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CompileOptions {
+    pub optimization_level: u8,
+    pub debug_level: u8,
+    pub type_info_level: u8,
+}
+
+impl Default for CompileOptions {
+    /// The defaults of ``mluau::Compiler::new``
+    fn default() -> Self {
+        Self {
+            optimization_level: 1,
+            debug_level: 1,
+            type_info_level: 0,
+        }
+    }
+}
+
+impl CompileOptions {
+    pub fn compiler(&self) -> mluau::Compiler {
+        mluau::Compiler::new()
+            .set_optimization_level(self.optimization_level)
+            .set_debug_level(self.debug_level)
+            .set_type_info_level(self.type_info_level)
+    }
+}
+
+struct Entry {
+    bytecode: Arc<[u8]>,
+    last_used: u64,
+}
+
+struct Inner {
+    entries: HashMap<CacheKey, Entry>,
+    /// Keys ordered by last use, oldest first
+    lru: BTreeMap<u64, CacheKey>,
+    size: usize,
+    max_bytes: usize,
+    tick: u64,
+    hits: u64,
+    misses: u64,
+}
+
+impl Inner {
+    fn get(&mut self, key: &CacheKey) -> Option<Arc<[u8]>> {
+        self.tick += 1;
+        let tick = self.tick;
+        let Some(entry) = self.entries.get_mut(key) else {
+            self.misses += 1;
+            return None;
+        };
+
+        self.lru.remove(&entry.last_used);
+        self.lru.insert(tick, *key);
+        entry.last_used = tick;
+        self.hits += 1;
+        Some(entry.bytecode.clone())
+    }
+
+    fn insert(&mut self, key: CacheKey, bytecode: Arc<[u8]>) {
+        // Never cache something that would evict everything else
+        if bytecode.len() > self.max_bytes || self.entries.contains_key(&key) {
+            return;
+        }
+
+        self.tick += 1;
+        self.size += bytecode.len();
+        self.lru.insert(self.tick, key);
+        self.entries.insert(key, Entry { bytecode, last_used: self.tick });
+        self.evict();
+    }
+
+    fn evict(&mut self) {
+        while self.size > self.max_bytes {
+            let Some((_, key)) = self.lru.pop_first() else {
+                break;
+            };
+
+            if let Some(entry) = self.entries.remove(&key) {
+                self.size -= entry.bytecode.len();
+            }
+        }
+    }
+}
+
+/// Statistics about a bytecode cache
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BytecodeCacheStats {
+    pub entries: usize,
+    pub bytes: usize,
+    pub max_bytes: usize,
+    pub hits: u64,
+    pub misses: u64,
+}
+
+/// A bounded cache of compiled bytecode keyed by source and compiler options
+pub struct BytecodeCache {
+    inner: Mutex<Inner>,
+}
+
+impl BytecodeCache {
+    pub fn new(max_bytes: usize) -> Self {
+        Self {
+            inner: Mutex::new(Inner {
+                entries: HashMap::new(),
+                lru: BTreeMap::new(),
+                size: 0,
+                max_bytes,
+                tick: 0,
+                hits: 0,
+                misses: 0,
+            }),
+        }
+    }
+
+    /// Returns the cache shared by all runtimes in the process
+    pub fn global() -> &'static BytecodeCache {
+        &GLOBAL_CACHE
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        // The cache is always left in a consistent state, so a poisoned lock is still usable
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn key(options: &CompileOptions, source: &[u8]) -> CacheKey {
+        let mut hasher = Sha256::new();
+        hasher.update([options.optimization_level, options.debug_level, options.type_info_level]);
+        hasher.update(source);
+        hasher.finalize().into()
+    }
+
+    /// Returns the bytecode for the source, compiling (and caching) it if needed
+    ///
+    /// Compile errors are returned as-is and are not cached
+    pub fn get_or_compile(&self, options: &CompileOptions, source: &[u8]) -> LuaResult<Arc<[u8]>> {
+        let key = Self::key(options, source);
+        if let Some(bytecode) = self.lock().get(&key) {
+            return Ok(bytecode);
+        }
+
+        // Compile without holding the lock so other runtimes are not blocked
+        let bytecode: Arc<[u8]> = options.compiler().compile(source)?.into();
+        self.lock().insert(key, bytecode.clone());
+        Ok(bytecode)
+    }
+
+    /// Compiles and caches every ``.luau``/``.lua`` file, e.g. the files a template's Vfs is built
+    /// from at deploy time. Files that fail to compile are skipped
+    ///
+    /// Returns the number of files compiled successfully
+    pub fn prewarm<'a>(
+        &self,
+        options: &CompileOptions,
+        files: impl IntoIterator<Item = (&'a str, &'a str)>,
+    ) -> usize {
+        files
+            .into_iter()
+            .filter(|(path, _)| path.ends_with(".luau") || path.ends_with(".lua"))
+            .filter(|(_, source)| self.get_or_compile(options, source.as_bytes()).is_ok())
+            .count()
+    }
+
+    /// Sets the maximum total size of cached bytecode, evicting entries if needed
+    pub fn set_max_bytes(&self, max_bytes: usize) {
+        let mut inner = self.lock();
+        inner.max_bytes = max_bytes;
+        inner.evict();
+    }
+
+    /// Removes all cached bytecode
+    pub fn clear(&self) {
+        let mut inner = self.lock();
+        inner.entries.clear();
+        inner.lru.clear();
+        inner.size = 0;
+    }
+
+    pub fn stats(&self) -> BytecodeCacheStats {
+        let inner = self.lock();
+        BytecodeCacheStats {
+            entries: inner.entries.len(),
+            bytes: inner.size,
+            max_bytes: inner.max_bytes,
+            hits: inner.hits,
+            misses: inner.misses,
+        }
+    }
+}
+
+/// The module requires made from outside of a Vfs resolve against: its root ``init.luau``
+pub const VFS_ROOT_MODULE: &str = "/init.luau";
+
+/// Wraps a ``Require`` implementation (e.g. ``mluau_require::AssetRequirer``) so that the modules
+/// it loads are compiled through the process-wide cache
+///
+/// ``files`` holds the contents of the Vfs the inner requirer reads from. Assets are converted
+/// to Luau values directly (see ``rt::assets``). Modules whose cache key (their resolved path) is
+/// not found in it are loaded by the inner requirer
+pub struct CachedRequirer<R> {
+    inner: R,
+    files: Arc<VfsFiles>,
+    options: CompileOptions,
+    environment: LuaTable,
+    /// The chunk name requires made from outside of the Vfs are resolved against
+    base: Option<String>,
+    /// Chunk names of the modules loaded by this requirer
+    loaded: Mutex<HashSet<String>>,
+}
+
+impl<R: Require> CachedRequirer<R> {
+    pub fn new(inner: R, files: Arc<VfsFiles>, options: CompileOptions, environment: LuaTable) -> Self {
+        Self {
+            inner,
+            files,
+            options,
+            environment,
+            base: None,
+            loaded: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// Resolves requires made by chunks that were not loaded by this requirer as if they were made
+    /// by the module ``base`` (e.g. ``/init.luau`` for the root of the Vfs), rather than relative to
+    /// the calling chunk
+    pub fn with_base(mut self, base: impl Into<String>) -> Self {
+        self.base = Some(base.into());
+        self
+    }
+
+    fn is_loaded(&self, chunk_name: &str) -> bool {
+        self.loaded
+            .lock()
+            .map(|loaded| loaded.contains(chunk_name.trim_start_matches('@')))
+            .unwrap_or(false)
+    }
+}
+
+impl<R: Require> Require for CachedRequirer<R> {
+    fn is_require_allowed(&self, chunk_name: &str) -> bool {
+        self.base.is_some() || self.inner.is_require_allowed(chunk_name)
+    }
+
+    fn reset(&mut self, chunk_name: &str) -> Result<(), NavigateError> {
+        match self.base {
+            Some(ref base) if !self.is_loaded(chunk_name) => self.inner.reset(base),
+            _ => self.inner.reset(chunk_name),
+        }
+    }
+
+    fn jump_to_alias(&mut self, path: &str) -> Result<(), NavigateError> {
+        self.inner.jump_to_alias(path)
+    }
+
+    fn to_parent(&mut self) -> Result<(), NavigateError> {
+        self.inner.to_parent()
+    }
+
+    fn to_child(&mut self, name: &str) -> Result<(), NavigateError> {
+        self.inner.to_child(name)
+    }
+
+    fn has_module(&self) -> bool {
+        self.inner.has_module()
+    }
+
+    fn cache_key(&self) -> String {
+        self.inner.cache_key()
+    }
+
+    fn has_config(&self) -> bool {
+        self.inner.has_config()
+    }
+
+    fn config(&self) -> IoResult<Vec<u8>> {
+        self.inner.config()
+    }
+
+    fn loader(&self, lua: &Lua) -> LuaResult<LuaFunction> {
+        let key = self.inner.cache_key();
+        let path = key.trim_start_matches('@').trim_start_matches("./").trim_start_matches('/');
+        if let Some(asset) = self.files.assets.get(path) {
+            let asset = asset.clone();
+            return lua.create_function(move |lua, ()| asset.load(lua));
+        }
+
+        let Some(source) = self.files.files.get(path) else {
+            return self.inner.loader(lua);
+        };
+
+        let bytecode = BytecodeCache::global().get_or_compile(&self.options, source)?;
+
+        // Named by its path from the root of the Vfs so that requires made by the module resolve relative to it
+        let name = format!("/{path}");
+        if let Ok(mut loaded) = self.loaded.lock() {
+            loaded.insert(name.clone());
+        }
+
+        lua.load(&bytecode[..])
+            .set_name(name)
+            .set_mode(mluau::ChunkMode::Binary)
+            .set_environment(self.environment.clone())
+            .into_function()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_eviction() {
+        let cache = BytecodeCache::new(10);
+        {
+            let mut inner = cache.lock();
+            inner.insert([1; 32], vec![0; 4].into());
+            inner.insert([2; 32], vec![0; 4].into());
+            assert!(inner.get(&[1; 32]).is_some()); // [2] is now the least recently used
+            inner.insert([3; 32], vec![0; 4].into());
+            assert!(inner.get(&[2; 32]).is_none());
+            assert!(inner.get(&[1; 32]).is_some());
+            assert!(inner.get(&[3; 32]).is_some());
+
+            // Too large to ever be cached
+            inner.insert([4; 32], vec![0; 11].into());
+            assert!(inner.get(&[4; 32]).is_none());
+        }
+
+        let stats = cache.stats();
+        assert_eq!((stats.entries, stats.bytes), (2, 8));
+
+        cache.set_max_bytes(4);
+        assert_eq!(cache.stats().entries, 1);
+    }
+
+    #[test]
+    fn test_get_or_compile() {
+        let cache = BytecodeCache::new(DEFAULT_MAX_BYTES);
+        let options = CompileOptions::default();
+        let a = cache.get_or_compile(&options, b"return 1").unwrap();
+        let b = cache.get_or_compile(&options, b"return 1").unwrap();
+        assert!(Arc::ptr_eq(&a, &b));
+
+        // Different options are cached separately
+        let c = cache
+            .get_or_compile(&CompileOptions { optimization_level: 2, ..options }, b"return 1")
+            .unwrap();
+        assert!(!Arc::ptr_eq(&a, &c));
+        let d = cache
+            .get_or_compile(&CompileOptions { type_info_level: 1, ..options }, b"return 1")
+            .unwrap();
+        assert!(!Arc::ptr_eq(&a, &d) && !Arc::ptr_eq(&c, &d));
+
+        assert!(cache.get_or_compile(&options, b"return (").is_err());
+        assert_eq!(cache.stats().entries, 3);
+    }
+
+    #[test]
+    fn test_cached_requirer() {
+        use std::collections::HashMap;
+
+        use crate::rt::assets::AssetOptions;
+        use crate::rt::testutils::run_files;
+        use crate::rt::{KhronosRuntime, RuntimeCreateOpts};
+
+        let util = "return { value = 'test_cached_requirer' }";
+        let loaded = "return 'test_cached_requirer (luau.load)'";
+        let init = format!(
+            "return function()
+                assert(require('./lib/util').value == 'test_cached_requirer')
+                assert(require('@antiraid/luau').load({loaded:?}):call() == 'test_cached_requirer (luau.load)')
+            end"
+        );
+        let files = HashMap::from([
+            ("init.luau".to_string(), init),
+            ("lib/util.luau".to_string(), util.to_string()),
+        ]);
+
+        run_files(RuntimeCreateOpts::default(), files, &AssetOptions::empty(), |_| Ok(())).unwrap();
+
+        // The required module was compiled through the process-wide cache, template-built code was not
+        let options = KhronosRuntime::compile_options();
+        let inner = BytecodeCache::global().lock();
+        assert!(inner.entries.contains_key(&BytecodeCache::key(&options, util.as_bytes())));
+        assert!(!inner.entries.contains_key(&BytecodeCache::key(&options, loaded.as_bytes())));
+        assert!(!inner.entries.contains_key(&BytecodeCache::key(&CompileOptions::default(), loaded.as_bytes())));
+    }
+
+    #[test]
+    fn test_host_vfs() {
+        use std::collections::HashMap;
+
+        use mluau_require::create_memory_vfs_from_map;
+
+        use crate::rt::{KhronosRuntime, RuntimeCreateOpts};
+
+        // Runtimes created from a host Vfs load its modules through the Vfs itself
+        let files = HashMap::from([
+            ("init.luau".to_string(), "return require('./lib/util').value".to_string()),
+            ("lib/util.luau".to_string(), "return { value = 'test_host_vfs' }".to_string()),
+        ]);
+        let tokio_rt = tokio::runtime::Builder::new_current_thread()
+            .build_local(tokio::runtime::LocalOptions::default())
+            .unwrap();
+
+        tokio_rt.block_on(async move {
+            let rt = KhronosRuntime::new(
+                RuntimeCreateOpts::default(),
+                None::<(fn(&Lua, LuaThread) -> Result<(), LuaError>, fn(LuaLightUserData) -> ())>,
+                create_memory_vfs_from_map(files).into(),
+                "antiraid",
+            )
+            .unwrap();
+
+            assert_eq!(rt.eval_script::<String>("./init").unwrap(), "test_host_vfs");
+        });
+    }
+}
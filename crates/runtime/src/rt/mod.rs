@@ -1,7 +1,12 @@
 //! Single threaded khronos runtime struct/runner
 
+pub mod assets;
+pub mod bytecodecache;
 pub mod runtime;
 
+#[cfg(test)]
+pub(crate) mod testutils;
+
 // Re-exports
 
 pub use runtime::{KhronosRuntime, RuntimeCreateOpts};
@@ -4,13 +4,15 @@
 
 use std::cell::{Cell, RefCell};
 use std::rc::Rc;
-use std::sync::{Arc, Once};
+use std::sync::{Arc, Mutex, Once};
 use std::time::Instant;
 
 use mlua_scheduler::taskmgr::{Hooks, SchedulerImpl};
 use mluau::prelude::*;
 use mluau_require::{AssetRequirer, Vfs};
 
+use super::assets::VfsFiles;
+
 pub type S = mlua_scheduler::schedulers::rodan::CoreScheduler;
 use crate::utils::proxyglobal::proxy_global;
 
@@ -30,10 +32,43 @@ pub struct RuntimeCreateOpts {
     
     /// Maximum number of instructions (fuel) a WASM instance can execute synchronously before trapping
     pub wasm_max_fuel_per_slice: Option<u64>,
+
+    /// Maximum Argon2 memory cost (in KiB) a template may use when hashing passwords
+    pub password_hash_max_m_cost: Option<u32>,
+
+    /// Maximum Argon2 iteration count a template may use when hashing passwords
+    pub password_hash_max_t_cost: Option<u32>,
+
+    /// Maximum Argon2 parallelism a template may use when hashing passwords
+    pub password_hash_max_p_cost: Option<u32>,
+}
+
+/// A ``Cell`` that can also be used outside of the Lua VM's thread (e.g. by WASM workers)
+#[derive(Debug, Default)]
+pub struct SyncCell<T: Copy>(Arc<Mutex<T>>);
+
+impl<T: Copy> Clone for SyncCell<T> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<T: Copy> SyncCell<T> {
+    pub fn new(value: T) -> Self {
+        Self(Arc::new(Mutex::new(value)))
+    }
+
+    pub fn get(&self) -> T {
+        *self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn set(&self, value: T) {
+        *self.0.lock().unwrap_or_else(|e| e.into_inner()) = value;
+    }
 }
 
 pub struct SchedulerHook {
-    execution_stop_time: Rc<Cell<Option<std::time::Instant>>>,
+    execution_stop_time: SyncCell<Option<std::time::Instant>>,
     give_time: std::time::Duration,
 }
 
@@ -84,14 +119,16 @@ pub struct KhronosRuntime {
     last_execution_time: Rc<Cell<Option<Instant>>>,
 
     /// The time limit for execution
-    time_limit: Rc<Cell<Option<std::time::Duration>>>,
+    ///
+    /// Shared with WASM workers, which also stop at the execution stop time
+    time_limit: SyncCell<Option<std::time::Duration>>,
 
     /// The time the execution should stop at
     /// 
     /// Automatically calculated (usually) from time_limit and last_execution_time
     /// 
     /// Scheduler resumes may extend this time
-    execution_stop_time: Rc<Cell<Option<Instant>>>,
+    execution_stop_time: SyncCell<Option<Instant>>,
 
     /// The time to allow a thread to run for before temporarily yielding it
     //time_slice: Rc<Cell<Option<std::time::Duration>>>,
@@ -112,6 +149,9 @@ pub struct KhronosRuntime {
 impl KhronosRuntime {
     /// Creates a new Khronos runtime from scratch
     ///
+    /// Modules required from ``vfs`` are loaded by the Vfs itself. Use ``KhronosRuntime::new_with_files``
+    /// to compile them through the process-wide bytecode cache and allow requiring assets
+    ///
     /// Note that the resulting lua vm is *not* sandboxed until KhronosRuntime::sandbox() is called
     pub fn new<
         ThreadCreationCallbackFunc: Fn(&Lua, LuaThread) -> Result<(), mluau::Error> + 'static,
@@ -124,6 +164,44 @@ impl KhronosRuntime {
         )>,
         vfs: Arc<Vfs>,
         prefix: &str,
+    ) -> Result<Self, LuaError> {
+        Self::new_inner(opts, on_thread_event_callback, vfs, Arc::new(VfsFiles::default()), prefix)
+    }
+
+    /// Creates a new Khronos runtime whose Vfs is built from ``files`` (see ``rt::assets``)
+    ///
+    /// Modules are compiled through the process-wide bytecode cache (see ``rt::bytecodecache``) and the
+    /// assets among ``files`` can be required
+    pub fn new_with_files<
+        ThreadCreationCallbackFunc: Fn(&Lua, LuaThread) -> Result<(), mluau::Error> + 'static,
+        ThreadDestructionCallbackFunc: Fn(LuaLightUserData) + 'static,
+    >(
+        opts: RuntimeCreateOpts,
+        on_thread_event_callback: Option<(
+            ThreadCreationCallbackFunc,
+            ThreadDestructionCallbackFunc,
+        )>,
+        files: VfsFiles,
+        prefix: &str,
+    ) -> Result<Self, LuaError> {
+        let vfs = Arc::new(files.to_vfs());
+        Self::new_inner(opts, on_thread_event_callback, vfs, Arc::new(files), prefix)
+    }
+
+    /// ``files`` holds the contents of ``vfs`` known to the runtime, modules (and assets) not found in
+    /// it are loaded by ``vfs`` itself
+    fn new_inner<
+        ThreadCreationCallbackFunc: Fn(&Lua, LuaThread) -> Result<(), mluau::Error> + 'static,
+        ThreadDestructionCallbackFunc: Fn(LuaLightUserData) + 'static,
+    >(
+        opts: RuntimeCreateOpts,
+        on_thread_event_callback: Option<(
+            ThreadCreationCallbackFunc,
+            ThreadDestructionCallbackFunc,
+        )>,
+        vfs: Arc<Vfs>,
+        files: Arc<VfsFiles>,
+        prefix: &str,
     ) -> Result<Self, LuaError> {
         assert!(!prefix.starts_with('@'), "Prefix should not start with `@`");        
         // Allow <<>> syntax
@@ -141,17 +219,12 @@ impl KhronosRuntime {
                 .disable_error_userdata(true),
         )?;
 
-        let compiler = mluau::Compiler::new()
-            .set_optimization_level(2)
-            .set_type_info_level(1);
+        let compiler = Self::compile_options().compiler();
 
         lua.set_compiler(compiler.clone());
 
-        let time_limit = Rc::new(Cell::new(opts.time_limit));
-        let execution_stop_time = match opts.time_limit {
-            Some(limit) => Rc::new(Cell::new(Some(Instant::now() + limit))),
-            None => Rc::new(Cell::new(None)),
-        };
+        let time_limit = SyncCell::new(opts.time_limit);
+        let execution_stop_time = SyncCell::new(opts.time_limit.map(|limit| Instant::now() + limit));
         let scheduler = S::setup(&lua, Rc::new(SchedulerHook {
             execution_stop_time: execution_stop_time.clone(),
             give_time: opts.give_time
@@ -167,15 +240,23 @@ impl KhronosRuntime {
         let last_execution_time: Rc<Cell<Option<Instant>>> = Rc::new(Cell::new(None));
         //let time_slice = Rc::new(Cell::new(opts.time_slice));
 
+        // Tracks Chunk's with their own resource limits (see core::luau)
+        let chunk_limiter = Rc::new(crate::core::luau::ChunkLimiter::new());
+        lua.set_app_data(chunk_limiter.clone());
+
+        let chunk_limiter_ref = chunk_limiter.clone();
         let execution_stop_time_ref = execution_stop_time.clone();
         //let time_slice_ref = time_slice.clone();
-        lua.set_interrupt(move |_lua| {
+        lua.set_interrupt(move |lua| {
             // If the runtime is broken, yield the lua vm immediately
             let broken = broken_ref.get();
             if broken {
                 return Ok(LuaVmState::Yield);
             }
-            
+
+            // Limited chunks are checked against their own limits first, the runtime's time limit still applies to them
+            chunk_limiter_ref.check(lua)?;
+
             if let Some(limit) = execution_stop_time_ref.get() {
                 if Instant::now() > limit {
                     return Err(LuaError::RuntimeError(
@@ -191,6 +272,9 @@ impl KhronosRuntime {
         lua.globals().set("getfenv", LuaValue::Nil)?;
         lua.globals().set("setfenv", LuaValue::Nil)?;
 
+        // Redact modules loaded from opaque Vfs's from debug.info and debug.traceback
+        crate::primitives::opaque::install_redaction(&lua)?;
+
         // Ensure _G.print and _G.eprint are nil
         lua.globals().set("print", LuaValue::Nil)?;
         lua.globals().set("eprint", LuaValue::Nil)?;
@@ -198,22 +282,44 @@ impl KhronosRuntime {
 
         // Setup require function
         let global_table = proxy_global(&lua)?;
-        let controller = AssetRequirer::new_arc(vfs, "main".to_string(), global_table.clone());
+        let controller = super::bytecodecache::CachedRequirer::new(
+            AssetRequirer::new_arc(vfs, "main".to_string(), global_table.clone()),
+            files,
+            Self::compile_options(),
+            global_table.clone(),
+        )
+        .with_base(super::bytecodecache::VFS_ROOT_MODULE);
         let require = lua.create_require_function(controller)?;
         global_table
             .set("require", require)?;
 
         let proxy_require = lua.load("return require(...)")
             .set_environment(global_table.clone())
-            .set_name("/init.luau")
+            .set_name("=[require]")
             .set_mode(mluau::ChunkMode::Text)
             .try_cache()
             .into_function()?;
 
-        if let Some(on_thread_event_callback) = on_thread_event_callback {
-            lua.set_thread_creation_callback(on_thread_event_callback.0);
-            lua.set_thread_collection_callback(on_thread_event_callback.1);
-        }
+        // Threads are tagged with the limited Chunk creating them (if any) before being handed to the host's callbacks
+        let (on_thread_created, on_thread_collected) = match on_thread_event_callback {
+            Some((created, collected)) => (Some(created), Some(collected)),
+            None => (None, None),
+        };
+        let chunk_limiter_ref = chunk_limiter.clone();
+        lua.set_thread_creation_callback(move |lua, thread| {
+            chunk_limiter_ref.on_thread_created(lua, &thread);
+            match on_thread_created {
+                Some(ref cb) => cb(lua, thread),
+                None => Ok(()),
+            }
+        });
+        let chunk_limiter_ref = chunk_limiter.clone();
+        lua.set_thread_collection_callback(move |thread| {
+            chunk_limiter_ref.on_thread_collected(thread.0 as *const _);
+            if let Some(ref cb) = on_thread_collected {
+                cb(thread);
+            }
+        });
 
         // Now, sandbox the lua vm
         lua.sandbox(true)?;
@@ -241,9 +347,37 @@ impl KhronosRuntime {
             &format!("@{prefix}/json"),
             crate::core::json::init_plugin(&lua)?,
         )?;
+        lua.register_module(
+            &format!("@{prefix}/toml"),
+            crate::core::toml::init_plugin(&lua)?,
+        )?;
+        lua.register_module(
+            &format!("@{prefix}/yaml"),
+            crate::core::yaml::init_plugin(&lua)?,
+        )?;
+        lua.register_module(
+            &format!("@{prefix}/encoding"),
+            crate::core::encoding::init_plugin(&lua)?,
+        )?;
+        lua.register_module(
+            &format!("@{prefix}/msgpack"),
+            crate::core::msgpack::init_plugin(&lua)?,
+        )?;
+        lua.register_module(
+            &format!("@{prefix}/cbor"),
+            crate::core::cbor::init_plugin(&lua)?,
+        )?;
+        let default_password_limits = crate::core::datamgmt::PasswordHashLimits::default();
         lua.register_module(
             &format!("@{prefix}/datamgmt"),
-            crate::core::datamgmt::init_plugin(&lua)?,
+            crate::core::datamgmt::init_plugin(
+                &lua,
+                crate::core::datamgmt::PasswordHashLimits {
+                    max_m_cost: opts.password_hash_max_m_cost.unwrap_or(default_password_limits.max_m_cost),
+                    max_t_cost: opts.password_hash_max_t_cost.unwrap_or(default_password_limits.max_t_cost),
+                    max_p_cost: opts.password_hash_max_p_cost.unwrap_or(default_password_limits.max_p_cost),
+                }
+            )?,
         )?;
         lua.register_module(
             &format!("@{prefix}/typesext"),
@@ -255,7 +389,11 @@ impl KhronosRuntime {
             crate::core::wasm::init_plugin(
                 &lua, 
                 opts.wasm_max_memory_bytes.unwrap_or(10 * 1024 * 1024), 
-                opts.wasm_max_fuel_per_slice.unwrap_or(100_000_000)
+                opts.wasm_max_fuel_per_slice.unwrap_or(100_000_000),
+                crate::core::wasm::WasmTimeLimits {
+                    time_limit: time_limit.clone(),
+                    stop_time: execution_stop_time.clone(),
+                },
             )?
         )?;
 
@@ -276,6 +414,33 @@ impl KhronosRuntime {
         })
     }
 
+    /// Returns the compiler options runtimes compile code with
+    pub fn compile_options() -> super::bytecodecache::CompileOptions {
+        super::bytecodecache::CompileOptions {
+            optimization_level: 2,
+            debug_level: 1,
+            type_info_level: 1,
+        }
+    }
+
+    /// Compiles the ``.luau``/``.lua`` files (e.g. the files a template's Vfs is built from) into the
+    /// process-wide bytecode cache ahead of time, returning the number of files compiled
+    pub fn prewarm_bytecode_cache<'a>(files: impl IntoIterator<Item = (&'a str, &'a str)>) -> usize {
+        super::bytecodecache::BytecodeCache::global().prewarm(&Self::compile_options(), files)
+    }
+
+    /// Sets which file extensions Vfs's created from within the runtime (e.g. with
+    /// ``Vfs.newoverlay``) allow requiring as data assets. No assets are allowed by default
+    ///
+    /// This does not affect the files the runtime was created with
+    pub fn set_asset_options(&self, opts: super::assets::AssetOptions) -> Result<(), LuaError> {
+        let Some(ref lua) = *self.lua.borrow() else {
+            return Err(LuaError::RuntimeError("Lua VM is not valid".to_string()));
+        };
+        lua.set_app_data(opts);
+        Ok(())
+    }
+
     /// Returns the scheduler
     pub fn scheduler(&self) -> &S {
         &self.scheduler
@@ -376,7 +541,11 @@ impl KhronosRuntime {
         let Some(ref lua) = *self.lua.borrow() else {
             return Err(LuaError::RuntimeError("Lua VM is not valid".to_string()));
         };
-        lua.set_memory_limit(limit)
+        // Chunks with their own memory limit lower the VM's limit while they run
+        match lua.app_data_ref::<Rc<crate::core::luau::ChunkLimiter>>() {
+            Some(limiter) => limiter.set_runtime_memory_limit(lua, limit),
+            None => lua.set_memory_limit(limit),
+        }
     }
 
     /// Returns the store table for the runtime
@@ -441,22 +610,43 @@ impl KhronosRuntime {
             return Err(LuaError::RuntimeError("Lua VM is not valid".to_string()));
         };
 
+        let bytecode = self.handle_error(
+            super::bytecodecache::BytecodeCache::global().get_or_compile(&Self::compile_options(), code.as_bytes()),
+        )?;
+
         let chunk = match name {
-            Some(n) => lua.load(code).set_name(n),
-            None => lua.load(code),
+            Some(n) => lua.load(bytecode.to_vec()).set_name(n),
+            None => lua.load(bytecode.to_vec()),
         };
         let chunk = match env {
             Some(e) => chunk.set_environment(e),
             None => chunk.set_environment(self.global_table.clone()),
         };
         let chunk = chunk
-            .set_compiler(self.compiler.clone())
-            .set_mode(mluau::ChunkMode::Text)
+            .set_mode(mluau::ChunkMode::Binary)
             .try_cache();
 
         self.handle_error(chunk.into_function())
     }
 
+    /// Parses (compiles without executing) a chunk of code, returning its diagnostics and
+    /// optionally the ``require`` paths and globals it references
+    ///
+    /// Globals in the runtime's global table are treated as known when linting
+    pub fn parse_chunk(
+        &self,
+        code: &str,
+        opts: &crate::utils::luauscan::ParseOptions,
+    ) -> LuaResult<crate::utils::luauscan::ParseResult> {
+        if self.lua.borrow().is_none() {
+            return Err(LuaError::RuntimeError("Lua VM is not valid".to_string()));
+        }
+
+        Ok(crate::utils::luauscan::parse_luau(&self.compiler, code, opts, |name| {
+            self.global_table.contains_key(name).unwrap_or(false)
+        }))
+    }
+
     /// Helper method to call a function inside of the scheduler as a thread
     pub async fn call_in_scheduler<A, R>(
         &self,
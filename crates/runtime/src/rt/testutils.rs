@@ -0,0 +1,68 @@
+//! Helpers for running Luau scripts in tests
+
+use std::collections::HashMap;
+
+use bytes::Bytes;
+use mluau::prelude::*;
+use tokio::runtime::LocalOptions;
+
+use super::assets::{AssetOptions, VfsFiles};
+use super::{KhronosRuntime, RuntimeCreateOpts};
+
+/// Creates a runtime whose ``init.luau`` is ``script``
+pub fn create_runtime(opts: RuntimeCreateOpts, script: &str) -> LuaResult<KhronosRuntime> {
+    create_runtime_with_files(
+        opts,
+        HashMap::from([("init.luau".to_string(), script.to_string())]),
+        &AssetOptions::empty(),
+    )
+}
+
+/// Creates a runtime from a set of files, which must include ``init.luau``
+pub fn create_runtime_with_files<C: Into<Bytes>>(
+    opts: RuntimeCreateOpts,
+    files: HashMap<String, C>,
+    assets: &AssetOptions,
+) -> LuaResult<KhronosRuntime> {
+    KhronosRuntime::new_with_files(
+        opts,
+        None::<(fn(&Lua, LuaThread) -> Result<(), LuaError>, fn(LuaLightUserData) -> ())>,
+        VfsFiles::new(files, assets),
+        "antiraid",
+    )
+}
+
+/// Runs ``script``, which must return a function, calling it inside of the scheduler with the
+/// arguments made by ``args``
+pub fn run_script<A: IntoLuaMulti>(
+    opts: RuntimeCreateOpts,
+    script: &str,
+    args: impl FnOnce(&Lua) -> LuaResult<A>,
+) -> LuaResult<()> {
+    run_files(
+        opts,
+        HashMap::from([("init.luau".to_string(), script.to_string())]),
+        &AssetOptions::empty(),
+        args,
+    )
+}
+
+/// Like ``run_script``, with ``init.luau`` (and the modules it requires) taken from ``files``
+pub fn run_files<A: IntoLuaMulti, C: Into<Bytes>>(
+    opts: RuntimeCreateOpts,
+    files: HashMap<String, C>,
+    assets: &AssetOptions,
+    args: impl FnOnce(&Lua) -> LuaResult<A>,
+) -> LuaResult<()> {
+    let tokio_rt = tokio::runtime::Builder::new_current_thread()
+        .enable_time()
+        .build_local(LocalOptions::default())
+        .unwrap();
+
+    tokio_rt.block_on(async move {
+        let rt = create_runtime_with_files(opts, files, assets)?;
+        let args = rt.with_lua(args)?;
+        let f = rt.eval_script::<LuaFunction>("./init")?;
+        rt.call_in_scheduler::<_, ()>(f, args).await
+    })
+}
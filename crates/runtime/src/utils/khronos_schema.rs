@@ -0,0 +1,708 @@
+//! Declarative schemas for validating (and coercing) KhronosValue's
+//!
+//! Schemas are plain data, so they can be built in Rust or deserialized from a Luau descriptor
+//! table such as:
+//!
+//! ```luau
+//! {
+//!     type = "struct",
+//!     fields = {
+//!         name = { type = "text", pattern = "^[a-z]+$", maxlength = 32 },
+//!         age = { type = "optional", inner = { type = "integer", min = 0 } },
+//!         tags = { type = "list", items = { type = "text" } },
+//!     },
+//! }
+//! ```
+//!
+//! Errors are annotated with the path of the offending value (``$.tags[2]``, list indices start
+//! at 1 as in Luau).
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+use mluau::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::utils::khronos_value::KhronosValue;
+
+/// Maximum nesting depth of a schema
+const MAX_SCHEMA_DEPTH: usize = 64;
+
+/// Maximum compiled size of a string pattern
+const MAX_PATTERN_SIZE: usize = 1024 * 1024;
+
+/// A regular expression a text value must match (anywhere, use ``^``/``$`` to anchor it)
+#[derive(Clone)]
+pub struct SchemaPattern(regex::Regex);
+
+impl SchemaPattern {
+    pub fn new(pattern: &str) -> Result<Self, regex::Error> {
+        regex::RegexBuilder::new(pattern)
+            .size_limit(MAX_PATTERN_SIZE)
+            .build()
+            .map(Self)
+    }
+}
+
+impl fmt::Debug for SchemaPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SchemaPattern({:?})", self.0.as_str())
+    }
+}
+
+impl Serialize for SchemaPattern {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.0.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for SchemaPattern {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let pattern = String::deserialize(deserializer)?;
+        Self::new(&pattern).map_err(serde::de::Error::custom)
+    }
+}
+
+/// An empty Luau table can't be told apart from an empty list or map, so ``fields = {}`` may be
+/// deserialized from an empty sequence and ``variants = {}`` from an empty map
+mod empty_table {
+    use std::collections::BTreeMap;
+    use std::fmt;
+    use std::marker::PhantomData;
+
+    use serde::de::{self, Deserialize, Deserializer, IgnoredAny, MapAccess, SeqAccess, Unexpected, Visitor};
+
+    struct SeqVisitor<T>(PhantomData<T>);
+
+    impl<'de, T: Deserialize<'de>> Visitor<'de> for SeqVisitor<T> {
+        type Value = Vec<T>;
+
+        fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str("a list")
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+            let mut out = Vec::new();
+            while let Some(v) = seq.next_element()? {
+                out.push(v);
+            }
+            Ok(out)
+        }
+
+        fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+            match map.next_key::<IgnoredAny>()? {
+                None => Ok(Vec::new()),
+                Some(_) => Err(de::Error::invalid_type(Unexpected::Map, &self)),
+            }
+        }
+    }
+
+    struct MapVisitor<T>(PhantomData<T>);
+
+    impl<'de, T: Deserialize<'de>> Visitor<'de> for MapVisitor<T> {
+        type Value = BTreeMap<String, T>;
+
+        fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str("a map")
+        }
+
+        fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+            let mut out = BTreeMap::new();
+            while let Some((k, v)) = map.next_entry()? {
+                out.insert(k, v);
+            }
+            Ok(out)
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+            match seq.next_element::<IgnoredAny>()? {
+                None => Ok(BTreeMap::new()),
+                Some(_) => Err(de::Error::invalid_type(Unexpected::Seq, &self)),
+            }
+        }
+    }
+
+    // deserialize_any as the derived (internally tagged) enum buffers its fields first
+
+    pub fn seq<'de, D: Deserializer<'de>, T: Deserialize<'de>>(deserializer: D) -> Result<Vec<T>, D::Error> {
+        deserializer.deserialize_any(SeqVisitor(PhantomData))
+    }
+
+    pub fn map<'de, D: Deserializer<'de>, T: Deserialize<'de>>(deserializer: D) -> Result<BTreeMap<String, T>, D::Error> {
+        deserializer.deserialize_any(MapVisitor(PhantomData))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum KhronosSchema {
+    Any,
+    /// Nil or null
+    Nil,
+    Boolean,
+    Integer {
+        min: Option<i64>,
+        max: Option<i64>,
+    },
+    Int64 {
+        min: Option<i64>,
+        max: Option<i64>,
+    },
+    /// Any number (Integer values are accepted as is)
+    Number {
+        min: Option<f64>,
+        max: Option<f64>,
+    },
+    Text {
+        /// Length in characters
+        #[serde(rename = "minlength")]
+        min_length: Option<usize>,
+        #[serde(rename = "maxlength")]
+        max_length: Option<usize>,
+        pattern: Option<SchemaPattern>,
+    },
+    Bytes {
+        #[serde(rename = "minlength")]
+        min_length: Option<usize>,
+        #[serde(rename = "maxlength")]
+        max_length: Option<usize>,
+    },
+    Vector,
+    Timestamptz,
+    Interval,
+    TimeZone,
+    MemoryVfs,
+    List {
+        items: Box<KhronosSchema>,
+        #[serde(rename = "minlength")]
+        min_length: Option<usize>,
+        #[serde(rename = "maxlength")]
+        max_length: Option<usize>,
+    },
+    /// A map with keys and values of the given types
+    Map {
+        keys: Box<KhronosSchema>,
+        values: Box<KhronosSchema>,
+    },
+    /// A table with the given (string) fields. Fields whose schema accepts nil are optional
+    Struct {
+        #[serde(deserialize_with = "empty_table::map")]
+        fields: BTreeMap<String, KhronosSchema>,
+        /// Whether fields not in ``fields`` are allowed (and kept as is)
+        #[serde(default)]
+        additional: bool,
+    },
+    /// The first of the variants the value matches
+    Union {
+        #[serde(deserialize_with = "empty_table::seq")]
+        variants: Vec<KhronosSchema>,
+    },
+    /// Nil (or null) or the inner type
+    Optional {
+        inner: Box<KhronosSchema>,
+    },
+}
+
+/// A value not matching a schema
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SchemaError {
+    pub path: String,
+    pub message: String,
+}
+
+impl fmt::Display for SchemaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.path, self.message)
+    }
+}
+
+fn value_type_name(value: &KhronosValue) -> &'static str {
+    match value {
+        KhronosValue::Text(_) => "text",
+        KhronosValue::Integer(_) => "integer",
+        KhronosValue::Int64(_) => "int64",
+        KhronosValue::Float(_) => "number",
+        KhronosValue::Boolean(_) => "boolean",
+        KhronosValue::Vector(_) => "vector",
+        KhronosValue::Map(_) | KhronosValue::StrMap(_) => "map",
+        KhronosValue::List(_) => "list",
+        KhronosValue::Timestamptz(_) => "timestamptz",
+        KhronosValue::Interval(_) => "interval",
+        KhronosValue::TimeZone(_) => "timezone",
+        KhronosValue::MemoryVfs(_) => "memoryvfs",
+        KhronosValue::Bytes(_) => "bytes",
+        KhronosValue::Nil(_) => "nil",
+        KhronosValue::Null(_) => "null",
+    }
+}
+
+/// Returns the entries of a map-like value (empty Luau tables may be converted to lists)
+fn map_entries(value: &KhronosValue) -> Option<Vec<(KhronosValue, &KhronosValue)>> {
+    match value {
+        KhronosValue::StrMap(m) => Some(m.iter().map(|(k, v)| (KhronosValue::Text(k.clone()), v)).collect()),
+        KhronosValue::Map(m) => Some(m.iter().map(|(k, v)| (k.clone(), v)).collect()),
+        KhronosValue::List(l) if l.is_empty() => Some(Vec::new()),
+        _ => None,
+    }
+}
+
+fn key_path(path: &str, key: &KhronosValue) -> String {
+    match key {
+        KhronosValue::Text(s) if !s.is_empty() && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') => {
+            format!("{path}.{s}")
+        }
+        KhronosValue::Text(s) => format!("{path}[{s:?}]"),
+        KhronosValue::Integer(i) | KhronosValue::Int64(i) => format!("{path}[{i}]"),
+        k => format!("{path}[<{}>]", value_type_name(k)),
+    }
+}
+
+impl KhronosSchema {
+    pub fn optional(inner: KhronosSchema) -> Self {
+        Self::Optional { inner: Box::new(inner) }
+    }
+
+    pub fn list(items: KhronosSchema) -> Self {
+        Self::List { items: Box::new(items), min_length: None, max_length: None }
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Self::Any => "any",
+            Self::Nil => "nil",
+            Self::Boolean => "boolean",
+            Self::Integer { .. } => "integer",
+            Self::Int64 { .. } => "int64",
+            Self::Number { .. } => "number",
+            Self::Text { .. } => "text",
+            Self::Bytes { .. } => "bytes",
+            Self::Vector => "vector",
+            Self::Timestamptz => "timestamptz",
+            Self::Interval => "interval",
+            Self::TimeZone => "timezone",
+            Self::MemoryVfs => "memoryvfs",
+            Self::List { .. } => "list",
+            Self::Map { .. } => "map",
+            Self::Struct { .. } => "struct",
+            Self::Union { .. } => "union",
+            Self::Optional { .. } => "optional",
+        }
+    }
+
+    /// Whether nil is a valid value (so a struct field of this type may be left out)
+    fn accepts_nil(&self) -> bool {
+        match self {
+            Self::Any | Self::Nil | Self::Optional { .. } => true,
+            Self::Union { variants } => variants.iter().any(|v| v.accepts_nil()),
+            _ => false,
+        }
+    }
+
+    /// Checks that ``value`` matches the schema exactly
+    pub fn validate(&self, value: &KhronosValue) -> Result<(), Vec<SchemaError>> {
+        let mut errors = Vec::new();
+        self.walk(value, "$", &mut errors, false, 0);
+        if errors.is_empty() { Ok(()) } else { Err(errors) }
+    }
+
+    /// Checks that ``value`` matches the schema, converting values where there is an unambiguous
+    /// conversion (between Integer and Int64, integral numbers to integers, RFC3339 strings to
+    /// timestamps, time zone names to time zones and strings to bytes)
+    pub fn coerce(&self, value: &KhronosValue) -> Result<KhronosValue, Vec<SchemaError>> {
+        let mut errors = Vec::new();
+        match self.walk(value, "$", &mut errors, true, 0) {
+            Some(value) if errors.is_empty() => Ok(value),
+            _ => Err(errors),
+        }
+    }
+
+    fn walk(
+        &self,
+        value: &KhronosValue,
+        path: &str,
+        errors: &mut Vec<SchemaError>,
+        coerce: bool,
+        depth: usize,
+    ) -> Option<KhronosValue> {
+        macro_rules! fail {
+            ($($arg:tt)*) => {{
+                errors.push(SchemaError { path: path.to_string(), message: format!($($arg)*) });
+                return None;
+            }};
+        }
+
+        macro_rules! mismatch {
+            () => {
+                fail!("expected {}, got {}", self.type_name(), value_type_name(value))
+            };
+        }
+
+        macro_rules! check_len {
+            ($len:expr, $min_length:expr, $max_length:expr) => {
+                if let Some(min) = $min_length {
+                    if $len < *min {
+                        fail!("length {} is less than the minimum of {min}", $len);
+                    }
+                }
+                if let Some(max) = $max_length {
+                    if $len > *max {
+                        fail!("length {} is greater than the maximum of {max}", $len);
+                    }
+                }
+            };
+        }
+
+        macro_rules! check_range {
+            ($n:expr, $min:expr, $max:expr) => {
+                if let Some(min) = $min {
+                    if $n < *min {
+                        fail!("{} is less than the minimum of {min}", $n);
+                    }
+                }
+                if let Some(max) = $max {
+                    if $n > *max {
+                        fail!("{} is greater than the maximum of {max}", $n);
+                    }
+                }
+            };
+        }
+
+        if depth > MAX_SCHEMA_DEPTH {
+            fail!("schema is nested deeper than {MAX_SCHEMA_DEPTH} levels");
+        }
+
+        match self {
+            Self::Any => Some(value.clone()),
+            Self::Nil => match value {
+                KhronosValue::Nil(_) | KhronosValue::Null(_) => Some(value.clone()),
+                _ => mismatch!(),
+            },
+            Self::Boolean => match value {
+                KhronosValue::Boolean(_) => Some(value.clone()),
+                _ => mismatch!(),
+            },
+            Self::Integer { min, max } | Self::Int64 { min, max } => {
+                let int64 = matches!(self, Self::Int64 { .. });
+                let i = match value {
+                    KhronosValue::Integer(i) if !int64 || coerce => *i,
+                    KhronosValue::Int64(i) if int64 || coerce => *i,
+                    KhronosValue::Float(f)
+                        if coerce && f.fract() == 0.0 && *f >= i64::MIN as f64 && *f < i64::MAX as f64 =>
+                    {
+                        *f as i64
+                    }
+                    _ => mismatch!(),
+                };
+                check_range!(i, min, max);
+                Some(if int64 { KhronosValue::Int64(i) } else { KhronosValue::Integer(i) })
+            }
+            Self::Number { min, max } => {
+                let (f, out) = match value {
+                    KhronosValue::Float(f) => (*f, value.clone()),
+                    KhronosValue::Integer(i) => (*i as f64, value.clone()),
+                    KhronosValue::Int64(i) if coerce => (*i as f64, KhronosValue::Float(*i as f64)),
+                    _ => mismatch!(),
+                };
+                if f.is_nan() {
+                    fail!("expected a number, got NaN");
+                }
+                check_range!(f, min, max);
+                Some(out)
+            }
+            Self::Text { min_length, max_length, pattern } => {
+                let KhronosValue::Text(s) = value else {
+                    mismatch!();
+                };
+                check_len!(s.chars().count(), min_length, max_length);
+                if let Some(pattern) = pattern {
+                    if !pattern.0.is_match(s) {
+                        fail!("{s:?} does not match the pattern {:?}", pattern.0.as_str());
+                    }
+                }
+                Some(value.clone())
+            }
+            Self::Bytes { min_length, max_length } => {
+                let bytes = match value {
+                    KhronosValue::Bytes(b) => b.clone(),
+                    KhronosValue::Text(s) if coerce => bytes::Bytes::copy_from_slice(s.as_bytes()),
+                    _ => mismatch!(),
+                };
+                check_len!(bytes.len(), min_length, max_length);
+                Some(KhronosValue::Bytes(bytes))
+            }
+            Self::Vector => match value {
+                KhronosValue::Vector(_) => Some(value.clone()),
+                _ => mismatch!(),
+            },
+            Self::Timestamptz => match value {
+                KhronosValue::Timestamptz(_) => Some(value.clone()),
+                KhronosValue::Text(s) if coerce => match chrono::DateTime::parse_from_rfc3339(s) {
+                    Ok(dt) => Some(KhronosValue::Timestamptz(dt.with_timezone(&chrono::Utc))),
+                    Err(e) => fail!("{s:?} is not a valid RFC3339 timestamp: {e}"),
+                },
+                _ => mismatch!(),
+            },
+            Self::Interval => match value {
+                KhronosValue::Interval(_) => Some(value.clone()),
+                _ => mismatch!(),
+            },
+            Self::TimeZone => match value {
+                KhronosValue::TimeZone(_) => Some(value.clone()),
+                KhronosValue::Text(s) if coerce => match s.parse::<chrono_tz::Tz>() {
+                    Ok(tz) => Some(KhronosValue::TimeZone(tz)),
+                    Err(e) => fail!("{s:?} is not a valid time zone: {e}"),
+                },
+                _ => mismatch!(),
+            },
+            Self::MemoryVfs => match value {
+                KhronosValue::MemoryVfs(_) => Some(value.clone()),
+                _ => mismatch!(),
+            },
+            Self::List { items, min_length, max_length } => {
+                let values: &[KhronosValue] = match value {
+                    KhronosValue::List(l) => l,
+                    // Empty Luau tables are converted to maps
+                    KhronosValue::StrMap(m) if m.is_empty() => &[],
+                    KhronosValue::Map(m) if m.is_empty() => &[],
+                    _ => mismatch!(),
+                };
+                check_len!(values.len(), min_length, max_length);
+
+                let mut out = Vec::with_capacity(values.len());
+                for (i, v) in values.iter().enumerate() {
+                    if let Some(v) = items.walk(v, &format!("{path}[{}]", i + 1), errors, coerce, depth + 1) {
+                        out.push(v);
+                    }
+                }
+                (out.len() == values.len()).then_some(KhronosValue::List(out))
+            }
+            Self::Map { keys, values } => {
+                let Some(entries) = map_entries(value) else {
+                    mismatch!();
+                };
+
+                let mut out = Vec::with_capacity(entries.len());
+                for (k, v) in entries.iter() {
+                    let path = key_path(path, k);
+                    let k = keys.walk(k, &path, errors, coerce, depth + 1);
+                    let v = values.walk(v, &path, errors, coerce, depth + 1);
+                    if let (Some(k), Some(v)) = (k, v) {
+                        out.push((k, v));
+                    }
+                }
+                if out.len() != entries.len() {
+                    return None;
+                }
+
+                if out.iter().all(|(k, _)| matches!(k, KhronosValue::Text(_))) {
+                    let str_map = out
+                        .into_iter()
+                        .map(|(k, v)| match k {
+                            KhronosValue::Text(k) => (k, v),
+                            _ => unreachable!(),
+                        })
+                        .collect::<HashMap<_, _>>();
+                    Some(KhronosValue::StrMap(Box::new(str_map)))
+                } else {
+                    Some(KhronosValue::Map(out))
+                }
+            }
+            Self::Struct { fields, additional } => {
+                let Some(entries) = map_entries(value) else {
+                    mismatch!();
+                };
+
+                let errors_before = errors.len();
+                // Sorted so unknown fields are reported in a stable order
+                let mut present = BTreeMap::new();
+                for (k, v) in entries.iter() {
+                    let KhronosValue::Text(name) = k else {
+                        errors.push(SchemaError {
+                            path: key_path(path, k),
+                            message: format!("expected a text key, got {}", value_type_name(k)),
+                        });
+                        continue;
+                    };
+                    present.insert(name.as_ref(), *v);
+                }
+
+                let mut out = HashMap::with_capacity(entries.len());
+                for (name, schema) in fields {
+                    let field_path = key_path(path, &KhronosValue::Text(name.clone().into()));
+                    match present.get(name.as_str()) {
+                        Some(v) => {
+                            if let Some(v) = schema.walk(v, &field_path, errors, coerce, depth + 1) {
+                                out.insert(name.clone().into(), v);
+                            }
+                        }
+                        None if schema.accepts_nil() => {}
+                        None => errors.push(SchemaError {
+                            path: field_path,
+                            message: format!("missing required field of type {}", schema.type_name()),
+                        }),
+                    }
+                }
+
+                for (name, v) in present.iter() {
+                    if fields.contains_key(*name) {
+                        continue;
+                    }
+                    if !additional {
+                        errors.push(SchemaError {
+                            path: key_path(path, &KhronosValue::Text(name.to_string().into())),
+                            message: "unknown field".to_string(),
+                        });
+                        continue;
+                    }
+                    out.insert(name.to_string().into(), (*v).clone());
+                }
+
+                (errors.len() == errors_before).then_some(KhronosValue::StrMap(Box::new(out)))
+            }
+            Self::Union { variants } => {
+                // Prefer variants matching exactly over ones that would need a conversion
+                let passes: &[bool] = if coerce { &[false, true] } else { &[false] };
+                for &pass_coerce in passes {
+                    for variant in variants {
+                        let mut scratch = Vec::new();
+                        if let Some(v) = variant.walk(value, path, &mut scratch, pass_coerce, depth + 1) {
+                            return Some(v);
+                        }
+                    }
+                }
+
+                if variants.is_empty() {
+                    fail!("an empty union matches no values, got {}", value_type_name(value));
+                }
+                let names = variants.iter().map(|v| v.type_name()).collect::<Vec<_>>();
+                fail!("expected one of {}, got {}", names.join(" | "), value_type_name(value))
+            }
+            Self::Optional { inner } => match value {
+                KhronosValue::Nil(_) | KhronosValue::Null(_) => Some(value.clone()),
+                _ => inner.walk(value, path, errors, coerce, depth + 1),
+            },
+        }
+    }
+
+    /// Deserializes a schema from a Luau descriptor table
+    pub fn from_lua_descriptor(lua: &Lua, descriptor: LuaValue) -> LuaResult<Self> {
+        lua.from_value(descriptor)
+            .map_err(|e| LuaError::external(format!("Invalid schema: {e}")))
+    }
+}
+
+fn errors_to_lua(lua: &Lua, errors: &[SchemaError]) -> LuaResult<LuaTable> {
+    let table = lua.create_table_with_capacity(errors.len(), 0)?;
+    for err in errors {
+        let entry = lua.create_table()?;
+        entry.set("path", err.path.as_str())?;
+        entry.set("message", err.message.as_str())?;
+        table.raw_push(entry)?;
+    }
+    table.set_metatable(Some(lua.array_metatable()))?;
+    Ok(table)
+}
+
+impl LuaUserData for KhronosSchema {
+    fn add_fields<F: LuaUserDataFields<Self>>(fields: &mut F) {
+        fields.add_meta_field(LuaMetaMethod::Type, "KhronosSchema");
+        fields.add_field_method_get("type", |_, this| Ok(this.type_name()));
+    }
+
+    fn add_methods<M: LuaUserDataMethods<Self>>(methods: &mut M) {
+        // Returns whether the value is valid along with a list of path-annotated errors
+        methods.add_method("validate", |lua, this, value: KhronosValue| {
+            let errors = match this.validate(&value) {
+                Ok(()) => Vec::new(),
+                Err(errors) => errors,
+            };
+            Ok((errors.is_empty(), errors_to_lua(lua, &errors)?))
+        });
+
+        // Returns the coerced value, or nil and a list of path-annotated errors
+        methods.add_method("coerce", |lua, this, value: KhronosValue| {
+            match this.coerce(&value) {
+                Ok(value) => Ok((value.into_lua(lua)?, LuaValue::Nil)),
+                Err(errors) => Ok((LuaValue::Nil, LuaValue::Table(errors_to_lua(lua, &errors)?))),
+            }
+        });
+    }
+
+    #[cfg(feature = "repl")]
+    fn register(registry: &mut LuaUserDataRegistry<Self>) {
+        Self::add_fields(registry);
+        Self::add_methods(registry);
+        let fields = registry.fields(false).iter().map(|x| x.to_string()).collect::<Vec<_>>();
+        registry.add_meta_field("__ud_fields", fields);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strmap(entries: &[(&'static str, KhronosValue)]) -> KhronosValue {
+        KhronosValue::StrMap(Box::new(entries.iter().map(|(k, v)| ((*k).into(), v.clone())).collect()))
+    }
+
+    #[test]
+    fn test_schema() {
+        let schema: KhronosSchema = serde_json::from_value(serde_json::json!({
+            "type": "struct",
+            "fields": {
+                "name": { "type": "text", "pattern": "^[a-z]+$", "maxlength": 8 },
+                "id": { "type": "int64", "min": 1 },
+                "at": { "type": "optional", "inner": { "type": "timestamptz" } },
+                "tags": { "type": "list", "items": { "type": "union", "variants": [{ "type": "text" }, { "type": "integer" }] } },
+            }
+        }))
+        .unwrap();
+
+        let value = strmap(&[
+            ("name", KhronosValue::Text("Bad Name".into())),
+            ("id", KhronosValue::Integer(5)),
+            ("tags", KhronosValue::List(vec![KhronosValue::Text("a".into()), KhronosValue::Boolean(true)])),
+            ("extra", KhronosValue::Boolean(true)),
+            ("another", KhronosValue::Boolean(true)),
+            ("zzz", KhronosValue::Boolean(true)),
+        ]);
+        let errors = schema.validate(&value).unwrap_err();
+        let paths = errors.iter().map(|e| e.path.as_str()).collect::<Vec<_>>();
+        assert_eq!(paths, vec!["$.id", "$.name", "$.tags[2]", "$.another", "$.extra", "$.zzz"]);
+
+        // Integer to Int64 and RFC3339 strings to timestamps
+        let value = strmap(&[
+            ("name", KhronosValue::Text("abc".into())),
+            ("id", KhronosValue::Integer(5)),
+            ("at", KhronosValue::Text("2024-01-02T03:04:05Z".into())),
+            ("tags", KhronosValue::StrMap(Box::default())),
+        ]);
+        assert!(schema.validate(&value).is_err());
+        let KhronosValue::StrMap(coerced) = schema.coerce(&value).unwrap() else {
+            panic!("expected a map");
+        };
+        assert!(matches!(coerced.get("id"), Some(KhronosValue::Int64(5))));
+        assert!(matches!(coerced.get("at"), Some(KhronosValue::Timestamptz(_))));
+        assert!(matches!(coerced.get("tags"), Some(KhronosValue::List(l)) if l.is_empty()));
+
+        let missing = schema.validate(&strmap(&[("name", KhronosValue::Text("abc".into()))])).unwrap_err();
+        assert_eq!(missing[0].to_string(), "$.id: missing required field of type int64");
+
+        assert!(serde_json::from_value::<KhronosSchema>(serde_json::json!({ "type": "text", "pattern": "(" })).is_err());
+    }
+
+    #[test]
+    fn test_empty_tables() {
+        let schema = |v| serde_json::from_value::<KhronosSchema>(v);
+
+        let empty_struct = schema(serde_json::json!({ "type": "struct", "fields": [] })).unwrap();
+        assert!(matches!(&empty_struct, KhronosSchema::Struct { fields, .. } if fields.is_empty()));
+        assert!(empty_struct.validate(&KhronosValue::List(Vec::new())).is_ok());
+        assert!(schema(serde_json::json!({ "type": "struct", "fields": [{ "type": "any" }] })).is_err());
+
+        let empty_union = schema(serde_json::json!({ "type": "union", "variants": {} })).unwrap();
+        assert!(matches!(&empty_union, KhronosSchema::Union { variants } if variants.is_empty()));
+        assert!(empty_union.validate(&KhronosValue::Boolean(true)).is_err());
+        assert!(schema(serde_json::json!({ "type": "union", "variants": { "a": { "type": "any" } } })).is_err());
+    }
+}
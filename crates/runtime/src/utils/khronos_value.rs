@@ -1,6 +1,7 @@
 use std::{borrow::Cow, collections::HashMap, fmt};
 
 use mluau::prelude::*;
+use base64::Engine;
 use serde::{Deserialize, Serialize, ser::{SerializeMap, SerializeSeq}};
 
 use crate::core::typesext::MemoryVfs;
@@ -38,6 +39,56 @@ mod string_i64 {
     }
 }
 
+/// Bytes as base64 in human readable formats (JSON) and as raw bytes otherwise
+mod base64_bytes {
+    use base64::Engine;
+    use serde::{de, Deserializer, Serializer};
+
+    pub fn serialize<S>(value: &bytes::Bytes, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        if serializer.is_human_readable() {
+            serializer.serialize_str(&base64::prelude::BASE64_STANDARD.encode(value))
+        } else {
+            serializer.serialize_bytes(value)
+        }
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<bytes::Bytes, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct BytesVisitor;
+
+        impl<'de> de::Visitor<'de> for BytesVisitor {
+            type Value = bytes::Bytes;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                formatter.write_str("bytes or a base64 string")
+            }
+
+            fn visit_str<E: de::Error>(self, value: &str) -> Result<Self::Value, E> {
+                base64::prelude::BASE64_STANDARD.decode(value).map(Into::into).map_err(de::Error::custom)
+            }
+
+            fn visit_bytes<E: de::Error>(self, value: &[u8]) -> Result<Self::Value, E> {
+                Ok(bytes::Bytes::copy_from_slice(value))
+            }
+
+            fn visit_byte_buf<E: de::Error>(self, value: Vec<u8>) -> Result<Self::Value, E> {
+                Ok(value.into())
+            }
+        }
+
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_str(BytesVisitor)
+        } else {
+            deserializer.deserialize_bytes(BytesVisitor)
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum KhronosValue {
     Text(Cow<'static, str>),
@@ -55,6 +106,8 @@ pub enum KhronosValue {
     MemoryVfs(Box<HashMap<String, String>>),
     Nil(()),
     Null(()),
+    // Kept last so the serde variant indices of existing variants do not shift
+    Bytes(#[serde(with = "base64_bytes")] bytes::Bytes), // Luau buffer
 }
 
 impl Default for KhronosValue {
@@ -84,10 +137,8 @@ impl KhronosValue {
             LuaValue::Int64(i) => Ok(KhronosValue::Int64(i)),
             LuaValue::Number(f) => Ok(KhronosValue::Float(f)),
             LuaValue::Boolean(b) => Ok(KhronosValue::Boolean(b)),
-            LuaValue::Buffer(_) => {
-                // We do not support storing values etc. yet
-                Err(LuaError::FromLuaConversionError { from: "buffer", to: "KhronosValue".to_string(), message: Some("Cannot send/store buffers.".to_string()) })
-            }
+            // Buffers are mutable, so the value gets its own copy rather than aliasing the buffer
+            LuaValue::Buffer(b) => Ok(KhronosValue::Bytes(b.to_vec().into())),
             LuaValue::Vector(v) => Ok(KhronosValue::Vector((v.x(), v.y(), v.z()))),
             LuaValue::Nil => Ok(KhronosValue::Nil(())),
             LuaValue::Table(table) => {
@@ -155,6 +206,10 @@ impl KhronosValue {
                     return Ok(KhronosValue::TimeZone(tz.tz));
                 }
                 if let Ok(mut s_map) = ud.borrow_mut::<MemoryVfs>() {
+                    if s_map.from_opaque {
+                        return Err(LuaError::external("Cannot convert an opaque MemoryVfs to a KhronosValue"));
+                    }
+
                     // Take out the contents of the lazy string map 
                     let data = std::mem::take(&mut s_map.data);
                     return Ok(KhronosValue::MemoryVfs(data.into()));
@@ -213,6 +268,8 @@ impl KhronosValue {
             KhronosValue::Interval(i) => crate::core::datetime::TimeDelta::new(i).into_lua(lua),
             KhronosValue::TimeZone(tz) => crate::core::datetime::Timezone::new(tz).into_lua(lua),
             KhronosValue::MemoryVfs(m) => MemoryVfs::new(*m).into_lua(lua),
+            // Copied for the same reason, as the value may be converted more than once
+            KhronosValue::Bytes(b) => Ok(LuaValue::Buffer(lua.create_buffer(&b[..])?)),
             KhronosValue::Nil(_) => Ok(LuaValue::Nil),
             KhronosValue::Null(_) => Ok(LuaValue::NULL),
         }
@@ -233,7 +290,8 @@ impl IntoLua for KhronosValue {
 
 /// A small 'compressed' representation of a KhronosValue (at the cost of non-self-describability)
 /// 
-/// Only supports JSON/MessagePack
+/// Only supports JSON/MessagePack. Bytes are stored as binary in MessagePack and as a base64
+/// string tagged ``B64`` in JSON
 #[derive(Debug, Clone)]
 pub struct CKhronosValue(pub KhronosValue);
 
@@ -305,6 +363,14 @@ impl<'a> Serialize for CKhronosValueRef<'a> {
             KhronosValue::TimeZone(m) => serializer.serialize_newtype_variant("Compressed", 6, "TZ", m),
             KhronosValue::MemoryVfs(m) => serializer.serialize_newtype_variant("Compressed", 7, "MVfs", m),
             KhronosValue::Null(m) => serializer.serialize_newtype_variant("Compressed", 8, "N", m),
+            KhronosValue::Bytes(b) => {
+                if serializer.is_human_readable() {
+                    let encoded = base64::prelude::BASE64_STANDARD.encode(b);
+                    serializer.serialize_newtype_variant("Compressed", 9, "B64", &encoded)
+                } else {
+                    serializer.serialize_bytes(b)
+                }
+            }
         }    
     }
 }
@@ -360,6 +426,20 @@ impl<'de> serde::de::Visitor<'de> for CKhronosVisitor {
         Ok(CKhronosValue(KhronosValue::Float(v)))
     }
 
+    fn visit_bytes<E>(self, v: &[u8]) -> Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        Ok(CKhronosValue(KhronosValue::Bytes(bytes::Bytes::copy_from_slice(v))))
+    }
+
+    fn visit_byte_buf<E>(self, v: Vec<u8>) -> Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        Ok(CKhronosValue(KhronosValue::Bytes(v.into())))
+    }
+
     fn visit_unit<E>(self) -> Result<Self::Value, E>
     where
         E: serde::de::Error,
@@ -417,6 +497,11 @@ impl<'de> serde::de::Visitor<'de> for CKhronosVisitor {
             "TZ" => KhronosValue::TimeZone(map.next_value()?),
             "MVfs" => KhronosValue::MemoryVfs(map.next_value()?),
             "N" => KhronosValue::Null(map.next_value()?),
+            "B64" => {
+                let s: String = map.next_value()?;
+                let decoded = base64::prelude::BASE64_STANDARD.decode(s).map_err(serde::de::Error::custom)?;
+                KhronosValue::Bytes(decoded.into())
+            }
             _ => return Err(serde::de::Error::custom(format!("Unknown tag: {}", key))),
         };
 
@@ -445,6 +530,9 @@ impl<'de> Deserialize<'de> for CKhronosValue {
 mod test_compressed {
     use std::collections::HashMap;
 
+    use mluau::prelude::*;
+
+    use crate::primitives::blob::Blob;
     use crate::utils::khronos_value::{CKhronosValue, CKhronosValueRef, KhronosValue};
 
     #[test]
@@ -461,4 +549,51 @@ mod test_compressed {
         let deser = serde_json::from_str::<CKhronosValue>(&s).expect("failed to deser");
         println!("{:?}", deser)
     }
+
+    #[test]
+    fn test_bytes_lua() -> LuaResult<()> {
+        let lua = Lua::new();
+        let zero: LuaFunction = lua.load("return function(b) buffer.fill(b, 0, 0) end").eval()?;
+        let is_bytes = |kv: &KhronosValue| matches!(kv, KhronosValue::Bytes(b) if b[..] == [0, 1, 255]);
+
+        // Buffers made in Luau and zero-copy buffers made from a Blob
+        let from_luau: LuaBuffer = lua.load(r#"return buffer.fromstring("\0\1\255")"#).eval()?;
+        let from_blob = Blob(vec![0, 1, 255].into()).into_lua(&lua)?;
+        for buf in [LuaValue::Buffer(from_luau), from_blob] {
+            let kv = KhronosValue::from_lua(buf.clone(), &lua)?;
+            zero.call::<()>(buf)?;
+            assert!(is_bytes(&kv));
+
+            let LuaValue::Buffer(back) = kv.clone().into_lua(&lua)? else {
+                panic!("bytes must convert to a buffer");
+            };
+            assert_eq!(back.to_vec(), [0, 1, 255]);
+            zero.call::<()>(back)?;
+            assert!(is_bytes(&kv));
+            assert!(is_bytes(&KhronosValue::from_lua(kv.into_lua(&lua)?, &lua)?));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_bytes() {
+        let kv = KhronosValue::List(vec![KhronosValue::Bytes(vec![0, 1, 255].into()), KhronosValue::Text("x".into())]);
+
+        let json = serde_json::to_string(&CKhronosValueRef(&kv)).expect("failed to serde");
+        assert_eq!(json, r#"[{"B64":"AAH/"},"x"]"#);
+        let deser = serde_json::from_str::<CKhronosValue>(&json).expect("failed to deser");
+        assert!(matches!(&deser.0, KhronosValue::List(l) if matches!(&l[0], KhronosValue::Bytes(b) if b[..] == [0, 1, 255])));
+
+        // Binary in MessagePack, distinct from strings
+        let packed = rmp_serde::to_vec(&CKhronosValueRef(&kv)).expect("failed to serde");
+        assert_eq!(&packed[..6], &[0x92, 0xc4, 0x03, 0, 1, 255]);
+        let deser = rmp_serde::from_slice::<CKhronosValue>(&packed).expect("failed to deser");
+        assert!(matches!(&deser.0, KhronosValue::List(l) if matches!(&l[0], KhronosValue::Bytes(b) if b[..] == [0, 1, 255]) && matches!(&l[1], KhronosValue::Text(_))));
+
+        // The self-describing form uses base64 in JSON too
+        let json = serde_json::to_string(&KhronosValue::Bytes(vec![0, 1, 255].into())).expect("failed to serde");
+        assert_eq!(json, r#"{"Bytes":"AAH/"}"#);
+        assert!(matches!(serde_json::from_str::<KhronosValue>(&json), Ok(KhronosValue::Bytes(b)) if b[..] == [0, 1, 255]));
+    }
 }
\ No newline at end of file
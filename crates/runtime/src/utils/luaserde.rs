@@ -0,0 +1,543 @@
+//! Direct (de)serialization of Luau values for binary formats (MessagePack, CBOR etc.)
+//!
+//! Unlike ``lua.from_value``/``lua.to_value``, this walks the Luau value itself so that
+//! buffers can be carried as binary and Int64's do not lose precision. Otherwise, the
+//! semantics match ``LUA_SERIALIZE_OPTIONS`` and ``LUA_DESERIALIZE_OPTIONS``:
+//!
+//! - Table keys are sorted
+//! - Empty tables and tables with the array metatable are encoded as arrays
+//! - Mixed tables are encoded as maps
+//! - Unsupported types (functions, threads, userdata) are rejected
+//! - Decoded arrays have the array metatable set and nulls are decoded to nil
+//!
+//! Int64's are encoded as plain integers when a Luau number can hold them exactly (and so decode
+//! as numbers). Larger Int64's and strings that are not valid UTF-8 have no safe native
+//! representation in MessagePack or CBOR, so they are encoded as extension types/tags (see
+//! ``Format``) to round-trip exactly.
+
+use std::cmp::Ordering;
+use std::fmt;
+
+use mluau::prelude::*;
+use serde::de::{DeserializeSeed, Error as _};
+use serde::ser::{Error as _, SerializeMap, SerializeSeq};
+use serde::{Serialize, Serializer};
+
+/// The maximum nesting depth allowed when encoding/decoding a value
+pub const MAX_DEPTH: usize = 64;
+
+/// Integers larger than this (in magnitude) cannot be represented exactly by a Luau number
+/// and are decoded as Int64's instead
+const MAX_SAFE_INTEGER: i64 = (1 << 53) - 1;
+
+/// Converts an integer to a Luau value, using an Int64 if it cannot be represented exactly by a number
+pub fn integer_to_lua(v: i64) -> LuaValue {
+    if (-MAX_SAFE_INTEGER..=MAX_SAFE_INTEGER).contains(&v) {
+        LuaValue::Integer(v)
+    } else {
+        LuaValue::Int64(v)
+    }
+}
+
+/// MessagePack extension type of an Int64 (8 bytes, big endian)
+pub const MSGPACK_INT64_EXT: i8 = 1;
+
+/// MessagePack extension type of a string that is not valid UTF-8
+pub const MSGPACK_STRING_EXT: i8 = 2;
+
+/// CBOR tag of an Int64 (wrapping an integer). Unassigned by IANA
+pub const CBOR_INT64_TAG: u64 = 0x4B48_0001;
+
+/// CBOR tag of a string that is not valid UTF-8 (wrapping a byte string). Unassigned by IANA
+pub const CBOR_STRING_TAG: u64 = 0x4B48_0002;
+
+/// The format a value is (de)serialized with, which decides how values without a native
+/// representation in the format are encoded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// Int64's beyond ``MAX_SAFE_INTEGER`` and non UTF-8 strings are encoded as extension types
+    MessagePack,
+    /// Int64's beyond ``MAX_SAFE_INTEGER`` and non UTF-8 strings are encoded as tags
+    Cbor,
+    /// Any serde format. Int64's are encoded as plain integers and non UTF-8 strings as binary
+    /// (and so decode as Integer's when small enough and as buffers respectively)
+    Plain,
+}
+
+/// Serializes a byte slice as binary
+struct RawBytes<'a>(&'a [u8]);
+
+impl Serialize for RawBytes<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(self.0)
+    }
+}
+
+/// Deserializes binary into a Vec<u8>
+struct BinaryVisitor;
+
+impl<'de> serde::de::Visitor<'de> for BinaryVisitor {
+    type Value = Vec<u8>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("binary data")
+    }
+
+    fn visit_bytes<E: serde::de::Error>(self, v: &[u8]) -> Result<Self::Value, E> {
+        Ok(v.to_vec())
+    }
+
+    fn visit_byte_buf<E: serde::de::Error>(self, v: Vec<u8>) -> Result<Self::Value, E> {
+        Ok(v)
+    }
+}
+
+impl<'de> DeserializeSeed<'de> for BinaryVisitor {
+    type Value = Vec<u8>;
+
+    fn deserialize<D: serde::Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_bytes(self)
+    }
+}
+
+/// How a Luau table should be encoded
+pub enum TableRepr {
+    /// The values of the table, in order
+    Array(Vec<LuaValue>),
+    /// The key-value pairs of the table, sorted by key
+    Map(Vec<(LuaValue, LuaValue)>),
+}
+
+/// Determines whether a table should be encoded as an array or a map
+///
+/// A table is an array if it has the array metatable, is empty or is a proper sequence.
+/// Everything else (including mixed tables) is a map
+pub fn table_repr(lua: &Lua, table: &LuaTable) -> LuaResult<TableRepr> {
+    let is_array_mt = table
+        .metatable()
+        .is_some_and(|mt| mt == lua.array_metatable());
+
+    let mut pairs = table
+        .pairs::<LuaValue, LuaValue>()
+        .collect::<LuaResult<Vec<_>>>()?;
+
+    let len = table.raw_len();
+
+    if is_array_mt || pairs.is_empty() || pairs.len() == len {
+        let mut values = Vec::with_capacity(len);
+        for i in 1..=len {
+            values.push(table.raw_get::<LuaValue>(i)?);
+        }
+        return Ok(TableRepr::Array(values));
+    }
+
+    pairs.sort_by(|(a, _), (b, _)| compare_keys(a, b));
+    Ok(TableRepr::Map(pairs))
+}
+
+/// A serializable reference to a Luau value
+pub struct LuaValueRef<'a> {
+    lua: &'a Lua,
+    value: &'a LuaValue,
+    format: Format,
+    depth: usize,
+}
+
+impl<'a> LuaValueRef<'a> {
+    pub fn new(lua: &'a Lua, value: &'a LuaValue, format: Format) -> Self {
+        Self { lua, value, format, depth: 0 }
+    }
+
+    fn child(&self, value: &'a LuaValue) -> Self {
+        Self {
+            lua: self.lua,
+            value,
+            format: self.format,
+            depth: self.depth + 1,
+        }
+    }
+
+    fn serialize_int64<S: Serializer>(&self, v: i64, serializer: S) -> Result<S::Ok, S::Error> {
+        // Any decoder can read integers that fit in a Luau (or JavaScript) number
+        if (-MAX_SAFE_INTEGER..=MAX_SAFE_INTEGER).contains(&v) {
+            return serializer.serialize_i64(v);
+        }
+
+        match self.format {
+            Format::MessagePack => serializer.serialize_newtype_struct(
+                rmp_serde::MSGPACK_EXT_STRUCT_NAME,
+                &(MSGPACK_INT64_EXT, RawBytes(&v.to_be_bytes())),
+            ),
+            Format::Cbor => ciborium::tag::Required::<i64, CBOR_INT64_TAG>(v).serialize(serializer),
+            Format::Plain => serializer.serialize_i64(v),
+        }
+    }
+
+    /// Serializes a string that is not valid UTF-8
+    fn serialize_binary_string<S: Serializer>(&self, v: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        match self.format {
+            Format::MessagePack => serializer.serialize_newtype_struct(
+                rmp_serde::MSGPACK_EXT_STRUCT_NAME,
+                &(MSGPACK_STRING_EXT, RawBytes(v)),
+            ),
+            Format::Cbor => ciborium::tag::Required::<RawBytes, CBOR_STRING_TAG>(RawBytes(v)).serialize(serializer),
+            Format::Plain => serializer.serialize_bytes(v),
+        }
+    }
+}
+
+fn number_of(v: &LuaValue) -> Option<f64> {
+    match v {
+        LuaValue::Integer(i) => Some(*i as f64),
+        LuaValue::Int64(i) => Some(*i as f64),
+        LuaValue::Number(n) => Some(*n),
+        _ => None,
+    }
+}
+
+/// Orders keys with numbers first (numerically), then strings (bytewise)
+fn compare_keys(a: &LuaValue, b: &LuaValue) -> Ordering {
+    match (a, b) {
+        (LuaValue::String(a), LuaValue::String(b)) => a.as_bytes()[..].cmp(&b.as_bytes()[..]),
+        (LuaValue::String(_), _) => Ordering::Greater,
+        (_, LuaValue::String(_)) => Ordering::Less,
+        _ => match (number_of(a), number_of(b)) {
+            (Some(a), Some(b)) => a.partial_cmp(&b).unwrap_or(Ordering::Equal),
+            _ => Ordering::Equal,
+        },
+    }
+}
+
+impl Serialize for LuaValueRef<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        if self.depth > MAX_DEPTH {
+            return Err(S::Error::custom("Recursion limit exceeded"));
+        }
+
+        if self.value.is_null() {
+            return serializer.serialize_unit();
+        }
+
+        match self.value {
+            LuaValue::Nil => serializer.serialize_unit(),
+            LuaValue::Boolean(b) => serializer.serialize_bool(*b),
+            LuaValue::Integer(i) => serializer.serialize_i64(*i),
+            LuaValue::Int64(i) => self.serialize_int64(*i, serializer),
+            LuaValue::Number(n) => serializer.serialize_f64(*n),
+            LuaValue::String(s) => match s.to_str() {
+                Ok(s) => serializer.serialize_str(&s),
+                Err(_) => self.serialize_binary_string(&s.as_bytes(), serializer),
+            },
+            LuaValue::Buffer(b) => b.with_bytes(|bytes| serializer.serialize_bytes(bytes)),
+            LuaValue::Vector(v) => {
+                let mut seq = serializer.serialize_seq(Some(3))?;
+                seq.serialize_element(&v.x())?;
+                seq.serialize_element(&v.y())?;
+                seq.serialize_element(&v.z())?;
+                seq.end()
+            }
+            LuaValue::Table(table) => match table_repr(self.lua, table).map_err(S::Error::custom)? {
+                TableRepr::Array(values) => {
+                    let mut seq = serializer.serialize_seq(Some(values.len()))?;
+                    for v in values.iter() {
+                        seq.serialize_element(&self.child(v))?;
+                    }
+                    seq.end()
+                }
+                TableRepr::Map(pairs) => {
+                    let mut map = serializer.serialize_map(Some(pairs.len()))?;
+                    for (k, v) in pairs.iter() {
+                        map.serialize_entry(&self.child(k), &self.child(v))?;
+                    }
+                    map.end()
+                }
+            },
+            _ => Err(S::Error::custom(format!(
+                "cannot serialize <{}>",
+                self.value.type_name()
+            ))),
+        }
+    }
+}
+
+/// A deserialization seed that produces a Luau value
+#[derive(Clone, Copy)]
+pub struct LuaValueSeed<'a> {
+    lua: &'a Lua,
+    format: Format,
+    depth: usize,
+}
+
+impl<'a> LuaValueSeed<'a> {
+    pub fn new(lua: &'a Lua, format: Format) -> Self {
+        Self { lua, format, depth: 0 }
+    }
+
+    fn child(&self) -> Self {
+        Self {
+            lua: self.lua,
+            format: self.format,
+            depth: self.depth + 1,
+        }
+    }
+
+    /// Converts a MessagePack extension type to a Luau value
+    fn msgpack_ext<E: serde::de::Error>(&self, ext: i8, data: Vec<u8>) -> Result<LuaValue, E> {
+        match ext {
+            MSGPACK_INT64_EXT => {
+                let bytes: [u8; 8] = data
+                    .try_into()
+                    .map_err(|_| E::custom("Int64 extension must be 8 bytes long"))?;
+                Ok(LuaValue::Int64(i64::from_be_bytes(bytes)))
+            }
+            MSGPACK_STRING_EXT => self.lua.create_string(data).map(LuaValue::String).map_err(E::custom),
+            _ => Err(E::custom(format!("Unsupported MessagePack extension type {ext}"))),
+        }
+    }
+}
+
+/// Reads the ``(type, data)`` of a MessagePack extension type
+struct MsgPackExtVisitor<'a>(LuaValueSeed<'a>);
+
+impl<'de> serde::de::Visitor<'de> for MsgPackExtVisitor<'_> {
+    type Value = LuaValue;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a MessagePack extension type")
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: serde::de::SeqAccess<'de>,
+    {
+        let ext: i8 = seq
+            .next_element()?
+            .ok_or_else(|| A::Error::custom("missing extension type"))?;
+        let data = seq
+            .next_element_seed(BinaryVisitor)?
+            .ok_or_else(|| A::Error::custom("missing extension data"))?;
+        self.0.msgpack_ext(ext, data)
+    }
+}
+
+/// Reads the ``(tag, value)`` of a CBOR tag. Unknown tags are ignored
+struct CborTagVisitor<'a>(LuaValueSeed<'a>);
+
+impl<'de> serde::de::Visitor<'de> for CborTagVisitor<'_> {
+    type Value = LuaValue;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a CBOR tag")
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: serde::de::SeqAccess<'de>,
+    {
+        let tag: u64 = seq
+            .next_element()?
+            .ok_or_else(|| A::Error::custom("missing tag"))?;
+        let value = match tag {
+            CBOR_INT64_TAG => seq.next_element::<i64>()?.map(LuaValue::Int64),
+            CBOR_STRING_TAG => match seq.next_element_seed(BinaryVisitor)? {
+                Some(data) => Some(self.0.lua.create_string(data).map(LuaValue::String).map_err(A::Error::custom)?),
+                None => None,
+            },
+            _ => seq.next_element_seed(self.0.child())?,
+        };
+        value.ok_or_else(|| A::Error::custom("missing tagged value"))
+    }
+}
+
+impl<'de> DeserializeSeed<'de> for LuaValueSeed<'_> {
+    type Value = LuaValue;
+
+    fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        if self.depth > MAX_DEPTH {
+            return Err(D::Error::custom("Recursion limit exceeded"));
+        }
+
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de> serde::de::Visitor<'de> for LuaValueSeed<'_> {
+    type Value = LuaValue;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a value representable in Luau")
+    }
+
+    fn visit_bool<E: serde::de::Error>(self, v: bool) -> Result<Self::Value, E> {
+        Ok(LuaValue::Boolean(v))
+    }
+
+    fn visit_i64<E: serde::de::Error>(self, v: i64) -> Result<Self::Value, E> {
+        Ok(integer_to_lua(v))
+    }
+
+    fn visit_u64<E: serde::de::Error>(self, v: u64) -> Result<Self::Value, E> {
+        match i64::try_from(v) {
+            Ok(v) => self.visit_i64(v),
+            Err(_) => Ok(LuaValue::Number(v as f64)),
+        }
+    }
+
+    fn visit_f64<E: serde::de::Error>(self, v: f64) -> Result<Self::Value, E> {
+        Ok(LuaValue::Number(v))
+    }
+
+    fn visit_str<E: serde::de::Error>(self, v: &str) -> Result<Self::Value, E> {
+        self.lua.create_string(v).map(LuaValue::String).map_err(E::custom)
+    }
+
+    fn visit_bytes<E: serde::de::Error>(self, v: &[u8]) -> Result<Self::Value, E> {
+        self.visit_byte_buf(v.to_vec())
+    }
+
+    fn visit_byte_buf<E: serde::de::Error>(self, v: Vec<u8>) -> Result<Self::Value, E> {
+        self.lua
+            .create_external_buffer(bytes::Bytes::from(v))
+            .map(LuaValue::Buffer)
+            .map_err(E::custom)
+    }
+
+    fn visit_unit<E: serde::de::Error>(self) -> Result<Self::Value, E> {
+        Ok(LuaValue::Nil)
+    }
+
+    fn visit_none<E: serde::de::Error>(self) -> Result<Self::Value, E> {
+        Ok(LuaValue::Nil)
+    }
+
+    fn visit_some<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        self.child().deserialize(deserializer)
+    }
+
+    fn visit_newtype_struct<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        // rmp_serde visits extension types as newtype structs
+        if self.format == Format::MessagePack {
+            return deserializer.deserialize_any(MsgPackExtVisitor(self.child()));
+        }
+
+        self.child().deserialize(deserializer)
+    }
+
+    fn visit_enum<A>(self, data: A) -> Result<Self::Value, A::Error>
+    where
+        A: serde::de::EnumAccess<'de>,
+    {
+        use serde::de::VariantAccess;
+
+        // ciborium visits tags as enums with a ``@@TAGGED@@`` variant holding ``(tag, value)``
+        if self.format != Format::Cbor {
+            return Err(A::Error::custom("enums cannot be represented in Luau"));
+        }
+
+        let (variant, access) = data.variant::<String>()?;
+        if variant != "@@TAGGED@@" {
+            return Err(A::Error::custom(format!("unexpected enum variant {variant}")));
+        }
+        access.tuple_variant(2, CborTagVisitor(self.child()))
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: serde::de::SeqAccess<'de>,
+    {
+        let table = self
+            .lua
+            .create_table_with_capacity(seq.size_hint().unwrap_or(0).min(1024), 0)
+            .map_err(A::Error::custom)?;
+
+        let mut i = 1;
+        while let Some(v) = seq.next_element_seed(self.child())? {
+            table.raw_set(i, v).map_err(A::Error::custom)?;
+            i += 1;
+        }
+
+        table
+            .set_metatable(Some(self.lua.array_metatable()))
+            .map_err(A::Error::custom)?;
+
+        Ok(LuaValue::Table(table))
+    }
+
+    fn visit_map<M>(self, mut map: M) -> Result<Self::Value, M::Error>
+    where
+        M: serde::de::MapAccess<'de>,
+    {
+        let table = self
+            .lua
+            .create_table_with_capacity(0, map.size_hint().unwrap_or(0).min(1024))
+            .map_err(M::Error::custom)?;
+
+        while let Some(k) = map.next_key_seed(self.child())? {
+            let v = map.next_value_seed(self.child())?;
+            if k.is_nil() {
+                continue; // Luau tables cannot have nil keys
+            }
+            table.raw_set(k, v).map_err(M::Error::custom)?;
+        }
+
+        Ok(LuaValue::Table(table))
+    }
+}
+
+/// Checks shared by the tests of every format built on ``LuaValueRef``/``LuaValueSeed``
+#[cfg(test)]
+pub(crate) fn check_roundtrip(lua: &Lua, encode: &LuaFunction, decode: &LuaFunction) -> LuaResult<()> {
+    let value = lua.create_table()?;
+    value.set("small", LuaValue::Int64(5))?;
+    value.set("large", LuaValue::Int64(i64::MIN))?;
+    value.set("integer", 7)?;
+    value.set("number", 1.5)?;
+    value.set("text", "héllo")?;
+    value.set("binary", lua.create_string([0xff, 0x00, 0xfe])?)?;
+    value.set("buffer", lua.create_buffer([1u8, 2, 3])?)?;
+    value.set("list", lua.create_sequence_from([1, 2, 3])?)?;
+
+    let decoded: LuaTable = decode.call(encode.call::<LuaValue>(value)?)?;
+    // Small Int64's are encoded as plain integers
+    assert!(matches!(decoded.get::<LuaValue>("small")?, LuaValue::Integer(5)));
+    assert!(matches!(decoded.get::<LuaValue>("large")?, LuaValue::Int64(i64::MIN)));
+    assert!(!matches!(decoded.get::<LuaValue>("integer")?, LuaValue::Int64(_)));
+    assert_eq!(decoded.get::<i64>("integer")?, 7);
+    assert_eq!(decoded.get::<f64>("number")?, 1.5);
+    assert_eq!(decoded.get::<String>("text")?, "héllo");
+    match decoded.get::<LuaValue>("binary")? {
+        LuaValue::String(s) => assert_eq!(&s.as_bytes()[..], &[0xff, 0x00, 0xfe]),
+        v => panic!("expected a string, got {v:?}"),
+    }
+    match decoded.get::<LuaValue>("buffer")? {
+        LuaValue::Buffer(b) => assert_eq!(b.to_vec(), vec![1, 2, 3]),
+        v => panic!("expected a buffer, got {v:?}"),
+    }
+    let list: LuaTable = decoded.get("list")?;
+    assert_eq!(list.raw_len(), 3);
+    assert!(list.metatable() == Some(lua.array_metatable()));
+
+    // Too deeply nested
+    let nested = lua.create_table()?;
+    let mut inner = nested.clone();
+    for _ in 0..100 {
+        let t = lua.create_table()?;
+        inner.set(1, t.clone())?;
+        inner = t;
+    }
+    assert!(encode.call::<LuaValue>(nested).is_err());
+
+    Ok(())
+}
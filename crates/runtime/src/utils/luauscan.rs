@@ -0,0 +1,619 @@
+//! Parse-only checking of Luau source
+//!
+//! Everything here comes from Luau's own compiler rather than a second parser: syntax errors are
+//! the compiler's (it only reports the first one, with a line but no column), and ``require``
+//! paths and global references are read back from the bytecode it produces, so scoping is
+//! exactly the compiler's. See Luau's ``Bytecode.h`` for the bytecode format
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+const LOP_LOADK: u8 = 5;
+const LOP_GETGLOBAL: u8 = 7;
+const LOP_SETGLOBAL: u8 = 8;
+const LOP_GETIMPORT: u8 = 12;
+const LOP_CALL: u8 = 21;
+const LOP_LOADKX: u8 = 66;
+
+/// Opcodes followed by an auxiliary word
+const AUX_OPCODES: &[u8] = &[
+    7,  // GETGLOBAL
+    8,  // SETGLOBAL
+    12, // GETIMPORT
+    15, // GETTABLEKS
+    16, // SETTABLEKS
+    20, // NAMECALL
+    27, 28, 29, 30, 31, 32, // JUMPIFEQ, JUMPIFLE, JUMPIFLT, JUMPIFNOTEQ, JUMPIFNOTLE, JUMPIFNOTLT
+    53, // NEWTABLE
+    55, // SETLIST
+    58, // FORGLOOP
+    60, // FASTCALL3
+    66, // LOADKX
+    74, // FASTCALL2
+    75, // FASTCALL2K
+    77, 78, 79, 80, // JUMPXEQKNIL, JUMPXEQKB, JUMPXEQKN, JUMPXEQKS
+];
+
+const LBC_VERSION_MIN: u8 = 3;
+const LBC_VERSION_MAX: u8 = 6;
+
+struct Reader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        let bytes = self.data.get(self.offset..self.offset.checked_add(len)?)?;
+        self.offset += len;
+        Some(bytes)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.bytes(1)?[0])
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.bytes(4)?.try_into().ok()?))
+    }
+
+    fn varint(&mut self) -> Option<usize> {
+        let mut result = 0usize;
+        for shift in (0..35).step_by(7) {
+            let byte = self.u8()?;
+            result |= ((byte & 0x7f) as usize) << shift;
+            if byte & 0x80 == 0 {
+                return Some(result);
+            }
+        }
+        None
+    }
+}
+
+/// The parts of a function prototype needed to find globals and ``require`` calls
+struct Proto {
+    code: Vec<u32>,
+    /// String constants (by constant index) as indices into the string table
+    strings: HashMap<usize, usize>,
+    /// The line of each instruction, if the chunk was compiled with line info
+    lines: Option<Vec<u32>>,
+}
+
+impl Proto {
+    fn read(r: &mut Reader, version: u8) -> Option<Self> {
+        r.bytes(4)?; // maxstacksize, numparams, nups, is_vararg
+        if version >= 4 {
+            r.u8()?; // flags
+            let typesize = r.varint()?;
+            r.bytes(typesize)?;
+        }
+
+        let sizecode = r.varint()?;
+        let code = (0..sizecode).map(|_| r.u32()).collect::<Option<Vec<_>>>()?;
+
+        let mut strings = HashMap::new();
+        for k in 0..r.varint()? {
+            match r.u8()? {
+                0 => {} // nil
+                1 => {
+                    r.u8()?; // boolean
+                }
+                2 => {
+                    r.bytes(8)?; // number
+                }
+                3 => {
+                    // string, 1-based (0 is no string)
+                    if let Some(id) = r.varint()?.checked_sub(1) {
+                        strings.insert(k, id);
+                    }
+                }
+                4 => {
+                    r.u32()?; // import
+                }
+                5 => {
+                    // table shape
+                    for _ in 0..r.varint()? {
+                        r.varint()?;
+                    }
+                }
+                6 => {
+                    r.varint()?; // closure
+                }
+                7 => {
+                    r.bytes(16)?; // vector
+                }
+                8 => {
+                    // table shape with constant values
+                    for _ in 0..r.varint()? {
+                        r.varint()?;
+                        r.u32()?;
+                    }
+                }
+                _ => return None,
+            }
+        }
+
+        for _ in 0..r.varint()? {
+            r.varint()?; // child protos
+        }
+        r.varint()?; // linedefined
+        r.varint()?; // debugname
+
+        let mut lines = None;
+        if r.u8()? != 0 {
+            let gap = r.u8()?;
+            if gap >= 32 {
+                return None;
+            }
+            let intervals = (sizecode.saturating_sub(1) >> gap) + 1;
+
+            let mut offset = 0u8;
+            let offsets = r
+                .bytes(sizecode)?
+                .iter()
+                .map(|d| {
+                    offset = offset.wrapping_add(*d);
+                    offset
+                })
+                .collect::<Vec<_>>();
+
+            let mut line = 0i32;
+            let mut abs = Vec::with_capacity(intervals);
+            for _ in 0..intervals {
+                line = line.wrapping_add(r.u32()? as i32);
+                abs.push(line);
+            }
+
+            lines = Some(
+                (0..sizecode)
+                    .map(|pc| (abs[pc >> gap] + offsets[pc] as i32).max(0) as u32)
+                    .collect(),
+            );
+        }
+
+        if r.u8()? != 0 {
+            for _ in 0..r.varint()? {
+                // locals: name, startpc, endpc, register
+                r.varint()?;
+                r.varint()?;
+                r.varint()?;
+                r.u8()?;
+            }
+            for _ in 0..r.varint()? {
+                r.varint()?; // upvalue names
+            }
+        }
+
+        Some(Self { code, strings, lines })
+    }
+
+    fn line(&self, pc: usize) -> Option<u32> {
+        self.lines.as_ref()?.get(pc).copied()
+    }
+
+    /// The string constant loaded into register ``reg`` by the instruction at ``pc``, if any
+    fn loaded_string(&self, pc: usize, reg: u32) -> Option<usize> {
+        let insn = *self.code.get(pc)?;
+        if (insn >> 8) & 0xff != reg {
+            return None;
+        }
+
+        let k = match (insn & 0xff) as u8 {
+            LOP_LOADK => (insn >> 16) as usize,
+            LOP_LOADKX => *self.code.get(pc + 1)? as usize,
+            _ => return None,
+        };
+        self.strings.get(&k).copied()
+    }
+
+    /// The literal path of a ``require`` call whose function was loaded into ``reg`` at ``pc``
+    fn require_path(&self, pc: usize, reg: u32) -> Option<usize> {
+        let next = pc + insn_len(*self.code.get(pc)?);
+        let path = self.loaded_string(next, reg + 1)?;
+
+        let call = *self.code.get(next + insn_len(*self.code.get(next)?))?;
+        // CALL A B C with B - 1 arguments
+        ((call & 0xff) as u8 == LOP_CALL && (call >> 8) & 0xff == reg && (call >> 16) & 0xff == 2).then_some(path)
+    }
+}
+
+fn insn_len(insn: u32) -> usize {
+    if AUX_OPCODES.contains(&((insn & 0xff) as u8)) { 2 } else { 1 }
+}
+
+/// A reference to a global variable
+#[derive(Debug, Clone, PartialEq)]
+pub struct GlobalUse {
+    pub name: String,
+    /// The line of the reference, if the chunk was compiled with line info
+    pub line: Option<u32>,
+    /// Whether the global is assigned to (``name = value``)
+    pub write: bool,
+}
+
+/// The ``require`` paths and global references found in a chunk
+#[derive(Debug, Clone, Default)]
+pub struct ScanResult {
+    /// Literal ``require`` paths, in order of first appearance
+    pub requires: Vec<String>,
+    /// Every reference to a global variable, in order of appearance
+    pub globals: Vec<GlobalUse>,
+}
+
+impl ScanResult {
+    /// Returns the sorted, deduplicated names of the globals referenced
+    pub fn global_names(&self) -> Vec<String> {
+        self.globals
+            .iter()
+            .map(|g| g.name.clone())
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect()
+    }
+}
+
+/// Finds the literal ``require`` paths and global references in bytecode produced by the Luau
+/// compiler
+///
+/// Returns ``None`` if the bytecode is malformed or of an unsupported version
+pub fn scan_bytecode(bytecode: &[u8]) -> Option<ScanResult> {
+    let mut r = Reader { data: bytecode, offset: 0 };
+
+    let version = r.u8()?;
+    if !(LBC_VERSION_MIN..=LBC_VERSION_MAX).contains(&version) {
+        return None;
+    }
+    let types_version = if version >= 4 { r.u8()? } else { 0 };
+
+    let strings = (0..r.varint()?)
+        .map(|_| {
+            let len = r.varint()?;
+            Some(String::from_utf8_lossy(r.bytes(len)?).into_owned())
+        })
+        .collect::<Option<Vec<_>>>()?;
+
+    if types_version == 3 {
+        // Userdata type names
+        while r.u8()? != 0 {
+            r.varint()?;
+        }
+    }
+
+    let protos = (0..r.varint()?)
+        .map(|_| Proto::read(&mut r, version))
+        .collect::<Option<Vec<_>>>()?;
+
+    // Protos are stored children first, so sort what they reference by line afterwards
+    let mut requires = Vec::new();
+    let mut globals = Vec::new();
+    for proto in protos.iter() {
+        let mut pc = 0;
+        while let Some(&insn) = proto.code.get(pc) {
+            let aux = proto.code.get(pc + 1).copied().unwrap_or_default();
+            let reg = (insn >> 8) & 0xff;
+
+            // (constant holding the name, whether it is a write, whether the global itself is loaded)
+            let global = match (insn & 0xff) as u8 {
+                LOP_GETGLOBAL => Some((aux as usize, false, true)),
+                LOP_SETGLOBAL => Some((aux as usize, true, false)),
+                // An import is up to three constants (``a.b.c``), the first of which is the global
+                LOP_GETIMPORT => Some((((aux >> 20) & 0x3ff) as usize, false, aux >> 30 == 1)),
+                _ => None,
+            };
+
+            if let Some((name, write, loaded)) =
+                global.and_then(|(k, write, loaded)| Some((strings.get(*proto.strings.get(&k)?)?, write, loaded)))
+            {
+                let line = proto.line(pc);
+
+                if loaded && name == "require" {
+                    if let Some(path) = proto.require_path(pc, reg).and_then(|id| strings.get(id)) {
+                        requires.push((line, path.clone()));
+                    }
+                }
+
+                globals.push(GlobalUse {
+                    name: name.clone(),
+                    line,
+                    write,
+                });
+            }
+
+            pc += insn_len(insn);
+        }
+    }
+
+    requires.sort_by_key(|(line, _)| *line);
+    globals.sort_by_key(|g| g.line);
+
+    let mut seen = HashSet::new();
+    let requires = requires
+        .into_iter()
+        .filter_map(|(_, path)| seen.insert(path.clone()).then_some(path))
+        .collect();
+
+    Some(ScanResult { requires, globals })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiagnosticSeverity {
+    Error,
+    Warning,
+}
+
+impl DiagnosticSeverity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Error => "error",
+            Self::Warning => "warning",
+        }
+    }
+}
+
+/// A problem found while parsing a chunk
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: DiagnosticSeverity,
+    pub line: Option<u32>,
+    /// The Luau compiler does not report columns, so for compile errors this is a best guess at
+    /// the token the message points at (see ``compile_error_column``). Lints have no column
+    pub column: Option<u32>,
+    pub message: String,
+}
+
+impl Diagnostic {
+    /// Converts a Luau compile error (``:<line>: <message>``, possibly prefixed with a chunk name)
+    /// into a diagnostic
+    pub fn from_compile_error(message: &str) -> Self {
+        // The chunk name may itself contain ':', so look for the first ``:<digits>: ``
+        let parsed = message.match_indices(':').find_map(|(i, _)| {
+            let rest = &message[i + 1..];
+            let digits = rest.len() - rest.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+            let msg = rest[digits..].strip_prefix(": ")?;
+            Some((rest[..digits].parse::<u32>().ok()?, msg))
+        });
+
+        match parsed {
+            Some((line, msg)) => Self {
+                severity: DiagnosticSeverity::Error,
+                line: Some(line),
+                column: None,
+                message: msg.to_string(),
+            },
+            None => Self {
+                severity: DiagnosticSeverity::Error,
+                line: None,
+                column: None,
+                message: message.to_string(),
+            },
+        }
+    }
+}
+
+/// Guesses the column of the token a compile error on ``line`` points at
+///
+/// Luau quotes the offending token at the end of most messages (``..., got 'end'``). If the
+/// token can't be found on the line, the first non-blank character of the line is used instead
+fn compile_error_column(code: &str, line: u32, message: &str) -> Option<u32> {
+    let text = code.split('\n').nth(line.checked_sub(1)? as usize)?.trim_end_matches('\r');
+    let got = message.rsplit_once("got ").map(|(_, got)| got.trim());
+
+    if got == Some("<eof>") {
+        return Some(text.len() as u32 + 1);
+    }
+
+    let got = got.and_then(|g| g.strip_prefix('\'')?.strip_suffix('\''));
+    if let Some(i) = got.filter(|g| !g.is_empty()).and_then(|g| text.find(g)) {
+        return Some(i as u32 + 1);
+    }
+
+    text.find(|c: char| !c.is_whitespace()).map(|i| i as u32 + 1)
+}
+
+/// What to collect when parsing a chunk
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ParseOptions {
+    /// Collect literal ``require`` paths
+    pub requires: bool,
+    /// Collect the names of referenced globals
+    pub globals: bool,
+    /// Warn about globals that are read but neither known nor assigned in the chunk
+    pub lint_globals: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ParseResult {
+    pub diagnostics: Vec<Diagnostic>,
+    pub requires: Option<Vec<String>>,
+    pub globals: Option<Vec<String>>,
+}
+
+impl ParseResult {
+    /// Returns true if there are no errors (warnings are allowed)
+    pub fn ok(&self) -> bool {
+        !self.diagnostics.iter().any(|d| d.severity == DiagnosticSeverity::Error)
+    }
+}
+
+/// Compiles (without executing) a chunk and collects diagnostics and optionally the
+/// ``require`` paths and globals it references
+///
+/// ``known_global`` is used to decide whether a global read is unknown when ``lint_globals`` is set
+pub fn parse_luau(
+    compiler: &mluau::Compiler,
+    code: &str,
+    opts: &ParseOptions,
+    known_global: impl Fn(&str) -> bool,
+) -> ParseResult {
+    let mut result = ParseResult::default();
+
+    let bytecode = match compiler.compile(code) {
+        Ok(bytecode) => bytecode,
+        Err(e) => {
+            let mut diagnostic = match e {
+                mluau::Error::SyntaxError { ref message, .. } => Diagnostic::from_compile_error(message),
+                e => Diagnostic::from_compile_error(&e.to_string()),
+            };
+            if let Some(line) = diagnostic.line {
+                diagnostic.column = compile_error_column(code, line, &diagnostic.message);
+            }
+            result.diagnostics.push(diagnostic);
+            return result;
+        }
+    };
+
+    if !opts.requires && !opts.globals && !opts.lint_globals {
+        return result;
+    }
+
+    let Some(scanned) = scan_bytecode(&bytecode) else {
+        result.diagnostics.push(Diagnostic {
+            severity: DiagnosticSeverity::Warning,
+            line: None,
+            column: None,
+            message: "Could not read the compiled bytecode, requires and globals are unavailable".to_string(),
+        });
+        return result;
+    };
+
+    if opts.lint_globals {
+        let written = scanned
+            .globals
+            .iter()
+            .filter(|g| g.write)
+            .map(|g| g.name.as_str())
+            .collect::<HashSet<_>>();
+
+        let mut reported = HashSet::new();
+        for g in scanned.globals.iter() {
+            if g.write || written.contains(g.name.as_str()) || known_global(&g.name) || !reported.insert(g.name.as_str()) {
+                continue;
+            }
+
+            result.diagnostics.push(Diagnostic {
+                severity: DiagnosticSeverity::Warning,
+                line: g.line,
+                column: None,
+                message: format!("Unknown global '{}'", g.name),
+            });
+        }
+    }
+
+    if opts.globals {
+        result.globals = Some(scanned.global_names());
+    }
+
+    if opts.requires {
+        result.requires = Some(scanned.requires);
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scan(code: &str) -> ScanResult {
+        let bytecode = mluau::Compiler::new().compile(code).unwrap();
+        scan_bytecode(&bytecode).expect("bytecode should be readable")
+    }
+
+    #[test]
+    fn test_scan() {
+        let code = r#"
+            local http = require("@antiraid/http")
+            local util = require "./util"
+            local print = print
+            type Foo<T> = { value: T, cb: (number) -> string? }
+
+            local function helper(a: number, ...: string): Foo<number>
+                local t = { key = a, [a] = b }
+                for i, v in ipairs(t) do
+                    total = (total or 0) + i + v
+                end
+                return t :: any
+            end
+
+            function obj:method(x)
+                return self, x, `value {missing} and {x}`, game.Workspace
+            end
+
+            repeat
+                local done = true
+            until done
+
+            --[[ require("ignored") ]]
+            return helper, string.format("%s", 'require("also ignored")'), require("@antiraid/http")
+        "#;
+
+        let result = scan(code);
+        assert_eq!(result.requires, vec!["@antiraid/http", "./util"]);
+        assert_eq!(
+            result.global_names(),
+            vec!["b", "game", "ipairs", "missing", "obj", "print", "require", "string", "total"]
+        );
+        assert!(result.globals.iter().any(|g| g.name == "total" && g.write));
+        assert!(!result.globals.iter().any(|g| g.name == "print" && g.write));
+
+        let missing = result.globals.iter().find(|g| g.name == "missing").unwrap();
+        assert_eq!(missing.line, Some(16));
+    }
+
+    #[test]
+    fn test_compile_error() {
+        let d = Diagnostic::from_compile_error(":3: Expected 'end' (to close 'function' at line 1), got <eof>");
+        assert_eq!(d.line, Some(3));
+        assert_eq!(d.message, "Expected 'end' (to close 'function' at line 1), got <eof>");
+
+        let d = Diagnostic::from_compile_error("[string \"main\"]:12: Incomplete statement");
+        assert_eq!(d.line, Some(12));
+        assert_eq!(d.message, "Incomplete statement");
+
+        // Chunk names containing ':'
+        let d = Diagnostic::from_compile_error("[string \"@antiraid:templates/3\"]:7: Incomplete statement: expected assignment");
+        assert_eq!(d.line, Some(7));
+        assert_eq!(d.message, "Incomplete statement: expected assignment");
+    }
+
+    #[test]
+    fn test_compile_error_column() {
+        let code = "local x = 1\nlocal y = (x + )\nlocal function f()\n    return y";
+
+        assert_eq!(
+            compile_error_column(code, 2, "Expected identifier when parsing expression, got ')'"),
+            Some(16)
+        );
+        assert_eq!(
+            compile_error_column(code, 4, "Expected 'end' (to close 'function' at line 3), got <eof>"),
+            Some(13)
+        );
+        // Falls back to the first token on the line
+        assert_eq!(compile_error_column(code, 2, "Incomplete statement"), Some(1));
+        assert_eq!(compile_error_column(code, 9, "Incomplete statement"), None);
+
+        let result = parse_luau(&mluau::Compiler::new(), code, &ParseOptions::default(), |_| true);
+        assert!(!result.ok());
+        let d = &result.diagnostics[0];
+        assert!(d.line.is_some() && d.column.is_some(), "{d:?}");
+    }
+
+    #[test]
+    fn test_lint_globals() {
+        let code = "repeat local done = true until done\ncount = 1\nreturn count, unknown, known";
+        let opts = ParseOptions {
+            requires: true,
+            globals: true,
+            lint_globals: true,
+        };
+
+        let result = parse_luau(&mluau::Compiler::new(), code, &opts, |name| name == "known");
+        assert!(result.ok());
+        assert_eq!(result.globals, Some(vec!["count".to_string(), "known".to_string(), "unknown".to_string()]));
+        assert_eq!(result.requires, Some(vec![]));
+
+        // Only the unknown global (and not the repeat...until local) is reported
+        assert_eq!(result.diagnostics.len(), 1, "{:?}", result.diagnostics);
+        assert_eq!(result.diagnostics[0].message, "Unknown global 'unknown'");
+        assert_eq!(result.diagnostics[0].line, Some(3));
+    }
+}
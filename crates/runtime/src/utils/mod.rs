@@ -1,4 +1,7 @@
+pub mod khronos_schema;
 pub mod khronos_value;
+pub mod luaserde;
+pub mod luauscan;
 pub mod prelude;
 pub mod proxyglobal;
-pub mod pp;
\ No newline at end of file
+pub mod pp;
@@ -1,3 +1,5 @@
+pub mod rpc;
+
 #[link(wasm_import_module = "env")]
 extern "C" {
     fn send(ptr: *const u8, len: u32);
@@ -31,3 +33,9 @@ pub extern "C" fn main() {
         unsafe { send(reply.as_ptr(), reply.len() as u32) };
     }
 }
+
+/// Serves RPC requests, replying with the payload reversed
+#[no_mangle]
+pub extern "C" fn serve_rpc() {
+    rpc::serve(|payload| payload.iter().rev().copied().collect())
+}
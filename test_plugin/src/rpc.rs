@@ -0,0 +1,35 @@
+//! Guest side of the RPC ABI used by ``WasmState:request`` (see ``core::wasm`` in the runtime)
+
+#[link(wasm_import_module = "env")]
+extern "C" {
+    fn rpc_next() -> u32;
+    fn rpc_take(ptr: *mut u8) -> u64;
+    fn rpc_reply(id: u64, ptr: *const u8, len: u32);
+}
+
+/// A request awaiting a reply
+pub struct Request {
+    pub id: u64,
+    pub payload: Vec<u8>,
+}
+
+/// Awaits the next request
+pub fn next() -> Request {
+    let len = unsafe { rpc_next() };
+    let mut payload = vec![0u8; len as usize];
+    let id = unsafe { rpc_take(payload.as_mut_ptr()) };
+    Request { id, payload }
+}
+
+/// Replies to the request with the given id. Replies to requests that timed out are dropped
+pub fn reply(id: u64, payload: &[u8]) {
+    unsafe { rpc_reply(id, payload.as_ptr(), payload.len() as u32) };
+}
+
+/// Serves requests in order, replying to each with the result of ``handler``
+pub fn serve(mut handler: impl FnMut(&[u8]) -> Vec<u8>) -> ! {
+    loop {
+        let request = next();
+        reply(request.id, &handler(&request.payload));
+    }
+}